@@ -1,9 +1,16 @@
-use crate::client::cli::eval::eval;
-use crate::client::cli::parser::{parse, tokenize, verify_command, verify_flags, ParseError};
+use crate::client::cli::diagnostics;
+use crate::client::cli::eval::{eval, EvalError, Session};
+use crate::client::cli::macros;
+use crate::client::cli::parser::{
+    parse, tokenize, verify_command, Connector, ParseError, Statement,
+};
+use crate::client::cli::registry;
+use crate::client::cli::value::Value;
 use crate::client::local_api_proxy::ApiProxy;
-use log::{debug, info};
+use log::debug;
 
-use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 // use crate::core::playlist_manager::PlaylistManager;
 // use crate::core::queue_manager::QueueManager;
 // use crate::core::search_manager::SearchManager;
@@ -179,43 +186,294 @@ use std::collections::{HashMap, HashSet};
 //     }
 // }
 
-async fn run(
-    api_proxy: &mut ApiProxy,
-    input: String,
-    flags: HashMap<String, Vec<String>>,
-) -> Result<Option<String>, ParseError> {
-    let command_list: HashSet<String> = HashSet::from_iter(flags.keys().map(|s| s.to_string()));
+// Errors from either parsing or evaluating a command.
+#[derive(Debug)]
+enum RunError {
+    Parse(ParseError),
+    Eval(EvalError),
+}
 
-    debug!("Received command {}", input);
+impl From<ParseError> for RunError {
+    fn from(e: ParseError) -> Self {
+        RunError::Parse(e)
+    }
+}
+
+impl From<EvalError> for RunError {
+    fn from(e: EvalError) -> Self {
+        RunError::Eval(e)
+    }
+}
+
+// Renders an error against the input it came from, suggesting the closest
+// command or flag for unknown ones.
+fn report_error(input: &str, e: &RunError) -> String {
+    match e {
+        RunError::Parse(ParseError::NoCommandFound(span)) => {
+            let word = input.get(span.start..span.end).unwrap_or_default();
+            let commands = registry::command_names();
+            let mut commands: Vec<&str> = commands
+                .iter()
+                .map(|s| s.as_str())
+                .chain(macros::all().iter().map(|m| m.name.as_str()))
+                .collect();
+            commands.sort();
+            let hint =
+                diagnostics::suggest(word, commands).map(|c| format!("did you mean '{}'?", c));
+            diagnostics::render(input, &format!("Unknown command '{}'", word), *span, hint)
+        }
+        RunError::Parse(e @ ParseError::UnknownFlag(flag, command, span)) => {
+            let hint = registry::find(command).and_then(|spec| {
+                diagnostics::suggest(flag, spec.flags.iter().map(|f| f.name))
+                    .map(|f| format!("did you mean '{}'?", f))
+            });
+            diagnostics::render(input, &e.to_string(), *span, hint)
+        }
+        RunError::Parse(e) => diagnostics::render(input, &e.to_string(), e.span(), None),
+        RunError::Eval(e) => diagnostics::render(input, &e.message, e.span, None),
+    }
+}
+
+fn parse_input(input: &str) -> Result<Vec<(Connector, Statement)>, ParseError> {
+    let command_list = registry::command_names();
 
-    let tokens = tokenize(&input)?;
+    let tokens = tokenize(input)?;
     verify_command(&tokens, &command_list)?;
 
-    let cmd = parse(&tokens, &command_list)?;
-    verify_flags(&cmd, &flags)?;
+    let statements = parse(&tokens, &command_list)?;
+    for (_, statement) in &statements {
+        match statement {
+            Statement::Command(cmd) | Statement::Let(_, cmd) => registry::validate(cmd)?,
+            Statement::Sequence(cmds) => {
+                for cmd in cmds {
+                    registry::validate(cmd)?;
+                }
+            }
+        }
+    }
 
     debug!("Tokenized and parsed {:?}", tokens);
 
-    return Ok(eval(api_proxy, &cmd).await);
+    Ok(statements)
+}
+
+// Output of a command, or an error report, as produced by execute.
+#[derive(Debug, Clone)]
+pub enum Output {
+    Value(String),
+    Error(String),
+}
+
+// Runs the statements in the input, passing each one's output or error to emit
+// as it goes, errors prefixed with origin (e.g. "script.spt:3: "). Nothing runs
+// if the input doesn't parse. A statement after && is skipped if the one
+// before failed or was skipped. Returns whether the last statement succeeded.
+pub async fn execute(
+    api_proxy: &ApiProxy,
+    session: &mut Session,
+    input: &str,
+    origin: &str,
+    emit: &mut dyn FnMut(Output),
+) -> bool {
+    debug!("Received command {}", input);
+
+    let statements = match parse_input(input) {
+        Ok(statements) => statements,
+        Err(e) => {
+            emit(Output::Error(format!(
+                "{}{}",
+                origin,
+                report_error(input, &e.into())
+            )));
+            return false;
+        }
+    };
+
+    let mut succeeded = true;
+    for (connector, statement) in &statements {
+        if *connector == Connector::IfSucceeded && !succeeded {
+            continue;
+        }
+        succeeded = match eval(api_proxy, session, statement).await {
+            Ok(Some(value)) => {
+                let output = value.render();
+                // skip commands that have nothing to say
+                if !output.is_empty() {
+                    emit(Output::Value(output));
+                }
+                !value.is_error()
+            }
+            Ok(None) => true,
+            Err(e) => {
+                emit(Output::Error(format!(
+                    "{}{}",
+                    origin,
+                    report_error(input, &e.into())
+                )));
+                false
+            }
+        };
+    }
+
+    succeeded
+}
+
+// Runs the statements in the input like execute, printing output to stdout
+// and errors to stderr.
+async fn run(api_proxy: &ApiProxy, session: &mut Session, input: &str, origin: &str) -> bool {
+    execute(
+        api_proxy,
+        session,
+        input,
+        origin,
+        &mut |output| match output {
+            Output::Value(text) => println!("{}", text),
+            Output::Error(text) => eprintln!("{}", text),
+        },
+    )
+    .await
+}
+
+// Prints a command's output, skipping commands that have nothing to say.
+fn print_value(value: &Value) {
+    let output = value.render();
+    if !output.is_empty() {
+        println!("{}", output);
+    }
+}
+
+// Runs a script line by line with its own variables, printing the output of
+// each command as it goes. Blank lines and # comments are skipped. After
+// "set -e" the script stops at the first failing command, "set +e" turns that
+// off again. Fails if any command failed.
+pub async fn run_script(
+    api_proxy: &ApiProxy,
+    name: &str,
+    source: &str,
+    abort_on_error: bool,
+) -> Value {
+    let mut session = Session::default();
+    let mut abort_on_error = abort_on_error;
+    let mut failed = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        match line {
+            "" => continue,
+            _ if line.starts_with('#') => continue,
+            "set -e" => {
+                abort_on_error = true;
+                continue;
+            }
+            "set +e" => {
+                abort_on_error = false;
+                continue;
+            }
+            _ => {}
+        }
+
+        let origin = format!("{}:{}: ", name, line_number);
+        if !run(api_proxy, &mut session, line, &origin).await {
+            if abort_on_error {
+                return Value::Error(format!(
+                    "{}:{}: stopped after a failed command",
+                    name, line_number
+                ));
+            }
+            failed.push(line_number.to_string());
+        }
+    }
+
+    if failed.is_empty() {
+        Value::Str(String::new())
+    } else {
+        let lines = if failed.len() == 1 { "line" } else { "lines" };
+        Value::Error(format!(
+            "{}: commands failed on {} {}",
+            name,
+            lines,
+            failed.join(", ")
+        ))
+    }
+}
+
+// Reads and runs commands from stdin until EOF or "exit", keeping variables
+// between commands.
+async fn run_repl(api_proxy: &ApiProxy) {
+    let mut session = Session::default();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        print!("spt> ");
+        let _ = std::io::stdout().flush();
+
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            _ => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "exit" || line == "quit" {
+            break;
+        }
+
+        run(api_proxy, &mut session, line, "").await;
+    }
+}
+
+// Quotes one of several arguments the shell split off if it would not come
+// out of the tokenizer as the same single word, as with device "Desk Speaker"
+// or a JSON body. Others are left as is, so spt play \; next still runs two
+// commands.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
-pub async fn run_cli(api_proxy: &mut ApiProxy, args: Vec<String>) {
-    let mut flags = HashMap::new();
-    flags.insert("play".to_string(), vec![]);
-    flags.insert("pause".to_string(), vec![]);
-    flags.insert("next".to_string(), vec![]);
-    flags.insert("previous".to_string(), vec![]);
-    flags.insert("volume".to_string(), vec![]);
-    flags.insert("device".to_string(), vec![]);
-    flags.insert("devices".to_string(), vec!["-h".to_string()]);
-    flags.insert("now".to_string(), vec!["-h".to_string()]);
-    flags.insert("queue".to_string(), vec!["-h".to_string()]);
-    flags.insert("recent".to_string(), vec!["-h".to_string()]);
-
-    let input = args[1..].join(" ");
-    let output = run(api_proxy, input, flags).await.unwrap_or(None);
-
-    if output.is_some() {
-        println!("{}", output.unwrap());
+// Runs the command given in args, or an interactive session if there is none.
+// Returns the process exit code, non-zero if the command failed.
+pub async fn run_cli(api_proxy: &mut ApiProxy, args: Vec<String>) -> i32 {
+    // no command given, start an interactive session
+    if args.len() <= 1 {
+        api_proxy.set_interactive();
+        run_repl(api_proxy).await;
+        return 0;
+    }
+
+    // "spt -" runs a script read from stdin
+    if args.len() == 2 && args[1] == "-" {
+        let mut source = String::new();
+        if let Err(e) = tokio::io::stdin().read_to_string(&mut source).await {
+            eprintln!("Error: Failed to read stdin: {}", e);
+            return 1;
+        }
+        let value = run_script(api_proxy, "<stdin>", &source, false).await;
+        print_value(&value);
+        return if value.is_error() { 1 } else { 0 };
+    }
+
+    if args[1] == "tui" {
+        api_proxy.set_interactive();
+    }
+
+    // a lone argument is a whole command line, quoted to get ; | and && past
+    // the shell
+    let input = match args.len() {
+        2 => args[1].clone(),
+        _ => args[1..]
+            .iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<String>>()
+            .join(" "),
+    };
+    if run(api_proxy, &mut Session::default(), &input, "").await {
+        0
+    } else {
+        1
     }
 }
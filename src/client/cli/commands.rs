@@ -0,0 +1,2401 @@
+use crate::client::cli::cli_app;
+use crate::client::cli::completion_cache::{self, CompletionKind};
+use crate::client::cli::docs;
+use crate::client::cli::eval::{EvalContext, EvalError, Invocation};
+use crate::client::cli::formatter::{self, Column};
+use crate::client::cli::parser::Span;
+use crate::client::cli::registry::{Arity, CommandSpec, FlagSpec, HandlerFuture, ParamSpec};
+use crate::client::cli::value::{ArgType, TrackRecord, Value};
+use crate::client::core::history_manager::{self, StatsSection};
+use crate::client::core::library_manager;
+use crate::client::core::playlist_file::{self, PlaylistFormat};
+use crate::client::core::playlist_manager::{count_tracks, DuplicateMatch};
+use crate::client::core::queue_manager::AddOptions;
+use crate::client::core::radio_manager::{RadioOptions, RadioSeeds};
+use crate::client::core::smart_playlist_manager::{SmartPlaylist, SmartRule, Source};
+use crate::client::core::track_query;
+use crate::client::paging::PageRange;
+use crate::client::tui::tui_app;
+use crate::util::errors::ApiError;
+use crate::util::models::PlaylistItem;
+use crate::util::uri_helper::{self, UriType};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const HUMAN_READABLE: FlagSpec = FlagSpec {
+    name: "-h",
+    value: None,
+    description: "Human readable output",
+};
+
+const COLUMNS: FlagSpec = FlagSpec {
+    name: "--columns",
+    value: Some("LIST"),
+    description: "Table columns, e.g. title,artists,duration (implies -h)",
+};
+
+const SHUFFLE: FlagSpec = FlagSpec {
+    name: "--shuffle",
+    value: None,
+    description: "Shuffle the tracks before adding them",
+};
+
+const LIMIT: FlagSpec = FlagSpec {
+    name: "--limit",
+    value: Some("N"),
+    description: "Add at most N tracks",
+};
+
+const DISCOGRAPHY: FlagSpec = FlagSpec {
+    name: "--discography",
+    value: None,
+    description: "Expand artists to their full discography instead of top tracks",
+};
+
+// Flags for adding to the queue.
+const ADD_FLAGS: &[FlagSpec] = &[SHUFFLE, LIMIT, DISCOGRAPHY];
+
+// Flags selecting part of a paged list, read by page_range.
+const ALL: FlagSpec = FlagSpec {
+    name: "--all",
+    value: None,
+    description: "List every item, fetching as many pages as needed",
+};
+
+const PAGE_LIMIT: FlagSpec = FlagSpec {
+    name: "--limit",
+    value: Some("N"),
+    description: "List at most N items",
+};
+
+const OFFSET: FlagSpec = FlagSpec {
+    name: "--offset",
+    value: Some("N"),
+    description: "Skip the first N items",
+};
+
+const BEFORE: FlagSpec = FlagSpec {
+    name: "--before",
+    value: Some("TIME"),
+    description: "Only items played before TIME (YYYY-MM-DD, RFC 3339 or unix ms)",
+};
+
+const AFTER: FlagSpec = FlagSpec {
+    name: "--after",
+    value: Some("TIME"),
+    description: "Only items played after TIME (YYYY-MM-DD, RFC 3339 or unix ms)",
+};
+
+const LIBRARY_URIS: ParamSpec = ParamSpec {
+    name: "uris",
+    ty: ArgType::Uris(Some(UriType::Track)),
+    arity: Arity::Many,
+    default: None,
+    description: "Tracks, albums or shows, the current track if left out",
+    complete: Some(CompletionKind::Tracks),
+};
+
+const FOLLOW_URIS: ParamSpec = ParamSpec {
+    name: "uris",
+    ty: ArgType::Uris(Some(UriType::Artist)),
+    arity: Arity::Many,
+    default: None,
+    description: "Artists or playlists, bare IDs are taken as artists",
+    complete: None,
+};
+
+// What like and follow accept.
+const SAVABLE: &[UriType] = &[UriType::Track, UriType::Album, UriType::Show];
+const FOLLOWABLE: &[UriType] = &[UriType::Artist, UriType::Playlist];
+
+const PLAYLIST: ParamSpec = ParamSpec {
+    name: "playlist",
+    ty: ArgType::Text,
+    arity: Arity::One,
+    default: None,
+    description: "Playlist URI, URL, ID or name",
+    complete: Some(CompletionKind::Playlists),
+};
+
+// Flags tuning a radio, read by radio_options.
+const RADIO_FLAGS: &[FlagSpec] = &[
+    FlagSpec {
+        name: "--genre",
+        value: Some("LIST"),
+        description: "Also seed from genres, e.g. indie,shoegaze",
+    },
+    FlagSpec {
+        name: "--energy",
+        value: Some("X"),
+        description: "Target energy, from 0.0 to 1.0",
+    },
+    FlagSpec {
+        name: "--tempo",
+        value: Some("MIN-MAX"),
+        description: "Tempo range in BPM, e.g. 100-130",
+    },
+    FlagSpec {
+        name: "--popularity",
+        value: Some("MIN-MAX"),
+        description: "Popularity range, from 0 to 100",
+    },
+    FlagSpec {
+        name: "--limit",
+        value: Some("N"),
+        description: "At most N tracks, up to 100 (default 20)",
+    },
+    FlagSpec {
+        name: "--keep-recent",
+        value: None,
+        description: "Keep tracks you played recently",
+    },
+    FlagSpec {
+        name: "--save",
+        value: Some("NAME"),
+        description: "Save the tracks as a new playlist",
+    },
+    HUMAN_READABLE,
+    COLUMNS,
+];
+
+const PLAYLIST_FORMAT: FlagSpec = FlagSpec {
+    name: "--format",
+    value: Some("FORMAT"),
+    description: "One of csv, json, m3u or xspf",
+};
+
+const OTHER_PLAYLIST: ParamSpec = ParamSpec {
+    name: "other",
+    ty: ArgType::Text,
+    arity: Arity::One,
+    default: None,
+    description: "Second playlist URI, URL, ID or name",
+    complete: Some(CompletionKind::Playlists),
+};
+
+const DRY_RUN: FlagSpec = FlagSpec {
+    name: "--dry-run",
+    value: None,
+    description: "Only show what would change",
+};
+
+// Curation commands only show what they would change unless given this.
+const APPLY: FlagSpec = FlagSpec {
+    name: "--apply",
+    value: None,
+    description: "Make the changes shown, instead of only showing them",
+};
+
+const APPLY_HINT: &str = "Run again with --apply to make these changes.";
+
+const SMART_NAME: ParamSpec = ParamSpec {
+    name: "name",
+    ty: ArgType::Text,
+    arity: Arity::Optional,
+    default: None,
+    description: "Name of a smart playlist, all of them if left out",
+    complete: None,
+};
+
+const QUEUE_URIS: ParamSpec = ParamSpec {
+    name: "uris",
+    ty: ArgType::Uris(Some(UriType::Track)),
+    arity: Arity::Many,
+    default: None,
+    description: "Tracks, episodes, albums, playlists or artists",
+    complete: Some(CompletionKind::Tracks),
+};
+
+const fn count_param(description: &'static str, default: &'static str) -> ParamSpec {
+    ParamSpec {
+        name: "n",
+        ty: ArgType::Number,
+        arity: Arity::Optional,
+        default: Some(default),
+        description,
+        complete: None,
+    }
+}
+
+const fn position_param(name: &'static str, description: &'static str) -> ParamSpec {
+    ParamSpec {
+        name,
+        ty: ArgType::Number,
+        arity: Arity::One,
+        default: None,
+        description,
+        complete: None,
+    }
+}
+
+pub static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "play",
+        aliases: &[],
+        summary: "Resume playback",
+        params: &[],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: play,
+    },
+    CommandSpec {
+        name: "pause",
+        aliases: &[],
+        summary: "Pause playback",
+        params: &[],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: pause,
+    },
+    CommandSpec {
+        name: "next",
+        aliases: &["skip"],
+        summary: "Skip to the next track",
+        params: &[count_param("Number of tracks to skip", "1")],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: next,
+    },
+    CommandSpec {
+        name: "previous",
+        aliases: &["prev"],
+        summary: "Go back to the previous track",
+        params: &[count_param("Number of tracks to go back", "1")],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: previous,
+    },
+    CommandSpec {
+        name: "volume",
+        aliases: &["vol"],
+        summary: "Show or set the volume",
+        params: &[ParamSpec {
+            name: "level",
+            ty: ArgType::Number,
+            arity: Arity::Optional,
+            default: None,
+            description: "Volume from 0 to 100, shows the volume if left out",
+            complete: None,
+        }],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: volume,
+    },
+    CommandSpec {
+        name: "device",
+        aliases: &[],
+        summary: "Switch playback to another device",
+        params: &[ParamSpec {
+            name: "name",
+            ty: ArgType::Text,
+            arity: Arity::One,
+            default: None,
+            description: "Name of the device",
+            complete: Some(CompletionKind::Devices),
+        }],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: device,
+    },
+    CommandSpec {
+        name: "devices",
+        aliases: &[],
+        summary: "List available devices",
+        params: &[],
+        flags: &[HUMAN_READABLE],
+        subcommands: &[],
+        literal_args: false,
+        handler: devices,
+    },
+    CommandSpec {
+        name: "now",
+        aliases: &[],
+        summary: "Show the currently playing track",
+        params: &[],
+        flags: &[HUMAN_READABLE, COLUMNS],
+        subcommands: &[],
+        literal_args: false,
+        handler: now,
+    },
+    CommandSpec {
+        name: "queue",
+        aliases: &["q"],
+        summary: "Show the queue, or add tracks to it",
+        params: &[QUEUE_URIS],
+        flags: &[HUMAN_READABLE, COLUMNS, SHUFFLE, LIMIT, DISCOGRAPHY],
+        subcommands: &[
+            CommandSpec {
+                name: "add",
+                aliases: &[],
+                summary: "Add tracks to the end of the queue",
+                params: &[QUEUE_URIS],
+                flags: ADD_FLAGS,
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_add,
+            },
+            CommandSpec {
+                name: "insert-next",
+                aliases: &[],
+                summary: "Add tracks to the front of the queue",
+                params: &[QUEUE_URIS],
+                flags: ADD_FLAGS,
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_insert_next,
+            },
+            CommandSpec {
+                name: "remove",
+                aliases: &["rm"],
+                summary: "Remove a queued item",
+                params: &[position_param("n", "Position of the item, starting at 1")],
+                flags: &[],
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_remove,
+            },
+            CommandSpec {
+                name: "move",
+                aliases: &["mv"],
+                summary: "Move a queued item to another position",
+                params: &[
+                    position_param("from", "Current position of the item"),
+                    position_param("to", "New position of the item"),
+                ],
+                flags: &[],
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_move,
+            },
+            CommandSpec {
+                name: "clear",
+                aliases: &[],
+                summary: "Remove all queued items",
+                params: &[],
+                flags: &[],
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_clear,
+            },
+            CommandSpec {
+                name: "shuffle",
+                aliases: &[],
+                summary: "Shuffle the queued items",
+                params: &[],
+                flags: &[],
+                subcommands: &[],
+                literal_args: false,
+                handler: queue_shuffle,
+            },
+        ],
+        literal_args: false,
+        handler: queue,
+    },
+    CommandSpec {
+        name: "recent",
+        aliases: &[],
+        summary: "List recently played tracks",
+        params: &[count_param("Number of tracks to list", "20")],
+        flags: &[HUMAN_READABLE, COLUMNS, ALL, PAGE_LIMIT, BEFORE, AFTER],
+        subcommands: &[],
+        literal_args: false,
+        handler: recent,
+    },
+    CommandSpec {
+        name: "search",
+        aliases: &[],
+        summary: "Search for tracks",
+        params: &[ParamSpec {
+            name: "query",
+            ty: ArgType::Text,
+            arity: Arity::One,
+            default: None,
+            description: "Search query, e.g. \"artist:radiohead creep\"",
+            complete: None,
+        }],
+        flags: &[HUMAN_READABLE, COLUMNS, ALL, PAGE_LIMIT, OFFSET],
+        subcommands: &[],
+        literal_args: false,
+        handler: search,
+    },
+    CommandSpec {
+        name: "playlists",
+        aliases: &[],
+        summary: "List your playlists",
+        params: &[],
+        flags: &[HUMAN_READABLE, ALL, PAGE_LIMIT, OFFSET],
+        subcommands: &[],
+        literal_args: false,
+        handler: playlists,
+    },
+    CommandSpec {
+        name: "playlist",
+        aliases: &[],
+        summary: "List the tracks of a playlist",
+        params: &[PLAYLIST],
+        flags: &[HUMAN_READABLE, COLUMNS, ALL, PAGE_LIMIT, OFFSET],
+        subcommands: &[
+            CommandSpec {
+                name: "export",
+                aliases: &[],
+                summary: "Write the tracks of a playlist with their metadata",
+                params: &[PLAYLIST],
+                flags: &[
+                    PLAYLIST_FORMAT,
+                    FlagSpec {
+                        name: "--output",
+                        value: Some("FILE"),
+                        description:
+                            "Write to FILE instead of printing, in the format of its extension",
+                    },
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_export,
+            },
+            CommandSpec {
+                name: "import",
+                aliases: &[],
+                summary: "Create a playlist from a file, or add its tracks to one",
+                params: &[ParamSpec {
+                    name: "file",
+                    ty: ArgType::Text,
+                    arity: Arity::One,
+                    default: None,
+                    description: "CSV, JSON, M3U or XSPF file",
+                    complete: None,
+                }],
+                flags: &[
+                    PLAYLIST_FORMAT,
+                    FlagSpec {
+                        name: "--into",
+                        value: Some("PLAYLIST"),
+                        description: "Add to this playlist instead of creating one",
+                    },
+                    FlagSpec {
+                        name: "--name",
+                        value: Some("NAME"),
+                        description: "Name of the new playlist, by default the one in the file",
+                    },
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_import,
+            },
+            CommandSpec {
+                name: "smart",
+                aliases: &[],
+                summary: "Define a playlist by a rule, or list the rules",
+                params: &[SMART_NAME],
+                flags: &[
+                    FlagSpec {
+                        name: "--from",
+                        value: Some("SOURCE"),
+                        description: "Where tracks come from: liked, recent, top or a playlist",
+                    },
+                    FlagSpec {
+                        name: "--where",
+                        value: Some("CONDITIONS"),
+                        description: "e.g. \"age<30,energy>0.6,artist~bowie\"",
+                    },
+                    FlagSpec {
+                        name: "--sort",
+                        value: Some("FIELD"),
+                        description: "Order by a field, e.g. tempo or added:desc",
+                    },
+                    FlagSpec {
+                        name: "--limit",
+                        value: Some("N"),
+                        description: "At most N tracks",
+                    },
+                    FlagSpec {
+                        name: "--delete",
+                        value: None,
+                        description: "Remove the rule, keeping the playlist",
+                    },
+                    HUMAN_READABLE,
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_smart,
+            },
+            CommandSpec {
+                name: "sync",
+                aliases: &[],
+                summary: "Regenerate smart playlists, pushing only what changed",
+                params: &[SMART_NAME],
+                flags: &[DRY_RUN],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_sync,
+            },
+            CommandSpec {
+                name: "dedupe",
+                aliases: &[],
+                summary: "Remove repeated tracks from a playlist, keeping the first",
+                params: &[PLAYLIST],
+                flags: &[
+                    FlagSpec {
+                        name: "--by",
+                        value: Some("uri|track"),
+                        description:
+                            "What counts as a repeat: the same URI, or also the same ISRC or title and artists",
+                    },
+                    APPLY,
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_dedupe,
+            },
+            CommandSpec {
+                name: "sort",
+                aliases: &[],
+                summary: "Reorder a playlist",
+                params: &[PLAYLIST],
+                flags: &[
+                    FlagSpec {
+                        name: "--by",
+                        value: Some("FIELD"),
+                        description: "e.g. added, title, artist, tempo or popularity:desc",
+                    },
+                    APPLY,
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_sort,
+            },
+            CommandSpec {
+                name: "diff",
+                aliases: &[],
+                summary: "List the tracks of a playlist that another doesn't have",
+                params: &[PLAYLIST, OTHER_PLAYLIST],
+                flags: &[FlagSpec {
+                    name: "--common",
+                    value: None,
+                    description: "List the tracks both have instead",
+                }],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_diff,
+            },
+            CommandSpec {
+                name: "merge",
+                aliases: &[],
+                summary: "Add the tracks of two playlists to another, once each",
+                params: &[PLAYLIST, OTHER_PLAYLIST],
+                flags: &[
+                    FlagSpec {
+                        name: "--into",
+                        value: Some("PLAYLIST"),
+                        description: "Playlist to add to, created if there's none of the name",
+                    },
+                    APPLY,
+                ],
+                subcommands: &[],
+                literal_args: false,
+                handler: playlist_merge,
+            },
+        ],
+        literal_args: false,
+        handler: playlist,
+    },
+    CommandSpec {
+        name: "radio",
+        aliases: &[],
+        summary: "List tracks like the given ones, to queue, filter or save",
+        params: &[ParamSpec {
+            name: "uris",
+            ty: ArgType::Uris(Some(UriType::Artist)),
+            arity: Arity::Many,
+            default: None,
+            description: "Tracks or artists to start from (bare IDs are artists), the current track if left out",
+            complete: Some(CompletionKind::Tracks),
+        }],
+        flags: RADIO_FLAGS,
+        subcommands: &[],
+        literal_args: false,
+        handler: radio,
+    },
+    CommandSpec {
+        name: "like",
+        aliases: &["save"],
+        summary: "Save tracks, albums or shows to your library",
+        params: &[LIBRARY_URIS],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: like,
+    },
+    CommandSpec {
+        name: "unlike",
+        aliases: &["unsave"],
+        summary: "Remove tracks, albums or shows from your library",
+        params: &[LIBRARY_URIS],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: unlike,
+    },
+    CommandSpec {
+        name: "liked",
+        aliases: &[],
+        summary: "List your liked songs, latest first",
+        params: &[],
+        flags: &[HUMAN_READABLE, COLUMNS, ALL, PAGE_LIMIT, OFFSET],
+        subcommands: &[],
+        literal_args: false,
+        handler: liked,
+    },
+    CommandSpec {
+        name: "library",
+        aliases: &[],
+        summary: "List the albums, artists or shows in your library",
+        params: &[ParamSpec {
+            name: "kind",
+            ty: ArgType::Text,
+            arity: Arity::One,
+            default: None,
+            description: "One of albums, artists or shows",
+            complete: None,
+        }],
+        flags: &[HUMAN_READABLE, ALL, PAGE_LIMIT, OFFSET],
+        subcommands: &[],
+        literal_args: false,
+        handler: library,
+    },
+    CommandSpec {
+        name: "follow",
+        aliases: &[],
+        summary: "Follow artists or playlists",
+        params: &[FOLLOW_URIS],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: follow,
+    },
+    CommandSpec {
+        name: "unfollow",
+        aliases: &[],
+        summary: "Unfollow artists or playlists",
+        params: &[FOLLOW_URIS],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: unfollow,
+    },
+    CommandSpec {
+        name: "contains",
+        aliases: &[],
+        summary: "Check whether items are saved or followed",
+        params: &[ParamSpec {
+            name: "uris",
+            ty: ArgType::Uris(Some(UriType::Track)),
+            arity: Arity::Many,
+            default: None,
+            description:
+                "Tracks, albums, shows, artists or playlists, the current track if left out",
+            complete: Some(CompletionKind::Tracks),
+        }],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: contains,
+    },
+    CommandSpec {
+        name: "stats",
+        aliases: &[],
+        summary: "Show listening statistics",
+        params: &[ParamSpec {
+            name: "section",
+            ty: ArgType::Text,
+            arity: Arity::Optional,
+            default: None,
+            description: "One of tracks, artists, albums, days or skips",
+            complete: None,
+        }],
+        flags: &[
+            FlagSpec {
+                name: "-j",
+                value: None,
+                description: "Output as JSON",
+            },
+            FlagSpec {
+                name: "--from",
+                value: Some("DATE"),
+                description: "Only count plays from this day on (YYYY-MM-DD)",
+            },
+            FlagSpec {
+                name: "--to",
+                value: Some("DATE"),
+                description: "Only count plays up to and including this day (YYYY-MM-DD)",
+            },
+            FlagSpec {
+                name: "--limit",
+                value: Some("N"),
+                description: "Number of entries per list (default 10)",
+            },
+        ],
+        subcommands: &[],
+        literal_args: false,
+        handler: stats,
+    },
+    CommandSpec {
+        name: "filter",
+        aliases: &[],
+        summary: "Keep tracks whose name, artists or album contain a pattern",
+        params: &[
+            ParamSpec {
+                name: "pattern",
+                ty: ArgType::Text,
+                arity: Arity::One,
+                default: None,
+                description: "Text to look for, case insensitive",
+                complete: None,
+            },
+            ParamSpec {
+                name: "tracks",
+                ty: ArgType::Tracks,
+                arity: Arity::Many,
+                default: None,
+                description: "Tracks to filter",
+                complete: None,
+            },
+        ],
+        flags: &[HUMAN_READABLE, COLUMNS],
+        subcommands: &[],
+        literal_args: false,
+        handler: filter,
+    },
+    CommandSpec {
+        name: "api",
+        aliases: &[],
+        summary: "Send a raw request to the Web API and print the response",
+        params: &[
+            ParamSpec {
+                name: "method",
+                ty: ArgType::Text,
+                arity: Arity::One,
+                default: None,
+                description: "One of GET, POST, PUT or DELETE",
+                complete: None,
+            },
+            ParamSpec {
+                name: "path",
+                ty: ArgType::Text,
+                arity: Arity::One,
+                default: None,
+                description: "Endpoint and query, e.g. /me/top/tracks?limit=5",
+                complete: None,
+            },
+            ParamSpec {
+                name: "body",
+                ty: ArgType::Text,
+                arity: Arity::Optional,
+                default: None,
+                description: "JSON request body",
+                complete: None,
+            },
+        ],
+        flags: &[],
+        subcommands: &[],
+        literal_args: true,
+        handler: api,
+    },
+    CommandSpec {
+        name: "run",
+        aliases: &[],
+        summary: "Run the commands in a script file, one per line",
+        params: &[ParamSpec {
+            name: "path",
+            ty: ArgType::Text,
+            arity: Arity::One,
+            default: None,
+            description: "Script to run, # starts a comment",
+            complete: None,
+        }],
+        flags: &[FlagSpec {
+            name: "-e",
+            value: None,
+            description: "Stop at the first failing command, like set -e in the script",
+        }],
+        subcommands: &[],
+        literal_args: true,
+        handler: run,
+    },
+    CommandSpec {
+        name: "sleep",
+        aliases: &[],
+        summary: "Wait for a number of seconds",
+        params: &[ParamSpec {
+            name: "seconds",
+            ty: ArgType::Number,
+            arity: Arity::One,
+            default: None,
+            description: "Seconds to wait",
+            complete: None,
+        }],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: sleep,
+    },
+    CommandSpec {
+        name: "wait-until-track-ends",
+        aliases: &[],
+        summary: "Wait until the current track has finished playing",
+        params: &[],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: wait_until_track_ends,
+    },
+    CommandSpec {
+        name: "tui",
+        aliases: &[],
+        summary: "Open the full-screen interface",
+        params: &[],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: tui,
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &[],
+        summary: "List commands, or show help for a command",
+        params: &[
+            ParamSpec {
+                name: "command",
+                ty: ArgType::Text,
+                arity: Arity::Optional,
+                default: None,
+                description: "Command to show help for",
+                complete: None,
+            },
+            ParamSpec {
+                name: "subcommand",
+                ty: ArgType::Text,
+                arity: Arity::Optional,
+                default: None,
+                description: "Subcommand to show help for",
+                complete: None,
+            },
+        ],
+        flags: &[],
+        subcommands: &[],
+        literal_args: true,
+        handler: help,
+    },
+    CommandSpec {
+        name: "completions",
+        aliases: &[],
+        summary: "Print a shell completion script",
+        params: &[ParamSpec {
+            name: "shell",
+            ty: ArgType::Text,
+            arity: Arity::One,
+            default: None,
+            description: "One of bash, zsh or fish",
+            complete: None,
+        }],
+        flags: &[],
+        subcommands: &[CommandSpec {
+            name: "refresh",
+            aliases: &[],
+            summary: "Update the devices, playlists and tracks offered by completion, if authorized",
+            params: &[ParamSpec {
+                name: "kind",
+                ty: ArgType::Text,
+                arity: Arity::Optional,
+                default: None,
+                description: "One of devices, playlists or tracks, all if left out",
+                complete: None,
+            }],
+            flags: &[],
+            subcommands: &[],
+            literal_args: true,
+            handler: completions_refresh,
+        }],
+        literal_args: true,
+        handler: completions,
+    },
+    CommandSpec {
+        name: "manpage",
+        aliases: &[],
+        summary: "Print the manual page in roff format",
+        params: &[],
+        flags: &[],
+        subcommands: &[],
+        literal_args: false,
+        handler: manpage,
+    },
+];
+
+fn text_or_error(output: Result<String, ApiError>) -> Value {
+    match output {
+        Ok(text) => Value::Str(text),
+        Err(err) => Value::Error(err.to_string()),
+    }
+}
+
+// Checks that an optional number lies within min..=max.
+fn in_range(arg: Option<(i64, Span)>, min: i64, max: i64) -> Result<Option<i64>, EvalError> {
+    match arg {
+        Some((n, span)) if !(min..=max).contains(&n) => Err(EvalError::new(
+            format!("Expected a number between {} and {}, got {}", min, max, n),
+            span,
+        )),
+        arg => Ok(arg.map(|(n, _)| n)),
+    }
+}
+
+fn date_flag(inv: &Invocation, flag: &str, end_of_day: bool) -> Result<Option<i64>, EvalError> {
+    match inv.flag_value(flag) {
+        Some((date, span)) => match history_manager::parse_date_bound(date, end_of_day) {
+            Some(ms) => Ok(Some(ms)),
+            None => Err(EvalError::new(
+                format!("Invalid date '{}' (expected YYYY-MM-DD)", date),
+                span,
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+// A count given with a flag, e.g. --limit 10.
+fn count_flag(inv: &Invocation, flag: &str) -> Result<Option<(usize, Span)>, EvalError> {
+    match inv.flag_value(flag) {
+        Some((n, span)) => match n.parse::<usize>() {
+            Ok(n) => Ok(Some((n, span))),
+            Err(_) => Err(EvalError::new(
+                format!("Expected a number for {}, got '{}'", flag, n),
+                span,
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn time_flag(inv: &Invocation, flag: &str) -> Result<Option<i64>, EvalError> {
+    match inv.flag_value(flag) {
+        Some((time, span)) => match history_manager::parse_time(time) {
+            Some(ms) => Ok(Some(ms)),
+            None => Err(EvalError::new(
+                format!(
+                    "Invalid time '{}' (expected YYYY-MM-DD, RFC 3339 or unix ms)",
+                    time
+                ),
+                span,
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+// The part of a paged list selected by --all, --limit, --offset, --before
+// and --after, the first default_limit items if none are given.
+fn page_range(inv: &Invocation, default_limit: usize) -> Result<PageRange, EvalError> {
+    let limit = match (inv.has_flag("--all"), count_flag(inv, "--limit")?) {
+        (true, Some((_, span))) => {
+            return Err(EvalError::new(
+                "--all and --limit can't be combined".to_string(),
+                span,
+            ))
+        }
+        (true, None) => None,
+        (false, Some((n, _))) => Some(n),
+        (false, None) => Some(default_limit),
+    };
+    let before = time_flag(inv, "--before")?;
+    let after = time_flag(inv, "--after")?;
+    if let (Some(_), Some((_, span))) = (before, inv.flag_value("--after")) {
+        return Err(EvalError::new(
+            "--before and --after can't be combined".to_string(),
+            span,
+        ));
+    }
+
+    Ok(PageRange {
+        limit,
+        offset: count_flag(inv, "--offset")?.map_or(0, |(n, _)| n),
+        before,
+        after,
+    })
+}
+
+// A range given with a flag, e.g. --tempo 100-130.
+fn range_flag<T: std::str::FromStr + PartialOrd>(
+    inv: &Invocation,
+    flag: &str,
+) -> Result<Option<(T, T)>, EvalError> {
+    let Some((range, span)) = inv.flag_value(flag) else {
+        return Ok(None);
+    };
+    match range
+        .split_once('-')
+        .and_then(|(min, max)| Some((min.trim().parse::<T>().ok()?, max.trim().parse::<T>().ok()?)))
+    {
+        Some((min, max)) if min <= max => Ok(Some((min, max))),
+        _ => Err(EvalError::new(
+            format!(
+                "Expected a range like MIN-MAX for {}, got '{}'",
+                flag, range
+            ),
+            span,
+        )),
+    }
+}
+
+fn radio_options(inv: &Invocation) -> Result<RadioOptions, EvalError> {
+    let limit = match count_flag(inv, "--limit")? {
+        Some((n, span)) if !(1..=100).contains(&n) => {
+            return Err(EvalError::new(
+                format!("Expected a number between 1 and 100, got {}", n),
+                span,
+            ))
+        }
+        Some((n, _)) => n,
+        None => 20,
+    };
+    let target_energy = match inv.flag_value("--energy") {
+        Some((x, span)) => match x.parse::<f64>() {
+            Ok(x) if (0.0..=1.0).contains(&x) => Some(x),
+            _ => {
+                return Err(EvalError::new(
+                    format!("Expected an energy between 0.0 and 1.0, got '{}'", x),
+                    span,
+                ))
+            }
+        },
+        None => None,
+    };
+    let popularity = range_flag::<u32>(inv, "--popularity")?;
+    if let (Some((_, max)), Some((_, span))) = (popularity, inv.flag_value("--popularity")) {
+        if max > 100 {
+            return Err(EvalError::new(
+                format!("Expected a popularity between 0 and 100, got {}", max),
+                span,
+            ));
+        }
+    }
+
+    Ok(RadioOptions {
+        limit,
+        target_energy,
+        tempo: range_flag(inv, "--tempo")?,
+        popularity,
+        keep_recent: inv.has_flag("--keep-recent"),
+    })
+}
+
+// Caches completion values as a side effect of a command. Failing to write
+// the cache shouldn't fail the command.
+fn remember(kind: CompletionKind, entries: &[(String, String)]) {
+    if let Err(e) = completion_cache::store(kind, entries) {
+        debug!("Failed to cache {} for completion: {}", kind.name(), e);
+    }
+}
+
+fn device_entries(ctx: &EvalContext<'_>) -> Vec<(String, String)> {
+    ctx.playback_manager
+        .device_names()
+        .into_iter()
+        .map(|name| (name, String::new()))
+        .collect()
+}
+
+// Recently seen tracks complete to their URIs, described by title.
+fn track_entries(tracks: &[TrackRecord]) -> Vec<(String, String)> {
+    let mut seen = HashSet::new();
+    tracks
+        .iter()
+        .filter(|t| seen.insert(t.uri.clone()))
+        .map(|t| {
+            (
+                t.uri.clone(),
+                format!("{} - {}", t.name, t.artists.join(", ")),
+            )
+        })
+        .collect()
+}
+
+// Table columns when a command should print a table rather than URIs: those
+// given with --columns, or the defaults with -h.
+fn table_columns(inv: &Invocation, defaults: &[Column]) -> Result<Option<Vec<Column>>, EvalError> {
+    match inv.flag_value("--columns") {
+        Some((list, span)) => Column::parse_list(list)
+            .map(Some)
+            .map_err(|e| EvalError::new(e, span)),
+        None if inv.has_flag("-h") => Ok(Some(defaults.to_vec())),
+        None => Ok(None),
+    }
+}
+
+fn add_options(inv: &Invocation) -> Result<AddOptions, EvalError> {
+    Ok(AddOptions {
+        shuffle: inv.has_flag("--shuffle"),
+        limit: count_flag(inv, "--limit")?.map(|(n, _)| n),
+        discography: inv.has_flag("--discography"),
+    })
+}
+
+fn play<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(text_or_error(ctx.playback_manager.play().await)) })
+}
+
+fn pause<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(text_or_error(ctx.playback_manager.pause().await)) })
+}
+
+fn next<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let n = in_range(inv.number(0), 1, u8::MAX as i64)?.unwrap_or(1);
+        Ok(text_or_error(ctx.playback_manager.next(n as u8).await))
+    })
+}
+
+fn previous<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let n = in_range(inv.number(0), 1, u8::MAX as i64)?.unwrap_or(1);
+        Ok(text_or_error(ctx.playback_manager.previous(n as u8).await))
+    })
+}
+
+fn volume<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        match in_range(inv.number(0), 0, 100)? {
+            Some(level) => Ok(text_or_error(
+                ctx.playback_manager.set_volume(level as u8).await,
+            )),
+            None => Ok(text_or_error(ctx.playback_manager.get_volume().await)),
+        }
+    })
+}
+
+fn device<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let name = inv.text(0).unwrap_or_default();
+        match ctx.playback_manager.device(name).await {
+            Ok(Some(text)) => Ok(Value::Str(text)),
+            Ok(None) => Err(EvalError::new(
+                format!("No device named '{}'", name),
+                inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+            )),
+            Err(err) => Ok(Value::Error(err.to_string())),
+        }
+    })
+}
+
+fn devices<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let output = ctx.playback_manager.devices(inv.has_flag("-h")).await;
+        remember(CompletionKind::Devices, &device_entries(ctx));
+        Ok(text_or_error(output))
+    })
+}
+
+fn now<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        // a single track needs no index
+        let columns = table_columns(&inv, &Column::DEFAULT[1..])?;
+        Ok(match (ctx.playback_manager.now().await, columns) {
+            (Ok(tracks), Some(_)) if tracks.is_empty() => {
+                Value::Str("No track currently playing.".to_string())
+            }
+            (Ok(tracks), Some(columns)) => {
+                Value::Str(formatter::print_track_table(&tracks, &columns, 0))
+            }
+            (Ok(tracks), None) => Value::Tracks(tracks),
+            (Err(err), _) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn queue<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        let opts = add_options(&inv)?;
+        Ok(match (inv.uris(0), columns) {
+            (Some(uris), _) => text_or_error(ctx.queue_manager.add(uris, opts).await),
+            (None, Some(columns)) => text_or_error(ctx.queue_manager.queue(&columns).await),
+            (None, None) => match ctx.queue_manager.queue_uris().await {
+                Ok(uris) => Value::Uris(uris),
+                Err(err) => Value::Error(err.to_string()),
+            },
+        })
+    })
+}
+
+fn queue_add<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let uris = inv.uris(0).unwrap_or_default();
+        let opts = add_options(&inv)?;
+        Ok(text_or_error(ctx.queue_manager.add(uris, opts).await))
+    })
+}
+
+fn queue_insert_next<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let uris = inv.uris(0).unwrap_or_default();
+        let opts = add_options(&inv)?;
+        Ok(text_or_error(
+            ctx.queue_manager.insert_next(uris, opts).await,
+        ))
+    })
+}
+
+fn queue_remove<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let n = in_range(inv.number(0), 1, i64::MAX)?.unwrap_or(1);
+        match ctx.queue_manager.remove(n as usize).await {
+            Err(ApiError::ResponseError400) => Err(EvalError::new(
+                format!("No item {} in queue", n),
+                inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+            )),
+            res => Ok(text_or_error(res)),
+        }
+    })
+}
+
+fn queue_move<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let from = in_range(inv.number(0), 1, i64::MAX)?.unwrap_or(1);
+        let to = in_range(inv.number(1), 1, i64::MAX)?.unwrap_or(1);
+        match ctx
+            .queue_manager
+            .move_item(from as usize, to as usize)
+            .await
+        {
+            Err(ApiError::ResponseError400) => Err(EvalError::new(
+                format!("Cannot move item {} to position {}", from, to),
+                inv.span,
+            )),
+            res => Ok(text_or_error(res)),
+        }
+    })
+}
+
+fn queue_clear<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(text_or_error(ctx.queue_manager.clear().await)) })
+}
+
+fn queue_shuffle<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(text_or_error(ctx.queue_manager.shuffle().await)) })
+}
+
+fn recent<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let n = in_range(inv.number(0), 1, i64::MAX)?.unwrap_or(20);
+        let range = page_range(&inv, n as usize)?;
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        let recent = ctx.playback_manager.recent(&range).await;
+        if let Ok(tracks) = &recent {
+            remember(CompletionKind::Tracks, &track_entries(tracks));
+        }
+        Ok(match (recent, columns) {
+            (Ok(tracks), Some(columns)) => Value::Str(format!(
+                "Recently Played:\n{}",
+                formatter::print_track_table(&tracks, &columns, 1)
+            )),
+            (Ok(tracks), None) => Value::Tracks(tracks),
+            (Err(err), _) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn search<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let query = inv.text(0).unwrap_or_default();
+        let range = page_range(&inv, 20)?;
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        Ok(
+            match (ctx.search_manager.search(query, &range).await, columns) {
+                (Ok(tracks), Some(columns)) => {
+                    Value::Str(formatter::print_track_table(&tracks, &columns, 0))
+                }
+                (Ok(tracks), None) => Value::Tracks(tracks),
+                (Err(err), _) => Value::Error(err.to_string()),
+            },
+        )
+    })
+}
+
+fn playlists<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let range = page_range(&inv, 50)?;
+        let playlists = match ctx.playlist_manager.playlists(&range).await {
+            Ok(playlists) => playlists,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        Ok(match inv.has_flag("-h") {
+            true => Value::Str(formatter::print_playlist_table(
+                &playlists,
+                range.offset + 1,
+                0,
+            )),
+            false => Value::Uris(playlists.into_iter().map(|p| p.uri).collect()),
+        })
+    })
+}
+
+// Finds the ID of a playlist given by URI, URL, ID or name. Not finding one
+// is an error of the argument, failing to look an error value.
+async fn find_playlist(
+    ctx: &EvalContext<'_>,
+    input: &str,
+    span: Span,
+) -> Result<Result<String, Value>, EvalError> {
+    match ctx.playlist_manager.find(input).await {
+        Ok(Some(id)) => Ok(Ok(id)),
+        Ok(None) => Err(EvalError::new(
+            format!("No playlist named '{}'", input),
+            span,
+        )),
+        Err(err) => Ok(Err(Value::Error(err.to_string()))),
+    }
+}
+
+fn playlist<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let name = inv.text(0).unwrap_or_default();
+        let range = page_range(&inv, 100)?;
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        let span = inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span);
+        let id = match find_playlist(ctx, name, span).await? {
+            Ok(id) => id,
+            Err(value) => return Ok(value),
+        };
+        Ok(
+            match (ctx.playlist_manager.tracks(&id, &range).await, columns) {
+                (Ok(tracks), Some(columns)) => {
+                    Value::Str(formatter::print_track_table(&tracks, &columns, 0))
+                }
+                (Ok(tracks), None) => Value::Tracks(tracks),
+                (Err(err), _) => Value::Error(err.to_string()),
+            },
+        )
+    })
+}
+
+fn radio<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let opts = radio_options(&inv)?;
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        let mut seeds = RadioSeeds {
+            genres: inv
+                .flag_value("--genre")
+                .map(|(list, _)| {
+                    list.split(',')
+                        .map(|g| g.trim().to_lowercase())
+                        .filter(|g| !g.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        // genres alone are enough to start from
+        let uris = match inv.uris(0).filter(|uris| !uris.is_empty()) {
+            Some(uris) => uris,
+            None if !seeds.genres.is_empty() => Vec::new(),
+            None => match uris_or_current(ctx, &inv).await {
+                Ok((uris, _)) => uris,
+                Err(value) => return Ok(value),
+            },
+        };
+        if !uris.is_empty() {
+            check_types(
+                &inv,
+                &uris,
+                &[UriType::Track, UriType::Artist],
+                "tracks or artists",
+            )?;
+        }
+        for uri in &uris {
+            let Some(id) = uri_helper::get_id_from_uri(uri) else {
+                continue;
+            };
+            match uri_helper::get_uri_type(uri) {
+                UriType::Artist => seeds.artists.push(id),
+                _ => seeds.tracks.push(id),
+            }
+        }
+        if seeds.is_empty() {
+            return Err(EvalError::new(
+                "Expected tracks, artists or genres to start from".to_string(),
+                inv.span,
+            ));
+        }
+        seeds.truncate();
+
+        let tracks = match ctx.radio_manager.radio(&seeds, &opts).await {
+            Ok(tracks) => tracks,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        remember(CompletionKind::Tracks, &track_entries(&tracks));
+
+        if let Some((name, _)) = inv.flag_value("--save") {
+            let uris: Vec<String> = tracks.iter().map(|t| t.uri.clone()).collect();
+            return Ok(match ctx.playlist_manager.create(name, &uris).await {
+                Ok(playlist) => Value::Str(format!(
+                    "Saved {} to {}.",
+                    library_manager::describe(&uris),
+                    playlist.name
+                )),
+                Err(err) => Value::Error(err.to_string()),
+            });
+        }
+        Ok(match columns {
+            Some(columns) => Value::Str(formatter::print_track_table(&tracks, &columns, 1)),
+            None => Value::Tracks(tracks),
+        })
+    })
+}
+
+// The format given with --format, or else the one of the file.
+fn playlist_format(
+    inv: &Invocation,
+    path: Option<&str>,
+) -> Result<Option<PlaylistFormat>, EvalError> {
+    match inv.flag_value("--format") {
+        Some((name, span)) => match PlaylistFormat::parse(name) {
+            Some(format) => Ok(Some(format)),
+            None => Err(EvalError::new(
+                format!(
+                    "Unknown format '{}' (expected csv, json, m3u or xspf)",
+                    name
+                ),
+                span,
+            )),
+        },
+        None => Ok(path.and_then(PlaylistFormat::from_path)),
+    }
+}
+
+fn playlist_export<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let name = inv.text(0).unwrap_or_default();
+        let span = inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span);
+        let output = inv.flag_value("--output");
+        let format =
+            playlist_format(&inv, output.map(|(path, _)| path))?.unwrap_or(PlaylistFormat::Csv);
+        let id = match find_playlist(ctx, name, span).await? {
+            Ok(id) => id,
+            Err(value) => return Ok(value),
+        };
+
+        let (playlist, items) = match (
+            ctx.playlist_manager.playlist(&id).await,
+            ctx.playlist_manager.items(&id, &PageRange::all()).await,
+        ) {
+            (Ok(playlist), Ok(items)) => (playlist, items),
+            (Err(err), _) | (_, Err(err)) => return Ok(Value::Error(err.to_string())),
+        };
+        // unavailable items have nothing left to export
+        let entries: Vec<playlist_file::PlaylistEntry> = items
+            .iter()
+            .filter(|item| item.track.is_some())
+            .map(playlist_file::PlaylistEntry::from)
+            .collect();
+        let text = playlist_file::write(format, &playlist.name, &entries);
+
+        match output {
+            Some((path, span)) => {
+                std::fs::write(path, text).map_err(|e| {
+                    EvalError::new(format!("Failed to write '{}': {}", path, e), span)
+                })?;
+                Ok(Value::Str(format!(
+                    "Exported {} {} to {}.",
+                    entries.len(),
+                    if entries.len() == 1 {
+                        "track"
+                    } else {
+                        "tracks"
+                    },
+                    path
+                )))
+            }
+            None => Ok(Value::Str(text.trim_end().to_string())),
+        }
+    })
+}
+
+fn playlist_import<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let path = inv.text(0).unwrap_or_default();
+        let span = inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span);
+        let Some(format) = playlist_format(&inv, Some(path))? else {
+            return Err(EvalError::new(
+                format!(
+                    "Unknown format of '{}' (set one with --format csv|json|m3u|xspf)",
+                    path
+                ),
+                span,
+            ));
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| EvalError::new(format!("Failed to read '{}': {}", path, e), span))?;
+        let (file_name, entries) = playlist_file::read(format, &text)
+            .map_err(|e| EvalError::new(format!("Failed to read '{}': {}", path, e), span))?;
+
+        let (uris, unmatched) = match playlist_file::resolve(&ctx.search_manager, &entries).await {
+            Ok(found) => found,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+
+        let res = match inv.flag_value("--into") {
+            Some((into, span)) => {
+                let id = match find_playlist(ctx, into, span).await? {
+                    Ok(id) => id,
+                    Err(value) => return Ok(value),
+                };
+                match ctx.playlist_manager.add(&id, &uris).await {
+                    Ok(()) => ctx.playlist_manager.playlist(&id).await,
+                    Err(err) => Err(err),
+                }
+            }
+            None => {
+                let name = inv
+                    .flag_value("--name")
+                    .map(|(name, _)| name.to_string())
+                    .or(file_name)
+                    .unwrap_or_else(|| {
+                        std::path::Path::new(path)
+                            .file_stem()
+                            .and_then(|stem| stem.to_str())
+                            .unwrap_or(path)
+                            .to_string()
+                    });
+                ctx.playlist_manager.create(&name, &uris).await
+            }
+        };
+        let playlist = match res {
+            Ok(playlist) => playlist,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+
+        let mut report = format!(
+            "Added {} to {}.",
+            library_manager::describe(&uris),
+            playlist.name
+        );
+        if !unmatched.is_empty() {
+            report.push_str(&format!(
+                "\nCouldn't match {} of {}:",
+                unmatched.len(),
+                entries.len()
+            ));
+            for entry in unmatched {
+                report.push_str(&format!(
+                    "\n  {} {}: {}",
+                    format.position_name(),
+                    entry.pos,
+                    entry.label()
+                ));
+            }
+        }
+        Ok(Value::Str(report))
+    })
+}
+
+fn playlist_smart<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let smart = &ctx.smart_playlist_manager;
+        let Some(name) = inv.text(0) else {
+            return Ok(match smart.list().await {
+                Ok(all) if inv.has_flag("-h") => {
+                    let rows: Vec<Vec<String>> = all
+                        .into_iter()
+                        .map(|s| {
+                            vec![
+                                s.name,
+                                s.rule.describe(),
+                                s.playlist_id.map_or("not synced yet".to_string(), |id| {
+                                    format!("spotify:playlist:{}", id)
+                                }),
+                            ]
+                        })
+                        .collect();
+                    Value::Str(formatter::print_table(
+                        &["Name", "Rule", "Playlist"],
+                        &rows,
+                        0,
+                    ))
+                }
+                Ok(all) => Value::Str(
+                    all.iter()
+                        .map(|s| format!("{}\t{}", s.name, s.rule.describe()))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ),
+                Err(err) => Value::Error(err.to_string()),
+            });
+        };
+        let span = inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span);
+
+        if inv.has_flag("--delete") {
+            return match smart.remove(name).await {
+                Ok(true) => Ok(Value::Str(format!("Removed smart playlist {}.", name))),
+                Ok(false) => Err(EvalError::new(
+                    format!("No smart playlist named '{}'", name),
+                    span,
+                )),
+                Err(err) => Ok(Value::Error(err.to_string())),
+            };
+        }
+
+        let existing = match smart.get(name).await {
+            Ok(existing) => existing,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        let Some((from, from_span)) = inv.flag_value("--from") else {
+            return match existing {
+                Some(s) => Ok(Value::Str(format!("{}\t{}", s.name, s.rule.describe()))),
+                None => Err(EvalError::new(
+                    format!(
+                        "No smart playlist named '{}' (define one with --from)",
+                        name
+                    ),
+                    span,
+                )),
+            };
+        };
+
+        // playlists may be given by name, but are kept by ID
+        let source = match from.parse::<Source>() {
+            Ok(source) => source,
+            Err(_) => match find_playlist(ctx, from, from_span).await? {
+                Ok(id) => Source::Playlist(id),
+                Err(value) => return Ok(value),
+            },
+        };
+        let conditions = match inv.flag_value("--where") {
+            Some((list, span)) => {
+                track_query::parse_conditions(list).map_err(|e| EvalError::new(e, span))?
+            }
+            None => Vec::new(),
+        };
+        let sort = match inv.flag_value("--sort") {
+            Some((key, span)) => Some(key.parse().map_err(|e| EvalError::new(e, span))?),
+            None => None,
+        };
+        let smart_playlist = SmartPlaylist {
+            name: existing
+                .as_ref()
+                .map_or(name.to_string(), |s| s.name.clone()),
+            playlist_id: existing.and_then(|s| s.playlist_id),
+            rule: SmartRule {
+                source,
+                conditions,
+                sort,
+                limit: count_flag(&inv, "--limit")?.map(|(n, _)| n),
+            },
+        };
+        Ok(
+            match ctx.smart_playlist_manager.save(&smart_playlist).await {
+                Ok(()) => Value::Str(format!(
+                    "Saved smart playlist {}: {}.",
+                    smart_playlist.name,
+                    smart_playlist.rule.describe()
+                )),
+                Err(err) => Value::Error(err.to_string()),
+            },
+        )
+    })
+}
+
+fn playlist_sync<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let smart = &ctx.smart_playlist_manager;
+        let dry_run = inv.has_flag("--dry-run");
+        let all = match smart.list().await {
+            Ok(all) => all,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        let selected: Vec<SmartPlaylist> = match inv.text(0) {
+            Some(name) => match all.into_iter().find(|s| s.name.eq_ignore_ascii_case(name)) {
+                Some(found) => vec![found],
+                None => {
+                    return Err(EvalError::new(
+                        format!("No smart playlist named '{}'", name),
+                        inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+                    ))
+                }
+            },
+            None => all,
+        };
+
+        let mut lines = Vec::new();
+        let mut failures = Vec::new();
+        for mut smart_playlist in selected {
+            let report = match smart.sync(&mut smart_playlist, dry_run).await {
+                Ok(report) => report,
+                Err(err) => {
+                    failures.push(format!("Failed to sync {}: {}", smart_playlist.name, err));
+                    continue;
+                }
+            };
+            let name = &smart_playlist.name;
+            lines.push(match (report.created, dry_run, report.is_empty()) {
+                (true, false, _) => {
+                    format!("Created {} with {}.", name, count_tracks(report.added))
+                }
+                (true, true, _) => {
+                    format!("Would create {} with {}.", name, count_tracks(report.added))
+                }
+                (false, _, true) => format!("{} is up to date.", name),
+                (false, false, false) => format!("Synced {}: {}.", name, report.describe(false)),
+                (false, true, false) => {
+                    format!("Would sync {}: {}.", name, report.describe(true))
+                }
+            });
+        }
+        // the others are still synced, and reported after the failures
+        if !failures.is_empty() {
+            failures.extend(lines);
+            return Ok(Value::Error(failures.join("\n")));
+        }
+        Ok(Value::Str(lines.join("\n")))
+    })
+}
+
+fn playlist_dedupe<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let by = match inv.flag_value("--by") {
+            None | Some(("uri", _)) => DuplicateMatch::Uri,
+            Some(("track", _)) => DuplicateMatch::Track,
+            Some((other, span)) => {
+                return Err(EvalError::new(
+                    format!("Unknown kind of repeat '{}' (expected uri or track)", other),
+                    span,
+                ))
+            }
+        };
+        let dry_run = !inv.has_flag("--apply");
+        let (id, name) = match playlist_arg(ctx, &inv, 0).await? {
+            Ok(found) => found,
+            Err(value) => return Ok(value),
+        };
+
+        let duplicates = match ctx.playlist_manager.dedupe(&id, by, dry_run).await {
+            Ok(duplicates) => duplicates,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        if duplicates.is_empty() {
+            return Ok(Value::Str(format!("{} has no repeated tracks.", name)));
+        }
+        let mut lines = vec![format!(
+            "{} {} from {}:",
+            if dry_run { "Would remove" } else { "Removed" },
+            count_tracks(duplicates.len()),
+            name
+        )];
+        lines.extend(duplicates.iter().map(|d| {
+            format!(
+                "  {}: {}, a repeat of {}",
+                d.position + 1,
+                item_label(&d.item),
+                d.first + 1
+            )
+        }));
+        if dry_run {
+            lines.push(APPLY_HINT.to_string());
+        }
+        Ok(Value::Str(lines.join("\n")))
+    })
+}
+
+fn playlist_sort<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let Some((key, span)) = inv.flag_value("--by") else {
+            return Err(EvalError::new(
+                "Sorting takes --by FIELD, e.g. --by tempo".to_string(),
+                inv.span,
+            ));
+        };
+        let key: track_query::SortKey = key.parse().map_err(|e| EvalError::new(e, span))?;
+        let dry_run = !inv.has_flag("--apply");
+        let (id, name) = match playlist_arg(ctx, &inv, 0).await? {
+            Ok(found) => found,
+            Err(value) => return Ok(value),
+        };
+
+        let moves = match ctx.playlist_manager.sort(&id, &key, dry_run).await {
+            Ok(moves) => moves,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        if moves.is_empty() {
+            return Ok(Value::Str(format!(
+                "{} is already sorted by {}.",
+                name, key
+            )));
+        }
+        let mut lines = vec![match dry_run {
+            true => format!(
+                "Sorting {} by {} would move {}:",
+                name,
+                key,
+                count_tracks(moves.len())
+            ),
+            false => format!(
+                "Sorted {} by {}, moving {}:",
+                name,
+                key,
+                count_tracks(moves.len())
+            ),
+        }];
+        lines.extend(
+            moves
+                .iter()
+                .map(|m| format!("  {} -> {}: {}", m.from + 1, m.to + 1, item_label(&m.item))),
+        );
+        if dry_run {
+            lines.push(APPLY_HINT.to_string());
+        }
+        Ok(Value::Str(lines.join("\n")))
+    })
+}
+
+fn playlist_diff<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let (a, _) = match playlist_arg(ctx, &inv, 0).await? {
+            Ok(found) => found,
+            Err(value) => return Ok(value),
+        };
+        let (b, _) = match playlist_arg(ctx, &inv, 1).await? {
+            Ok(found) => found,
+            Err(value) => return Ok(value),
+        };
+        let common = inv.has_flag("--common");
+        Ok(match ctx.playlist_manager.diff(&a, &b, common).await {
+            Ok(uris) => Value::Uris(uris),
+            Err(err) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn playlist_merge<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let Some((into, _)) = inv.flag_value("--into") else {
+            return Err(EvalError::new(
+                "Merging takes --into PLAYLIST".to_string(),
+                inv.span,
+            ));
+        };
+        let dry_run = !inv.has_flag("--apply");
+        let mut sources = Vec::new();
+        for i in 0..2 {
+            match playlist_arg(ctx, &inv, i).await? {
+                Ok((id, _)) => sources.push(id),
+                Err(value) => return Ok(value),
+            }
+        }
+        let target = match ctx.playlist_manager.find(into).await {
+            Ok(target) => target,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+
+        let merged = match ctx
+            .playlist_manager
+            .merge(&sources, target.as_deref(), dry_run)
+            .await
+        {
+            Ok(merged) => merged,
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        };
+        let header = match (&target, dry_run) {
+            (Some(_), _) if merged.is_empty() => {
+                return Ok(Value::Str(format!("{} already has every track.", into)))
+            }
+            (Some(_), true) => format!("Would add {} to {}:", count_tracks(merged.len()), into),
+            (Some(_), false) => format!("Added {} to {}:", count_tracks(merged.len()), into),
+            (None, true) => format!("Would create {} with {}:", into, count_tracks(merged.len())),
+            (None, false) => {
+                let uris: Vec<String> = merged
+                    .iter()
+                    .filter_map(|item| Some(item.track.as_ref()?.uri().to_string()))
+                    .collect();
+                if let Err(err) = ctx.playlist_manager.create(into, &uris).await {
+                    return Ok(Value::Error(err.to_string()));
+                }
+                format!("Created {} with {}:", into, count_tracks(merged.len()))
+            }
+        };
+        let mut lines = vec![header];
+        lines.extend(merged.iter().map(|item| format!("  {}", item_label(item))));
+        if dry_run {
+            lines.push(APPLY_HINT.to_string());
+        }
+        Ok(Value::Str(lines.join("\n")))
+    })
+}
+
+// Finds the playlist given as the i-th argument, returning its ID and the
+// argument to name it by.
+async fn playlist_arg<'i>(
+    ctx: &EvalContext<'_>,
+    inv: &'i Invocation,
+    i: usize,
+) -> Result<Result<(String, &'i str), Value>, EvalError> {
+    let input = inv.text(i).unwrap_or_default();
+    let span = inv.arg(i).map(|(_, span)| *span).unwrap_or(inv.span);
+    Ok(find_playlist(ctx, input, span).await?.map(|id| (id, input)))
+}
+
+fn item_label(item: &PlaylistItem) -> String {
+    playlist_file::PlaylistEntry::from(item).label()
+}
+
+// URIs given to a library command, or the current track if none are, along
+// with its name to report.
+async fn uris_or_current(
+    ctx: &EvalContext<'_>,
+    inv: &Invocation,
+) -> Result<(Vec<String>, Option<String>), Value> {
+    if let Some(uris) = inv.uris(0).filter(|uris| !uris.is_empty()) {
+        return Ok((uris, None));
+    }
+    match ctx.playback_manager.now().await {
+        Ok(tracks) => match tracks.into_iter().next() {
+            Some(track) => Ok((vec![track.uri], Some(track.name))),
+            None => Err(Value::Error("No track currently playing.".to_string())),
+        },
+        Err(err) => Err(Value::Error(err.to_string())),
+    }
+}
+
+// Checks that a command was given only URIs of the types it takes.
+fn check_types(
+    inv: &Invocation,
+    uris: &[String],
+    allowed: &[UriType],
+    expected: &str,
+) -> Result<(), EvalError> {
+    let span = inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span);
+    if uris.is_empty() {
+        return Err(EvalError::new(format!("Expected {}", expected), span));
+    }
+    match uris
+        .iter()
+        .find(|uri| !allowed.contains(&uri_helper::get_uri_type(uri)))
+    {
+        Some(uri) => Err(EvalError::new(
+            format!("Expected {}, got {}", expected, uri),
+            span,
+        )),
+        None => Ok(()),
+    }
+}
+
+// Saves or removes the given items, the current track if none are given.
+async fn update_library(
+    ctx: &EvalContext<'_>,
+    inv: Invocation,
+    save: bool,
+    done: &str,
+) -> Result<Value, EvalError> {
+    let (uris, current) = match uris_or_current(ctx, &inv).await {
+        Ok(target) => target,
+        Err(value) => return Ok(value),
+    };
+    check_types(&inv, &uris, SAVABLE, "tracks, albums or shows")?;
+    let res = match save {
+        true => ctx.library_manager.save(&uris).await,
+        false => ctx.library_manager.remove(&uris).await,
+    };
+    Ok(match res {
+        Ok(()) => Value::Str(format!(
+            "{} {}.",
+            done,
+            current.unwrap_or_else(|| library_manager::describe(&uris))
+        )),
+        Err(err) => Value::Error(err.to_string()),
+    })
+}
+
+// Follows or unfollows the given artists and playlists.
+async fn update_follows(
+    ctx: &EvalContext<'_>,
+    inv: Invocation,
+    follow: bool,
+    done: &str,
+) -> Result<Value, EvalError> {
+    let uris = inv.uris(0).unwrap_or_default();
+    check_types(&inv, &uris, FOLLOWABLE, "artists or playlists")?;
+    let res = match follow {
+        true => ctx.library_manager.save(&uris).await,
+        false => ctx.library_manager.remove(&uris).await,
+    };
+    Ok(match res {
+        Ok(()) => Value::Str(format!("{} {}.", done, library_manager::describe(&uris))),
+        Err(err) => Value::Error(err.to_string()),
+    })
+}
+
+fn like<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(update_library(ctx, inv, true, "Liked"))
+}
+
+fn unlike<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(update_library(ctx, inv, false, "Removed"))
+}
+
+fn follow<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(update_follows(ctx, inv, true, "Followed"))
+}
+
+fn unfollow<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(update_follows(ctx, inv, false, "Unfollowed"))
+}
+
+fn liked<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let range = page_range(&inv, 50)?;
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        Ok(match (ctx.library_manager.liked(&range).await, columns) {
+            (Ok(tracks), Some(columns)) => Value::Str(format!(
+                "Liked Songs:\n{}",
+                formatter::print_track_table(&tracks, &columns, 1)
+            )),
+            (Ok(tracks), None) => Value::Tracks(tracks),
+            (Err(err), _) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn library<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let kind = inv.text(0).unwrap_or_default();
+        let range = page_range(&inv, 50)?;
+        // each row is the cells of the table followed by the URI
+        let (headers, rows): (&[&str], _) = match kind {
+            "albums" => (
+                &["Name", "Artists", "URI"],
+                ctx.library_manager.albums(&range).await.map(|albums| {
+                    albums
+                        .into_iter()
+                        .map(|a| {
+                            let artists: Vec<String> =
+                                a.artists.into_iter().map(|a| a.name).collect();
+                            vec![a.name, artists.join(", "), a.uri.unwrap_or_default()]
+                        })
+                        .collect::<Vec<_>>()
+                }),
+            ),
+            "artists" => (
+                &["Name", "URI"],
+                ctx.library_manager.artists(&range).await.map(|artists| {
+                    artists
+                        .into_iter()
+                        .map(|a| vec![a.name, a.uri.unwrap_or_default()])
+                        .collect()
+                }),
+            ),
+            "shows" => (
+                &["Name", "Publisher", "URI"],
+                ctx.library_manager.shows(&range).await.map(|shows| {
+                    shows
+                        .into_iter()
+                        .map(|s| {
+                            vec![
+                                s.name,
+                                s.publisher.unwrap_or_default(),
+                                s.uri.unwrap_or_default(),
+                            ]
+                        })
+                        .collect()
+                }),
+            ),
+            _ => {
+                return Err(EvalError::new(
+                    format!(
+                        "Unknown library section '{}' (expected albums, artists or shows)",
+                        kind
+                    ),
+                    inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+                ))
+            }
+        };
+        Ok(match (rows, inv.has_flag("-h")) {
+            (Ok(rows), true) => Value::Str(formatter::print_table(headers, &rows, 0)),
+            (Ok(rows), false) => {
+                Value::Uris(rows.into_iter().filter_map(|mut row| row.pop()).collect())
+            }
+            (Err(err), _) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn contains<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let (uris, _) = match uris_or_current(ctx, &inv).await {
+            Ok(target) => target,
+            Err(value) => return Ok(value),
+        };
+        // other items can't be saved or followed, so there is no answer
+        check_types(
+            &inv,
+            &uris,
+            &[
+                UriType::Track,
+                UriType::Album,
+                UriType::Show,
+                UriType::Artist,
+                UriType::Playlist,
+            ],
+            "tracks, albums, shows, artists or playlists",
+        )?;
+        Ok(match ctx.library_manager.contains(&uris).await {
+            Ok(saved) => Value::Str(
+                uris.iter()
+                    .zip(saved)
+                    .map(|(uri, saved)| format!("{}\t{}", uri, if saved { "yes" } else { "no" }))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ),
+            Err(err) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn stats<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let from = date_flag(&inv, "--from", false)?;
+        let to = date_flag(&inv, "--to", true)?;
+        let limit = count_flag(&inv, "--limit")?.map_or(10, |(n, _)| n);
+        // an unknown section is reported before anything is requested
+        let section = match (inv.text(0), inv.arg(0)) {
+            (Some(name), Some((_, span))) => match StatsSection::parse(name) {
+                Some(section) => Some(section),
+                None => {
+                    return Err(EvalError::new(
+                        format!(
+                            "Unknown stats section '{}' (expected tracks, artists, albums, days or skips)",
+                            name
+                        ),
+                        *span,
+                    ))
+                }
+            },
+            _ => None,
+        };
+        Ok(
+            match ctx
+                .history_manager
+                .stats(section, from, to, limit, inv.has_flag("-j"))
+                .await
+            {
+                Ok(text) => Value::Str(text),
+                Err(err) => Value::Error(err.to_string()),
+            },
+        )
+    })
+}
+
+fn filter<'a>(_ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let pattern = inv.text(0).unwrap_or_default().to_lowercase();
+        let columns = table_columns(&inv, Column::DEFAULT)?;
+        let matches: Vec<TrackRecord> = inv
+            .tracks(1)
+            .into_iter()
+            .filter(|t| {
+                t.name.to_lowercase().contains(&pattern)
+                    || t.artists
+                        .iter()
+                        .any(|a| a.to_lowercase().contains(&pattern))
+                    || t.album
+                        .as_ref()
+                        .is_some_and(|a| a.to_lowercase().contains(&pattern))
+            })
+            .collect();
+        Ok(match columns {
+            Some(columns) => Value::Str(formatter::print_track_table(&matches, &columns, 0)),
+            None => Value::Tracks(matches),
+        })
+    })
+}
+
+fn api<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let span = |i: usize| inv.arg(i).map(|(_, span)| *span).unwrap_or(inv.span);
+        let method = inv.text(0).unwrap_or_default().to_ascii_uppercase();
+        let target = inv.text(1).unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let endpoint = format!("api/spt-fwd/{}", path.trim_start_matches('/'));
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let body = match inv.text(2) {
+            Some(text) => Some(
+                serde_json::from_str(text)
+                    .map_err(|e| EvalError::new(format!("Invalid JSON body: {}", e), span(2)))?,
+            ),
+            None => None,
+        };
+
+        let api_proxy = ctx.api_proxy;
+        let res = match method.as_str() {
+            "GET" => api_proxy.get(&endpoint, Some(params)).await,
+            "POST" => api_proxy.post(&endpoint, body, Some(params)).await,
+            "PUT" => api_proxy.put(&endpoint, body, Some(params)).await,
+            "DELETE" => api_proxy.delete(&endpoint, body, Some(params)).await,
+            _ => {
+                return Err(EvalError::new(
+                    format!(
+                        "Unsupported method '{}' (expected GET, POST, PUT or DELETE)",
+                        method
+                    ),
+                    span(0),
+                ))
+            }
+        };
+        Ok(match res {
+            Ok((_, json)) => Value::Str(serde_json::to_string_pretty(&json).unwrap_or_default()),
+            Err(err) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn run<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let path = inv.text(0).unwrap_or_default();
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            EvalError::new(
+                format!("Failed to read script '{}': {}", path, e),
+                inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+            )
+        })?;
+        Ok(cli_app::run_script(ctx.api_proxy, path, &source, inv.has_flag("-e")).await)
+    })
+}
+
+fn sleep<'a>(_ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let seconds = in_range(inv.number(0), 0, i64::MAX)?.unwrap_or(0);
+        tokio::time::sleep(Duration::from_secs(seconds as u64)).await;
+        Ok(Value::Str(String::new()))
+    })
+}
+
+fn wait_until_track_ends<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        Ok(match ctx.playback_manager.wait_until_track_ends().await {
+            Ok(()) => Value::Str(String::new()),
+            Err(err) => Value::Error(err.to_string()),
+        })
+    })
+}
+
+fn help<'a>(_ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let path = match (inv.text(0), inv.text(1)) {
+            (Some(command), Some(sub)) => format!("{} {}", command, sub),
+            (Some(command), None) => command.to_string(),
+            _ => return Ok(Value::Str(docs::help_overview())),
+        };
+        match docs::help_command(&path) {
+            Some(help) => Ok(Value::Str(help)),
+            None => Err(EvalError::new(
+                format!("No help for unknown command '{}'", path),
+                inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+            )),
+        }
+    })
+}
+
+fn completions<'a>(_ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let shell = inv.text(0).unwrap_or_default();
+        match docs::completion_script(shell) {
+            Some(script) => Ok(Value::Str(script)),
+            None => Err(EvalError::new(
+                format!("Unsupported shell '{}' (expected bash, zsh or fish)", shell),
+                inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+            )),
+        }
+    })
+}
+
+fn tui<'a>(ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(tui_app::run(ctx.api_proxy).await) })
+}
+
+fn manpage<'a>(_ctx: &'a mut EvalContext<'_>, _inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move { Ok(Value::Str(docs::man_page())) })
+}
+
+// Looks up the completion values of the given kinds and caches them.
+fn completions_refresh<'a>(ctx: &'a mut EvalContext<'_>, inv: Invocation) -> HandlerFuture<'a> {
+    Box::pin(async move {
+        let kinds = match inv.text(0) {
+            Some(name) => match CompletionKind::from_name(name) {
+                Some(kind) => vec![kind],
+                None => {
+                    return Err(EvalError::new(
+                        format!(
+                        "Unknown completion values '{}' (expected devices, playlists or tracks)",
+                        name
+                    ),
+                        inv.arg(0).map(|(_, span)| *span).unwrap_or(inv.span),
+                    ))
+                }
+            },
+            None => CompletionKind::ALL.to_vec(),
+        };
+
+        // run by shell completion in the background, where nobody is around
+        // to authorize in a browser
+        match ctx.api_proxy.get("api/authorized", None).await {
+            Ok((_, json)) if json["authorized"].as_bool() == Some(true) => {}
+            Ok(_) => {
+                return Ok(Value::Error(
+                    "Not authorized, run another command first".to_string(),
+                ))
+            }
+            Err(err) => return Ok(Value::Error(err.to_string())),
+        }
+
+        let mut counts = Vec::new();
+        for kind in kinds {
+            let entries = match kind {
+                CompletionKind::Devices => match ctx.playback_manager.list_devices().await {
+                    Ok(_) => device_entries(ctx),
+                    Err(err) => return Ok(Value::Error(err.to_string())),
+                },
+                CompletionKind::Playlists => {
+                    match ctx.playlist_manager.playlists(&PageRange::all()).await {
+                        Ok(playlists) => playlists
+                            .into_iter()
+                            .map(|p| (p.name.clone(), format!("{} tracks", p.track_count())))
+                            .collect(),
+                        Err(err) => return Ok(Value::Error(err.to_string())),
+                    }
+                }
+                CompletionKind::Tracks => {
+                    match ctx.playback_manager.recent(&PageRange::first(50)).await {
+                        Ok(tracks) => track_entries(&tracks),
+                        Err(err) => return Ok(Value::Error(err.to_string())),
+                    }
+                }
+            };
+            if let Err(e) = completion_cache::store(kind, &entries) {
+                return Ok(Value::Error(format!(
+                    "Failed to write {}: {}",
+                    completion_cache::cache_path(kind).display(),
+                    e
+                )));
+            }
+            counts.push(format!("{} {}", entries.len(), kind.name()));
+        }
+
+        Ok(Value::Str(format!("Cached {}.", counts.join(", "))))
+    })
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Values offered by shell completion that have to be looked up through the
+// server. They're kept in one file per kind, a "value<TAB>description" line
+// per entry, so completion scripts can read them without starting spt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Devices,
+    Playlists,
+    Tracks,
+}
+
+impl CompletionKind {
+    pub const ALL: [CompletionKind; 3] = [
+        CompletionKind::Devices,
+        CompletionKind::Playlists,
+        CompletionKind::Tracks,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionKind::Devices => "devices",
+            CompletionKind::Playlists => "playlists",
+            CompletionKind::Tracks => "tracks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CompletionKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+// Directory holding the cache files, made absolute so generated scripts find
+// it from any working directory.
+pub fn cache_dir() -> PathBuf {
+    let dir =
+        PathBuf::from(env::var("COMPLETION_CACHE_DIR").unwrap_or("data/completions".to_string()));
+    match env::current_dir() {
+        Ok(cwd) if dir.is_relative() => cwd.join(dir),
+        _ => dir,
+    }
+}
+
+// Minutes after which completion scripts refresh a cache file in the
+// background.
+pub fn max_age_minutes() -> u64 {
+    env::var("COMPLETION_CACHE_MINUTES")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(10)
+}
+
+pub fn cache_path(kind: CompletionKind) -> PathBuf {
+    cache_dir().join(kind.name())
+}
+
+// Tabs and newlines would break the line format.
+fn clean(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+// Replaces the cached values of a kind. Entries are (value, description),
+// the description may be empty.
+pub fn store(kind: CompletionKind, entries: &[(String, String)]) -> io::Result<()> {
+    let mut lines = String::new();
+    for (value, description) in entries {
+        lines.push_str(&clean(value));
+        if !description.is_empty() {
+            lines.push('\t');
+            lines.push_str(&clean(description));
+        }
+        lines.push('\n');
+    }
+
+    fs::create_dir_all(cache_dir())?;
+    fs::write(cache_path(kind), lines)
+}
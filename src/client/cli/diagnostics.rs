@@ -0,0 +1,103 @@
+use crate::client::cli::parser::Span;
+
+// Levenshtein distance between two strings, counted in chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+// Returns the candidate closest to word, if any is close enough to be a
+// likely typo. Ties go to the candidate listed first.
+pub fn suggest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (word.chars().count() / 3).max(2);
+
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(word, c), c))
+        .filter(|(d, _)| *d > 0 && *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+// Renders an error message followed by the input with a caret line under the
+// given span, and an optional hint, e.g.
+//
+//   Error: Unknown command 'paly'
+//     paly 3
+//     ^^^^
+//     did you mean 'play'?
+pub fn render(input: &str, message: &str, span: Span, hint: Option<String>) -> String {
+    let start = span.start.min(input.len());
+    let end = span.end.clamp(start, input.len());
+
+    let offset = input[..start].chars().count();
+    let width = input[start..end].chars().count().max(1);
+
+    let mut lines = vec![
+        format!("Error: {}", message),
+        format!("  {}", input),
+        format!("  {}{}", " ".repeat(offset), "^".repeat(width)),
+    ];
+    if let Some(hint) = hint {
+        lines.push(format!("  {}", hint));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puts_the_caret_under_the_span() {
+        assert_eq!(
+            render(
+                "paly 3",
+                "Unknown command 'paly'",
+                Span { start: 0, end: 4 },
+                None
+            ),
+            "Error: Unknown command 'paly'\n  paly 3\n  ^^^^"
+        );
+        // offsets are bytes, columns are chars
+        assert_eq!(
+            render("search é x", "e", Span { start: 10, end: 11 }, None),
+            "Error: e\n  search é x\n           ^"
+        );
+        // an empty span at the end still gets a caret
+        assert_eq!(
+            render(
+                "play &&",
+                "e",
+                Span { start: 7, end: 7 },
+                Some("hint".to_string())
+            ),
+            "Error: e\n  play &&\n         ^\n  hint"
+        );
+    }
+
+    #[test]
+    fn suggests_the_closest_candidate() {
+        assert_eq!(edit_distance("paly", "play"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest("paly", ["pause", "play", "queue"]), Some("play"));
+        assert_eq!(suggest("--colums", ["--columns", "-h"]), Some("--columns"));
+        // not an exact match, nor one too far off
+        assert_eq!(suggest("play", ["play"]), None);
+        assert_eq!(suggest("xyzzy", ["play", "pause"]), None);
+        // ties go to the first
+        assert_eq!(suggest("pay", ["play", "pat"]), Some("play"));
+    }
+}
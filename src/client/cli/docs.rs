@@ -0,0 +1,527 @@
+use crate::client::cli::commands::COMMANDS;
+use crate::client::cli::completion_cache;
+use crate::client::cli::macros;
+use crate::client::cli::registry::{self, CommandSpec};
+
+// Pads the first column of each row so the second columns line up.
+fn two_columns(rows: &[(String, &str)], indent: usize) -> Vec<String> {
+    let width = rows.iter().map(|(left, _)| left.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(left, right)| format!("{}{:width$}  {}", " ".repeat(indent), left, right))
+        .collect()
+}
+
+pub fn help_overview() -> String {
+    let rows: Vec<(String, &str)> = COMMANDS
+        .iter()
+        .map(|spec| (spec.name.to_string(), spec.summary))
+        .collect();
+
+    let mut lines = vec!["Commands:".to_string()];
+    lines.extend(two_columns(&rows, 2));
+
+    let macros: Vec<(String, &str)> = macros::all()
+        .iter()
+        .map(|m| (m.usage(), m.body.as_str()))
+        .collect();
+    if !macros.is_empty() {
+        lines.push(String::new());
+        lines.push(format!("Macros ({}):", macros::config_path()));
+        lines.extend(two_columns(&macros, 2));
+    }
+
+    lines.push(String::new());
+    lines.push("Run 'help <command>' for details on a command.".to_string());
+    lines.join("\n")
+}
+
+// Full help for a command or subcommand path, e.g. "queue add".
+pub fn help_command(path: &str) -> Option<String> {
+    if let Some(m) = macros::lookup(path) {
+        return Some(format!(
+            "Usage: {}\n\nMacro from {}, runs:\n  {}",
+            m.usage(),
+            macros::config_path(),
+            m.body
+        ));
+    }
+
+    let spec = registry::find(path)?;
+    let parent = path
+        .split_whitespace()
+        .next()
+        .and_then(registry::lookup)
+        .filter(|parent| !std::ptr::eq(*parent, spec))
+        .map(|parent| parent.name);
+
+    let mut lines = vec![
+        format!("Usage: {}", spec.usage(parent)),
+        String::new(),
+        spec.summary.to_string(),
+    ];
+
+    if !spec.aliases.is_empty() {
+        lines.push(format!("Aliases: {}", spec.aliases.join(", ")));
+    }
+
+    if !spec.params.is_empty() {
+        let rows: Vec<(String, String)> = spec
+            .params
+            .iter()
+            .map(|param| {
+                let description = match param.default {
+                    Some(default) => format!("{} (default {})", param.description, default),
+                    None => param.description.to_string(),
+                };
+                (
+                    param.name.to_uppercase(),
+                    format!("{} [{}]", description, param.ty.name()),
+                )
+            })
+            .collect();
+        let rows: Vec<(String, &str)> = rows.iter().map(|(l, r)| (l.clone(), r.as_str())).collect();
+        lines.push(String::new());
+        lines.push("Arguments:".to_string());
+        lines.extend(two_columns(&rows, 2));
+    }
+
+    if !spec.flags.is_empty() {
+        let rows: Vec<(String, &str)> = spec
+            .flags
+            .iter()
+            .map(|flag| match flag.value {
+                Some(value) => (format!("{} {}", flag.name, value), flag.description),
+                None => (flag.name.to_string(), flag.description),
+            })
+            .collect();
+        lines.push(String::new());
+        lines.push("Flags:".to_string());
+        lines.extend(two_columns(&rows, 2));
+    }
+
+    if !spec.subcommands.is_empty() {
+        let rows: Vec<(String, &str)> = spec
+            .subcommands
+            .iter()
+            .map(|sub| (sub.name.to_string(), sub.summary))
+            .collect();
+        lines.push(String::new());
+        lines.push("Subcommands:".to_string());
+        lines.extend(two_columns(&rows, 2));
+    }
+
+    Some(lines.join("\n"))
+}
+
+// Words completed after a command: its subcommands and flags.
+fn command_words(spec: &CommandSpec) -> Vec<&'static str> {
+    let mut words: Vec<&str> = spec.subcommands.iter().map(|sub| sub.name).collect();
+    words.extend(spec.flags.iter().map(|flag| flag.name));
+    words
+}
+
+fn names(spec: &CommandSpec) -> Vec<&'static str> {
+    std::iter::once(spec.name)
+        .chain(spec.aliases.iter().copied())
+        .collect()
+}
+
+// Command names, aliases and macros.
+fn all_names() -> Vec<&'static str> {
+    COMMANDS
+        .iter()
+        .flat_map(names)
+        .chain(macros::all().iter().map(|m| m.name.as_str()))
+        .collect()
+}
+
+// Shell case arms mapping "command:subcommand" (including aliases) to a
+// canonical key, shared by the bash and zsh scripts.
+fn subcommand_keys() -> String {
+    COMMANDS
+        .iter()
+        .flat_map(|spec| spec.subcommands.iter().map(move |sub| (spec, sub)))
+        .map(|(spec, sub)| {
+            let patterns: Vec<String> = names(spec)
+                .iter()
+                .flat_map(|p| names(sub).into_iter().map(move |s| format!("{}:{}", p, s)))
+                .collect();
+            format!(
+                "            {}) key=\"{}:{}\" ;;",
+                patterns.join("|"),
+                spec.name,
+                sub.name
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Shell case arms setting the given variable to the words to complete for
+// each key.
+fn word_cases(var: &str) -> String {
+    let mut cases = Vec::new();
+    for spec in COMMANDS {
+        cases.push(format!(
+            "        {}) {}=\"{}\" ;;",
+            names(spec).join("|"),
+            var,
+            command_words(spec).join(" ")
+        ));
+        for sub in spec.subcommands {
+            cases.push(format!(
+                "        {}:{}) {}=\"{}\" ;;",
+                spec.name,
+                sub.name,
+                var,
+                command_words(sub).join(" ")
+            ));
+        }
+    }
+    cases.join("\n")
+}
+
+// The cache kind completing the first parameter that has one, if any.
+fn value_kind(spec: &CommandSpec) -> Option<&'static str> {
+    spec.params
+        .iter()
+        .find_map(|param| param.complete)
+        .map(|kind| kind.name())
+}
+
+// Shell case arms setting the kind of cached values to complete for each key.
+fn value_cases() -> String {
+    let mut cases = Vec::new();
+    for spec in COMMANDS {
+        if let Some(kind) = value_kind(spec) {
+            cases.push(format!(
+                "        {}) values=\"{}\" ;;",
+                names(spec).join("|"),
+                kind
+            ));
+        }
+        for sub in spec.subcommands {
+            if let Some(kind) = value_kind(sub) {
+                cases.push(format!(
+                    "        {}:{}) values=\"{}\" ;;",
+                    spec.name, sub.name, kind
+                ));
+            }
+        }
+    }
+    cases.join("\n")
+}
+
+// Prints the cached values of a kind, nothing if there are none yet. A missing
+// or stale cache is refreshed in the background, which never blocks or asks
+// for authorization. Shared by bash and zsh.
+fn sh_values_function() -> String {
+    format!(
+        r#"_spt_values() {{
+    local file="{dir}/$1"
+    if [ ! -f "$file" ] || [ -n "$(find "$file" -mmin +{minutes} 2>/dev/null)" ]; then
+        (spt completions refresh "$1" >/dev/null 2>&1 &)
+    fi
+    [ -f "$file" ] && cat "$file"
+}}"#,
+        dir = completion_cache::cache_dir().display(),
+        minutes = completion_cache::max_age_minutes(),
+    )
+}
+
+fn bash_script() -> String {
+    format!(
+        r#"# bash completion for spt
+{values_function}
+
+_spt() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local words="" values=""
+
+    # keep URIs like spotify:track:... in one word when bash-completion is loaded
+    if declare -F _get_comp_words_by_ref >/dev/null; then
+        _get_comp_words_by_ref -n : cur
+    fi
+
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+        return
+    fi
+
+    local key="${{COMP_WORDS[1]}}"
+    if [ "$COMP_CWORD" -gt 2 ]; then
+        case "${{COMP_WORDS[1]}}:${{COMP_WORDS[2]}}" in
+{keys}
+        esac
+    fi
+
+    case "$key" in
+{cases}
+    esac
+
+    case "$key" in
+{value_cases}
+    esac
+
+    local IFS=$'\n'
+    words="${{words// /$'\n'}}"
+    if [ -n "$values" ] && [[ "$cur" != -* ]]; then
+        words="$words"$'\n'"$(_spt_values "$values" | cut -f1)"
+    fi
+    COMPREPLY=($(compgen -W "$words" -- "$cur"))
+
+    # device names may contain spaces
+    if [ -n "$values" ]; then
+        COMPREPLY=($(printf '%q\n' "${{COMPREPLY[@]}}"))
+    fi
+    if declare -F __ltrim_colon_completions >/dev/null; then
+        __ltrim_colon_completions "$cur"
+    fi
+}}
+complete -F _spt spt
+"#,
+        values_function = sh_values_function(),
+        commands = all_names().join(" "),
+        keys = subcommand_keys(),
+        cases = word_cases("words"),
+        value_cases = value_cases(),
+    )
+}
+
+// Escapes a description for use inside single quotes in zsh and fish.
+fn quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+// Commands and macros with their descriptions, for zsh and fish.
+fn described_commands() -> Vec<(&'static str, &'static str)> {
+    COMMANDS
+        .iter()
+        .map(|spec| (spec.name, spec.summary))
+        .chain(
+            macros::all()
+                .iter()
+                .map(|m| (m.name.as_str(), m.body.as_str())),
+        )
+        .collect()
+}
+
+fn zsh_script() -> String {
+    let commands = described_commands()
+        .into_iter()
+        .map(|(name, description)| {
+            format!(
+                "        '{}:{}'",
+                name,
+                quote(description).replace(':', "\\:")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        r#"#compdef spt
+{values_function}
+
+_spt() {{
+    local -a commands
+    commands=(
+{commands}
+    )
+
+    if (( CURRENT == 2 )); then
+        _describe 'command' commands
+        return
+    fi
+
+    local key="$words[2]" candidates="" values=""
+    if (( CURRENT > 3 )); then
+        case "$words[2]:$words[3]" in
+{keys}
+        esac
+    fi
+
+    case "$key" in
+{cases}
+    esac
+
+    case "$key" in
+{value_cases}
+    esac
+
+    if [[ -n $values && $PREFIX != -* ]]; then
+        local -a described
+        local entry
+        for entry in ${{(f)"$(_spt_values $values)"}}; do
+            described+=("${{${{entry%%$'\t'*}}//:/\\:}}:${{entry#*$'\t'}}")
+        done
+        _describe "$values" described
+    fi
+
+    compadd -- ${{=candidates}}
+}}
+_spt "$@"
+"#,
+        values_function = sh_values_function(),
+        commands = commands,
+        keys = subcommand_keys(),
+        cases = word_cases("candidates"),
+        value_cases = value_cases(),
+    )
+}
+
+fn fish_flag(command: &str, condition: &str, name: &str, description: &str) -> String {
+    let option = match name.strip_prefix("--") {
+        Some(long) => format!("-l {}", long),
+        None => format!("-s {}", name.trim_start_matches('-')),
+    };
+    format!(
+        "complete -c spt -n '{}' {} -d '{}' # {}",
+        condition,
+        option,
+        quote(description),
+        command
+    )
+}
+
+fn fish_script() -> String {
+    let values_function = format!(
+        r#"function __spt_values
+    set -l file "{dir}/$argv[1]"
+    set -l stale (find $file -mmin +{minutes} 2>/dev/null)
+    if not test -f $file; or test -n "$stale"
+        spt completions refresh $argv[1] >/dev/null 2>&1 &
+    end
+    test -f $file; and cat $file
+end"#,
+        dir = completion_cache::cache_dir().display(),
+        minutes = completion_cache::max_age_minutes(),
+    );
+    let mut lines = vec![
+        "# fish completion for spt".to_string(),
+        values_function,
+        "complete -c spt -f".to_string(),
+    ];
+
+    for (name, description) in described_commands() {
+        lines.push(format!(
+            "complete -c spt -n __fish_use_subcommand -a {} -d '{}'",
+            name,
+            quote(description)
+        ));
+    }
+
+    for spec in COMMANDS {
+        let seen = format!("__fish_seen_subcommand_from {}", names(spec).join(" "));
+        for sub in spec.subcommands {
+            lines.push(format!(
+                "complete -c spt -n '{}' -a {} -d '{}'",
+                seen,
+                sub.name,
+                quote(sub.summary)
+            ));
+        }
+        for flag in spec.flags {
+            lines.push(fish_flag(spec.name, &seen, flag.name, flag.description));
+        }
+        if let Some(kind) = value_kind(spec) {
+            lines.push(format!(
+                "complete -c spt -n '{}' -a '(__spt_values {})'",
+                seen, kind
+            ));
+        }
+        for sub in spec.subcommands {
+            let seen = format!(
+                "{}; and __fish_seen_subcommand_from {}",
+                seen,
+                names(sub).join(" ")
+            );
+            if let Some(kind) = value_kind(sub) {
+                lines.push(format!(
+                    "complete -c spt -n '{}' -a '(__spt_values {})'",
+                    seen, kind
+                ));
+            }
+            for flag in sub.flags {
+                let command = format!("{} {}", spec.name, sub.name);
+                lines.push(fish_flag(&command, &seen, flag.name, flag.description));
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+// Returns the completion script for the given shell.
+pub fn completion_script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash_script()),
+        "zsh" => Some(zsh_script()),
+        "fish" => Some(fish_script()),
+        _ => None,
+    }
+}
+
+// Escapes text for roff, where backslashes and leading dots are special.
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\\\").replace('-', "\\-");
+    match text.strip_prefix('.') {
+        Some(rest) => format!("\\&.{}", rest),
+        None => text,
+    }
+}
+
+fn man_command(spec: &CommandSpec, parent: Option<&str>, lines: &mut Vec<String>) {
+    lines.push(".TP".to_string());
+    lines.push(format!(".B {}", roff(&spec.usage(parent))));
+    lines.push(roff(spec.summary));
+    if !spec.aliases.is_empty() {
+        lines.push(".br".to_string());
+        lines.push(format!("Aliases: {}", roff(&spec.aliases.join(", "))));
+    }
+    for param in spec.params {
+        lines.push(".br".to_string());
+        lines.push(format!(
+            "\\fI{}\\fR: {}",
+            param.name.to_uppercase(),
+            roff(param.description)
+        ));
+    }
+    for flag in spec.flags {
+        lines.push(".br".to_string());
+        let name = match flag.value {
+            Some(value) => format!("{} {}", flag.name, value),
+            None => flag.name.to_string(),
+        };
+        lines.push(format!(
+            "\\fB{}\\fR: {}",
+            roff(&name),
+            roff(flag.description)
+        ));
+    }
+}
+
+// The manual page, in roff format for man(1).
+pub fn man_page() -> String {
+    let mut lines = vec![
+        ".TH SPT 1".to_string(),
+        ".SH NAME".to_string(),
+        "spt \\- Spotify terminal application".to_string(),
+        ".SH SYNOPSIS".to_string(),
+        ".B spt".to_string(),
+        "[\\fICOMMAND\\fR [\\fIARGS\\fR...]]".to_string(),
+        ".SH DESCRIPTION".to_string(),
+        "Controls Spotify playback from the terminal. Without a command, starts an interactive session.".to_string(),
+        "Commands can be nested in parentheses or piped with |, the output of one command becomes the last argument of the next.".to_string(),
+        ".SH COMMANDS".to_string(),
+    ];
+
+    for spec in COMMANDS {
+        man_command(spec, None, &mut lines);
+        for sub in spec.subcommands {
+            man_command(sub, Some(spec.name), &mut lines);
+        }
+    }
+
+    lines.join("\n")
+}
@@ -1,107 +1,373 @@
-use crate::client::cli::parser::{Arg, CommandNode, ParseError};
+use crate::client::cli::parser::{Arg, CommandNode, Span, Statement};
+use crate::client::cli::registry::{self, Arity, ParamSpec};
+use crate::client::cli::value::{ArgType, TrackRecord, Value};
+use crate::client::core::history_manager::HistoryManager;
+use crate::client::core::id_resolver;
+use crate::client::core::library_manager::LibraryManager;
 use crate::client::core::playback_manager::PlaybackManager;
+use crate::client::core::playlist_manager::PlaylistManager;
+use crate::client::core::queue_manager::QueueManager;
+use crate::client::core::radio_manager::RadioManager;
+use crate::client::core::search_manager::SearchManager;
+use crate::client::core::smart_playlist_manager::SmartPlaylistManager;
 use crate::client::local_api_proxy::ApiProxy;
+use crate::util::uri_helper::UriType;
+use log::debug;
+use std::collections::HashMap;
 
-struct EvalContext<'a> {
-    playback_manager: PlaybackManager<'a>,
+// An error raised while evaluating a command, e.g. an argument of the wrong
+// type. span points at the offending part of the input.
+#[derive(Debug, Clone)]
+pub struct EvalError {
+    pub message: String,
+    pub span: Span,
 }
 
-async fn eval_simple(
-    ctx: &mut EvalContext<'_>,
-    command: String,
-    args: Vec<String>,
-) -> Option<String> {
-    let args_nf: Vec<String> = args // args without flags
-        .clone()
-        .into_iter()
-        .filter(|arg| !arg.starts_with('-'))
-        .map(|arg| arg.trim().trim_matches('"').to_string())
-        .collect();
-
-    println!("Evaluating command: {} with args: {:?}\n", command, args_nf);
-
-    match command.as_str() {
-        "play" => ctx.playback_manager.play().await,
-        "pause" => ctx.playback_manager.pause().await,
-        "next" => {
-            let n = args_nf
-                .get(0)
-                .and_then(|s| s.parse::<u8>().ok())
-                .unwrap_or(1);
-            ctx.playback_manager.next(n).await
+impl EvalError {
+    pub fn new(message: String, span: Span) -> Self {
+        EvalError { message, span }
+    }
+}
+
+// State kept between statements of a REPL session, currently the variables
+// bound with let.
+#[derive(Debug, Default)]
+pub struct Session {
+    vars: HashMap<String, Value>,
+}
+
+pub struct EvalContext<'a> {
+    pub api_proxy: &'a ApiProxy,
+    pub playback_manager: PlaybackManager<'a>,
+    pub history_manager: HistoryManager<'a>,
+    pub library_manager: LibraryManager<'a>,
+    pub playlist_manager: PlaylistManager<'a>,
+    pub queue_manager: QueueManager<'a>,
+    pub radio_manager: RadioManager<'a>,
+    pub search_manager: SearchManager<'a>,
+    pub smart_playlist_manager: SmartPlaylistManager<'a>,
+}
+
+// A bound argument, None if an optional argument was left out.
+type Bound = Option<(Value, Span)>;
+
+// A flag as given: its name, its value if it takes one, and its span.
+type BoundFlag = (String, Option<(String, Span)>, Span);
+
+// Arguments and flags of a command, as passed to its handler. Positional
+// arguments are in declaration order and already coerced to their types.
+#[derive(Debug)]
+pub struct Invocation {
+    pub span: Span,
+    pub args: Vec<Bound>,
+    pub flags: Vec<BoundFlag>,
+}
+
+impl Invocation {
+    pub fn arg(&self, i: usize) -> Option<&(Value, Span)> {
+        self.args.get(i).and_then(|arg| arg.as_ref())
+    }
+
+    pub fn number(&self, i: usize) -> Option<(i64, Span)> {
+        match self.arg(i) {
+            Some((Value::Number(n), span)) => Some((*n, *span)),
+            _ => None,
         }
-        "previous" => {
-            let n = args_nf
-                .get(0)
-                .and_then(|s| s.parse::<u8>().ok())
-                .unwrap_or(1);
-            ctx.playback_manager.previous(n).await
+    }
+
+    pub fn text(&self, i: usize) -> Option<&str> {
+        match self.arg(i) {
+            Some((Value::Str(s), _)) => Some(s.as_str()),
+            _ => None,
         }
-        "volume" => {
-            if args_nf.is_empty() {
-                ctx.playback_manager.get_volume().await
-            } else {
-                let level = args_nf.get(0).and_then(|s| s.parse::<u8>().ok());
-                ctx.playback_manager.set_volume(level.unwrap_or(100)).await
-            }
+    }
+
+    pub fn uris(&self, i: usize) -> Option<Vec<String>> {
+        match self.arg(i) {
+            Some((Value::Uris(uris), _)) => Some(uris.clone()),
+            _ => None,
         }
-        "device" => {
-            if let Some(name) = args_nf.get(0) {
-                ctx.playback_manager.device(name).await
-            } else {
-                Some("Device name is required".to_string())
-            }
+    }
+
+    pub fn tracks(&self, i: usize) -> Vec<TrackRecord> {
+        match self.arg(i) {
+            Some((Value::Tracks(tracks), _)) => tracks.clone(),
+            _ => vec![],
+        }
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(f, _, _)| f == flag)
+    }
+
+    pub fn flag_value(&self, flag: &str) -> Option<(&str, Span)> {
+        self.flags
+            .iter()
+            .find(|(f, _, _)| f == flag)
+            .and_then(|(_, value, _)| value.as_ref())
+            .map(|(value, span)| (value.as_str(), *span))
+    }
+}
+
+// Converts a value to the given type. Track lists convert to URI lists,
+// strings are parsed as numbers, bools or Spotify URIs/URLs, and URI lists
+// are looked up to get track records.
+async fn coerce(
+    ctx: &EvalContext<'_>,
+    value: Value,
+    ty: ArgType,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let type_error = |value: &Value| {
+        let got = match value {
+            Value::Str(s) => format!("{} \"{}\"", value.type_name(), s),
+            _ => value.type_name().to_string(),
+        };
+        EvalError::new(format!("Expected {} but got {}", ty.name(), got), span)
+    };
+
+    match (ty, value) {
+        (ArgType::Uris(_), Value::Uris(uris)) => Ok(Value::Uris(uris)),
+        (ArgType::Uris(_), Value::Tracks(tracks)) => {
+            Ok(Value::Uris(tracks.into_iter().map(|t| t.uri).collect()))
         }
-        "devices" => {
-            let h = args.contains(&"-h".to_string());
-            ctx.playback_manager.devices(h).await
+        (ArgType::Uris(bare_type), Value::Str(s)) => {
+            id_resolver::resolve_uris(ctx.api_proxy, &[s], bare_type)
+                .await
+                .map(Value::Uris)
+                .map_err(|err| EvalError::new(err.to_string(), span))
         }
-        "now" => {
-            let h = args.contains(&"-h".to_string());
-            ctx.playback_manager.now(h).await
+        (ArgType::Tracks, Value::Tracks(tracks)) => Ok(Value::Tracks(tracks)),
+        (ArgType::Tracks, value @ (Value::Uris(_) | Value::Str(_))) => {
+            let uris = match Box::pin(coerce(
+                ctx,
+                value,
+                ArgType::Uris(Some(UriType::Track)),
+                span,
+            ))
+            .await?
+            {
+                Value::Uris(uris) => uris,
+                _ => vec![],
+            };
+            ctx.search_manager
+                .describe(&uris)
+                .await
+                .map(Value::Tracks)
+                .map_err(|err| EvalError::new(err.to_string(), span))
         }
-        "queue" => {
-            let h = args.contains(&"-h".to_string());
-            if args_nf.is_empty() {
-                ctx.playback_manager.queue(h).await
-            } else {
-                ctx.playback_manager.queue_add(args_nf).await
+        (ArgType::Number, Value::Number(n)) => Ok(Value::Number(n)),
+        (ArgType::Number, Value::Str(s)) => match s.parse::<i64>() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => Err(type_error(&Value::Str(s))),
+        },
+        (ArgType::Text, Value::Str(s)) => Ok(Value::Str(s)),
+        (ArgType::Text, Value::Number(n)) => Ok(Value::Str(n.to_string())),
+        (ArgType::Text, Value::Bool(b)) => Ok(Value::Str(b.to_string())),
+        (ArgType::Bool, Value::Bool(b)) => Ok(Value::Bool(b)),
+        (ArgType::Bool, Value::Str(s)) => match s.as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(type_error(&Value::Str(s))),
+        },
+        (_, value) => Err(type_error(&value)),
+    }
+}
+
+// Matches evaluated arguments against the parameters of a command, coercing
+// each to the declared type. Left out optional arguments take their default.
+async fn bind(
+    ctx: &EvalContext<'_>,
+    cmd: &CommandNode,
+    params: &[ParamSpec],
+    args: Vec<(Value, Span)>,
+) -> Result<Vec<Bound>, EvalError> {
+    let mut args = args.into_iter();
+    let mut bound = Vec::with_capacity(params.len());
+
+    for param in params {
+        let ty = param.ty;
+        if param.arity == Arity::Many {
+            let rest: Vec<(Value, Span)> = args.by_ref().collect();
+            let Some(span) = rest.first().map(|(_, s)| s.to(rest[rest.len() - 1].1)) else {
+                bound.push(None);
+                continue;
+            };
+
+            let mut items = match ty {
+                ArgType::Tracks => Value::Tracks(vec![]),
+                _ => Value::Uris(vec![]),
+            };
+            for (value, span) in rest {
+                match (&mut items, coerce(ctx, value, ty, span).await?) {
+                    (Value::Uris(all), Value::Uris(uris)) => all.extend(uris),
+                    (Value::Tracks(all), Value::Tracks(tracks)) => all.extend(tracks),
+                    _ => {}
+                }
             }
+            bound.push(Some((items, span)));
+            continue;
         }
-        "recent" => {
-            let h = args.contains(&"-h".to_string());
-            let n = args_nf
-                .get(0)
-                .and_then(|s| s.parse::<u8>().ok())
-                .unwrap_or(20);
-            ctx.playback_manager.recent(n, h).await
+
+        match (args.next(), param.default) {
+            (Some((value, span)), _) => {
+                bound.push(Some((coerce(ctx, value, ty, span).await?, span)))
+            }
+            (None, Some(default)) => bound.push(Some((
+                coerce(ctx, Value::Str(default.to_string()), ty, cmd.span).await?,
+                cmd.span,
+            ))),
+            (None, None) if param.arity == Arity::Optional => bound.push(None),
+            (None, None) => {
+                return Err(EvalError::new(
+                    format!(
+                        "{} expects a {} argument ({})",
+                        cmd.name,
+                        ty.name(),
+                        param.name.to_uppercase()
+                    ),
+                    cmd.span,
+                ))
+            }
         }
-        _ => Some("Unknown command".to_string()),
+    }
+
+    match args.next() {
+        Some((_, span)) => Err(EvalError::new(
+            format!("Unexpected argument for {}", cmd.name),
+            span,
+        )),
+        None => Ok(bound),
     }
 }
 
-pub async fn eval(api_proxy: &mut ApiProxy, cmd: &CommandNode) -> Option<String> {
+pub async fn eval(
+    api_proxy: &ApiProxy,
+    session: &mut Session,
+    statement: &Statement,
+) -> Result<Option<Value>, EvalError> {
     let mut ctx = EvalContext {
+        api_proxy,
         playback_manager: PlaybackManager::new(api_proxy),
+        history_manager: HistoryManager::new(api_proxy),
+        library_manager: LibraryManager::new(api_proxy),
+        playlist_manager: PlaylistManager::new(api_proxy),
+        queue_manager: QueueManager::new(api_proxy),
+        radio_manager: RadioManager::new(api_proxy),
+        search_manager: SearchManager::new(api_proxy),
+        smart_playlist_manager: SmartPlaylistManager::new(api_proxy),
     };
-    return eval_rec(&mut ctx, cmd).await;
-}
 
-async fn eval_rec(ctx: &mut EvalContext<'_>, cmd: &CommandNode) -> Option<String> {
-    let mut evaluated_args: Vec<String> = Vec::new();
-    for (i, arg) in cmd.args.iter().enumerate() {
-        match arg {
-            Arg::Command(subcmd) => {
-                // Recursively evaluate subcommands
-                evaluated_args.push(Box::pin(eval_rec(ctx, subcmd)).await?);
+    match statement {
+        Statement::Command(cmd) => Ok(Some(eval_rec(&mut ctx, session, cmd).await?)),
+        Statement::Let(name, cmd) => {
+            let value = eval_rec(&mut ctx, session, cmd).await?;
+            if value.is_error() {
+                return Ok(Some(value));
             }
-            Arg::Text(text) => {
-                if !text.is_empty() {
-                    evaluated_args.push(text.clone());
+            session.vars.insert(name.clone(), value);
+            Ok(None)
+        }
+        // runs each command in turn, stopping at the first failure
+        Statement::Sequence(cmds) => {
+            let mut outputs = Vec::new();
+            for cmd in cmds {
+                let value = eval_rec(&mut ctx, session, cmd).await?;
+                if value.is_error() {
+                    return Ok(Some(value));
                 }
+                outputs.push(value.render());
             }
+            outputs.retain(|output| !output.is_empty());
+            Ok(Some(Value::Str(outputs.join("\n"))))
         }
     }
+}
 
-    return eval_simple(ctx, cmd.name.clone(), evaluated_args).await;
+async fn eval_rec(
+    ctx: &mut EvalContext<'_>,
+    session: &Session,
+    cmd: &CommandNode,
+) -> Result<Value, EvalError> {
+    let Some(spec) = registry::lookup(&cmd.name) else {
+        return Err(EvalError::new(
+            format!("Unknown command {}", cmd.name),
+            cmd.span,
+        ));
+    };
+    let (spec, args) = spec.resolve(&cmd.args);
+
+    let mut flags = Vec::new();
+    let mut positional: Vec<(Value, Span)> = Vec::new();
+
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let span = arg.span();
+        match arg {
+            Arg::Flag(flag, _) => {
+                let takes_value = match spec.flag(flag) {
+                    Some(flag_spec) => flag_spec.value.is_some(),
+                    None => {
+                        return Err(EvalError::new(
+                            format!("Flag {} cannot be used with {}", flag, spec.name),
+                            span,
+                        ))
+                    }
+                };
+                let value = match args.peek() {
+                    Some(Arg::Text(value, value_span)) if takes_value => {
+                        let value = Some((value.clone(), *value_span));
+                        args.next();
+                        value
+                    }
+                    _ if takes_value => {
+                        return Err(EvalError::new(
+                            format!("Flag {} expects a value", flag),
+                            span,
+                        ))
+                    }
+                    _ => None,
+                };
+                flags.push((flag.clone(), value, span));
+            }
+            _ => match eval_arg(ctx, session, arg).await? {
+                Some((value, _)) if value.is_error() => return Ok(value),
+                Some(value) => positional.push(value),
+                None => {}
+            },
+        }
+    }
+
+    let args = bind(ctx, cmd, spec.params, positional).await?;
+    let invocation = Invocation {
+        span: cmd.span,
+        args,
+        flags,
+    };
+    debug!("Evaluating command: {} with {:?}", spec.name, invocation);
+
+    (spec.handler)(ctx, invocation).await
+}
+
+// Evaluates a positional argument, None for empty text.
+async fn eval_arg(
+    ctx: &mut EvalContext<'_>,
+    session: &Session,
+    arg: &Arg,
+) -> Result<Option<(Value, Span)>, EvalError> {
+    let span = arg.span();
+    match arg {
+        Arg::Text(text, _) if text.is_empty() => Ok(None),
+        Arg::Text(text, _) | Arg::Flag(text, _) => Ok(Some((Value::Str(text.clone()), span))),
+        Arg::Var(name, _) => match session.vars.get(name) {
+            Some(value) => Ok(Some((value.clone(), span))),
+            None => Err(EvalError::new(format!("Unknown variable ${}", name), span)),
+        },
+        // a failing subcommand fails the whole command, its error value is
+        // passed on as is
+        Arg::Command(subcmd) => Ok(Some((
+            Box::pin(eval_rec(ctx, session, subcmd)).await?,
+            span,
+        ))),
+    }
 }
@@ -1,108 +1,389 @@
+use crate::client::cli::value::TrackRecord;
+use crate::util::models::{Device, Playlist};
 use serde_json::Value;
+use std::env;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
-pub fn print_track_pretty(json: &Value, indent_level: usize) -> String {
-    return format!(
-        "{}\"{}\" - {} by {}",
-        "\t".repeat(indent_level),
-        json["name"].as_str().unwrap_or("null"),
-        json["album"]["name"].as_str().unwrap_or("null"),
-        json["artists"][0]["name"].as_str().unwrap_or("null"),
-    );
+// What the terminal stdout is attached to supports. Plain output when stdout
+// isn't a terminal, e.g. when piped into another program.
+struct Terminal {
+    color: bool,
+    width: Option<usize>,
 }
 
-pub fn print_episode_pretty(json: &Value, indent_level: usize) -> String {
-    return format!(
-        "{}\"{}\" - {} by {}",
-        "\t".repeat(indent_level),
-        json["name"].as_str().unwrap_or("null"),
-        json["show"]["name"].as_str().unwrap_or("null"),
-        json["show"]["publisher"].as_str().unwrap_or("null"),
-    );
+// Set while output is shown somewhere other than the terminal itself, e.g. in
+// a pane of the TUI.
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
 }
 
-pub fn print_track_episode_pretty(json: &Value, indent_level: usize) -> String {
-    if json["type"] == "track" {
-        return print_track_pretty(json, indent_level);
-    } else if json["type"] == "episode" {
-        return print_episode_pretty(json, indent_level);
+fn terminal() -> &'static Terminal {
+    static PLAIN_TERMINAL: Terminal = Terminal {
+        color: false,
+        width: None,
+    };
+    static TERMINAL: OnceLock<Terminal> = OnceLock::new();
+    if PLAIN.load(Ordering::Relaxed) {
+        return &PLAIN_TERMINAL;
     }
+    TERMINAL.get_or_init(|| {
+        let is_tty = std::io::stdout().is_terminal();
+        // https://no-color.org: any non-empty value turns color off
+        let no_color = env::var("NO_COLOR").is_ok_and(|v| !v.is_empty());
+        Terminal {
+            color: is_tty && !no_color,
+            width: match is_tty {
+                true => terminal_size::terminal_size().map(|(width, _)| width.0 as usize),
+                false => None,
+            },
+        }
+    })
+}
 
-    return format!("{}Unknown Item", "\t".repeat(indent_level),);
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+fn paint(text: &str, code: &str) -> String {
+    match terminal().color {
+        true => format!("{}{}{}", code, text, RESET),
+        false => text.to_string(),
+    }
 }
 
-pub fn print_track(json: &Value, indent_level: usize) -> String {
-    print_track_episode(json, indent_level)
+// Shortens text to at most width chars, marking the cut with an ellipsis.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(width.saturating_sub(1)).collect();
+    short.push('…');
+    short
 }
 
-pub fn print_episode(json: &Value, indent_level: usize) -> String {
-    print_track_episode(json, indent_level)
+pub fn join_artists(artists: &[String]) -> String {
+    match artists.is_empty() {
+        true => "null".to_string(),
+        false => artists.join(", "),
+    }
 }
 
-pub fn print_track_episode(json: &Value, indent_level: usize) -> String {
-    return format!(
+// Fields of a track table, chosen with --columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Index,
+    Title,
+    Artists,
+    Album,
+    Duration,
+    Uri,
+}
+
+impl Column {
+    pub const ALL: [Column; 6] = [
+        Column::Index,
+        Column::Title,
+        Column::Artists,
+        Column::Album,
+        Column::Duration,
+        Column::Uri,
+    ];
+    pub const DEFAULT: &'static [Column] = &[
+        Column::Index,
+        Column::Title,
+        Column::Artists,
+        Column::Album,
+        Column::Duration,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Index => "index",
+            Column::Title => "title",
+            Column::Artists => "artists",
+            Column::Album => "album",
+            Column::Duration => "duration",
+            Column::Uri => "uri",
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Index => "#",
+            Column::Title => "Title",
+            Column::Artists => "Artists",
+            Column::Album => "Album",
+            Column::Duration => "Time",
+            Column::Uri => "URI",
+        }
+    }
+
+    // Parses a comma separated list such as "title,artists".
+    pub fn parse_list(list: &str) -> Result<Vec<Column>, String> {
+        list.split(',')
+            .map(|name| {
+                let name = name.trim();
+                Column::ALL
+                    .into_iter()
+                    .find(|c| c.name() == name)
+                    .ok_or(format!(
+                        "Unknown column '{}' (expected {})",
+                        name,
+                        Column::ALL.map(|c| c.name()).join(", ")
+                    ))
+            })
+            .collect()
+    }
+
+    fn cell(&self, index: usize, track: &TrackRecord) -> String {
+        match self {
+            Column::Index => (index + 1).to_string(),
+            Column::Title => track.name.clone(),
+            Column::Artists => join_artists(&track.artists),
+            Column::Album => track.album.clone().unwrap_or_default(),
+            Column::Duration => track.duration_ms.map(print_duration).unwrap_or_default(),
+            Column::Uri => track.uri.clone(),
+        }
+    }
+}
+
+// Formats tracks as a table with the given columns.
+pub fn print_track_table(
+    tracks: &[TrackRecord],
+    columns: &[Column],
+    indent_level: usize,
+) -> String {
+    let headers: Vec<&str> = columns.iter().map(|c| c.header()).collect();
+    let rows: Vec<Vec<String>> = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| columns.iter().map(|c| c.cell(i, track)).collect())
+        .collect();
+    let dim: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| matches!(c, Column::Index | Column::Duration))
+        .map(|(i, _)| i)
+        .collect();
+    render_table(&headers, &rows, &dim, indent_level)
+}
+
+pub fn print_device(device: &Device, indent_level: usize) -> String {
+    format!(
         "{}{}",
         "\t".repeat(indent_level),
-        json["uri"].as_str().unwrap_or("null")
-    );
+        device.id.as_deref().unwrap_or("null")
+    )
 }
 
-pub fn print_item_list(json: &Value, indent_level: usize) -> String {
-    json.as_array()
-        .unwrap_or(&vec![])
+pub fn print_device_list(devices: &[Device], indent_level: usize) -> String {
+    devices
         .iter()
-        .map(|item| print_track_episode(item, indent_level))
+        .map(|device| print_device(device, indent_level))
         .collect::<Vec<String>>()
         .join("\n")
 }
 
-pub fn print_item_list_pretty(json: &Value, indent_level: usize) -> String {
-    json.as_array()
-        .and_then(|arr| {
-            Some(
-                arr.iter()
-                    .map(|item| print_track_episode_pretty(item, indent_level))
-                    .collect::<Vec<String>>()
-                    .join("\n"),
-            )
+// Formats devices as a table, the active device marked with *.
+pub fn print_device_list_pretty(devices: &[Device], indent_level: usize) -> String {
+    let rows: Vec<Vec<String>> = devices
+        .iter()
+        .map(|device| {
+            vec![
+                match device.is_active {
+                    true => "*".to_string(),
+                    false => String::new(),
+                },
+                device.name.clone(),
+                device.kind.clone(),
+                device
+                    .volume_percent
+                    .map(|v| format!("{}%", v))
+                    .unwrap_or_default(),
+                device.id.clone().unwrap_or("null".to_string()),
+            ]
         })
-        .unwrap_or(format!("{}{}", "\t".repeat(indent_level), "None"))
+        .collect();
+    render_table(
+        &["", "Name", "Type", "Volume", "ID"],
+        &rows,
+        &[4],
+        indent_level,
+    )
 }
 
-pub fn print_device_pretty(json: &Value, indent_level: usize) -> String {
-    return format!(
-        "{}{} ({})",
-        "\t".repeat(indent_level),
-        json["name"].as_str().unwrap_or("null"),
-        json["id"].as_str().unwrap_or("null")
-    );
+// Formats playlists as a table, numbered from first.
+pub fn print_playlist_table(playlists: &[Playlist], first: usize, indent_level: usize) -> String {
+    let rows: Vec<Vec<String>> = playlists
+        .iter()
+        .enumerate()
+        .map(|(i, playlist)| {
+            vec![
+                (first + i).to_string(),
+                playlist.name.clone(),
+                playlist.track_count().to_string(),
+                playlist
+                    .owner
+                    .as_ref()
+                    .and_then(|o| o.display_name.clone().or(o.id.clone()))
+                    .unwrap_or_default(),
+                playlist.uri.clone(),
+            ]
+        })
+        .collect();
+    render_table(
+        &["#", "Name", "Tracks", "Owner", "URI"],
+        &rows,
+        &[0, 4],
+        indent_level,
+    )
 }
 
-pub fn print_device(json: &Value, indent_level: usize) -> String {
-    return format!(
-        "{}{}",
-        "\t".repeat(indent_level),
-        json["id"].as_str().unwrap_or("null")
-    );
+// Formats a duration in ms as e.g. "1h 05m" or "3m 20s".
+pub fn print_duration(ms: i64) -> String {
+    let secs = ms.max(0) / 1000;
+    if secs >= 3600 {
+        return format!("{}h {:02}m", secs / 3600, (secs % 3600) / 60);
+    }
+    format!("{}m {:02}s", secs / 60, secs % 60)
+}
+
+// Formats rows as a table with left-aligned columns padded to the widest cell.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>], indent_level: usize) -> String {
+    render_table(headers, rows, &[], indent_level)
 }
 
-pub fn print_device_list(json: &Value, indent_level: usize) -> String {
-    json.as_array()
+// Columns are never truncated below this width.
+const MIN_COLUMN_WIDTH: usize = 6;
+
+// Formats a table, bold headers and the columns in dim dimmed when color is
+// on. On a terminal, the widest columns are truncated until lines fit its
+// width.
+fn render_table(
+    headers: &[&str],
+    rows: &[Vec<String>],
+    dim: &[usize],
+    indent_level: usize,
+) -> String {
+    if rows.is_empty() {
+        return format!("{}None", "\t".repeat(indent_level));
+    }
+
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    if let Some(max_width) = terminal().width {
+        // tabs are 8 columns wide, columns are separated by 2 spaces
+        let available = max_width.saturating_sub(8 * indent_level + 2 * (widths.len() - 1));
+        while widths.iter().sum::<usize>() > available {
+            match widths.iter_mut().filter(|w| **w > MIN_COLUMN_WIDTH).max() {
+                Some(widest) => *widest -= 1,
+                None => break,
+            }
+        }
+    }
+
+    let last = widths.len() - 1;
+    let format_row = |cells: Vec<&str>, header: bool| {
+        let line = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let cell = truncate(cell, widths[i]);
+                // the last column isn't padded, so lines have no trailing spaces
+                let cell = match i == last {
+                    true => cell,
+                    false => format!("{:width$}", cell, width = widths[i]),
+                };
+                match (header, dim.contains(&i)) {
+                    (true, _) => paint(&cell, BOLD),
+                    (false, true) => paint(&cell, DIM),
+                    (false, false) => cell,
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("  ");
+        format!("{}{}", "\t".repeat(indent_level), line)
+    };
+
+    let mut lines = vec![format_row(headers.to_vec(), true)];
+    for row in rows {
+        lines.push(format_row(row.iter().map(|s| s.as_str()).collect(), false));
+    }
+    lines.join("\n")
+}
+
+pub fn print_track_stats(json: &Value, indent_level: usize) -> String {
+    let rows: Vec<Vec<String>> = json
+        .as_array()
         .unwrap_or(&vec![])
         .iter()
-        .map(|item| print_device(item, indent_level))
-        .collect::<Vec<String>>()
-        .join("\n")
+        .enumerate()
+        .map(|(i, item)| {
+            vec![
+                (i + 1).to_string(),
+                item["name"].as_str().unwrap_or("null").to_string(),
+                item["artists"].as_str().unwrap_or("null").to_string(),
+                item["plays"].as_i64().unwrap_or(0).to_string(),
+                print_duration(item["played_ms"].as_i64().unwrap_or(0)),
+            ]
+        })
+        .collect();
+    print_table(
+        &["#", "Track", "Artists", "Plays", "Time"],
+        &rows,
+        indent_level,
+    )
+}
+
+pub fn print_named_stats(json: &Value, label: &str, indent_level: usize) -> String {
+    let rows: Vec<Vec<String>> = json
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            vec![
+                (i + 1).to_string(),
+                item["name"].as_str().unwrap_or("null").to_string(),
+                item["plays"].as_i64().unwrap_or(0).to_string(),
+                print_duration(item["played_ms"].as_i64().unwrap_or(0)),
+            ]
+        })
+        .collect();
+    print_table(&["#", label, "Plays", "Time"], &rows, indent_level)
 }
 
-pub fn print_device_list_pretty(json: &Value, indent_level: usize) -> String {
-    json.as_array()
-        .and_then(|arr| {
-            Some(
-                arr.iter()
-                    .map(|item| print_device_pretty(item, indent_level))
-                    .collect::<Vec<String>>()
-                    .join("\n"),
-            )
+pub fn print_day_stats(json: &Value, indent_level: usize) -> String {
+    let rows: Vec<Vec<String>> = json
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|item| {
+            vec![
+                item["day"].as_str().unwrap_or("null").to_string(),
+                item["plays"].as_i64().unwrap_or(0).to_string(),
+                print_duration(item["played_ms"].as_i64().unwrap_or(0)),
+            ]
         })
-        .unwrap_or(format!("{}{}", "\t".repeat(indent_level), "None"))
+        .collect();
+    print_table(&["Day", "Plays", "Time"], &rows, indent_level)
+}
+
+pub fn print_skip_stats(json: &Value, indent_level: usize) -> String {
+    format!(
+        "{}{} skipped of {} plays ({:.1}%)",
+        "\t".repeat(indent_level),
+        json["skips"].as_i64().unwrap_or(0),
+        json["plays"].as_i64().unwrap_or(0),
+        json["rate"].as_f64().unwrap_or(0.0) * 100.0
+    )
 }
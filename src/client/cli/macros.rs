@@ -0,0 +1,106 @@
+use crate::client::cli::parser::{self, is_identifier};
+use crate::client::cli::registry;
+use log::warn;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+// A user defined command from the config file, e.g.
+//
+//   focus = (device "Desk Speaker") (volume 40)
+//   loud level = volume $level
+//
+// Using a macro replaces its name and one argument per parameter with the
+// body, where $param stands for the argument given.
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: String,
+}
+
+impl Macro {
+    // One line usage, e.g. "loud LEVEL".
+    pub fn usage(&self) -> String {
+        std::iter::once(self.name.clone())
+            .chain(self.params.iter().map(|p| p.to_uppercase()))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+static MACROS: OnceLock<Vec<Macro>> = OnceLock::new();
+
+pub fn config_path() -> String {
+    env::var("CONFIG_PATH").unwrap_or("config/spt.conf".to_string())
+}
+
+// Macros from the config file, read on first use. A missing file means no
+// macros, invalid lines are logged and skipped.
+pub fn all() -> &'static [Macro] {
+    MACROS.get_or_init(|| {
+        let path = config_path();
+        let Ok(source) = fs::read_to_string(&path) else {
+            return Vec::new();
+        };
+
+        let mut macros: Vec<Macro> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_definition(line) {
+                Ok(m) if macros.iter().any(|other| other.name == m.name) => {
+                    warn!("{}:{}: macro '{}' is defined twice", path, i + 1, m.name)
+                }
+                Ok(m) => macros.push(m),
+                Err(e) => warn!("{}:{}: {}", path, i + 1, e),
+            }
+        }
+        macros
+    })
+}
+
+pub fn lookup(name: &str) -> Option<&'static Macro> {
+    all().iter().find(|m| m.name == name)
+}
+
+fn is_name(text: &str) -> bool {
+    is_identifier(&text.replace('-', "_"))
+}
+
+// Parses "name param... = body".
+fn parse_definition(line: &str) -> Result<Macro, String> {
+    let Some((head, body)) = line.split_once('=') else {
+        return Err("expected name = body".to_string());
+    };
+
+    let mut words = head.split_whitespace();
+    let name = words.next().ok_or("missing macro name")?.to_string();
+    let params: Vec<String> = words.map(String::from).collect();
+    let body = body.trim().to_string();
+
+    if !is_name(&name) || name == "let" {
+        return Err(format!("invalid macro name '{}'", name));
+    }
+    if registry::lookup(&name).is_some() {
+        return Err(format!("macro '{}' would hide the built-in command", name));
+    }
+    if let Some(param) = params.iter().find(|p| !is_identifier(p)) {
+        return Err(format!("invalid parameter name '{}'", param));
+    }
+    for (i, param) in params.iter().enumerate() {
+        if params[..i].contains(param) {
+            return Err(format!("parameter '{}' is listed twice", param));
+        }
+    }
+    if body.is_empty() {
+        return Err(format!("macro '{}' has an empty body", name));
+    }
+    if let Err(e) = parser::tokenize(&body) {
+        return Err(format!("macro '{}': {}", name, e));
+    }
+
+    Ok(Macro { name, params, body })
+}
@@ -1,8 +1,25 @@
-use std::collections::HashMap;
+use crate::client::cli::macros::{self, Macro};
+use crate::client::cli::registry;
 use std::collections::HashSet;
 
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
+
+// Byte range of a token or command in the input, end is exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
 
 // Command struct:
 // name is the command word (e.g. "play", "search", etc.)
@@ -11,24 +28,75 @@ use std::str::Chars;
 pub struct CommandNode {
     pub name: String,
     pub args: Vec<Arg>,
+    pub span: Span,
 }
 
 // An argument can be either:
 // - Another Command (subcommand)
-// - Plain text (e.g. a string or a flag like "-h")
+// - Plain text (e.g. a string or a number like "-10")
+// - A flag (e.g. "-h" or "--limit")
+// - A variable reference (e.g. "$x")
 #[derive(Debug, Clone)]
 pub enum Arg {
     Command(CommandNode),
-    Text(String),
+    Text(String, Span),
+    Flag(String, Span),
+    Var(String, Span),
+}
+
+impl Arg {
+    pub fn span(&self) -> Span {
+        match self {
+            Arg::Command(cmd) => cmd.span,
+            Arg::Text(_, span) | Arg::Flag(_, span) | Arg::Var(_, span) => *span,
+        }
+    }
+}
+
+// A top level statement is either a command (possibly a pipeline, which is
+// parsed into nested commands), a variable binding: let x = command, or a
+// sequence of parenthesized commands run in order: (a) (b) (c)
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Command(CommandNode),
+    Let(String, CommandNode),
+    Sequence(Vec<CommandNode>),
 }
 
-// Parsing/Tokenizing Errors:
+// Parsing/Tokenizing Errors, each pointing at the offending part of the input:
 #[derive(Debug)]
 pub enum ParseError {
-    MismatchedParentheses,
-    UnexpectedEndOfInput,
-    NoCommandFound,
-    General(String),
+    MismatchedParentheses(Span),
+    UnexpectedEndOfInput(Span),
+    NoCommandFound(Span),
+    UnknownFlag(String, String, Span), // flag, command
+    General(String, Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::MismatchedParentheses(span)
+            | ParseError::UnexpectedEndOfInput(span)
+            | ParseError::NoCommandFound(span)
+            | ParseError::UnknownFlag(_, _, span)
+            | ParseError::General(_, span) => *span,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::MismatchedParentheses(_) => write!(f, "Mismatched parentheses"),
+            ParseError::UnexpectedEndOfInput(_) => write!(f, "Unexpected end of input"),
+            ParseError::NoCommandFound(_) => write!(f, "Unknown command"),
+            ParseError::UnknownFlag(flag, command, _) => {
+                write!(f, "Flag {} cannot be used with {}", flag, command)
+            }
+            ParseError::General(message, _) => write!(f, "{}", message),
+        }
+    }
 }
 
 // A token, with a flag indicating whether it was originally quoted or not.
@@ -36,138 +104,483 @@ pub enum ParseError {
 pub struct Token {
     text: String,
     is_quoted: bool,
+    span: Span,
 }
 
 pub fn verify_command(tokens: &[Token], command_list: &HashSet<String>) -> Result<(), ParseError> {
     if tokens.is_empty() {
-        return Err(ParseError::UnexpectedEndOfInput);
+        return Err(ParseError::UnexpectedEndOfInput(Span::default()));
     }
 
-    if tokens[0].is_quoted || !is_recognized_command(&tokens[0].text, command_list) {
-        return Err(ParseError::NoCommandFound);
-    }
+    // a binding must be followed by its name, "=" and a command
+    let first = if !tokens[0].is_quoted && tokens[0].text == "let" {
+        match tokens.get(3) {
+            Some(token) => token,
+            None => return Err(ParseError::UnexpectedEndOfInput(end_span(tokens))),
+        }
+    } else {
+        &tokens[0]
+    };
 
-    Ok(())
-}
+    // a leading separator is reported by parse
+    if is_separator(tokens, 0) {
+        return Ok(());
+    }
 
-pub fn verify_flags(
-    cmd: &CommandNode,
-    allowed_flags: &HashMap<String, Vec<String>>,
-) -> Result<(), ParseError> {
-    for arg in &cmd.args {
-        match arg {
-            Arg::Command(subcmd) => {
-                verify_flags(subcmd, allowed_flags)?;
-            }
-            Arg::Text(text) => {
-                if text.starts_with("-")
-                    && !allowed_flags
-                        .get(&cmd.name)
-                        .is_some_and(|v| v.contains(text))
-                {
-                    return Err(ParseError::General(format!(
-                        "Flag {} cannot be used as an argument for {}",
-                        text, cmd.name
-                    )));
-                }
-            }
-        }
+    if first.text != "(" && (first.is_quoted || !is_recognized_command(&first.text, command_list)) {
+        return Err(ParseError::NoCommandFound(first.span));
     }
 
     Ok(())
 }
 
-// Splits the input string into tokens, respecting quotes and parentheses.
-// Store an additional boolean is_quoted to indicate if the token came from a
-// quoted region.
+// Splits the input string into tokens, respecting quotes, escapes and
+// parentheses. A token is a word made of unquoted text, "double quoted"
+// strings (with backslash escapes) and 'single quoted' strings (taken
+// literally), so "a"'b'c is the single token abc. is_quoted is set if any
+// part of the word was quoted or escaped, such tokens are always plain text.
+// A --long=value flag is split into the flag and a value token.
 pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
     let mut result = Vec::new();
-    let mut chars = input.chars().peekable();
+    let mut chars = input.char_indices().peekable();
 
-    while let Some(&ch) = chars.peek() {
+    while let Some(&(start, ch)) = chars.peek() {
         match ch {
             c if c.is_whitespace() => {
                 // Skip whitespace
                 chars.next();
             }
-            '(' | ')' => {
-                // Parentheses become individual tokens
+            '(' | ')' | '|' | ';' => {
+                // Parentheses, pipes and separators become individual tokens
                 result.push(Token {
                     text: ch.to_string(),
                     is_quoted: false,
+                    span: Span {
+                        start,
+                        end: start + ch.len_utf8(),
+                    },
                 });
                 chars.next();
             }
-            '"' => {
-                // Read a quoted string
-                let quoted_text = read_quoted(&mut chars)?;
+            '&' if input[start..].starts_with("&&") => {
                 result.push(Token {
-                    text: quoted_text,
-                    is_quoted: true, // was inside quotes
-                });
-            }
-            _ => {
-                // Read an unquoted token
-                let unquoted_text = read_unquoted(&mut chars)?;
-                result.push(Token {
-                    text: unquoted_text,
+                    text: "&&".to_string(),
                     is_quoted: false,
+                    span: Span {
+                        start,
+                        end: start + 2,
+                    },
                 });
+                chars.next();
+                chars.next();
             }
+            // An unquoted # at the start of a word comments out the rest of the line
+            '#' => break,
+            _ => result.extend(read_word(&mut chars, input)?),
         }
     }
 
     Ok(result)
 }
 
-// Reads everything until the next unescaped quote.
-fn read_quoted(chars: &mut Peekable<Chars>) -> Result<String, ParseError> {
-    // Consume the leading quote
-    if chars.next() != Some('"') {
-        return Err(ParseError::General("Expected opening quote".to_string()));
+// Returns the byte offset of the next unread character, which is the end of
+// the token just read.
+fn end_of(chars: &mut Peekable<CharIndices>, input: &str) -> usize {
+    chars.peek().map(|&(i, _)| i).unwrap_or(input.len())
+}
+
+// Reads a word until unquoted whitespace, parentheses, pipes or separators
+// (a single & is part of the word, as in R&B). Returns two tokens for
+// --long=value flags, one otherwise.
+fn read_word(chars: &mut Peekable<CharIndices>, input: &str) -> Result<Vec<Token>, ParseError> {
+    let start = end_of(chars, input);
+    let mut text = String::new();
+    let mut is_quoted = false;
+    let mut flag_end: Option<(usize, usize)> = None; // (text length, byte offset) of '='
+
+    while let Some(&(i, ch)) = chars.peek() {
+        match ch {
+            c if c.is_whitespace() || c == '(' || c == ')' || c == '|' || c == ';' => break,
+            '&' if input[i..].starts_with("&&") => break,
+            '"' | '\'' => {
+                chars.next();
+                text.push_str(&read_quoted(chars, ch).ok_or(ParseError::General(
+                    format!(
+                        "Unclosed {} quote",
+                        if ch == '"' { "double" } else { "single" }
+                    ),
+                    Span {
+                        start: i,
+                        end: input.len(),
+                    },
+                ))?);
+                is_quoted = true;
+            }
+            '\\' => {
+                chars.next();
+                match chars.next() {
+                    Some((_, escaped)) => text.push(escaped),
+                    None => {
+                        return Err(ParseError::General(
+                            "Nothing to escape after backslash".to_string(),
+                            Span {
+                                start: i,
+                                end: input.len(),
+                            },
+                        ))
+                    }
+                }
+                is_quoted = true;
+            }
+            '=' if flag_end.is_none() && !is_quoted && text.starts_with("--") && text.len() > 2 => {
+                chars.next();
+                flag_end = Some((text.len(), i));
+                text.push(ch);
+            }
+            _ => {
+                chars.next();
+                text.push(ch);
+            }
+        }
+    }
+
+    let end = end_of(chars, input);
+    match flag_end {
+        Some((len, offset)) => Ok(vec![
+            Token {
+                text: text[..len].to_string(),
+                is_quoted: false,
+                span: Span { start, end: offset },
+            },
+            Token {
+                text: text[len + 1..].to_string(),
+                is_quoted: true, // never a command or flag
+                span: Span {
+                    start: offset + 1,
+                    end,
+                },
+            },
+        ]),
+        None => Ok(vec![Token {
+            text,
+            is_quoted,
+            span: Span { start, end },
+        }]),
     }
+}
 
+// Reads the rest of a quoted string after its opening quote, None if the
+// quote is never closed. Double quoted strings support \n, \t and escaping
+// any other character with a backslash, single quoted strings are literal.
+fn read_quoted(chars: &mut Peekable<CharIndices>, quote: char) -> Option<String> {
     let mut result = String::new();
-    while let Some(ch) = chars.next() {
+    while let Some((_, ch)) = chars.next() {
         match ch {
-            '"' => {
-                // closing quote
-                return Ok(format!("\"{}\"", result));
-            }
+            c if c == quote => return Some(result), // closing quote
+            '\\' if quote == '"' => match chars.next()?.1 {
+                'n' => result.push('\n'),
+                't' => result.push('\t'),
+                escaped => result.push(escaped),
+            },
             _ => result.push(ch),
         }
     }
 
     // If we reach EOF without a matching quote
-    Err(ParseError::General("Unclosed double quote".to_string()))
+    None
 }
 
-// Reads an unquoted token until whitespace, parentheses, or quotes.
-fn read_unquoted(chars: &mut Peekable<Chars>) -> Result<String, ParseError> {
-    let mut result = String::new();
-    while let Some(&ch) = chars.peek() {
-        if ch.is_whitespace() || ch == '(' || ch == ')' || ch == '"' {
+// Returns true for flag arguments like -h or --limit. Negative numbers such
+// as -10 are arguments, not flags.
+pub fn is_flag(text: &str) -> bool {
+    text.len() > 1 && text.starts_with('-') && text.parse::<f64>().is_err()
+}
+
+// How a statement is joined to the one before it: after ; it always runs,
+// after && only if the one before succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    Always,
+    IfSucceeded,
+}
+
+// Parses statements separated by ; or &&, e.g. device Kitchen; volume 30.
+// The first statement is joined with Connector::Always, a trailing ; is
+// allowed.
+pub fn parse(
+    tokens: &[Token],
+    command_list: &HashSet<String>,
+) -> Result<Vec<(Connector, Statement)>, ParseError> {
+    let expanded = expand_macros(tokens, 0)?;
+    let tokens = &expanded[..];
+
+    let mut statements = Vec::new();
+    let mut connector = Connector::Always;
+    let mut pos = 0;
+    loop {
+        let end = next_separator(tokens, pos);
+        if pos == end {
+            if end == tokens.len() && connector == Connector::Always && !statements.is_empty() {
+                break;
+            }
+            return Err(match tokens.get(end) {
+                Some(separator) => ParseError::General(
+                    format!("Expected a command before {}", separator.text),
+                    separator.span,
+                ),
+                None => ParseError::UnexpectedEndOfInput(end_span(tokens)),
+            });
+        }
+
+        statements.push((connector, parse_statement(&tokens[pos..end], command_list)?));
+        if end == tokens.len() {
             break;
         }
-        result.push(ch);
-        chars.next();
+        connector = match tokens[end].text.as_str() {
+            "&&" => Connector::IfSucceeded,
+            _ => Connector::Always,
+        };
+        pos = end + 1;
     }
-    Ok(result)
+
+    Ok(statements)
+}
+
+// Returns the position of the next ; or && outside parentheses, or the end.
+fn next_separator(tokens: &[Token], start: usize) -> usize {
+    let mut parens = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        if token.is_quoted {
+            continue;
+        }
+        match token.text.as_str() {
+            "(" => parens += 1,
+            ")" => parens -= 1,
+            _ if parens == 0 && is_separator(tokens, i) => return i,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+fn is_separator(tokens: &[Token], pos: usize) -> bool {
+    is_symbol(tokens, pos, ";") || is_symbol(tokens, pos, "&&")
 }
 
-pub fn parse(tokens: &[Token], command_list: &HashSet<String>) -> Result<CommandNode, ParseError> {
+fn parse_statement(
+    tokens: &[Token],
+    command_list: &HashSet<String>,
+) -> Result<Statement, ParseError> {
     let mut pos: usize = 0;
-    let tree = parse_rec(tokens, &mut pos, command_list);
+
+    let statement = if is_symbol(tokens, 0, "let") {
+        let name = match tokens.get(1) {
+            Some(token) if !token.is_quoted && is_identifier(&token.text) => token.text.clone(),
+            Some(token) => {
+                return Err(ParseError::General(
+                    format!("Invalid variable name {}", token.text),
+                    token.span,
+                ))
+            }
+            None => return Err(ParseError::UnexpectedEndOfInput(end_span(tokens))),
+        };
+        if !is_symbol(tokens, 2, "=") {
+            return Err(ParseError::General(
+                format!("Expected = after let {}", name),
+                tokens.get(2).map(|t| t.span).unwrap_or(end_span(tokens)),
+            ));
+        }
+        pos = 3;
+        Statement::Let(name, parse_pipeline(tokens, &mut pos, command_list)?)
+    } else {
+        let mut commands = vec![parse_pipeline(tokens, &mut pos, command_list)?];
+        while is_symbol(tokens, pos, "(") {
+            commands.push(parse_pipeline(tokens, &mut pos, command_list)?);
+        }
+        match commands.len() {
+            1 => Statement::Command(commands.remove(0)),
+            _ => Statement::Sequence(commands),
+        }
+    };
 
     // If there are leftover tokens, error
+    if is_symbol(tokens, pos, ")") {
+        return Err(ParseError::MismatchedParentheses(tokens[pos].span));
+    }
     if pos < tokens.len() {
-        return Err(ParseError::General(format!(
-            "Unconsumed tokens remain after parsing: {:?}",
-            &tokens[pos..]
-        )));
+        return Err(ParseError::General(
+            format!("Unexpected {}", tokens[pos].text),
+            tokens[pos].span.to(end_span(tokens)),
+        ));
+    }
+
+    Ok(statement)
+}
+
+// Returns an empty span just past the last token, used for errors about
+// missing input.
+fn end_span(tokens: &[Token]) -> Span {
+    let end = tokens.last().map(|t| t.span.end).unwrap_or(0);
+    Span { start: end, end }
+}
+
+// Returns true if the token at pos is the given unquoted text.
+fn is_symbol(tokens: &[Token], pos: usize, symbol: &str) -> bool {
+    tokens
+        .get(pos)
+        .is_some_and(|t| !t.is_quoted && t.text == symbol)
+}
+
+pub fn is_identifier(txt: &str) -> bool {
+    !txt.is_empty()
+        && txt.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !txt.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// Macros may use other macros, but not endlessly.
+const MAX_MACRO_DEPTH: usize = 16;
+
+// Replaces each use of a macro with its body. Only words in command position
+// are expanded, so arguments of commands like help are left alone, as is the
+// name in let name = .... Body tokens take the span of the macro name, while
+// arguments substituted for $param keep their own.
+fn expand_macros(tokens: &[Token], depth: usize) -> Result<Vec<Token>, ParseError> {
+    let mut result = Vec::new();
+    let mut parens = 0;
+    let mut literal_at: Option<usize> = None; // nesting level of a literal_args command
+    let mut statement_start = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if !token.is_quoted {
+            match token.text.as_str() {
+                "(" => parens += 1,
+                ")" | "|" | ";" | "&&" => {
+                    if literal_at == Some(parens) {
+                        literal_at = None;
+                    }
+                    if token.text == ")" {
+                        parens = parens.saturating_sub(1);
+                    }
+                    if is_separator(tokens, i) {
+                        statement_start = i + 1;
+                    }
+                }
+                _ if literal_at.is_some()
+                    || (i == statement_start + 1 && is_symbol(tokens, statement_start, "let")) => {}
+                text => {
+                    if let Some(m) = macros::lookup(text) {
+                        if depth >= MAX_MACRO_DEPTH {
+                            return Err(ParseError::General(
+                                format!("Macro '{}' expands too deeply, is it recursive?", text),
+                                token.span,
+                            ));
+                        }
+                        let (body, consumed) = expand_macro(m, tokens, i)?;
+                        result.extend(expand_macros(&body, depth + 1)?);
+                        i += consumed;
+                        continue;
+                    }
+                    if registry::lookup(text).is_some_and(|spec| spec.literal_args) {
+                        literal_at = Some(parens);
+                    }
+                }
+            }
+        }
+        result.push(token.clone());
+        i += 1;
+    }
+
+    Ok(result)
+}
+
+// Returns the body of the macro used at tokens[start] with its parameters
+// substituted, and the number of tokens used: the name and one argument per
+// parameter, where a parenthesized command counts as one argument.
+fn expand_macro(
+    m: &Macro,
+    tokens: &[Token],
+    start: usize,
+) -> Result<(Vec<Token>, usize), ParseError> {
+    let name_span = tokens[start].span;
+    let mut pos = start + 1;
+    let mut args: Vec<&[Token]> = Vec::new();
+
+    for _ in &m.params {
+        let arg_start = pos;
+        match tokens.get(pos) {
+            Some(t) if !t.is_quoted && (t.text == ")" || t.text == "|") => break,
+            Some(t) if !t.is_quoted && t.text == "(" => {
+                let mut depth = 0;
+                while let Some(t) = tokens.get(pos) {
+                    pos += 1;
+                    if !t.is_quoted && t.text == "(" {
+                        depth += 1;
+                    } else if !t.is_quoted && t.text == ")" {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+            Some(_) => pos += 1,
+            None => break,
+        }
+        args.push(&tokens[arg_start..pos]);
+    }
+
+    if args.len() < m.params.len() {
+        return Err(ParseError::General(
+            format!(
+                "Macro {} expects {} argument{}",
+                m.usage(),
+                m.params.len(),
+                if m.params.len() == 1 { "" } else { "s" }
+            ),
+            name_span.to(tokens[pos - 1].span),
+        ));
+    }
+
+    // the body was checked when the config was loaded
+    let body = tokenize(&m.body).unwrap_or_default();
+    let mut result = Vec::new();
+    for token in body {
+        let param = token
+            .text
+            .strip_prefix('$')
+            .and_then(|name| m.params.iter().position(|p| p == name));
+        match param {
+            Some(index) if !token.is_quoted => result.extend_from_slice(args[index]),
+            _ => result.push(Token {
+                span: name_span,
+                ..token
+            }),
+        }
+    }
+
+    Ok((result, pos - start))
+}
+
+// Parses a pipeline: command | command | ...
+// Each stage receives the result of the previous stage as its last argument,
+// so "a | b x" is parsed the same as "b x (a)".
+fn parse_pipeline(
+    tokens: &[Token],
+    pos: &mut usize,
+    command_list: &HashSet<String>,
+) -> Result<CommandNode, ParseError> {
+    let mut cmd = parse_rec(tokens, pos, command_list)?;
+
+    while is_symbol(tokens, *pos, "|") {
+        *pos += 1; // consume '|'
+        let mut next = parse_rec(tokens, pos, command_list)?;
+        next.args.push(Arg::Command(cmd));
+        cmd = next;
     }
 
-    return tree;
+    Ok(cmd)
 }
 
 // Parses a command from the token stream.
@@ -180,36 +593,29 @@ fn parse_rec(
     command_list: &HashSet<String>,
 ) -> Result<CommandNode, ParseError> {
     if *pos >= tokens.len() {
-        return Err(ParseError::UnexpectedEndOfInput);
+        return Err(ParseError::UnexpectedEndOfInput(end_span(tokens)));
     }
 
     // If the next token is "(" then we parse one command inside parentheses
     if tokens[*pos].text == "(" && !tokens[*pos].is_quoted {
-        *pos += 1; // consume '('
-                   // parse a command inside the parentheses
-        let cmd = parse_rec(tokens, pos, command_list)?;
-
-        // The next token must be ")"
-        if *pos >= tokens.len() {
-            return Err(ParseError::MismatchedParentheses);
-        }
-        if tokens[*pos].text != ")" || tokens[*pos].is_quoted {
-            return Err(ParseError::MismatchedParentheses);
-        }
-        *pos += 1; // consume ')'
-        return Ok(cmd);
+        return parse_parenthesized(tokens, pos, command_list);
     }
 
     // Otherwise, it should be a recognized command (unquoted) to be valid:
     if tokens[*pos].is_quoted || !is_recognized_command(&tokens[*pos].text, command_list) {
-        return Err(ParseError::NoCommandFound);
+        return Err(ParseError::NoCommandFound(tokens[*pos].span));
     }
     let name = tokens[*pos].text.clone();
+    let span = tokens[*pos].span;
     *pos += 1; // consume the command name
 
+    // e.g. help queue, where queue is the topic rather than a command to run
+    let literal = registry::lookup(&name).is_some_and(|spec| spec.literal_args);
+
     let mut command = CommandNode {
         name,
         args: Vec::new(),
+        span,
     };
 
     // Gather arguments while we have tokens left and until we reach a closing parenthesis
@@ -218,44 +624,352 @@ fn parse_rec(
     // - A recognized unquoted command
     // - A text token
     while *pos < tokens.len() {
-        // If the next token is ")" that means we close the current command.
-        if tokens[*pos].text == ")" && !tokens[*pos].is_quoted {
+        // If the next token is ")" or "|" that means we close the current command.
+        if is_symbol(tokens, *pos, ")") || is_symbol(tokens, *pos, "|") {
             break;
         }
 
+        // Top level separators were split off by parse, so this one is nested
+        if is_separator(tokens, *pos) {
+            return Err(ParseError::General(
+                format!("{} can't be used inside parentheses", tokens[*pos].text),
+                tokens[*pos].span,
+            ));
+        }
+
         // If the next token is "(" => parse subcommand inside parentheses as a single Arg
         if tokens[*pos].text == "(" && !tokens[*pos].is_quoted {
-            // parse a nested command
-            *pos += 1; // consume '('
-            let subcmd = parse_rec(tokens, pos, command_list)?;
-            // must see ")"
-            if *pos >= tokens.len() {
-                return Err(ParseError::MismatchedParentheses);
-            }
-            if tokens[*pos].text != ")" || tokens[*pos].is_quoted {
-                return Err(ParseError::MismatchedParentheses);
-            }
-            *pos += 1; // consume ')'
+            let subcmd = parse_parenthesized(tokens, pos, command_list)?;
+            command.span = command.span.to(subcmd.span);
             command.args.push(Arg::Command(subcmd));
             continue;
         }
 
-        // If the next token is an unquoted recognized command => parse as subcommand
-        if !tokens[*pos].is_quoted && is_recognized_command(&tokens[*pos].text, command_list) {
+        // If the next token is an unquoted recognized command => parse as subcommand,
+        // unless it's the value of a flag, as in --from liked
+        if !literal
+            && !tokens[*pos].is_quoted
+            && !expects_flag_value(&command)
+            && is_recognized_command(&tokens[*pos].text, command_list)
+        {
             let subcmd = parse_rec(tokens, pos, command_list)?;
+            command.span = command.span.to(subcmd.span);
             command.args.push(Arg::Command(subcmd));
             continue;
         }
 
+        // Unquoted $name tokens are variable references
+        if !tokens[*pos].is_quoted && tokens[*pos].text.starts_with('$') {
+            command.args.push(Arg::Var(
+                tokens[*pos].text[1..].to_string(),
+                tokens[*pos].span,
+            ));
+            command.span = command.span.to(tokens[*pos].span);
+            *pos += 1;
+            continue;
+        }
+
+        // Unquoted tokens starting with - (except numbers) are flags
+        if !tokens[*pos].is_quoted && is_flag(&tokens[*pos].text) {
+            command
+                .args
+                .push(Arg::Flag(tokens[*pos].text.clone(), tokens[*pos].span));
+            command.span = command.span.to(tokens[*pos].span);
+            *pos += 1;
+            continue;
+        }
+
         // Otherwise, treat it as plain text
-        command.args.push(Arg::Text(tokens[*pos].text.clone()));
+        command
+            .args
+            .push(Arg::Text(tokens[*pos].text.clone(), tokens[*pos].span));
+        command.span = command.span.to(tokens[*pos].span);
         *pos += 1;
     }
 
     Ok(command)
 }
 
-// Returns true if the given token text is in the recognized command list.
+// Whether the last argument is a flag of the command that takes a value.
+fn expects_flag_value(command: &CommandNode) -> bool {
+    let Some(Arg::Flag(flag, _)) = command.args.last() else {
+        return false;
+    };
+    registry::lookup(&command.name).is_some_and(|spec| {
+        spec.resolve(&command.args)
+            .0
+            .flag(flag)
+            .is_some_and(|f| f.value.is_some())
+    })
+}
+
+// Parses a pipeline inside parentheses, the resulting span includes the
+// parentheses.
+fn parse_parenthesized(
+    tokens: &[Token],
+    pos: &mut usize,
+    command_list: &HashSet<String>,
+) -> Result<CommandNode, ParseError> {
+    let open = tokens[*pos].span;
+    *pos += 1; // consume '('
+    let mut cmd = parse_pipeline(tokens, pos, command_list)?;
+
+    // The next token must be ")"
+    if !is_symbol(tokens, *pos, ")") {
+        return Err(ParseError::MismatchedParentheses(open));
+    }
+    cmd.span = open.to(tokens[*pos].span);
+    *pos += 1; // consume ')'
+    Ok(cmd)
+}
+
+// Returns true if the given token text is in the recognized command list or
+// names a macro.
 fn is_recognized_command(txt: &str, command_list: &HashSet<String>) -> bool {
-    command_list.contains(txt)
+    command_list.contains(txt) || macros::lookup(txt).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // Text, quotedness and span of each token.
+    fn tokens(input: &str) -> Vec<(String, bool, Span)> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.text, t.is_quoted, t.span))
+            .collect()
+    }
+
+    fn texts(input: &str) -> Vec<String> {
+        tokens(input).into_iter().map(|(text, _, _)| text).collect()
+    }
+
+    fn parse_str(input: &str) -> Result<Vec<(Connector, Statement)>, ParseError> {
+        let command_list = registry::command_names();
+        let tokens = tokenize(input)?;
+        verify_command(&tokens, &command_list)?;
+        parse(&tokens, &command_list)
+    }
+
+    fn command(input: &str) -> CommandNode {
+        match parse_str(input).unwrap().remove(0).1 {
+            Statement::Command(cmd) => cmd,
+            other => panic!("expected a command, got {:?}", other),
+        }
+    }
+
+    fn parse_error(input: &str) -> (String, Span) {
+        let err = parse_str(input).unwrap_err();
+        (err.to_string(), err.span())
+    }
+
+    #[test]
+    fn tokenizes_words_with_their_spans() {
+        assert_eq!(
+            tokens("play  \"a b\"(x)"),
+            vec![
+                ("play".to_string(), false, span(0, 4)),
+                ("a b".to_string(), true, span(6, 11)),
+                ("(".to_string(), false, span(11, 12)),
+                ("x".to_string(), false, span(12, 13)),
+                (")".to_string(), false, span(13, 14)),
+            ]
+        );
+        // spans are byte offsets
+        assert_eq!(tokens("é x")[1].2, span(3, 4));
+    }
+
+    #[test]
+    fn spans_commands_and_their_arguments() {
+        let cmd = command("devices -h");
+        assert_eq!(cmd.span, span(0, 10));
+        assert!(matches!(&cmd.args[..], [Arg::Flag(f, s)] if f == "-h" && *s == span(8, 10)));
+
+        let cmd = command("queue add (search \"x\")");
+        // subcommands are resolved later, parenthesized commands span their parentheses
+        match &cmd.args[..] {
+            [Arg::Text(sub, s), Arg::Command(search)] => {
+                assert_eq!((sub.as_str(), *s), ("add", span(6, 9)));
+                assert_eq!(search.span, span(10, 22));
+            }
+            args => panic!("unexpected arguments {:?}", args),
+        }
+    }
+
+    #[test]
+    fn reports_errors_where_they_are() {
+        assert_eq!(
+            parse_error("search \"abc"),
+            ("Unclosed double quote".to_string(), span(7, 11))
+        );
+        assert_eq!(
+            parse_error("bogus 1"),
+            ("Unknown command".to_string(), span(0, 5))
+        );
+        assert_eq!(
+            parse_error("queue add (search x"),
+            ("Mismatched parentheses".to_string(), span(10, 11))
+        );
+        assert_eq!(
+            parse_error("play )"),
+            ("Mismatched parentheses".to_string(), span(5, 6))
+        );
+    }
+
+    #[test]
+    fn passes_each_pipeline_stage_to_the_next() {
+        // each stage gets the previous one as its last argument
+        let cmd = command("recent 5 | queue --shuffle");
+        assert_eq!(cmd.name, "queue");
+        assert_eq!(cmd.span, span(11, 26));
+        match &cmd.args[..] {
+            [Arg::Flag(flag, _), Arg::Command(inner)] => {
+                assert_eq!(flag, "--shuffle");
+                assert_eq!(inner.name, "recent");
+                assert_eq!(inner.span, span(0, 8));
+            }
+            args => panic!("unexpected arguments {:?}", args),
+        }
+    }
+
+    #[test]
+    fn binds_and_references_variables() {
+        match parse_str("let x = (recent 5)").unwrap().remove(0).1 {
+            Statement::Let(name, cmd) => {
+                assert_eq!((name.as_str(), cmd.name.as_str()), ("x", "recent"))
+            }
+            other => panic!("expected a binding, got {:?}", other),
+        }
+        let cmd = command("queue add $x \"$y\"");
+        match &cmd.args[..] {
+            [Arg::Text(_, _), Arg::Var(var, s), Arg::Text(text, _)] => {
+                assert_eq!((var.as_str(), *s), ("x", span(10, 12)));
+                // quoted, it is only text
+                assert_eq!(text, "$y");
+            }
+            args => panic!("unexpected arguments {:?}", args),
+        }
+
+        assert_eq!(
+            parse_error("let 1x = now"),
+            ("Invalid variable name 1x".to_string(), span(4, 6))
+        );
+        assert_eq!(
+            parse_error("let x now play"),
+            ("Expected = after let x".to_string(), span(6, 9))
+        );
+    }
+
+    #[test]
+    fn unescapes_double_quotes_and_backslashes() {
+        assert_eq!(texts(r#""say \"hi\"\n\tnow""#), vec!["say \"hi\"\n\tnow"]);
+        assert_eq!(
+            tokens(r"Desk\ Speaker"),
+            vec![("Desk Speaker".to_string(), true, span(0, 13))]
+        );
+        assert_eq!(texts(r"\;"), vec![";"]);
+    }
+
+    #[test]
+    fn takes_single_quotes_literally() {
+        assert_eq!(texts(r"'a\nb'"), vec![r"a\nb"]);
+        assert_eq!(texts(r#"'say "hi"'"#), vec![r#"say "hi""#]);
+        // adjacent parts make up one word
+        assert_eq!(
+            tokens(r#""a"'b'c"#),
+            vec![("abc".to_string(), true, span(0, 7))]
+        );
+    }
+
+    #[test]
+    fn splits_long_flags_from_their_values() {
+        assert_eq!(
+            tokens("queue --limit=5"),
+            vec![
+                ("queue".to_string(), false, span(0, 5)),
+                ("--limit".to_string(), false, span(6, 13)),
+                ("5".to_string(), true, span(14, 15)),
+            ]
+        );
+        assert_eq!(texts("x --name=a=b"), vec!["x", "--name", "a=b"]);
+        // only unquoted long flags are split
+        assert_eq!(texts(r#"x "--a=b" -a=b"#), vec!["x", "--a=b", "-a=b"]);
+    }
+
+    #[test]
+    fn tells_negative_numbers_from_flags() {
+        assert!(is_flag("-h"));
+        assert!(is_flag("--limit"));
+        assert!(!is_flag("-10"));
+        assert!(!is_flag("-0.5"));
+        assert!(!is_flag("-"));
+
+        let cmd = command("volume -10");
+        assert!(matches!(&cmd.args[..], [Arg::Text(n, _)] if n == "-10"));
+        let cmd = command("devices -h");
+        assert!(matches!(&cmd.args[..], [Arg::Flag(f, _)] if f == "-h"));
+    }
+
+    #[test]
+    fn reports_unfinished_quotes_and_escapes() {
+        assert_eq!(
+            parse_error("search 'abc"),
+            ("Unclosed single quote".to_string(), span(7, 11))
+        );
+        assert_eq!(
+            parse_error("search abc\\"),
+            (
+                "Nothing to escape after backslash".to_string(),
+                span(10, 11)
+            )
+        );
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(texts("play # later, maybe"), vec!["play"]);
+        assert_eq!(texts("# nothing"), Vec::<String>::new());
+        // only a # starting a word, and not when quoted
+        assert_eq!(
+            texts(r##"search R#B "#1" \#2"##),
+            vec!["search", "R#B", "#1", "#2"]
+        );
+    }
+
+    #[test]
+    fn splits_statements() {
+        assert_eq!(
+            texts("pause;play&&next | x R&B"),
+            vec!["pause", ";", "play", "&&", "next", "|", "x", "R&B"]
+        );
+
+        let statements = parse_str("pause; play && next 2;").unwrap();
+        let connectors: Vec<Connector> = statements.iter().map(|(c, _)| *c).collect();
+        assert_eq!(
+            connectors,
+            vec![Connector::Always, Connector::Always, Connector::IfSucceeded]
+        );
+
+        assert_eq!(
+            parse_error("; play"),
+            ("Expected a command before ;".to_string(), span(0, 1))
+        );
+        assert_eq!(
+            parse_error("play &&"),
+            ("Unexpected end of input".to_string(), span(7, 7))
+        );
+        assert_eq!(
+            parse_error("queue (pause; play)"),
+            (
+                "; can't be used inside parentheses".to_string(),
+                span(12, 13)
+            )
+        );
+    }
 }
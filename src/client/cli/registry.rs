@@ -0,0 +1,165 @@
+use crate::client::cli::commands::COMMANDS;
+use crate::client::cli::completion_cache::CompletionKind;
+use crate::client::cli::eval::{EvalContext, EvalError, Invocation};
+use crate::client::cli::parser::{Arg, CommandNode, ParseError};
+use crate::client::cli::value::{ArgType, Value};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<Value, EvalError>> + 'a>>;
+
+// Runs a command once its arguments have been bound and coerced.
+pub type Handler = for<'a, 'b> fn(&'a mut EvalContext<'b>, Invocation) -> HandlerFuture<'a>;
+
+// How many arguments a positional parameter takes. Many collects all
+// remaining arguments (possibly none) into a single list, so it must be the
+// last parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    One,
+    Optional,
+    Many,
+}
+
+#[derive(Debug)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub ty: ArgType,
+    pub arity: Arity,
+    pub default: Option<&'static str>, // used when an optional argument is left out
+    pub description: &'static str,
+    pub complete: Option<CompletionKind>, // values offered by shell completion
+}
+
+#[derive(Debug)]
+pub struct FlagSpec {
+    pub name: &'static str,
+    pub value: Option<&'static str>, // name of the value, for flags that take one
+    pub description: &'static str,
+}
+
+// Declaration of a command: everything needed to parse, validate, document
+// and run it. Subcommands are selected by the first argument, e.g. queue add.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub summary: &'static str,
+    pub params: &'static [ParamSpec],
+    pub flags: &'static [FlagSpec],
+    pub subcommands: &'static [CommandSpec],
+    pub literal_args: bool, // arguments are never parsed as nested commands
+    pub handler: Handler,
+}
+
+impl CommandSpec {
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&CommandSpec> {
+        self.subcommands.iter().find(|sub| sub.matches(name))
+    }
+
+    pub fn flag(&self, name: &str) -> Option<&FlagSpec> {
+        self.flags.iter().find(|flag| flag.name == name)
+    }
+
+    // Returns the spec that handles the given arguments and the arguments
+    // left after the subcommand name, if any.
+    pub fn resolve<'c>(&self, args: &'c [Arg]) -> (&CommandSpec, &'c [Arg]) {
+        match args.first() {
+            Some(Arg::Text(text, _)) => match self.subcommand(text) {
+                Some(sub) => (sub, &args[1..]),
+                None => (self, args),
+            },
+            _ => (self, args),
+        }
+    }
+
+    // One line usage, e.g. "queue move FROM TO". prefix is the parent command
+    // for subcommands.
+    pub fn usage(&self, prefix: Option<&str>) -> String {
+        let mut parts: Vec<String> = prefix.into_iter().map(String::from).collect();
+        parts.push(self.name.to_string());
+        for flag in self.flags {
+            parts.push(match flag.value {
+                Some(value) => format!("[{} {}]", flag.name, value),
+                None => format!("[{}]", flag.name),
+            });
+        }
+        for param in self.params {
+            let name = param.name.to_uppercase();
+            parts.push(match param.arity {
+                Arity::One => name,
+                Arity::Optional => format!("[{}]", name),
+                Arity::Many => format!("[{}...]", name),
+            });
+        }
+        parts.join(" ")
+    }
+}
+
+// Finds a command by name or alias.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.matches(name))
+}
+
+// Finds a command or subcommand by its path, e.g. "queue add".
+pub fn find(path: &str) -> Option<&'static CommandSpec> {
+    let mut words = path.split_whitespace();
+    let mut spec = lookup(words.next()?)?;
+    for word in words {
+        spec = spec.subcommand(word)?;
+    }
+    Some(spec)
+}
+
+// Names and aliases of all commands, as recognized by the parser.
+pub fn command_names() -> HashSet<String> {
+    COMMANDS
+        .iter()
+        .flat_map(|spec| std::iter::once(spec.name).chain(spec.aliases.iter().copied()))
+        .map(String::from)
+        .collect()
+}
+
+// Checks that every flag in the command tree is declared by its command and
+// that flags taking a value are followed by one.
+pub fn validate(cmd: &CommandNode) -> Result<(), ParseError> {
+    let Some(spec) = lookup(&cmd.name) else {
+        return Err(ParseError::NoCommandFound(cmd.span));
+    };
+    let (sub, args) = spec.resolve(&cmd.args);
+    let path = if std::ptr::eq(sub, spec) {
+        spec.name.to_string()
+    } else {
+        format!("{} {}", spec.name, sub.name)
+    };
+    let spec = sub;
+
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg {
+            Arg::Command(subcmd) => validate(subcmd)?,
+            Arg::Text(..) | Arg::Var(..) => {}
+            Arg::Flag(flag, span) => match spec.flag(flag) {
+                None => return Err(ParseError::UnknownFlag(flag.clone(), path, *span)),
+                Some(FlagSpec {
+                    value: Some(value), ..
+                }) => match args.next() {
+                    Some(Arg::Text(..)) => {}
+                    _ => {
+                        return Err(ParseError::General(
+                            format!("Flag {} expects a value ({})", flag, value),
+                            *span,
+                        ))
+                    }
+                },
+                Some(_) => {}
+            },
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::util::models::{Episode, PlayableItem, Track};
+use crate::util::uri_helper::UriType;
+use serde::Serialize;
+
+// Track or episode details carried between commands.
+// For episodes, album is the show name and artists holds the publisher.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackRecord {
+    pub uri: String,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<i64>,
+}
+
+impl From<&Track> for TrackRecord {
+    fn from(track: &Track) -> Self {
+        TrackRecord {
+            uri: track.uri.clone(),
+            name: track.name.clone(),
+            artists: track.artists.iter().map(|a| a.name.clone()).collect(),
+            album: track.album.as_ref().map(|a| a.name.clone()),
+            duration_ms: Some(track.duration_ms as i64),
+        }
+    }
+}
+
+impl From<&Episode> for TrackRecord {
+    fn from(episode: &Episode) -> Self {
+        let show = episode.show.as_ref();
+        TrackRecord {
+            uri: episode.uri.clone(),
+            name: episode.name.clone(),
+            artists: show.and_then(|s| s.publisher.clone()).into_iter().collect(),
+            album: show.map(|s| s.name.clone()),
+            duration_ms: Some(episode.duration_ms as i64),
+        }
+    }
+}
+
+impl From<&PlayableItem> for TrackRecord {
+    fn from(item: &PlayableItem) -> Self {
+        match item {
+            PlayableItem::Track(track) => track.into(),
+            PlayableItem::Episode(episode) => episode.into(),
+        }
+    }
+}
+
+// Result of evaluating a command or argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Uris(Vec<String>),
+    Tracks(Vec<TrackRecord>),
+    Number(i64),
+    Str(String),
+    Bool(bool),
+    Error(String),
+}
+
+// Types a command can declare for its positional arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Uris(Option<UriType>), // what a bare ID refers to, bare IDs are rejected if None
+    Tracks,
+    Number,
+    Text,
+    Bool,
+}
+
+impl ArgType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArgType::Uris(_) => "URI list",
+            ArgType::Tracks => "track list",
+            ArgType::Number => "number",
+            ArgType::Text => "string",
+            ArgType::Bool => "bool",
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Uris(_) => ArgType::Uris(None).name(),
+            Value::Tracks(_) => ArgType::Tracks.name(),
+            Value::Number(_) => ArgType::Number.name(),
+            Value::Str(_) => ArgType::Text.name(),
+            Value::Bool(_) => ArgType::Bool.name(),
+            Value::Error(_) => "error",
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Value::Error(_))
+    }
+
+    // Renders the value for printing, lists are printed one item per line.
+    pub fn render(&self) -> String {
+        match self {
+            Value::Uris(uris) => uris.join("\n"),
+            Value::Tracks(tracks) => tracks
+                .iter()
+                .map(|t| t.uri.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            Value::Number(n) => n.to_string(),
+            Value::Str(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Error(e) => format!("Error: {}", e),
+        }
+    }
+}
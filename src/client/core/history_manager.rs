@@ -0,0 +1,137 @@
+use crate::client::cli::formatter;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::util::errors::ApiError;
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use std::collections::HashMap;
+
+// One of the lists stats can show on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsSection {
+    Tracks,
+    Artists,
+    Albums,
+    Days,
+    Skips,
+}
+
+impl StatsSection {
+    pub fn parse(name: &str) -> Option<StatsSection> {
+        match name {
+            "tracks" => Some(StatsSection::Tracks),
+            "artists" => Some(StatsSection::Artists),
+            "albums" => Some(StatsSection::Albums),
+            "days" => Some(StatsSection::Days),
+            "skips" => Some(StatsSection::Skips),
+            _ => None,
+        }
+    }
+
+    // The key of the section in the stats response.
+    fn key(&self) -> &'static str {
+        match self {
+            StatsSection::Tracks => "top_tracks",
+            StatsSection::Artists => "top_artists",
+            StatsSection::Albums => "top_albums",
+            StatsSection::Days => "per_day",
+            StatsSection::Skips => "skips",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryManager<'a> {
+    api_manager: &'a ApiProxy,
+}
+
+// Converts a YYYY-MM-DD date into a unix time in ms at local midnight. If
+// end_of_day is set, returns the start of the following day instead so the
+// date can be used as an exclusive upper bound.
+pub fn parse_date_bound(date: &str, end_of_day: bool) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let date = if end_of_day { date.succ_opt()? } else { date };
+    let start = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()?;
+    Some(start.timestamp_millis())
+}
+
+// Converts a point in time into a unix time in ms. Accepts a YYYY-MM-DD date,
+// taken as local midnight, an RFC 3339 timestamp or a unix time in ms.
+pub fn parse_time(time: &str) -> Option<i64> {
+    if let Ok(ms) = time.parse::<i64>() {
+        return Some(ms);
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(time) {
+        return Some(datetime.timestamp_millis());
+    }
+    parse_date_bound(time, false)
+}
+
+impl<'a> HistoryManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        HistoryManager { api_manager }
+    }
+
+    pub async fn stats(
+        &self,
+        section: Option<StatsSection>,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+        as_json: bool,
+    ) -> Result<String, ApiError> {
+        let mut params = HashMap::from([("limit".to_string(), limit.to_string())]);
+        if let Some(from) = from {
+            params.insert("from".to_string(), from.to_string());
+        }
+        if let Some(to) = to {
+            params.insert("to".to_string(), to.to_string());
+        }
+
+        let (_, json) = self
+            .api_manager
+            .get("api/history/stats", Some(params))
+            .await?;
+        let json = match section {
+            Some(section) => json[section.key()].clone(),
+            None => json,
+        };
+
+        if as_json {
+            return Ok(serde_json::to_string_pretty(&json).unwrap_or_default());
+        }
+
+        let output = match section {
+            None => [
+                format!(
+                    "Top Tracks:\n{}",
+                    formatter::print_track_stats(&json["top_tracks"], 1)
+                ),
+                format!(
+                    "Top Artists:\n{}",
+                    formatter::print_named_stats(&json["top_artists"], "Artist", 1)
+                ),
+                format!(
+                    "Top Albums:\n{}",
+                    formatter::print_named_stats(&json["top_albums"], "Album", 1)
+                ),
+                format!(
+                    "Listening Time:\n{}",
+                    formatter::print_day_stats(&json["per_day"], 1)
+                ),
+                format!(
+                    "Skip Rate:\n{}",
+                    formatter::print_skip_stats(&json["skips"], 1)
+                ),
+            ]
+            .join("\n\n"),
+            Some(StatsSection::Tracks) => formatter::print_track_stats(&json, 0),
+            Some(StatsSection::Artists) => formatter::print_named_stats(&json, "Artist", 0),
+            Some(StatsSection::Albums) => formatter::print_named_stats(&json, "Album", 0),
+            Some(StatsSection::Days) => formatter::print_day_stats(&json, 0),
+            Some(StatsSection::Skips) => formatter::print_skip_stats(&json, 0),
+        };
+
+        Ok(output)
+    }
+}
@@ -0,0 +1,49 @@
+use crate::client::local_api_proxy::ApiProxy;
+use crate::util::uri_helper::{self, SpotifyId, UriError, UriType};
+use std::collections::HashMap;
+
+// Parses user supplied URIs, URLs and bare IDs into Spotify IDs. Short share
+// links are resolved through the server first. Bare IDs are interpreted as
+// default_type, if given.
+pub async fn resolve_ids(
+    api_manager: &ApiProxy,
+    inputs: &[String],
+    default_type: Option<UriType>,
+) -> Result<Vec<SpotifyId>, UriError> {
+    let mut ids = Vec::with_capacity(inputs.len());
+
+    for input in inputs {
+        if !uri_helper::is_short_link(input) {
+            ids.push(SpotifyId::parse(input, default_type)?);
+            continue;
+        }
+
+        let link = if input.starts_with("http") {
+            input.clone()
+        } else {
+            format!("https://{}", input)
+        };
+        let params = HashMap::from([("url".to_string(), link)]);
+        let resolved = match api_manager.get("api/resolve-link", Some(params)).await {
+            Ok((_, json)) => json["url"].as_str().map(String::from),
+            Err(_) => None,
+        };
+
+        match resolved {
+            Some(url) => ids.push(SpotifyId::parse(&url, default_type)?),
+            None => return Err(UriError::UnresolvedLink(input.clone())),
+        }
+    }
+
+    Ok(ids)
+}
+
+// Same as resolve_ids, returning canonical URI strings.
+pub async fn resolve_uris(
+    api_manager: &ApiProxy,
+    inputs: &[String],
+    default_type: Option<UriType>,
+) -> Result<Vec<String>, UriError> {
+    let ids = resolve_ids(api_manager, inputs, default_type).await?;
+    Ok(ids.iter().map(|id| id.uri()).collect())
+}
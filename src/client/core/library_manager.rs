@@ -0,0 +1,203 @@
+use crate::client::cli::value::TrackRecord;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{Album, Artist, SavedAlbum, SavedShow, SavedTrack, Show};
+use crate::util::uri_helper::{self, UriType};
+use std::collections::HashMap;
+
+// The most IDs the library endpoints take per request.
+const IDS_PER_REQUEST: usize = 50;
+
+// The user's library: saved tracks, albums and shows, and followed artists
+// and playlists.
+#[derive(Debug)]
+pub struct LibraryManager<'a> {
+    api_manager: &'a ApiProxy,
+}
+
+// Endpoint holding saved or followed items of a type, and the type parameter
+// it needs. Playlists are followed one at a time, see playlist_endpoint.
+fn library_endpoint(uri_type: UriType) -> Option<(&'static str, Option<&'static str>)> {
+    match uri_type {
+        UriType::Track => Some(("api/spt-fwd/me/tracks", None)),
+        UriType::Album => Some(("api/spt-fwd/me/albums", None)),
+        UriType::Show => Some(("api/spt-fwd/me/shows", None)),
+        UriType::Artist => Some(("api/spt-fwd/me/following", Some("artist"))),
+        _ => None,
+    }
+}
+
+fn playlist_endpoint(id: &str) -> String {
+    format!("api/spt-fwd/playlists/{}/followers", id)
+}
+
+fn type_name(uri_type: UriType) -> &'static str {
+    match uri_type {
+        UriType::Track => "track",
+        UriType::Album => "album",
+        UriType::Show => "show",
+        UriType::Artist => "artist",
+        UriType::Playlist => "playlist",
+        UriType::Episode => "episode",
+        _ => "item",
+    }
+}
+
+// Describes URIs by type, e.g. "2 tracks and 1 album".
+pub fn describe(uris: &[String]) -> String {
+    let mut counts: Vec<(UriType, usize)> = Vec::new();
+    for uri in uris {
+        let uri_type = uri_helper::get_uri_type(uri);
+        match counts.iter_mut().find(|(t, _)| *t == uri_type) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((uri_type, 1)),
+        }
+    }
+    let parts: Vec<String> = counts
+        .into_iter()
+        .map(|(t, n)| format!("{} {}{}", n, type_name(t), if n != 1 { "s" } else { "" }))
+        .collect();
+    match parts.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => "nothing".to_string(),
+    }
+}
+
+impl<'a> LibraryManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        LibraryManager { api_manager }
+    }
+
+    // Returns the saved tracks within the range, latest first.
+    pub async fn liked(&self, range: &PageRange) -> Result<Vec<TrackRecord>, ApiError> {
+        let list = PagedList::offset("api/spt-fwd/me/tracks", 50);
+        let saved: Vec<SavedTrack> = self.api_manager.collect_paged(list, range).await?;
+        Ok(saved.iter().map(|s| TrackRecord::from(&s.track)).collect())
+    }
+
+    pub async fn albums(&self, range: &PageRange) -> Result<Vec<Album>, ApiError> {
+        let list = PagedList::offset("api/spt-fwd/me/albums", 50);
+        let saved: Vec<SavedAlbum> = self.api_manager.collect_paged(list, range).await?;
+        Ok(saved.into_iter().map(|s| s.album).collect())
+    }
+
+    pub async fn shows(&self, range: &PageRange) -> Result<Vec<Show>, ApiError> {
+        let list = PagedList::offset("api/spt-fwd/me/shows", 50);
+        let saved: Vec<SavedShow> = self.api_manager.collect_paged(list, range).await?;
+        Ok(saved.into_iter().map(|s| s.show).collect())
+    }
+
+    // Followed artists are paged by cursor, so only the limit of the range
+    // applies.
+    pub async fn artists(&self, range: &PageRange) -> Result<Vec<Artist>, ApiError> {
+        let list = PagedList::cursor("api/spt-fwd/me/following", 50)
+            .param("type", "artist")
+            .in_field("artists");
+        let range = PageRange {
+            limit: range.limit,
+            ..Default::default()
+        };
+        self.api_manager.collect_paged(list, &range).await
+    }
+
+    // Saves tracks, albums and shows, or follows artists and playlists.
+    pub async fn save(&self, uris: &[String]) -> Result<(), ApiError> {
+        self.update(uris, true).await
+    }
+
+    // Removes saved items, or unfollows artists and playlists.
+    pub async fn remove(&self, uris: &[String]) -> Result<(), ApiError> {
+        self.update(uris, false).await
+    }
+
+    async fn update(&self, uris: &[String], save: bool) -> Result<(), ApiError> {
+        for (uri_type, ids) in group_ids(uris) {
+            if uri_type == UriType::Playlist {
+                for id in ids {
+                    let endpoint = playlist_endpoint(&id);
+                    match save {
+                        true => self.api_manager.put(&endpoint, None, None).await?,
+                        false => self.api_manager.delete(&endpoint, None, None).await?,
+                    };
+                }
+                continue;
+            }
+
+            let Some((endpoint, kind)) = library_endpoint(uri_type) else {
+                continue;
+            };
+            for chunk in ids.chunks(IDS_PER_REQUEST) {
+                let mut params = HashMap::from([("ids".to_string(), chunk.join(","))]);
+                if let Some(kind) = kind {
+                    params.insert("type".to_string(), kind.to_string());
+                }
+                match save {
+                    true => self.api_manager.put(endpoint, None, Some(params)).await?,
+                    false => {
+                        self.api_manager
+                            .delete(endpoint, None, Some(params))
+                            .await?
+                    }
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // Whether each URI is saved or followed, in the given order. URIs of
+    // other types never are.
+    pub async fn contains(&self, uris: &[String]) -> Result<Vec<bool>, ApiError> {
+        let mut saved: HashMap<String, bool> = HashMap::new();
+
+        for (uri_type, ids) in group_ids(uris) {
+            if uri_type == UriType::Playlist {
+                for id in ids {
+                    let endpoint = format!("{}/contains", playlist_endpoint(&id));
+                    let found: Vec<bool> = self.api_manager.get_model(&endpoint, None).await?;
+                    saved.insert(id, found.first().copied().unwrap_or(false));
+                }
+                continue;
+            }
+
+            let Some((endpoint, kind)) = library_endpoint(uri_type) else {
+                continue;
+            };
+            let endpoint = format!("{}/contains", endpoint);
+            for chunk in ids.chunks(IDS_PER_REQUEST) {
+                let mut params = HashMap::from([("ids".to_string(), chunk.join(","))]);
+                if let Some(kind) = kind {
+                    params.insert("type".to_string(), kind.to_string());
+                }
+                let found: Vec<bool> = self.api_manager.get_model(&endpoint, Some(params)).await?;
+                saved.extend(chunk.iter().cloned().zip(found));
+            }
+        }
+
+        Ok(uris
+            .iter()
+            .map(|uri| {
+                uri_helper::get_id_from_uri(uri)
+                    .and_then(|id| saved.get(&id).copied())
+                    .unwrap_or(false)
+            })
+            .collect())
+    }
+}
+
+// IDs of the URIs, grouped by type in order of first appearance.
+fn group_ids(uris: &[String]) -> Vec<(UriType, Vec<String>)> {
+    let mut groups: Vec<(UriType, Vec<String>)> = Vec::new();
+    for uri in uris {
+        let uri_type = uri_helper::get_uri_type(uri);
+        let Some(id) = uri_helper::get_id_from_uri(uri) else {
+            continue;
+        };
+        match groups.iter_mut().find(|(t, _)| *t == uri_type) {
+            Some((_, ids)) => ids.push(id),
+            None => groups.push((uri_type, vec![id])),
+        }
+    }
+    groups
+}
@@ -1,17 +1,34 @@
 use crate::client::cli::formatter;
+use crate::client::cli::value::TrackRecord;
 use crate::client::local_api_proxy::ApiProxy;
-use serde_json::{json, Value};
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{Device, Devices, PlayHistory, PlaybackState};
+use serde_json::json;
 use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// Playback as reported by Spotify: the item playing, how far into it, and the
+// device it's playing on.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerState {
+    pub track: Option<TrackRecord>,
+    pub progress_ms: u64,
+    pub is_playing: bool,
+    pub device: Option<String>,
+    pub volume: Option<u8>,
+}
 
 #[derive(Debug)]
 pub struct PlaybackManager<'a> {
     curr_device_name: Option<String>,
     device_list: HashMap<String, String>, // Maps device names to IDs
-    api_manager: &'a mut ApiProxy,
+    api_manager: &'a ApiProxy,
 }
 
 impl<'a> PlaybackManager<'a> {
-    pub fn new(api_manager: &'a mut ApiProxy) -> Self {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
         return PlaybackManager {
             curr_device_name: None,
             device_list: HashMap::new(),
@@ -19,501 +36,230 @@ impl<'a> PlaybackManager<'a> {
         };
     }
 
-    pub async fn now(&self, human_readable: bool) -> Option<String> {
-        let res = self
-            .api_manager
-            .get("api/spt-fwd/me/player/currently-playing", None)
-            .await;
+    async fn currently_playing(&self) -> Result<Option<PlaybackState>, ApiError> {
+        self.api_manager
+            .get_model("api/spt-fwd/me/player/currently-playing", None)
+            .await
+    }
 
-        if res.is_ok() {
-            let (status, json) = res.unwrap();
-            if status.as_u16() == 200 {
-                if human_readable {
-                    if !json["item"].is_null() {
-                        return Some(formatter::print_track_episode_pretty(&json["item"], 0));
-                    } else {
-                        return Some("No track currently playing.".to_string());
-                    }
-                } else {
-                    return Some(formatter::print_track_episode(&json["item"], 0));
-                }
-            } else if status.as_u16() == 204 {
-                return Some("No track currently playing.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            } else {
-                return Some(format!("Error: {}", status));
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
+    // Returns the currently playing item, or nothing if playback is stopped.
+    pub async fn now(&self) -> Result<Vec<TrackRecord>, ApiError> {
+        let state = self.currently_playing().await?;
 
-        return Some("Unknown error.".to_string());
+        Ok(state
+            .and_then(|s| s.item)
+            .iter()
+            .map(TrackRecord::from)
+            .collect())
     }
 
-    pub async fn play(&self) -> Option<String> {
-        let res = self
+    // Returns the playback state, the default state if nothing is playing.
+    pub async fn player(&self) -> Result<PlayerState, ApiError> {
+        let state: Option<PlaybackState> = self
             .api_manager
-            .put("api/spt-fwd/me/player/play", None, None)
-            .await;
-
-        if res.is_ok() {
-            let (status, _) = res.unwrap();
-            if status.as_u16() == 200 || status.as_u16() == 204 {
-                return Some("Now Playing.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            } else {
-                return Some(format!("Error: {}", status));
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
+            .get_model("api/spt-fwd/me/player", None)
+            .await?;
+        let Some(state) = state else {
+            return Ok(PlayerState::default());
+        };
 
-        return Some("Unknown error.".to_string());
+        Ok(PlayerState {
+            track: state.item.as_ref().map(TrackRecord::from),
+            progress_ms: state.progress_ms.unwrap_or(0),
+            is_playing: state.is_playing,
+            device: state.device.as_ref().map(|d| d.name.clone()),
+            volume: state.device.and_then(|d| d.volume_percent),
+        })
     }
 
-    pub async fn pause(&self) -> Option<String> {
-        let res = self
-            .api_manager
-            .put("api/spt-fwd/me/player/pause", None, None)
-            .await;
+    // Waits until the item playing now has finished, or playback moved on to
+    // another item. Waits through pauses; returns at once if nothing is playing.
+    pub async fn wait_until_track_ends(&self) -> Result<(), ApiError> {
+        let mut state = self.currently_playing().await?;
+        let Some(uri) = state
+            .as_ref()
+            .and_then(|s| s.item.as_ref())
+            .map(|item| item.uri().to_string())
+        else {
+            return Ok(());
+        };
 
-        if res.is_ok() {
-            let (status, _) = res.unwrap();
-            if status.as_u16() == 200 || status.as_u16() == 204 {
-                return Some("Now Paused.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
+        loop {
+            let Some((item, state_now)) = state
+                .as_ref()
+                .and_then(|s| Some((s.item.as_ref()?, s)))
+                .filter(|(item, _)| item.uri() == uri)
+            else {
+                return Ok(());
+            };
+
+            // sleep until just past the expected end, polling while paused
+            let remaining = item
+                .duration_ms()
+                .saturating_sub(state_now.progress_ms.unwrap_or(0));
+            let wait = if state_now.is_playing {
+                Duration::from_millis(remaining + 500).min(Duration::from_secs(30))
             } else {
-                return Some(format!("Error: {}", status));
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
+                Duration::from_secs(2)
+            };
+            sleep(wait).await;
+
+            state = self.currently_playing().await?;
         }
+    }
 
-        return Some("Unknown error.".to_string());
+    pub async fn play(&self) -> Result<String, ApiError> {
+        self.api_manager
+            .put("api/spt-fwd/me/player/play", None, None)
+            .await?;
+        Ok("Now Playing.".to_string())
     }
 
-    pub async fn next(&self, n: u8) -> Option<String> {
-        let mut results = Vec::with_capacity(n as usize);
+    pub async fn pause(&self) -> Result<String, ApiError> {
+        self.api_manager
+            .put("api/spt-fwd/me/player/pause", None, None)
+            .await?;
+        Ok("Now Paused.".to_string())
+    }
 
+    pub async fn next(&self, n: u8) -> Result<String, ApiError> {
         for _ in 0..n {
-            let res = self
-                .api_manager
+            self.api_manager
                 .post("api/spt-fwd/me/player/next", None, None)
-                .await;
-            results.push(res);
+                .await?;
         }
-
-        let mut output = Vec::new();
-
-        let all_failed = results
-            .iter()
-            .filter(|res| match res {
-                Ok((status, _)) => match status.as_u16() {
-                    200 => false,
-                    204 => false,
-                    401 | 403 => {
-                        output.push("User not authorized".to_string());
-                        true
-                    }
-                    429 => {
-                        output.push("Rate limit exceeded".to_string());
-                        true
-                    }
-                    _ => {
-                        output.push(format!("Error: {}", status));
-                        true
-                    }
-                },
-                Err(err) => {
-                    output.push(format!("Error: {}", err));
-                    true
-                }
-            })
-            .count() as u8;
-
-        output.insert(
-            0,
-            format!(
-                "Skipped {} track{}.",
-                n - all_failed,
-                if n - all_failed > 1 { "s" } else { "" }
-            ),
-        );
-
-        return Some(output.join("\n"));
+        Ok(format!(
+            "Skipped {} track{}.",
+            n,
+            if n > 1 { "s" } else { "" }
+        ))
     }
 
-    pub async fn previous(&self, n: u8) -> Option<String> {
-        let mut results = Vec::with_capacity(n as usize);
-
+    pub async fn previous(&self, n: u8) -> Result<String, ApiError> {
         for _ in 0..n {
-            let res = self
-                .api_manager
+            self.api_manager
                 .post("api/spt-fwd/me/player/previous", None, None)
-                .await;
-            results.push(res);
+                .await?;
         }
-
-        let mut output = Vec::new();
-
-        let all_failed = results
-            .iter()
-            .filter(|res| match res {
-                Ok((status, _)) => match status.as_u16() {
-                    200 => false,
-                    204 => false,
-                    401 | 403 => {
-                        output.push("User not authorized".to_string());
-                        true
-                    }
-                    429 => {
-                        output.push("Rate limit exceeded".to_string());
-                        true
-                    }
-                    _ => {
-                        output.push(format!("Error: {}", status));
-                        true
-                    }
-                },
-                Err(err) => {
-                    output.push(format!("Error: {}", err));
-                    true
-                }
-            })
-            .count() as u8;
-
-        output.insert(
-            0,
-            format!(
-                "Rewinded {} track{}.",
-                n - all_failed,
-                if n - all_failed > 1 { "s" } else { "" }
-            ),
-        );
-
-        return Some(output.join("\n"));
+        Ok(format!(
+            "Rewinded {} track{}.",
+            n,
+            if n > 1 { "s" } else { "" }
+        ))
     }
 
-    pub async fn set_volume(&self, level: u8) -> Option<String> {
+    pub async fn set_volume(&self, level: u8) -> Result<String, ApiError> {
         let mut params = HashMap::new();
         params.insert("volume_percent".to_string(), level.to_string());
 
-        let res = self
-            .api_manager
+        self.api_manager
             .put("api/spt-fwd/me/player/volume", None, Some(params))
-            .await;
-
-        if res.is_ok() {
-            let (status, _) = res.unwrap();
-            if status.as_u16() == 200 || status.as_u16() == 204 {
-                return Some(format!("Volume set to {}%.", level));
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            } else {
-                return Some(format!("Error: {}", status));
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
+            .await?;
+        Ok(format!("Volume set to {}%.", level))
+    }
 
-        return Some("Unknown error.".to_string());
+    pub async fn seek(&self, position_ms: u64) -> Result<String, ApiError> {
+        let mut params = HashMap::new();
+        params.insert("position_ms".to_string(), position_ms.to_string());
+
+        self.api_manager
+            .put("api/spt-fwd/me/player/seek", None, Some(params))
+            .await?;
+        Ok(format!(
+            "Seeked to {}.",
+            formatter::print_duration(position_ms as i64)
+        ))
     }
 
-    pub async fn get_volume(&self) -> Option<String> {
-        let res = self
+    pub async fn get_volume(&self) -> Result<String, ApiError> {
+        let state: Option<PlaybackState> = self
             .api_manager
-            .get("api/spt-fwd/me/player/devices", None)
-            .await;
-
-        if res.is_ok() {
-            let (status, json) = res.unwrap();
-            if status.as_u16() == 200 {
-                return Some(format!(
-                    "Volume: {}% ({})",
-                    json["device"]["volume_percent"].to_string(),
-                    json["device"]["name"],
-                ));
-            } else if status.as_u16() == 204 {
-                return Some("No devices found.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
+            .get_model("api/spt-fwd/me/player", None)
+            .await?;
+
+        Ok(match state {
+            Some(PlaybackState {
+                device: Some(device),
+                ..
+            }) => format!(
+                "Volume: {} ({})",
+                device
+                    .volume_percent
+                    .map(|v| format!("{}%", v))
+                    .unwrap_or("unknown".to_string()),
+                device.name,
+            ),
+            _ => "No devices found.".to_string(),
+        })
+    }
 
-        return Some("Unknown error.".to_string());
+    // Names of the devices found by the last call to devices.
+    pub fn device_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.device_list.keys().cloned().collect();
+        names.sort();
+        names
     }
 
-    pub async fn device(&mut self, name: &str) -> Option<String> {
-        let mut device_id = self.device_list.get(name).cloned();
-        if device_id.is_none() {
-            self.devices(false).await;
-            device_id = self.device_list.get(name).cloned();
-            if device_id.is_none() {
-                return Some(format!("Device '{}' not found.", name));
-            }
+    // Moves playback to the named device. Returns nothing if there is no
+    // device by that name.
+    pub async fn device(&mut self, name: &str) -> Result<Option<String>, ApiError> {
+        if !self.device_list.contains_key(name) {
+            self.list_devices().await?;
         }
+        let Some(device_id) = self.device_list.get(name) else {
+            return Ok(None);
+        };
 
-        let device_id = device_id.unwrap();
-
-        let res = self
-            .api_manager
+        self.api_manager
             .put(
                 "api/spt-fwd/me/player",
-                Some(json!({"device_ids": [device_id.to_string()]})),
+                Some(json!({"device_ids": [device_id]})),
                 None,
             )
-            .await;
-
-        if res.is_ok() {
-            let (status, _) = res.unwrap();
-            if status.as_u16() == 200 || status.as_u16() == 204 {
-                return Some(format!("Changing playback device to {}", name));
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            } else {
-                return Some(format!("Error: {}", status));
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
-
-        return Some("Unknown error.".to_string());
+            .await?;
+        Ok(Some(format!("Changing playback device to {}", name)))
     }
 
-    pub async fn devices(&mut self, human_readable: bool) -> Option<String> {
-        let res = self
-            .api_manager
-            .get("api/spt-fwd/me/player/devices", None)
-            .await;
-
-        if res.is_ok() {
-            let (status, json) = res.unwrap();
-            if status.as_u16() == 200 {
-                self.device_list.clear(); // Clear previous device list
-
-                let mut devices_list = String::new();
-                if human_readable {
-                    devices_list.push_str("Available Devices:\n");
-                    devices_list.push_str(&formatter::print_device_list_pretty(&json, 1));
-                } else {
-                    devices_list.push_str(&formatter::print_device_list(&json, 1));
-                }
-
-                if let Some(devices) = json["devices"].as_array() {
-                    for device in devices {
-                        if let (Some(name), Some(id)) =
-                            (device["name"].as_str(), device["id"].as_str())
-                        {
-                            //     if human_readable {
-                            //         devices_list.push_str(&format!("\t{} ({})\n", name, id));
-                            //     } else {
-                            //         devices_list.push_str(&format!("{}\n", id));
-                            //     }
-                            self.device_list.insert(name.to_string(), id.to_string());
-                        }
-                    }
-                }
-
-                return Some(devices_list);
-            } else if status.as_u16() == 204 {
-                return Some("No devices found.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
+    pub async fn devices(&mut self, human_readable: bool) -> Result<String, ApiError> {
+        let devices = self.list_devices().await?;
+        if devices.is_empty() {
+            return Ok("No devices found.".to_string());
         }
 
-        return Some("Unknown error.".to_string());
+        if human_readable {
+            Ok(format!(
+                "Available Devices:\n{}",
+                formatter::print_device_list_pretty(&devices, 1)
+            ))
+        } else {
+            Ok(formatter::print_device_list(&devices, 1))
+        }
     }
 
-    pub async fn queue(&mut self, human_readable: bool) -> Option<String> {
-        let res = self
+    // Returns the available devices, remembering their IDs for device.
+    pub async fn list_devices(&mut self) -> Result<Vec<Device>, ApiError> {
+        let devices: Devices = self
             .api_manager
-            .get("api/spt-fwd/me/player/queue", None)
-            .await;
+            .get_model("api/spt-fwd/me/player/devices", None)
+            .await?;
 
-        if res.is_ok() {
-            let (status, json) = res.unwrap();
-            if status.as_u16() == 200 {
-                let mut q_list = String::new();
-
-                if human_readable {
-                    q_list.push_str("Now Playing:\n");
-                    if !json["currently_playing"].is_null() {
-                        // q_list.push_str(&format!(
-                        //     "\t{} - {} by {}\n",
-                        //     json["currently_playing"]["name"],
-                        //     json["currently_playing"]["album"]["name"],
-                        //     json["currently_playing"]["artists"][0]["name"]
-                        // ));
-                        q_list.push_str(&formatter::print_track_episode_pretty(
-                            &json["currently_playing"],
-                            1,
-                        ))
-                    } else {
-                        q_list.push_str("\tNone");
-                    }
-                    q_list.push_str("\nQueue:\n");
-                    q_list.push_str(&formatter::print_item_list_pretty(&json["queue"], 1));
-                    // for item in json["queue"].as_array().unwrap_or(&vec![]) {
-                    //     q_list.push_str(&format!(
-                    //         "\t{} - {} by {}\n",
-                    //         item["name"], item["album"]["name"], item["artists"][0]["name"]
-                    //     ));
-
-                    // }
-                    if json["queue"].as_array().unwrap_or(&vec![]).len() == 0 {
-                        q_list.push_str("\tNone\n");
-                    }
-                } else {
-                    if !json["currently_playing"].is_null() {
-                        // q_list.push_str(&format!("{}\n", json["currently_playing"]["uri"]));
-                        q_list.push_str(&formatter::print_track_episode(
-                            &json["currently_playing"],
-                            1,
-                        ));
-                        q_list.push_str("\n");
-                    }
-                    q_list.push_str(&formatter::print_item_list(&json["queue"], 1));
-                    // for item in json["queue"].as_array().unwrap_or(&vec![]) {
-                    //     q_list.push_str(&format!("{}\n", item["uri"]));
-                    // }
-                }
-
-                return Some(q_list);
-            } else if status.as_u16() == 204 {
-                return Some("No queue found.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
+        self.device_list.clear();
+        for device in &devices.devices {
+            if let Some(id) = &device.id {
+                self.device_list.insert(device.name.clone(), id.clone());
             }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
         }
-
-        return Some("Unknown error.".to_string());
+        Ok(devices.devices)
     }
 
-    pub async fn queue_add(&mut self, uris: Vec<String>) -> Option<String> {
-        let n = uris.len();
-        let mut results = Vec::with_capacity(n);
+    // Returns the recently played tracks within the range, latest first.
+    pub async fn recent(&self, range: &PageRange) -> Result<Vec<TrackRecord>, ApiError> {
+        let list = PagedList::cursor("api/spt-fwd/me/player/recently-played", 50);
+        let history: Vec<PlayHistory> = self.api_manager.collect_paged(list, range).await?;
 
-        for _ in 0..n {
-            let params = HashMap::from([("uris".to_string(), uris[n].clone())]);
-            let res = self
-                .api_manager
-                .post("api/spt-fwd/me/player/queue", None, Some(params))
-                .await;
-            results.push(res);
-        }
-
-        let mut output = Vec::new();
-
-        let all_failed = results
+        Ok(history
             .iter()
-            .filter(|res| match res {
-                Ok((status, _)) => match status.as_u16() {
-                    200 => false,
-                    204 => false,
-                    401 | 403 => {
-                        output.push("User not authorized".to_string());
-                        true
-                    }
-                    429 => {
-                        output.push("Rate limit exceeded".to_string());
-                        true
-                    }
-                    _ => {
-                        output.push(format!("Error: {}", status));
-                        true
-                    }
-                },
-                Err(err) => {
-                    output.push(format!("Error: {}", err));
-                    true
-                }
-            })
-            .count();
-
-        output.insert(
-            0,
-            format!(
-                "Added {} track{} to queue.",
-                n - all_failed,
-                if n - all_failed > 1 { "s" } else { "" }
-            ),
-        );
-
-        return Some(output.join("\n"));
-    }
-
-    pub async fn recent(&mut self, n: u8, human_readable: bool) -> Option<String> {
-        let params = HashMap::from([("limit".to_string(), n.to_string())]);
-
-        let res = self
-            .api_manager
-            .get("api/spt-fwd/me/player/recently-played", Some(params))
-            .await;
-
-        if res.is_ok() {
-            let (status, json) = res.unwrap();
-            if status.as_u16() == 200 {
-                let mut q_list = String::new();
-
-                if human_readable {
-                    q_list.push_str("Recently Played:\n");
-                    // for item in json["items"].as_array().unwrap_or(&vec![]) {
-                    //     q_list.push_str(&format!(
-                    //         "\t{} - {} by {}\n",
-                    //         item["track"]["name"],
-                    //         item["track"]["album"]["name"],
-                    //         item["track"]["artists"][0]["name"]
-                    //     ));
-                    // }
-                    // if json["items"].as_array().unwrap_or(&vec![]).len() == 0 {
-                    //     q_list.push_str("\tNone\n");
-                    // }
-                    q_list.push_str(&formatter::print_item_list_pretty(&json, 1));
-                } else {
-                    // for item in json["items"].as_array().unwrap_or(&vec![]) {
-                    //     q_list.push_str(&format!("{}\n", item["track"]["uri"]));
-                    // }
-                    q_list.push_str(&formatter::print_item_list(&json, 1));
-                }
-
-                return Some(q_list);
-            } else if status.as_u16() == 204 {
-                return Some("No queue found.".to_string());
-            } else if status.as_u16() == 401 || status.as_u16() == 403 {
-                return Some("User not authorized".to_string());
-            } else if status.as_u16() == 429 {
-                return Some("Rate limit exceeded".to_string());
-            }
-        } else if res.is_err() {
-            return Some(format!("Error: {}", res.unwrap_err()));
-        }
-
-        return Some("Unknown error.".to_string());
+            .map(|item| TrackRecord::from(&item.track))
+            .collect())
     }
 }
@@ -0,0 +1,516 @@
+use crate::client::core::search_manager::SearchManager;
+use crate::client::paging::PageRange;
+use crate::util::errors::ApiError;
+use crate::util::models::{PlayableItem, PlaylistItem};
+use crate::util::uri_helper::{SpotifyId, UriType};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Playlists written to and read from files, for backups and for moving
+// playlists between players. Entries read from a file are matched to
+// Spotify tracks by URI, ISRC or a search for their title and artists.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    Csv,
+    Json,
+    M3u,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn parse(name: &str) -> Option<PlaylistFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(PlaylistFormat::Csv),
+            "json" => Some(PlaylistFormat::Json),
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            _ => None,
+        }
+    }
+
+    // The format a file's extension stands for.
+    pub fn from_path(path: &str) -> Option<PlaylistFormat> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(PlaylistFormat::parse)
+    }
+
+    // What the positions of entries count, to report unmatched ones by.
+    pub fn position_name(&self) -> &'static str {
+        match self {
+            PlaylistFormat::Json => "track",
+            _ => "line",
+        }
+    }
+}
+
+// A track of a playlist file. Read entries may have any of the fields, and
+// pos is their line, or their index for JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    #[serde(skip)]
+    pub pos: usize,
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub isrc: Option<String>,
+    pub added_at: Option<String>,
+}
+
+impl PlaylistEntry {
+    // "Artists - Title", or whichever of them is known.
+    pub fn label(&self) -> String {
+        match (self.artists.is_empty(), self.title.is_empty()) {
+            (false, false) => format!("{} - {}", self.artists.join(", "), self.title),
+            (false, true) => self.artists.join(", "),
+            _ => self.title.clone(),
+        }
+    }
+}
+
+impl From<&PlaylistItem> for PlaylistEntry {
+    fn from(item: &PlaylistItem) -> Self {
+        let mut entry = PlaylistEntry {
+            added_at: item.added_at.clone(),
+            ..Default::default()
+        };
+        match &item.track {
+            Some(PlayableItem::Track(track)) => {
+                entry.uri = Some(track.uri.clone());
+                entry.title = track.name.clone();
+                entry.artists = track.artists.iter().map(|a| a.name.clone()).collect();
+                entry.album = track.album.as_ref().map(|a| a.name.clone());
+                entry.duration_ms = Some(track.duration_ms);
+                entry.isrc = track.isrc().map(String::from);
+            }
+            Some(PlayableItem::Episode(episode)) => {
+                entry.uri = Some(episode.uri.clone());
+                entry.title = episode.name.clone();
+                entry.album = episode.show.as_ref().map(|s| s.name.clone());
+                entry.duration_ms = Some(episode.duration_ms);
+            }
+            None => {}
+        }
+        entry
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonPlaylist {
+    name: Option<String>,
+    tracks: Vec<PlaylistEntry>,
+}
+
+const CSV_HEADER: &[&str] = &[
+    "uri",
+    "title",
+    "artists",
+    "album",
+    "duration_ms",
+    "isrc",
+    "added_at",
+];
+
+// Writes a playlist in the format.
+pub fn write(format: PlaylistFormat, name: &str, entries: &[PlaylistEntry]) -> String {
+    match format {
+        PlaylistFormat::Csv => write_csv(entries),
+        PlaylistFormat::Json => {
+            let playlist = JsonPlaylist {
+                name: Some(name.to_string()),
+                tracks: entries.to_vec(),
+            };
+            serde_json::to_string_pretty(&playlist).unwrap_or_default()
+        }
+        PlaylistFormat::M3u => write_m3u(name, entries),
+        PlaylistFormat::Xspf => write_xspf(name, entries),
+    }
+}
+
+// Reads a playlist in the format, returning its name if the file has one.
+pub fn read(
+    format: PlaylistFormat,
+    text: &str,
+) -> Result<(Option<String>, Vec<PlaylistEntry>), String> {
+    match format {
+        PlaylistFormat::Csv => read_csv(text).map(|entries| (None, entries)),
+        PlaylistFormat::Json => {
+            let mut playlist: JsonPlaylist =
+                serde_json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+            for (i, entry) in playlist.tracks.iter_mut().enumerate() {
+                entry.pos = i + 1;
+            }
+            Ok((playlist.name, playlist.tracks))
+        }
+        PlaylistFormat::M3u => Ok(read_m3u(text)),
+        PlaylistFormat::Xspf => Ok(read_xspf(text)),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv(entries: &[PlaylistEntry]) -> String {
+    let mut lines = vec![CSV_HEADER.join(",")];
+    for entry in entries {
+        let fields = [
+            entry.uri.clone().unwrap_or_default(),
+            entry.title.clone(),
+            entry.artists.join("; "),
+            entry.album.clone().unwrap_or_default(),
+            entry.duration_ms.map(|d| d.to_string()).unwrap_or_default(),
+            entry.isrc.clone().unwrap_or_default(),
+            entry.added_at.clone().unwrap_or_default(),
+        ];
+        lines.push(
+            fields
+                .iter()
+                .map(|f| csv_field(f))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+    }
+    lines.join("\n") + "\n"
+}
+
+// Splits a CSV line into fields, unquoting quoted ones.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// Reads CSV with a header row. Columns are found by name, so files exported
+// by other tools work too, e.g. "Track URI", "Track Name", "Artist Name(s)".
+fn read_csv(text: &str) -> Result<Vec<PlaylistEntry>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = split_csv_line(header)
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let column = |test: &dyn Fn(&str) -> bool| header.iter().position(|h| test(h));
+    let uri = column(&|h| h.contains("uri"));
+    let title = column(&|h| h == "title" || h == "name" || h == "track name" || h == "track");
+    let artists = column(&|h| h.starts_with("artist"));
+    let album = column(&|h| h == "album" || h.starts_with("album name"));
+    let isrc = column(&|h| h == "isrc");
+    let duration = column(&|h| h.starts_with("duration"));
+    if uri.is_none() && isrc.is_none() && title.is_none() {
+        return Err("expected a header with uri, isrc or title columns".to_string());
+    }
+
+    Ok(lines
+        .map(|(i, line)| {
+            let fields = split_csv_line(line);
+            let get = |col: Option<usize>| {
+                col.and_then(|c| fields.get(c))
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+            };
+            PlaylistEntry {
+                pos: i + 1,
+                uri: get(uri),
+                title: get(title).unwrap_or_default(),
+                artists: get(artists)
+                    .map(|a| split_artists(&a, &[';', ',']))
+                    .unwrap_or_default(),
+                album: get(album),
+                duration_ms: get(duration).and_then(|d| d.parse().ok()),
+                isrc: get(isrc),
+                added_at: None,
+            }
+        })
+        .collect())
+}
+
+fn split_artists(artists: &str, separators: &[char]) -> Vec<String> {
+    artists
+        .split(separators)
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+fn write_m3u(name: &str, entries: &[PlaylistEntry]) -> String {
+    let mut lines = vec!["#EXTM3U".to_string(), format!("#PLAYLIST:{}", name)];
+    for entry in entries {
+        let secs = entry.duration_ms.map_or(-1, |d| (d / 1000) as i64);
+        lines.push(format!("#EXTINF:{},{}", secs, entry.label()));
+        lines.push(entry.uri.clone().unwrap_or_default());
+    }
+    lines.join("\n") + "\n"
+}
+
+// Reads extended M3U. Entries are Spotify URIs or URLs, or paths of local
+// files described by their #EXTINF line or, without one, by a file name like
+// "Artist - Title.mp3".
+fn read_m3u(text: &str) -> (Option<String>, Vec<PlaylistEntry>) {
+    let mut name = None;
+    let mut entries = Vec::new();
+    let mut info: Option<(Option<u64>, String)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if let Some(rest) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(rest.trim().to_string());
+        } else if let Some(rest) = line.strip_prefix("#EXTINF:") {
+            let (secs, label) = rest.split_once(',').unwrap_or((rest, ""));
+            let secs = secs.trim().parse::<i64>().ok().filter(|s| *s >= 0);
+            info = Some((secs.map(|s| s as u64 * 1000), label.trim().to_string()));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let mut entry = PlaylistEntry {
+                pos: i + 1,
+                ..Default::default()
+            };
+            let label = match info.take() {
+                Some((duration_ms, label)) => {
+                    entry.duration_ms = duration_ms;
+                    label
+                }
+                None => Path::new(line)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            entry.uri = playable_uri(line);
+            match label.split_once(" - ") {
+                Some((artists, title)) => {
+                    entry.artists = split_artists(artists, &[',']);
+                    entry.title = title.trim().to_string();
+                }
+                None => entry.title = label,
+            }
+            entries.push(entry);
+        }
+    }
+    (name, entries)
+}
+
+// The URI of a track or episode given as URI, URL or bare track ID.
+fn playable_uri(input: &str) -> Option<String> {
+    SpotifyId::parse(input, Some(UriType::Track))
+        .ok()
+        .filter(|id| matches!(id.uri_type, UriType::Track | UriType::Episode))
+        .map(|id| id.uri())
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn write_xspf(name: &str, entries: &[PlaylistEntry]) -> String {
+    let mut lines = vec![
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string(),
+        "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">".to_string(),
+        format!("  <title>{}</title>", xml_escape(name)),
+        "  <trackList>".to_string(),
+    ];
+    for entry in entries {
+        lines.push("    <track>".to_string());
+        let mut tag = |tag: &str, value: &str| {
+            lines.push(format!("      <{0}>{1}</{0}>", tag, xml_escape(value)));
+        };
+        if let Some(uri) = &entry.uri {
+            tag("location", uri);
+        }
+        if let Some(isrc) = &entry.isrc {
+            tag("identifier", &format!("isrc:{}", isrc));
+        }
+        tag("title", &entry.title);
+        if !entry.artists.is_empty() {
+            tag("creator", &entry.artists.join(", "));
+        }
+        if let Some(album) = &entry.album {
+            tag("album", album);
+        }
+        if let Some(duration_ms) = entry.duration_ms {
+            tag("duration", &duration_ms.to_string());
+        }
+        lines.push("    </track>".to_string());
+    }
+    lines.push("  </trackList>".to_string());
+    lines.push("</playlist>".to_string());
+    lines.join("\n") + "\n"
+}
+
+// The text of each <tag> element in the XML, in order. XSPF elements hold
+// text only, so no nesting needs to be handled.
+fn xml_elements<'t>(xml: &'t str, tag: &str) -> Vec<(usize, &'t str)> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = 0;
+    while let Some(start) = xml[rest..].find(&open).map(|i| rest + i + open.len()) {
+        let Some(end) = xml[start..].find(&close).map(|i| start + i) else {
+            break;
+        };
+        found.push((start, &xml[start..end]));
+        rest = end + close.len();
+    }
+    found
+}
+
+fn read_xspf(text: &str) -> (Option<String>, Vec<PlaylistEntry>) {
+    let list_start = text.find("<trackList>").unwrap_or(text.len());
+    let name = xml_elements(&text[..list_start], "title")
+        .first()
+        .map(|(_, title)| xml_unescape(title.trim()));
+
+    let entries = xml_elements(text, "track")
+        .into_iter()
+        .map(|(start, track)| {
+            let first = |tag: &str| {
+                xml_elements(track, tag)
+                    .first()
+                    .map(|(_, value)| xml_unescape(value.trim()))
+                    .filter(|value| !value.is_empty())
+            };
+            let isrc = xml_elements(track, "identifier")
+                .iter()
+                .map(|(_, id)| xml_unescape(id.trim()))
+                .find_map(|id| {
+                    let id = id.to_ascii_lowercase();
+                    id.strip_prefix("isrc:")
+                        .or_else(|| id.strip_prefix("urn:isrc:"))
+                        .map(|isrc| isrc.to_ascii_uppercase())
+                });
+            PlaylistEntry {
+                pos: text[..start].lines().count(),
+                uri: first("location").and_then(|loc| playable_uri(&loc)),
+                title: first("title").unwrap_or_default(),
+                artists: first("creator")
+                    .map(|c| split_artists(&c, &[',']))
+                    .unwrap_or_default(),
+                album: first("album"),
+                duration_ms: first("duration").and_then(|d| d.parse().ok()),
+                isrc,
+                added_at: None,
+            }
+        })
+        .collect();
+    (name, entries)
+}
+
+// Lowercase words of a title or name, without bracketed parts or suffixes
+// like " - Remastered 2011", so different releases compare equal.
+pub fn normalize(text: &str) -> String {
+    let mut depth = 0;
+    let mut kept = String::new();
+    let text = text.split(" - ").next().unwrap_or(text);
+    for c in text.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            c if depth == 0 && c.is_alphanumeric() => kept.extend(c.to_lowercase()),
+            _ if depth == 0 => kept.push(' '),
+            _ => {}
+        }
+    }
+    kept.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Whether a search result is the track an entry describes: the same title
+// and, if the entry names any, one of the same artists.
+fn same_track(entry: &PlaylistEntry, title: &str, artists: &[String]) -> bool {
+    let (wanted, found) = (normalize(&entry.title), normalize(title));
+    if wanted.is_empty() || wanted != found {
+        return false;
+    }
+    entry.artists.is_empty()
+        || entry
+            .artists
+            .iter()
+            .any(|a| artists.iter().any(|b| normalize(a) == normalize(b)))
+}
+
+// Matches entries to Spotify URIs: by their URI, by ISRC, or by searching for
+// their title and artists. Returns the URIs found, in order, and the entries
+// that couldn't be matched.
+pub async fn resolve<'e>(
+    search: &SearchManager<'_>,
+    entries: &'e [PlaylistEntry],
+) -> Result<(Vec<String>, Vec<&'e PlaylistEntry>), ApiError> {
+    let mut uris = Vec::new();
+    let mut unmatched = Vec::new();
+    for entry in entries {
+        if let Some(uri) = entry.uri.as_deref().and_then(playable_uri) {
+            uris.push(uri);
+            continue;
+        }
+        if let Some(isrc) = &entry.isrc {
+            let found = search
+                .search(&format!("isrc:{}", isrc), &PageRange::first(1))
+                .await?;
+            if let Some(track) = found.into_iter().next() {
+                uris.push(track.uri);
+                continue;
+            }
+        }
+        if entry.title.is_empty() {
+            unmatched.push(entry);
+            continue;
+        }
+
+        let mut query = format!("track:\"{}\"", entry.title);
+        if let Some(artist) = entry.artists.first() {
+            query.push_str(&format!(" artist:\"{}\"", artist));
+        }
+        let mut found = search.search(&query, &PageRange::first(5)).await?;
+        // field filters miss titles spelled differently, a plain search
+        // catches some of them
+        if !found.iter().any(|t| same_track(entry, &t.name, &t.artists)) {
+            let query = format!("{} {}", entry.title, entry.artists.join(" "));
+            found = search.search(query.trim(), &PageRange::first(5)).await?;
+        }
+        match found
+            .into_iter()
+            .find(|t| same_track(entry, &t.name, &t.artists))
+        {
+            Some(track) => uris.push(track.uri),
+            None => unmatched.push(entry),
+        }
+    }
+    Ok((uris, unmatched))
+}
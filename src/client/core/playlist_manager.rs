@@ -1,18 +1,637 @@
-use crate::core::api_manager::ApiManager;
+use crate::client::cli::value::TrackRecord;
+use crate::client::core::playlist_file::normalize;
+use crate::client::core::track_query::{self, SortKey, TrackInfo};
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{self, Playlist, PlaylistItem, PlaylistSnapshot, User};
+use crate::util::uri_helper::{self, SpotifyId, UriType};
+use futures_util::{pin_mut, TryStreamExt};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+// The most items a playlist takes per request.
+const ITEMS_PER_REQUEST: usize = 100;
+
+// e.g. "1 track" or "3 tracks".
+pub fn count_tracks(n: usize) -> String {
+    format!("{} {}", n, if n == 1 { "track" } else { "tracks" })
+}
+
+// How dedupe tells duplicates apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateMatch {
+    Uri,
+    // also the same ISRC, or the same title and artists, so that other
+    // releases of a track count
+    Track,
+}
+
+// An entry repeating the one at first, by positions from 0.
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub item: PlaylistItem,
+    pub position: usize,
+    pub first: usize,
+}
+
+// An entry moving between positions, from 0.
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub item: PlaylistItem,
+    pub from: usize,
+    pub to: usize,
+}
 
 #[derive(Debug)]
-pub struct PlaylistManager {
-    api_manager: &'static mut ApiManager,
+pub struct PlaylistManager<'a> {
+    api_manager: &'a ApiProxy,
 }
 
-impl PlaylistManager {
-    pub fn new() -> Self {
-        PlaylistManager {
-            api_manager: ApiManager::get_instance(),
+impl<'a> PlaylistManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        PlaylistManager { api_manager }
+    }
+
+    // Returns the current user's playlists within the range.
+    pub async fn playlists(&self, range: &PageRange) -> Result<Vec<Playlist>, ApiError> {
+        self.api_manager
+            .collect_paged(PagedList::offset("api/spt-fwd/me/playlists", 50), range)
+            .await
+    }
+
+    // Finds the ID of a playlist given as URI, URL, ID or the name of one of
+    // the user's playlists, ignoring case.
+    pub async fn find(&self, input: &str) -> Result<Option<String>, ApiError> {
+        if let Ok(id) = SpotifyId::parse(input, Some(UriType::Playlist)) {
+            if id.uri_type == UriType::Playlist {
+                return Ok(Some(id.id));
+            }
+        }
+
+        // stop paging at the first match
+        let playlists = self.api_manager.paged::<Playlist>(
+            PagedList::offset("api/spt-fwd/me/playlists", 50),
+            &PageRange::all(),
+        );
+        pin_mut!(playlists);
+        while let Some(playlist) = playlists.try_next().await? {
+            if playlist.name.eq_ignore_ascii_case(input) {
+                return Ok(playlist
+                    .id
+                    .or_else(|| uri_helper::get_id_from_uri(&playlist.uri)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn playlist(&self, id: &str) -> Result<Playlist, ApiError> {
+        self.api_manager
+            .get_model(&format!("api/spt-fwd/playlists/{}", id), None)
+            .await
+    }
+
+    // Returns the tracks and episodes of a playlist within the range.
+    // Unavailable items are left out.
+    pub async fn tracks(&self, id: &str, range: &PageRange) -> Result<Vec<TrackRecord>, ApiError> {
+        Ok(self
+            .items(id, range)
+            .await?
+            .iter()
+            .filter_map(|item| item.track.as_ref())
+            .map(TrackRecord::from)
+            .collect())
+    }
+
+    // Returns the entries of a playlist within the range, with when they were
+    // added.
+    pub async fn items(&self, id: &str, range: &PageRange) -> Result<Vec<PlaylistItem>, ApiError> {
+        let list = PagedList::offset(format!("api/spt-fwd/playlists/{}/tracks", id), 100);
+        self.api_manager.collect_paged(list, range).await
+    }
+
+    // Creates a private playlist of the current user holding the items.
+    pub async fn create(&self, name: &str, uris: &[String]) -> Result<Playlist, ApiError> {
+        let me: User = self.api_manager.get_model("api/spt-fwd/me", None).await?;
+        let user_id = me.id.ok_or(ApiError::ResponseDataError)?;
+
+        let body = json!({ "name": name, "public": false });
+        let (status, json) = self
+            .api_manager
+            .post(
+                &format!("api/spt-fwd/users/{}/playlists", user_id),
+                Some(body),
+                None,
+            )
+            .await?;
+        let playlist: Playlist = models::from_response(status, json)?;
+
+        let id = playlist
+            .id
+            .clone()
+            .or_else(|| uri_helper::get_id_from_uri(&playlist.uri))
+            .ok_or(ApiError::ResponseDataError)?;
+        self.add(&id, uris).await?;
+        Ok(playlist)
+    }
+
+    // Appends items to a playlist, in order.
+    pub async fn add(&self, id: &str, uris: &[String]) -> Result<(), ApiError> {
+        self.edit(id, None).insert(uris, None).await
+    }
+
+    // Removes entries repeating an earlier one, keeping the first, and
+    // returns them. With dry_run, only returns them.
+    pub async fn dedupe(
+        &self,
+        id: &str,
+        by: DuplicateMatch,
+        dry_run: bool,
+    ) -> Result<Vec<Duplicate>, ApiError> {
+        // the snapshot is taken first, so it's no newer than the items
+        let playlist = self.playlist(id).await?;
+        let items = self.items(id, &PageRange::all()).await?;
+        let duplicates = find_duplicates(&items, by);
+        if dry_run || duplicates.is_empty() {
+            return Ok(duplicates);
+        }
+
+        // by position, so the copies that are kept stay as they are
+        let current = item_uris(&items);
+        let entries: Vec<(usize, String)> = duplicates
+            .iter()
+            .map(|d| (d.position, current[d.position].clone()))
+            .collect();
+        self.edit(id, playlist.snapshot_id).remove(&entries).await?;
+        Ok(duplicates)
+    }
+
+    // Orders the playlist by the key, stably, with unavailable items last,
+    // and returns the entries that change position. With dry_run, only
+    // returns them.
+    pub async fn sort(
+        &self,
+        id: &str,
+        key: &SortKey,
+        dry_run: bool,
+    ) -> Result<Vec<Move>, ApiError> {
+        let playlist = self.playlist(id).await?;
+        let items = self.items(id, &PageRange::all()).await?;
+
+        let (positions, mut tracks): (Vec<usize>, Vec<TrackInfo>) = items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| Some((i, TrackInfo::from_item(item)?)))
+            .unzip();
+        if key.field.needs_features() {
+            track_query::load_features(self.api_manager, &mut tracks).await?;
+        }
+        let mut sorted: Vec<(usize, TrackInfo)> = positions.into_iter().zip(tracks).collect();
+        sorted.sort_by(|(_, a), (_, b)| key.compare(a, b));
+        let unavailable = (0..items.len()).filter(|i| items[*i].track.is_none());
+        let order: Vec<usize> = sorted
+            .into_iter()
+            .map(|(i, _)| i)
+            .chain(unavailable)
+            .collect();
+
+        let moves: Vec<Move> = order
+            .iter()
+            .enumerate()
+            .filter(|(to, from)| *to != **from)
+            .map(|(to, from)| Move {
+                item: items[*from].clone(),
+                from: *from,
+                to,
+            })
+            .collect();
+        if dry_run || moves.is_empty() {
+            return Ok(moves);
+        }
+
+        let current = item_uris(&items);
+        let target: Vec<String> = order.iter().map(|i| current[*i].clone()).collect();
+        self.edit(id, playlist.snapshot_id)
+            .arrange(&current, &target)
+            .await?;
+        Ok(moves)
+    }
+
+    // Appends the entries of the sources that into doesn't have yet, in order
+    // and once each, and returns them. Without into, or with dry_run, only
+    // returns them.
+    pub async fn merge(
+        &self,
+        sources: &[String],
+        into: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Vec<PlaylistItem>, ApiError> {
+        let mut seen: HashSet<String> = match into {
+            Some(id) => self.uris(id).await?.into_iter().collect(),
+            None => HashSet::new(),
+        };
+        let mut merged = Vec::new();
+        for source in sources {
+            for item in self.items(source, &PageRange::all()).await? {
+                let Some(track) = &item.track else {
+                    continue;
+                };
+                if seen.insert(track.uri().to_string()) {
+                    merged.push(item);
+                }
+            }
+        }
+        if let (Some(id), false) = (into, dry_run) {
+            let uris: Vec<String> = merged
+                .iter()
+                .filter_map(|item| Some(item.track.as_ref()?.uri().to_string()))
+                .collect();
+            self.add(id, &uris).await?;
+        }
+        Ok(merged)
+    }
+
+    // URIs of the items of a that b doesn't have or, with common, that b has
+    // too, in the order of a and once each.
+    pub async fn diff(&self, a: &str, b: &str, common: bool) -> Result<Vec<String>, ApiError> {
+        let other: HashSet<String> = self.uris(b).await?.into_iter().collect();
+        let mut seen = HashSet::new();
+        Ok(self
+            .uris(a)
+            .await?
+            .into_iter()
+            .filter(|uri| other.contains(uri) == common && seen.insert(uri.clone()))
+            .collect())
+    }
+
+    async fn uris(&self, id: &str) -> Result<Vec<String>, ApiError> {
+        Ok(self
+            .items(id, &PageRange::all())
+            .await?
+            .iter()
+            .filter_map(|item| Some(item.track.as_ref()?.uri().to_string()))
+            .collect())
+    }
+
+    // Starts editing a playlist's items as of the snapshot, the latest
+    // version if None.
+    pub fn edit(&self, id: &str, snapshot: Option<String>) -> PlaylistEdit<'a> {
+        PlaylistEdit {
+            api_manager: self.api_manager,
+            endpoint: format!("api/spt-fwd/playlists/{}/tracks", id),
+            snapshot,
+        }
+    }
+}
+
+// Edits of a playlist's items, batched by ITEMS_PER_REQUEST. Each is made
+// against the snapshot returned by the one before it, so that removals and
+// moves apply to the version of the playlist they were planned on.
+#[derive(Debug)]
+pub struct PlaylistEdit<'a> {
+    api_manager: &'a ApiProxy,
+    endpoint: String,
+    pub snapshot: Option<String>,
+}
+
+impl PlaylistEdit<'_> {
+    // Removes the entries at the positions, in the snapshot, given with
+    // their URIs from item_uris. Other occurrences of the same items stay.
+    // Unavailable entries have no URI and go by their position alone.
+    pub async fn remove(&mut self, entries: &[(usize, String)]) -> Result<(), ApiError> {
+        // highest first, so that the positions of the rest still hold
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.0));
+
+        let mut rest = &entries[..];
+        while let Some((_, first)) = rest.first() {
+            let unavailable = unavailable_position(first).is_some();
+            let length = rest
+                .iter()
+                .take(ITEMS_PER_REQUEST)
+                .take_while(|(_, uri)| unavailable_position(uri).is_some() == unavailable)
+                .count();
+            let (batch, tail) = rest.split_at(length);
+            rest = tail;
+
+            let body = match unavailable {
+                true => json!({ "positions": batch.iter().map(|(i, _)| i).collect::<Vec<_>>() }),
+                false => {
+                    let tracks: Vec<Value> = batch
+                        .iter()
+                        .map(|(i, uri)| json!({ "uri": uri, "positions": [i] }))
+                        .collect();
+                    json!({ "tracks": tracks })
+                }
+            };
+            let body = self.with_snapshot(body);
+            let (status, json) = self
+                .api_manager
+                .delete(&self.endpoint, Some(body), None)
+                .await?;
+            self.update_snapshot(status, json);
+        }
+        Ok(())
+    }
+
+    // Inserts items, in order, before the given position or at the end.
+    pub async fn insert(
+        &mut self,
+        uris: &[String],
+        position: Option<usize>,
+    ) -> Result<(), ApiError> {
+        for (i, chunk) in uris.chunks(ITEMS_PER_REQUEST).enumerate() {
+            let mut body = json!({ "uris": chunk });
+            if let Some(position) = position {
+                body["position"] = json!(position + i * ITEMS_PER_REQUEST);
+            }
+            let (status, json) = self
+                .api_manager
+                .post(&self.endpoint, Some(body), None)
+                .await?;
+            self.update_snapshot(status, json);
+        }
+        Ok(())
+    }
+
+    // Moves length items from start to before the item at insert_before.
+    pub async fn move_range(
+        &mut self,
+        start: usize,
+        length: usize,
+        insert_before: usize,
+    ) -> Result<(), ApiError> {
+        let body = self.with_snapshot(json!({
+            "range_start": start,
+            "range_length": length,
+            "insert_before": insert_before,
+        }));
+        let (status, json) = self
+            .api_manager
+            .put(&self.endpoint, Some(body), None)
+            .await?;
+        self.update_snapshot(status, json);
+        Ok(())
+    }
+
+    // Brings the items into the order of target by the moves of plan_moves,
+    // returning how many items were moved.
+    pub async fn arrange(
+        &mut self,
+        order: &[String],
+        target: &[String],
+    ) -> Result<usize, ApiError> {
+        let moves = plan_moves(order, target);
+        for (start, length, insert_before) in &moves {
+            self.move_range(*start, *length, *insert_before).await?;
+        }
+        Ok(moves.iter().map(|(_, length, _)| length).sum())
+    }
+
+    // Makes the edits of a plan from plan_edit, returning how many items
+    // were moved.
+    pub async fn apply(&mut self, plan: &EditPlan, target: &[String]) -> Result<usize, ApiError> {
+        self.remove(&plan.remove).await?;
+        self.insert(&plan.add, None).await?;
+        self.arrange(&plan.order, target).await
+    }
+
+    fn with_snapshot(&self, mut body: Value) -> Value {
+        if let Some(snapshot) = &self.snapshot {
+            body["snapshot_id"] = json!(snapshot);
+        }
+        body
+    }
+
+    fn update_snapshot(&mut self, status: StatusCode, json: Value) {
+        if let Ok(snapshot) = models::from_response::<PlaylistSnapshot>(status, json) {
+            self.snapshot = Some(snapshot.snapshot_id);
+        }
+    }
+}
+
+// Moves, as (start, length, insert_before), that bring items in order into
+// the order of target, front to back. Runs of items already in the right
+// order move together. Items missing from either list are left where they
+// are.
+pub fn plan_moves(order: &[String], target: &[String]) -> Vec<(usize, usize, usize)> {
+    let mut order = order.to_vec();
+    let mut moves = Vec::new();
+    let mut i = 0;
+    while i < target.len().min(order.len()) {
+        if order[i] == target[i] {
+            i += 1;
+            continue;
+        }
+        let Some(start) = order[i..].iter().position(|uri| *uri == target[i]) else {
+            i += 1;
+            continue;
+        };
+        let start = start + i;
+        let mut length = 1;
+        while start + length < order.len()
+            && i + length < target.len()
+            && order[start + length] == target[i + length]
+        {
+            length += 1;
+        }
+        moves.push((start, length, i));
+        let run: Vec<String> = order.drain(start..start + length).collect();
+        order.splice(i..i, run);
+        i += length;
+    }
+    moves
+}
+
+// The edits that turn a playlist's items into target: entries to remove, by
+// position and URI, items to append, and the order after both.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditPlan {
+    pub remove: Vec<(usize, String)>,
+    pub add: Vec<String>,
+    pub order: Vec<String>,
+}
+
+// Each item of target keeps the earliest entry of current not kept for
+// another, so that entries which stay keep when and by whom they were added.
+// The entries left over are removed.
+pub fn plan_edit(current: &[String], target: &[String]) -> EditPlan {
+    let mut wanted: HashMap<&String, usize> = HashMap::new();
+    for uri in target {
+        *wanted.entry(uri).or_default() += 1;
+    }
+
+    let mut remove = Vec::new();
+    let mut order = Vec::new();
+    for (i, uri) in current.iter().enumerate() {
+        match wanted.get_mut(uri) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                order.push(uri.clone());
+            }
+            _ => remove.push((i, uri.clone())),
+        }
+    }
+    let mut add = Vec::new();
+    for uri in target {
+        if let Some(n) = wanted.get_mut(uri).filter(|n| **n > 0) {
+            *n -= 1;
+            add.push(uri.clone());
+        }
+    }
+    order.extend(add.iter().cloned());
+    EditPlan { remove, add, order }
+}
+
+// The URIs of the items, with unavailable ones as "unavailable:POSITION" so
+// that they keep their place.
+pub(crate) fn item_uris(items: &[PlaylistItem]) -> Vec<String> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| match &item.track {
+            Some(track) => track.uri().to_string(),
+            None => format!("unavailable:{}", i),
+        })
+        .collect()
+}
+
+// The position of an unavailable entry from item_uris.
+fn unavailable_position(uri: &str) -> Option<usize> {
+    uri.strip_prefix("unavailable:")?.parse().ok()
+}
+
+fn find_duplicates(items: &[PlaylistItem], by: DuplicateMatch) -> Vec<Duplicate> {
+    // every key of an entry leads to the first entry it was seen on
+    let mut firsts: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (position, item) in items.iter().enumerate() {
+        let Some(info) = TrackInfo::from_item(item) else {
+            continue;
+        };
+        let mut keys = vec![info.uri.clone()];
+        if by == DuplicateMatch::Track {
+            keys.extend(info.isrc.as_ref().map(|isrc| format!("isrc:{}", isrc)));
+            let title = normalize(&info.name);
+            if !title.is_empty() {
+                let mut artists: Vec<String> = info.artists.iter().map(|a| normalize(a)).collect();
+                artists.sort();
+                keys.push(format!("{}|{}", title, artists.join("|")));
+            }
+        }
+
+        let first = keys
+            .iter()
+            .find_map(|key| firsts.get(key).copied())
+            .unwrap_or(position);
+        for key in keys {
+            firsts.entry(key).or_insert(first);
+        }
+        if first != position {
+            duplicates.push(Duplicate {
+                item: item.clone(),
+                position,
+                first,
+            });
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    // The order after making the moves, as Spotify would.
+    fn apply_moves(order: &[String], moves: &[(usize, usize, usize)]) -> Vec<String> {
+        let mut order = order.to_vec();
+        for (start, length, insert_before) in moves {
+            let run: Vec<String> = order.drain(*start..start + length).collect();
+            let at = match insert_before > start {
+                true => insert_before - length,
+                false => *insert_before,
+            };
+            order.splice(at..at, run);
+        }
+        order
+    }
+
+    #[test]
+    fn plans_removals_by_position() {
+        let plan = plan_edit(&uris(&["a", "b", "c"]), &uris(&["c", "a", "d"]));
+        assert_eq!(plan.remove, vec![(1, "b".to_string())]);
+        assert_eq!(plan.add, uris(&["d"]));
+        assert_eq!(plan.order, uris(&["a", "c", "d"]));
+    }
+
+    #[test]
+    fn keeps_the_first_of_repeated_entries() {
+        // only the later copy goes, the first keeps its place
+        let plan = plan_edit(&uris(&["a", "b", "a", "a"]), &uris(&["b", "a"]));
+        assert_eq!(
+            plan.remove,
+            vec![(2, "a".to_string()), (3, "a".to_string())]
+        );
+        assert!(plan.add.is_empty());
+        assert_eq!(plan.order, uris(&["a", "b"]));
+
+        // and an item wanted twice is only added once more
+        let plan = plan_edit(&uris(&["a"]), &uris(&["a", "b", "a"]));
+        assert!(plan.remove.is_empty());
+        assert_eq!(plan.add, uris(&["a", "b"]));
+    }
+
+    #[test]
+    fn removes_unavailable_entries() {
+        let current = uris(&["a", "unavailable:1", "b"]);
+        let plan = plan_edit(&current, &uris(&["b", "a"]));
+        assert_eq!(plan.remove, vec![(1, "unavailable:1".to_string())]);
+        assert_eq!(plan.order, uris(&["a", "b"]));
+        assert_eq!(unavailable_position("unavailable:1"), Some(1));
+        assert_eq!(unavailable_position("spotify:track:a"), None);
+    }
+
+    #[test]
+    fn plans_nothing_for_a_playlist_in_order() {
+        let items = uris(&["a", "b", "c"]);
+        let plan = plan_edit(&items, &items);
+        assert!(plan.remove.is_empty() && plan.add.is_empty());
+        assert!(plan_moves(&plan.order, &items).is_empty());
+    }
+
+    #[test]
+    fn moves_runs_together() {
+        let order = uris(&["a", "b", "c", "d"]);
+        let target = uris(&["c", "d", "a", "b"]);
+        let moves = plan_moves(&order, &target);
+        assert_eq!(moves, vec![(2, 2, 0)]);
+        assert_eq!(apply_moves(&order, &moves), target);
+    }
+
+    #[test]
+    fn moves_into_the_target_order() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["a", "b", "c"], &["c", "b", "a"]),
+            (&["a", "b", "c", "d", "e"], &["b", "d", "a", "e", "c"]),
+            (&["a", "b", "a"], &["b", "a", "a"]),
+            (&["x", "y"], &["y", "x"]),
+        ];
+        for (order, target) in cases {
+            let (order, target) = (uris(order), uris(target));
+            let moves = plan_moves(&order, &target);
+            assert_eq!(apply_moves(&order, &moves), target, "{:?}", moves);
         }
     }
 
-    pub fn playlists(&self) {
-        // self.api_manager.get("me/playlists", None);
+    #[test]
+    fn leaves_items_missing_from_the_target() {
+        let order = uris(&["a", "b", "c"]);
+        let moves = plan_moves(&order, &uris(&["c", "a"]));
+        assert_eq!(apply_moves(&order, &moves), uris(&["c", "a", "b"]));
     }
 }
@@ -1,14 +1,378 @@
-use crate::core::api_manager::ApiManager;
+use crate::client::cli::formatter::{self, Column};
+use crate::client::cli::value::TrackRecord;
+use crate::client::core::search_manager::SearchManager;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{Album, PlaylistItem, Queue, Track, Tracks};
+use crate::util::uri_helper::{self, UriType};
+use rand::seq::SliceRandom;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 
+// Options applied when adding URIs to the queue.
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    pub shuffle: bool,
+    pub limit: Option<usize>,
+    pub discography: bool, // expand artists to their full discography instead of top tracks
+}
+
+// Result of expanding album, playlist and artist URIs into tracks.
+#[derive(Debug, Default)]
+struct Expansion {
+    uris: Vec<String>,
+    albums: usize,
+    playlists: usize,
+    artists: usize,
+    errors: Vec<String>,
+}
+
+fn plural(n: usize, word: &str) -> String {
+    format!("{} {}{}", n, word, if n != 1 { "s" } else { "" })
+}
+
+// What plays next: the current track, Spotify's own queue, then the items
+// pending in the virtual queue.
+#[derive(Debug, Clone, Default)]
+pub struct QueueContents {
+    pub current: Option<TrackRecord>,
+    pub queued: Vec<TrackRecord>,
+    pub pending: Vec<TrackRecord>,
+}
+
+// Manages the virtual queue kept by the server. Tracks are held locally and
+// fed to Spotify one at a time, which allows reordering and removal.
 #[derive(Debug)]
-pub struct QueueManager {
-    api_manager: &'static ApiManager,
+pub struct QueueManager<'a> {
+    api_manager: &'a ApiProxy,
+}
+
+fn pending_from_json(json: &Value) -> Vec<String> {
+    json["pending"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .filter_map(|u| u.as_str().map(String::from))
+        .collect()
 }
 
-impl QueueManager {
-    pub fn new() -> Self {
-        QueueManager {
-            api_manager: ApiManager::get_instance(),
+impl<'a> QueueManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        QueueManager { api_manager }
+    }
+
+    async fn pending(&self) -> Result<Vec<String>, ApiError> {
+        let (_, json) = self.api_manager.get("api/queue", None).await?;
+        Ok(pending_from_json(&json))
+    }
+
+    async fn op(&self, op: &str, body: Value) -> Result<Vec<String>, ApiError> {
+        let (_, json) = self
+            .api_manager
+            .post(&format!("api/queue/{}", op), Some(body), None)
+            .await?;
+        Ok(pending_from_json(&json))
+    }
+
+    // Appends the URIs to Spotify's own queue, for when no server stays
+    // around to feed them from the virtual queue.
+    async fn push_direct(&self, uris: &[String]) -> Result<(), ApiError> {
+        for uri in uris {
+            let params = HashMap::from([("uri".to_string(), uri.clone())]);
+            self.api_manager
+                .post("api/spt-fwd/me/player/queue", None, Some(params))
+                .await?;
         }
+        Ok(())
+    }
+
+    // Looks up track names for the given URIs, returning one record per URI.
+    // URIs that aren't tracks are shown by their URI.
+    async fn describe(&self, uris: &[String]) -> Vec<TrackRecord> {
+        let tracks = SearchManager::new(self.api_manager)
+            .describe(uris)
+            .await
+            .unwrap_or_default();
+
+        uris.iter()
+            .map(|uri| match tracks.iter().find(|t| &t.uri == uri) {
+                Some(track) => track.clone(),
+                None => TrackRecord {
+                    uri: uri.clone(),
+                    name: uri.clone(),
+                    artists: vec![],
+                    album: None,
+                    duration_ms: None,
+                },
+            })
+            .collect()
+    }
+
+    // Spotify's own queue, nothing if playback is stopped.
+    async fn spotify_queue(&self) -> Result<Queue, ApiError> {
+        let queue: Option<Queue> = self
+            .api_manager
+            .get_model("api/spt-fwd/me/player/queue", None)
+            .await?;
+        Ok(queue.unwrap_or(Queue {
+            currently_playing: None,
+            queue: vec![],
+        }))
+    }
+
+    // Returns the URIs of the current track, Spotify's queue and the pending
+    // items, in playback order.
+    pub async fn queue_uris(&self) -> Result<Vec<String>, ApiError> {
+        let spotify_queue = self.spotify_queue().await?;
+
+        let mut uris: Vec<String> = spotify_queue
+            .currently_playing
+            .iter()
+            .chain(spotify_queue.queue.iter())
+            .map(|item| item.uri().to_string())
+            .collect();
+        uris.extend(self.pending().await?);
+        Ok(uris)
+    }
+
+    // Returns the current track, Spotify's queue and the pending items.
+    pub async fn contents(&self) -> Result<QueueContents, ApiError> {
+        let spotify_queue = self.spotify_queue().await?;
+        let pending = self.pending().await?;
+
+        Ok(QueueContents {
+            current: spotify_queue
+                .currently_playing
+                .as_ref()
+                .map(TrackRecord::from),
+            queued: spotify_queue.queue.iter().map(TrackRecord::from).collect(),
+            pending: match pending.is_empty() {
+                true => vec![],
+                false => self.describe(&pending).await,
+            },
+        })
+    }
+
+    // Formats the current track, Spotify's queue and the pending items as
+    // tables with the given columns.
+    pub async fn queue(&self, columns: &[Column]) -> Result<String, ApiError> {
+        let contents = self.contents().await?;
+        let current: Vec<TrackRecord> = contents.current.into_iter().collect();
+
+        Ok(format!(
+            "Now Playing:\n{}\nQueue:\n{}\nPending:\n{}",
+            formatter::print_track_table(&current, columns, 1),
+            formatter::print_track_table(&contents.queued, columns, 1),
+            formatter::print_track_table(&contents.pending, columns, 1),
+        ))
+    }
+
+    // Fetches every page of an offset paged endpoint and extracts a URI from
+    // each item.
+    async fn paged_uris<T: DeserializeOwned>(
+        &self,
+        list: PagedList,
+        extract: fn(&T) -> Option<String>,
+    ) -> Result<Vec<String>, ApiError> {
+        let items: Vec<T> = self
+            .api_manager
+            .collect_paged(list, &PageRange::all())
+            .await?;
+        Ok(items.iter().filter_map(extract).collect())
+    }
+
+    async fn album_tracks(&self, id: &str) -> Result<Vec<String>, ApiError> {
+        self.paged_uris(
+            PagedList::offset(format!("api/spt-fwd/albums/{}/tracks", id), 50),
+            |track: &Track| Some(track.uri.clone()),
+        )
+        .await
+    }
+
+    async fn playlist_tracks(&self, id: &str) -> Result<Vec<String>, ApiError> {
+        let uris = self
+            .paged_uris(
+                PagedList::offset(format!("api/spt-fwd/playlists/{}/tracks", id), 100),
+                |item: &PlaylistItem| item.track.as_ref().map(|t| t.uri().to_string()),
+            )
+            .await?;
+
+        // local files and unavailable items can't be queued
+        Ok(uris
+            .into_iter()
+            .filter(|uri| {
+                matches!(
+                    uri_helper::get_uri_type(uri),
+                    UriType::Track | UriType::Episode
+                )
+            })
+            .collect())
+    }
+
+    async fn artist_tracks(&self, id: &str, discography: bool) -> Result<Vec<String>, ApiError> {
+        if !discography {
+            let params = HashMap::from([("market".to_string(), "from_token".to_string())]);
+            let top: Tracks = self
+                .api_manager
+                .get_model(
+                    &format!("api/spt-fwd/artists/{}/top-tracks", id),
+                    Some(params),
+                )
+                .await?;
+            return Ok(top.tracks.into_iter().flatten().map(|t| t.uri).collect());
+        }
+
+        let list = PagedList::offset(format!("api/spt-fwd/artists/{}/albums", id), 50)
+            .param("include_groups", "album,single");
+        let albums = self
+            .paged_uris(list, |album: &Album| album.id.clone())
+            .await?;
+
+        let mut uris = Vec::new();
+        for album_id in albums {
+            uris.extend(self.album_tracks(&album_id).await?);
+        }
+        Ok(uris)
+    }
+
+    // Expands album, playlist and artist URIs into the tracks they contain.
+    // Track and episode URIs are kept as is.
+    async fn expand(&self, uris: Vec<String>, discography: bool) -> Expansion {
+        let mut expansion = Expansion::default();
+
+        for uri in uris {
+            let uri_type = uri_helper::get_uri_type(&uri);
+            let id = uri_helper::get_id_from_uri(&uri).unwrap_or_default();
+
+            let res = match uri_type {
+                UriType::Track | UriType::Episode => Ok(vec![uri.clone()]),
+                UriType::Album => {
+                    expansion.albums += 1;
+                    self.album_tracks(&id).await
+                }
+                UriType::Playlist => {
+                    expansion.playlists += 1;
+                    self.playlist_tracks(&id).await
+                }
+                UriType::Artist => {
+                    expansion.artists += 1;
+                    self.artist_tracks(&id, discography).await
+                }
+                UriType::Show | UriType::User | UriType::Collection | UriType::Unknown => {
+                    expansion.errors.push(format!("Cannot queue {}.", uri));
+                    continue;
+                }
+            };
+
+            match res {
+                Ok(tracks) => expansion.uris.extend(tracks),
+                Err(err) => expansion
+                    .errors
+                    .push(format!("Failed to expand {}: {}", uri, err)),
+            }
+        }
+
+        expansion
+    }
+
+    async fn add_expanded(
+        &self,
+        op: &str,
+        uris: Vec<String>,
+        opts: AddOptions,
+    ) -> Result<String, ApiError> {
+        let mut expansion = self.expand(uris, opts.discography).await;
+
+        if opts.shuffle {
+            expansion.uris.shuffle(&mut rand::thread_rng());
+        }
+        if let Some(limit) = opts.limit {
+            expansion.uris.truncate(limit);
+        }
+
+        // the server started for this command stops with it, taking the
+        // virtual queue along, so the tracks go to Spotify at once
+        let direct = !self.api_manager.server_outlives_command();
+
+        let n = expansion.uris.len();
+        if n > 0 {
+            match direct {
+                true => self.push_direct(&expansion.uris).await?,
+                false => {
+                    self.op(op, json!({ "uris": expansion.uris })).await?;
+                }
+            }
+        }
+
+        let sources: Vec<String> = [
+            (expansion.albums, "album"),
+            (expansion.playlists, "playlist"),
+            (expansion.artists, "artist"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, word)| plural(*count, word))
+        .collect();
+
+        // Spotify's queue can only be appended to
+        let summary = if op == "add" || direct {
+            format!("Added {} to queue", plural(n, "track"))
+        } else {
+            format!("Inserted {} at the front of the queue", plural(n, "track"))
+        };
+        let mut output = vec![if sources.is_empty() {
+            format!("{}.", summary)
+        } else {
+            format!("{} from {}.", summary, sources.join(", "))
+        }];
+        output.extend(expansion.errors);
+
+        Ok(output.join("\n"))
+    }
+
+    pub async fn add(&self, uris: Vec<String>, opts: AddOptions) -> Result<String, ApiError> {
+        self.add_expanded("add", uris, opts).await
+    }
+
+    // Same as add, but places the tracks at the front of the queue.
+    pub async fn insert_next(
+        &self,
+        uris: Vec<String>,
+        opts: AddOptions,
+    ) -> Result<String, ApiError> {
+        self.add_expanded("insert-next", uris, opts).await
+    }
+
+    // Removes the nth (1-based) pending item. Fails with ResponseError400 if
+    // there is no such item.
+    pub async fn remove(&self, n: usize) -> Result<String, ApiError> {
+        if n == 0 {
+            return Err(ApiError::ResponseError400);
+        }
+        self.op("remove", json!({ "index": n - 1 })).await?;
+        Ok(format!("Removed item {} from queue.", n))
+    }
+
+    // Moves the pending item at position a (1-based) to position b. Fails
+    // with ResponseError400 if either position is out of range.
+    pub async fn move_item(&self, a: usize, b: usize) -> Result<String, ApiError> {
+        if a == 0 || b == 0 {
+            return Err(ApiError::ResponseError400);
+        }
+        self.op("move", json!({ "from": a - 1, "to": b - 1 }))
+            .await?;
+        Ok(format!("Moved item {} to position {}.", a, b))
+    }
+
+    pub async fn clear(&self) -> Result<String, ApiError> {
+        self.op("clear", Value::Null).await?;
+        Ok("Cleared queue.".to_string())
+    }
+
+    pub async fn shuffle(&self) -> Result<String, ApiError> {
+        let pending = self.op("shuffle", Value::Null).await?;
+        Ok(format!("Shuffled {} queued items.", pending.len()))
     }
 }
@@ -0,0 +1,299 @@
+use crate::client::cli::value::TrackRecord;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{
+    Artist, PlayHistory, Recommendations, RelatedArtists, SearchResults, Track, Tracks,
+};
+use crate::util::uri_helper;
+use log::{debug, info};
+use rand::seq::SliceRandom;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Spotify takes at most this many seeds, of all kinds together.
+const MAX_SEEDS: usize = 5;
+
+// Artists whose top tracks make up a locally generated radio.
+const MAX_LOCAL_ARTISTS: usize = 20;
+
+// What a radio starts from, as Spotify IDs and genre names.
+#[derive(Debug, Clone, Default)]
+pub struct RadioSeeds {
+    pub tracks: Vec<String>,
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
+}
+
+impl RadioSeeds {
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty() && self.artists.is_empty() && self.genres.is_empty()
+    }
+
+    // Keeps the first five seeds, tracks before artists before genres.
+    pub fn truncate(&mut self) {
+        let mut room = MAX_SEEDS;
+        for list in [&mut self.tracks, &mut self.artists, &mut self.genres] {
+            list.truncate(room);
+            room -= list.len();
+        }
+    }
+}
+
+// Tunable attributes of a radio.
+#[derive(Debug, Clone)]
+pub struct RadioOptions {
+    pub limit: usize,
+    pub target_energy: Option<f64>, // 0.0 to 1.0
+    pub tempo: Option<(f64, f64)>,  // beats per minute
+    pub popularity: Option<(u32, u32)>,
+    pub keep_recent: bool, // keep tracks played recently
+}
+
+impl Default for RadioOptions {
+    fn default() -> Self {
+        RadioOptions {
+            limit: 20,
+            target_energy: None,
+            tempo: None,
+            popularity: None,
+            keep_recent: false,
+        }
+    }
+}
+
+// Related artists and top tracks change slowly, so the local fallback keeps
+// them in files under RADIO_CACHE_DIR for RADIO_CACHE_HOURS.
+fn cache_path(kind: &str, id: &str) -> PathBuf {
+    PathBuf::from(env::var("RADIO_CACHE_DIR").unwrap_or("data/radio".to_string()))
+        .join(kind)
+        .join(format!("{}.json", id))
+}
+
+fn cache_max_age() -> Duration {
+    let hours = env::var("RADIO_CACHE_HOURS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(24);
+    Duration::from_secs(hours * 3600)
+}
+
+fn read_cached<T: DeserializeOwned>(kind: &str, id: &str) -> Option<T> {
+    let path = cache_path(kind, id);
+    let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+    if age > cache_max_age() {
+        return None;
+    }
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+// Failing to write the cache only costs a later lookup.
+fn write_cached<T: Serialize>(kind: &str, id: &str, value: &T) {
+    let path = cache_path(kind, id);
+    let res = fs::create_dir_all(path.parent().unwrap_or(&path))
+        .and_then(|_| fs::write(&path, serde_json::to_string(value).unwrap_or_default()));
+    if let Err(e) = res {
+        debug!("Failed to cache {}: {}", path.display(), e);
+    }
+}
+
+// Not every response includes artist IDs, but all have their URIs.
+fn artist_id(artist: &Artist) -> Option<String> {
+    artist
+        .id
+        .clone()
+        .or_else(|| uri_helper::get_id_from_uri(artist.uri.as_deref()?))
+}
+
+// Builds track lists from seeds, through Spotify's recommendations or, where
+// those aren't available, from the top tracks of the seeds' related artists.
+#[derive(Debug)]
+pub struct RadioManager<'a> {
+    api_manager: &'a ApiProxy,
+}
+
+impl<'a> RadioManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        RadioManager { api_manager }
+    }
+
+    // Returns up to opts.limit tracks for the seeds, leaving out the seeds
+    // themselves and, unless opts.keep_recent is set, recently played tracks.
+    pub async fn radio(
+        &self,
+        seeds: &RadioSeeds,
+        opts: &RadioOptions,
+    ) -> Result<Vec<TrackRecord>, ApiError> {
+        let mut exclude: HashSet<String> = seeds
+            .tracks
+            .iter()
+            .map(|id| format!("spotify:track:{}", id))
+            .collect();
+        if !opts.keep_recent {
+            let list = PagedList::cursor("api/spt-fwd/me/player/recently-played", 50);
+            let recent: Vec<PlayHistory> = self
+                .api_manager
+                .collect_paged(list, &PageRange::first(50))
+                .await?;
+            exclude.extend(recent.into_iter().map(|item| item.track.uri));
+        }
+
+        let candidates = match self.recommendations(seeds, opts).await {
+            Ok(tracks) => tracks,
+            // apps registered after late 2024 are refused recommendations
+            Err(ApiError::ResponseError403 | ApiError::ResponseError404) => {
+                info!("Recommendations unavailable, generating a radio locally.");
+                self.local_radio(seeds, opts).await?
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut seen = HashSet::new();
+        Ok(candidates
+            .iter()
+            .filter(|t| !exclude.contains(&t.uri) && seen.insert(t.uri.clone()))
+            .take(opts.limit)
+            .map(TrackRecord::from)
+            .collect())
+    }
+
+    async fn recommendations(
+        &self,
+        seeds: &RadioSeeds,
+        opts: &RadioOptions,
+    ) -> Result<Vec<Track>, ApiError> {
+        // ask for more than needed, to make up for the ones left out
+        let mut params = HashMap::from([(
+            "limit".to_string(),
+            (opts.limit * 2).clamp(1, 100).to_string(),
+        )]);
+        for (name, ids) in [
+            ("seed_tracks", &seeds.tracks),
+            ("seed_artists", &seeds.artists),
+            ("seed_genres", &seeds.genres),
+        ] {
+            if !ids.is_empty() {
+                params.insert(name.to_string(), ids.join(","));
+            }
+        }
+        if let Some(energy) = opts.target_energy {
+            params.insert("target_energy".to_string(), energy.to_string());
+        }
+        if let Some((min, max)) = opts.tempo {
+            params.insert("min_tempo".to_string(), min.to_string());
+            params.insert("max_tempo".to_string(), max.to_string());
+        }
+        if let Some((min, max)) = opts.popularity {
+            params.insert("min_popularity".to_string(), min.to_string());
+            params.insert("max_popularity".to_string(), max.to_string());
+        }
+
+        let found: Recommendations = self
+            .api_manager
+            .get_model("api/spt-fwd/recommendations", Some(params))
+            .await?;
+        Ok(found.tracks)
+    }
+
+    // Top tracks of the seed artists, the artists of the seed tracks, artists
+    // found by genre and the artists related to all of them, shuffled. Energy
+    // and tempo aren't known without recommendations, so only popularity
+    // applies.
+    async fn local_radio(
+        &self,
+        seeds: &RadioSeeds,
+        opts: &RadioOptions,
+    ) -> Result<Vec<Track>, ApiError> {
+        let mut artists = seeds.artists.clone();
+        if !seeds.tracks.is_empty() {
+            let params = HashMap::from([("ids".to_string(), seeds.tracks.join(","))]);
+            let found: Tracks = self
+                .api_manager
+                .get_model("api/spt-fwd/tracks", Some(params))
+                .await?;
+            artists.extend(
+                found
+                    .tracks
+                    .iter()
+                    .flatten()
+                    .flat_map(|t| t.artists.first())
+                    .filter_map(artist_id),
+            );
+        }
+        for genre in &seeds.genres {
+            artists.extend(self.genre_artists(genre).await?);
+        }
+
+        let mut pool = artists.clone();
+        for id in &artists {
+            // not every artist has related artists on record
+            match self.related_artists(id).await {
+                Ok(related) => pool.extend(related.iter().filter_map(artist_id)),
+                Err(err) => debug!("No related artists for {}: {}", id, err),
+            }
+        }
+        let mut seen = HashSet::new();
+        pool.retain(|id| seen.insert(id.clone()));
+        pool.truncate(MAX_LOCAL_ARTISTS);
+
+        let mut tracks = Vec::new();
+        for id in &pool {
+            tracks.extend(self.top_tracks(id).await?);
+        }
+        if let Some((min, max)) = opts.popularity {
+            tracks.retain(|t| t.popularity.is_some_and(|p| (min..=max).contains(&p)));
+        }
+        tracks.shuffle(&mut rand::thread_rng());
+        Ok(tracks)
+    }
+
+    async fn genre_artists(&self, genre: &str) -> Result<Vec<String>, ApiError> {
+        let params = HashMap::from([
+            ("q".to_string(), format!("genre:\"{}\"", genre)),
+            ("type".to_string(), "artist".to_string()),
+            ("limit".to_string(), MAX_SEEDS.to_string()),
+        ]);
+        let found: SearchResults = self
+            .api_manager
+            .get_model("api/spt-fwd/search", Some(params))
+            .await?;
+        Ok(found
+            .artists
+            .map(|page| page.items.iter().filter_map(artist_id).collect())
+            .unwrap_or_default())
+    }
+
+    async fn related_artists(&self, id: &str) -> Result<Vec<Artist>, ApiError> {
+        if let Some(related) = read_cached("related", id) {
+            return Ok(related);
+        }
+        let found: RelatedArtists = self
+            .api_manager
+            .get_model(&format!("api/spt-fwd/artists/{}/related-artists", id), None)
+            .await?;
+        write_cached("related", id, &found.artists);
+        Ok(found.artists)
+    }
+
+    async fn top_tracks(&self, id: &str) -> Result<Vec<Track>, ApiError> {
+        if let Some(tracks) = read_cached("top-tracks", id) {
+            return Ok(tracks);
+        }
+        let params = HashMap::from([("market".to_string(), "from_token".to_string())]);
+        let found: Tracks = self
+            .api_manager
+            .get_model(
+                &format!("api/spt-fwd/artists/{}/top-tracks", id),
+                Some(params),
+            )
+            .await?;
+        let tracks: Vec<Track> = found.tracks.into_iter().flatten().collect();
+        write_cached("top-tracks", id, &tracks);
+        Ok(tracks)
+    }
+}
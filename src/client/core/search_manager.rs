@@ -1,14 +1,61 @@
-use crate::core::api_manager::ApiManager;
+use crate::client::cli::value::TrackRecord;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{Track, Tracks};
+use crate::util::uri_helper::{self, UriType};
+use std::collections::HashMap;
 
 #[derive(Debug)]
-pub struct SearchManager {
-    api_manager: &'static ApiManager,
+pub struct SearchManager<'a> {
+    api_manager: &'a ApiProxy,
 }
 
-impl SearchManager {
-    pub fn new() -> Self {
-        SearchManager {
-            api_manager: ApiManager::get_instance(),
+impl<'a> SearchManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        SearchManager { api_manager }
+    }
+
+    // Searches Spotify's catalog for tracks matching the query, returning the
+    // results within the range.
+    pub async fn search(
+        &self,
+        query: &str,
+        range: &PageRange,
+    ) -> Result<Vec<TrackRecord>, ApiError> {
+        let list = PagedList::offset("api/spt-fwd/search", 50)
+            .param("q", query)
+            .param("type", "track")
+            .in_field("tracks");
+        let tracks: Vec<Track> = self.api_manager.collect_paged(list, range).await?;
+
+        Ok(tracks.iter().map(TrackRecord::from).collect())
+    }
+
+    // Looks up track details for the given URIs, in the given order. URIs that
+    // aren't tracks or couldn't be found are left out.
+    pub async fn describe(&self, uris: &[String]) -> Result<Vec<TrackRecord>, ApiError> {
+        let ids: Vec<String> = uris
+            .iter()
+            .filter(|uri| matches!(uri_helper::get_uri_type(uri), UriType::Track))
+            .filter_map(|uri| uri_helper::get_id_from_uri(uri))
+            .collect();
+
+        let mut tracks: HashMap<String, TrackRecord> = HashMap::new();
+        for chunk in ids.chunks(50) {
+            let params = HashMap::from([("ids".to_string(), chunk.join(","))]);
+            let found: Tracks = self
+                .api_manager
+                .get_model("api/spt-fwd/tracks", Some(params))
+                .await?;
+            for track in found.tracks.iter().flatten() {
+                tracks.insert(track.uri.clone(), TrackRecord::from(track));
+            }
         }
+
+        Ok(uris
+            .iter()
+            .filter_map(|uri| tracks.get(uri).cloned())
+            .collect())
     }
 }
@@ -0,0 +1,348 @@
+use crate::client::core::playlist_manager::{self, count_tracks, PlaylistManager};
+use crate::client::core::track_query::{self, Condition, Field, SortKey, TrackInfo};
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::{PageRange, PagedList};
+use crate::util::errors::ApiError;
+use crate::util::models::{PlayHistory, PlayableItem, SavedTrack, Track};
+use crate::util::uri_helper::{SpotifyId, UriType};
+use futures_util::{pin_mut, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+// Where the tracks of a smart playlist come from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Source {
+    Liked,
+    Recent,
+    Top,
+    Playlist(String), // ID
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim() {
+            "liked" => Ok(Source::Liked),
+            "recent" => Ok(Source::Recent),
+            "top" => Ok(Source::Top),
+            other => match SpotifyId::parse(other, Some(UriType::Playlist)) {
+                Ok(id) if id.uri_type == UriType::Playlist => Ok(Source::Playlist(id.id)),
+                _ => Err(format!(
+                    "unknown source '{}' (expected liked, recent, top or a playlist)",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Source {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Source> for String {
+    fn from(source: Source) -> Self {
+        source.to_string()
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Liked => write!(f, "liked"),
+            Source::Recent => write!(f, "recent"),
+            Source::Top => write!(f, "top"),
+            Source::Playlist(id) => write!(f, "spotify:playlist:{}", id),
+        }
+    }
+}
+
+// The tracks of the source that meet every condition, in the order of sort
+// or else of the source, at most limit of them. Stored as JSON, e.g.
+//
+//   {"source":"liked","where":["age<30","energy>0.6"],"sort":"tempo","limit":100}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartRule {
+    pub source: Source,
+    #[serde(default, rename = "where")]
+    pub conditions: Vec<Condition>,
+    pub sort: Option<SortKey>,
+    pub limit: Option<usize>,
+}
+
+impl SmartRule {
+    // e.g. "liked where age<30, energy>0.6, by tempo, at most 100"
+    pub fn describe(&self) -> String {
+        let mut text = self.source.to_string();
+        if !self.conditions.is_empty() {
+            let conditions: Vec<String> = self.conditions.iter().map(|c| c.to_string()).collect();
+            text.push_str(&format!(" where {}", conditions.join(", ")));
+        }
+        if let Some(sort) = &self.sort {
+            text.push_str(&format!(", by {}", sort));
+        }
+        if let Some(limit) = self.limit {
+            text.push_str(&format!(", at most {}", limit));
+        }
+        text
+    }
+
+    fn needs_features(&self) -> bool {
+        self.conditions.iter().any(|c| c.field.needs_features())
+            || self.sort.is_some_and(|s| s.field.needs_features())
+    }
+
+    // The most days ago a track may have been added, if a condition says.
+    fn max_age(&self) -> Option<f64> {
+        self.conditions
+            .iter()
+            .filter(|c| c.field == Field::Age && matches!(c.op.as_str(), "<" | "<="))
+            .filter_map(|c| c.value.parse::<f64>().ok())
+            .reduce(f64::min)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub name: String,
+    // the Spotify playlist it's synced to, None before the first sync
+    pub playlist_id: Option<String>,
+    pub rule: SmartRule,
+}
+
+#[derive(Debug, Deserialize)]
+struct SmartPlaylists {
+    items: Vec<SmartPlaylist>,
+}
+
+// Changes a sync made, or would make.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub created: bool,
+    pub added: usize,
+    pub removed: usize,
+    pub moved: usize,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        !self.created && self.added == 0 && self.removed == 0 && self.moved == 0
+    }
+
+    // e.g. "added 2 tracks, removed 1 track and moved 3 tracks"
+    pub fn describe(&self, dry_run: bool) -> String {
+        let verbs = match dry_run {
+            true => ["add", "remove", "move"],
+            false => ["added", "removed", "moved"],
+        };
+        let parts: Vec<String> = [self.added, self.removed, self.moved]
+            .into_iter()
+            .zip(verbs)
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, verb)| format!("{} {}", verb, count_tracks(n)))
+            .collect();
+        match parts.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => "nothing to change".to_string(),
+        }
+    }
+}
+
+// Smart playlists: rules kept in the local database, and the Spotify
+// playlists they're synced to.
+#[derive(Debug)]
+pub struct SmartPlaylistManager<'a> {
+    api_manager: &'a ApiProxy,
+}
+
+impl<'a> SmartPlaylistManager<'a> {
+    pub fn new(api_manager: &'a ApiProxy) -> Self {
+        SmartPlaylistManager { api_manager }
+    }
+
+    pub async fn list(&self) -> Result<Vec<SmartPlaylist>, ApiError> {
+        let found: SmartPlaylists = self
+            .api_manager
+            .get_model("api/smart-playlists", None)
+            .await?;
+        Ok(found.items)
+    }
+
+    // Finds a definition by name, ignoring case.
+    pub async fn get(&self, name: &str) -> Result<Option<SmartPlaylist>, ApiError> {
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .find(|smart| smart.name.eq_ignore_ascii_case(name)))
+    }
+
+    // Adds a definition or replaces the one of the same name.
+    pub async fn save(&self, smart: &SmartPlaylist) -> Result<(), ApiError> {
+        self.api_manager
+            .put("api/smart-playlists", Some(json!(smart)), None)
+            .await?;
+        Ok(())
+    }
+
+    // Removes a definition, leaving its Spotify playlist as it is. Returns
+    // whether there was one.
+    pub async fn remove(&self, name: &str) -> Result<bool, ApiError> {
+        let params = HashMap::from([("name".to_string(), name.to_string())]);
+        let (_, json) = self
+            .api_manager
+            .delete("api/smart-playlists", None, Some(params))
+            .await?;
+        Ok(json["removed"].as_bool().unwrap_or(false))
+    }
+
+    // The tracks the rule selects, as of now.
+    pub async fn tracks(&self, rule: &SmartRule) -> Result<Vec<TrackInfo>, ApiError> {
+        let mut tracks = self.read_source(rule).await?;
+        if rule.needs_features() {
+            track_query::load_features(self.api_manager, &mut tracks).await?;
+        }
+        tracks.retain(|t| rule.conditions.iter().all(|c| c.matches(t)));
+        if let Some(sort) = &rule.sort {
+            sort.sort(&mut tracks);
+        }
+        let mut seen = HashSet::new();
+        tracks.retain(|t| seen.insert(t.uri.clone()));
+        if let Some(limit) = rule.limit {
+            tracks.truncate(limit);
+        }
+        Ok(tracks)
+    }
+
+    async fn read_source(&self, rule: &SmartRule) -> Result<Vec<TrackInfo>, ApiError> {
+        match &rule.source {
+            Source::Liked => {
+                // saved tracks come latest first, so a maximum age ends the
+                // list early
+                let max_age = rule.max_age();
+                let saved = self.api_manager.paged::<SavedTrack>(
+                    PagedList::offset("api/spt-fwd/me/tracks", 50),
+                    &PageRange::all(),
+                );
+                pin_mut!(saved);
+                let mut tracks = Vec::new();
+                while let Some(saved) = saved.try_next().await? {
+                    let track = TrackInfo::new(&saved.track, saved.added_at.as_deref());
+                    if let (Some(max), Some(age)) = (max_age, track.age_days()) {
+                        if age > max {
+                            break;
+                        }
+                    }
+                    tracks.push(track);
+                }
+                Ok(tracks)
+            }
+            Source::Recent => {
+                let list = PagedList::cursor("api/spt-fwd/me/player/recently-played", 50);
+                let played: Vec<PlayHistory> = self
+                    .api_manager
+                    .collect_paged(list, &PageRange::first(50))
+                    .await?;
+                Ok(played
+                    .iter()
+                    .map(|p| TrackInfo::new(&p.track, Some(&p.played_at)))
+                    .collect())
+            }
+            Source::Top => {
+                let list = PagedList::offset("api/spt-fwd/me/top/tracks", 50);
+                let top: Vec<Track> = self
+                    .api_manager
+                    .collect_paged(list, &PageRange::all())
+                    .await?;
+                Ok(top.iter().map(|t| TrackInfo::new(t, None)).collect())
+            }
+            Source::Playlist(id) => {
+                let items = PlaylistManager::new(self.api_manager)
+                    .items(id, &PageRange::all())
+                    .await?;
+                Ok(items
+                    .iter()
+                    .filter_map(|item| match &item.track {
+                        Some(PlayableItem::Track(track)) => {
+                            Some(TrackInfo::new(track, item.added_at.as_deref()))
+                        }
+                        _ => None,
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    // Regenerates the smart playlist and brings its Spotify playlist up to
+    // date, only removing, adding and moving what differs. The first sync,
+    // or one after the playlist was deleted, creates it. With dry_run, only
+    // reports what would change.
+    pub async fn sync(
+        &self,
+        smart: &mut SmartPlaylist,
+        dry_run: bool,
+    ) -> Result<SyncReport, ApiError> {
+        let target: Vec<String> = self
+            .tracks(&smart.rule)
+            .await?
+            .into_iter()
+            .map(|t| t.uri)
+            .collect();
+        let playlists = PlaylistManager::new(self.api_manager);
+
+        let existing = match &smart.playlist_id {
+            Some(id) => match playlists.playlist(id).await {
+                Ok(playlist) => Some((id.clone(), playlist)),
+                Err(ApiError::ResponseError404) => None,
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+        let Some((id, playlist)) = existing else {
+            if !dry_run {
+                let created = playlists.create(&smart.name, &target).await?;
+                smart.playlist_id = created
+                    .id
+                    .or_else(|| SpotifyId::parse(&created.uri, None).ok().map(|id| id.id));
+                self.save(smart).await?;
+            }
+            return Ok(SyncReport {
+                created: true,
+                added: target.len(),
+                ..Default::default()
+            });
+        };
+
+        // unavailable entries keep their place, so that positions line up
+        let current = playlist_manager::item_uris(&playlists.items(&id, &PageRange::all()).await?);
+        let plan = playlist_manager::plan_edit(&current, &target);
+        let report = SyncReport {
+            created: false,
+            added: plan.add.len(),
+            removed: plan.remove.len(),
+            moved: playlist_manager::plan_moves(&plan.order, &target)
+                .iter()
+                .map(|(_, length, _)| length)
+                .sum(),
+        };
+        if dry_run || report.is_empty() {
+            return Ok(report);
+        }
+
+        playlists
+            .edit(&id, playlist.snapshot_id)
+            .apply(&plan, &target)
+            .await?;
+        Ok(report)
+    }
+}
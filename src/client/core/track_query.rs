@@ -0,0 +1,380 @@
+use crate::client::local_api_proxy::ApiProxy;
+use crate::util::errors::ApiError;
+use crate::util::models::{AudioFeatures, AudioFeaturesList, PlayableItem, PlaylistItem, Track};
+use crate::util::uri_helper;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Conditions on and orderings of tracks, e.g. "energy>0.6" or "tempo:desc",
+// as used by smart playlists and playlist sort. Audio features are only
+// fetched when a condition or ordering needs them.
+
+// A track with what conditions and orderings look at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub uri: String,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: u64,
+    pub popularity: Option<u32>,
+    pub isrc: Option<String>,
+    // when it was added, saved or played, unix time in ms
+    pub added_at: Option<i64>,
+    pub features: Option<AudioFeatures>,
+}
+
+impl TrackInfo {
+    pub fn new(track: &Track, added_at: Option<&str>) -> Self {
+        TrackInfo {
+            uri: track.uri.clone(),
+            name: track.name.clone(),
+            artists: track.artists.iter().map(|a| a.name.clone()).collect(),
+            album: track.album.as_ref().map(|a| a.name.clone()),
+            duration_ms: track.duration_ms,
+            popularity: track.popularity,
+            isrc: track.isrc().map(String::from),
+            added_at: timestamp(added_at),
+            features: None,
+        }
+    }
+
+    // A playlist entry, None if it's no longer available. Episodes have
+    // their show for artist.
+    pub fn from_item(item: &PlaylistItem) -> Option<Self> {
+        match item.track.as_ref()? {
+            PlayableItem::Track(track) => Some(TrackInfo::new(track, item.added_at.as_deref())),
+            PlayableItem::Episode(episode) => Some(TrackInfo {
+                uri: episode.uri.clone(),
+                name: episode.name.clone(),
+                artists: episode.show.iter().map(|s| s.name.clone()).collect(),
+                album: None,
+                duration_ms: episode.duration_ms,
+                popularity: None,
+                isrc: None,
+                added_at: timestamp(item.added_at.as_deref()),
+                features: None,
+            }),
+        }
+    }
+
+    // Days since the track was added, None if unknown.
+    pub fn age_days(&self) -> Option<f64> {
+        let added_at = self.added_at?;
+        Some((Utc::now().timestamp_millis() - added_at) as f64 / 86_400_000.0)
+    }
+}
+
+fn timestamp(time: Option<&str>) -> Option<i64> {
+    time.and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.timestamp_millis())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Added,
+    Age,
+    Popularity,
+    Duration,
+    Energy,
+    Tempo,
+    Danceability,
+    Valence,
+    Acousticness,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("title", Field::Title),
+    ("artist", Field::Artist),
+    ("album", Field::Album),
+    ("added", Field::Added),
+    ("age", Field::Age),
+    ("popularity", Field::Popularity),
+    ("duration", Field::Duration),
+    ("energy", Field::Energy),
+    ("tempo", Field::Tempo),
+    ("danceability", Field::Danceability),
+    ("valence", Field::Valence),
+    ("acousticness", Field::Acousticness),
+];
+
+impl Field {
+    pub fn parse(name: &str) -> Option<Field> {
+        FIELDS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name.trim()))
+            .map(|(_, field)| *field)
+    }
+
+    pub fn name(&self) -> &'static str {
+        FIELDS
+            .iter()
+            .find(|(_, field)| field == self)
+            .map_or("", |(name, _)| name)
+    }
+
+    pub fn needs_features(&self) -> bool {
+        matches!(
+            self,
+            Field::Energy
+                | Field::Tempo
+                | Field::Danceability
+                | Field::Valence
+                | Field::Acousticness
+        )
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, Field::Title | Field::Artist | Field::Album)
+    }
+
+    fn text(&self, track: &TrackInfo) -> String {
+        match self {
+            Field::Title => track.name.to_lowercase(),
+            Field::Artist => track.artists.join(", ").to_lowercase(),
+            Field::Album => track.album.clone().unwrap_or_default().to_lowercase(),
+            _ => String::new(),
+        }
+    }
+
+    // The field as a number, duration in seconds and age in days. None for
+    // text fields and unknown values.
+    fn number(&self, track: &TrackInfo) -> Option<f64> {
+        let features = track.features.as_ref();
+        match self {
+            Field::Added => track.added_at.map(|t| t as f64),
+            Field::Age => track.age_days(),
+            Field::Popularity => track.popularity.map(f64::from),
+            Field::Duration => Some(track.duration_ms as f64 / 1000.0),
+            Field::Energy => features.map(|f| f.energy),
+            Field::Tempo => features.map(|f| f.tempo),
+            Field::Danceability => features.map(|f| f.danceability),
+            Field::Valence => features.map(|f| f.valence),
+            Field::Acousticness => features.map(|f| f.acousticness),
+            Field::Title | Field::Artist | Field::Album => None,
+        }
+    }
+}
+
+// Operators in the order they're looked for, longest first.
+const OPS: &[&str] = &[">=", "<=", "!=", ">", "<", "=", "~"];
+
+// A condition like "energy>0.6", "age<30" or "artist~bowie", where ~ means
+// contains. Text fields take =, != and ~, the others compare numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Condition {
+    pub field: Field,
+    pub op: String,
+    pub value: String,
+}
+
+impl Condition {
+    pub fn matches(&self, track: &TrackInfo) -> bool {
+        if self.field.is_text() {
+            let (text, value) = (self.field.text(track), self.value.to_lowercase());
+            return match self.op.as_str() {
+                "=" => text == value,
+                "!=" => text != value,
+                _ => text.contains(&value),
+            };
+        }
+        // tracks without the value never match
+        let (Some(n), Ok(value)) = (self.field.number(track), self.value.parse::<f64>()) else {
+            return false;
+        };
+        match self.op.as_str() {
+            ">=" => n >= value,
+            "<=" => n <= value,
+            "!=" => n != value,
+            ">" => n > value,
+            "<" => n < value,
+            _ => n == value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let Some((at, op)) = OPS
+            .iter()
+            .filter_map(|op| text.find(op).map(|at| (at, *op)))
+            .min_by_key(|(at, op)| (*at, std::cmp::Reverse(op.len())))
+        else {
+            return Err(format!(
+                "expected a condition like energy>0.6, got '{}'",
+                text
+            ));
+        };
+        let (name, value) = (&text[..at], text[at + op.len()..].trim());
+        let field = Field::parse(name).ok_or_else(|| format!("unknown field '{}'", name.trim()))?;
+        if value.is_empty() {
+            return Err(format!("missing value in '{}'", text));
+        }
+        match (field.is_text(), op) {
+            (true, "=" | "!=" | "~") => {}
+            (true, _) => return Err(format!("{} takes =, != or ~", field.name())),
+            (false, "~") => return Err(format!("{} takes a number to compare", field.name())),
+            (false, _) if value.parse::<f64>().is_err() => {
+                return Err(format!(
+                    "expected a number for {}, got '{}'",
+                    field.name(),
+                    value
+                ))
+            }
+            (false, _) => {}
+        }
+        Ok(Condition {
+            field,
+            op: op.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Condition> for String {
+    fn from(condition: Condition) -> Self {
+        condition.to_string()
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.field.name(), self.op, self.value)
+    }
+}
+
+// Parses a comma separated list of conditions.
+pub fn parse_conditions(list: &str) -> Result<Vec<Condition>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+// An ordering like "tempo" or "added:desc".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SortKey {
+    pub field: Field,
+    pub descending: bool,
+}
+
+impl SortKey {
+    // Sorts the tracks, stably. Tracks without the value go last.
+    pub fn sort(&self, tracks: &mut [TrackInfo]) {
+        tracks.sort_by(|a, b| self.compare(a, b));
+    }
+
+    pub fn compare(&self, a: &TrackInfo, b: &TrackInfo) -> std::cmp::Ordering {
+        let field = self.field;
+        let order = if field.is_text() {
+            field.text(a).cmp(&field.text(b))
+        } else {
+            match (field.number(a), field.number(b)) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (Some(_), None) => return std::cmp::Ordering::Less,
+                (None, Some(_)) => return std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            }
+        };
+        if self.descending {
+            order.reverse()
+        } else {
+            order
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (name, descending) = match text.trim().split_once(':') {
+            Some((name, "desc")) => (name, true),
+            Some((name, "asc")) => (name, false),
+            Some((_, order)) => {
+                return Err(format!("unknown order '{}' (expected asc or desc)", order))
+            }
+            None => (text, false),
+        };
+        match Field::parse(name) {
+            Some(Field::Age) | None => Err(format!("can't sort by '{}'", name.trim())),
+            Some(field) => Ok(SortKey { field, descending }),
+        }
+    }
+}
+
+impl TryFrom<String> for SortKey {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<SortKey> for String {
+    fn from(key: SortKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.descending {
+            true => write!(f, "{}:desc", self.field.name()),
+            false => write!(f, "{}", self.field.name()),
+        }
+    }
+}
+
+// Fills in the audio features of the tracks, 100 per request. Tracks Spotify
+// has no analysis of are left without.
+pub async fn load_features(
+    api_manager: &ApiProxy,
+    tracks: &mut [TrackInfo],
+) -> Result<(), ApiError> {
+    let ids: Vec<String> = tracks
+        .iter()
+        .filter(|t| t.features.is_none())
+        .filter_map(|t| uri_helper::get_id_from_uri(&t.uri))
+        .collect();
+
+    let mut features: HashMap<String, AudioFeatures> = HashMap::new();
+    for chunk in ids.chunks(100) {
+        let params = HashMap::from([("ids".to_string(), chunk.join(","))]);
+        let found: AudioFeaturesList = api_manager
+            .get_model("api/spt-fwd/audio-features", Some(params))
+            .await?;
+        features.extend(
+            found
+                .audio_features
+                .into_iter()
+                .flatten()
+                .map(|f| (f.uri.clone(), f)),
+        );
+    }
+    for track in tracks.iter_mut() {
+        if let Some(f) = features.get(&track.uri) {
+            track.features = Some(f.clone());
+        }
+    }
+    Ok(())
+}
@@ -1,15 +1,16 @@
 // use once_cell::sync::OnceCell;
 use crate::server::web::server::start_server;
 use crate::util::errors::{self, return_response_code, ApiError};
+use crate::util::models;
 use log::{debug, error, info};
 use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::env;
-use std::process::Command;
 use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ApiProxy {
     client: Client,
     client_id: Option<u64>,
@@ -17,6 +18,8 @@ pub struct ApiProxy {
     server_port: u16,
     server_timeout: Duration,
     max_server_retries: u8,
+    started_server: bool, // the server runs inside this process
+    interactive: bool,    // this process outlives a single command
 }
 
 impl ApiProxy {
@@ -44,6 +47,8 @@ impl ApiProxy {
             server_port,
             server_timeout,
             max_server_retries,
+            started_server: false,
+            interactive: false,
         };
 
         return api_manager;
@@ -54,7 +59,7 @@ impl ApiProxy {
 
         debug!("Setting up client API proxy.");
 
-        self.check_server(0).await?;
+        self.started_server = self.check_server(0).await?;
 
         let client_id =
             ApiProxy::get_client_id(&self.client, format!("{}/{}", self.base_url, "init")).await;
@@ -107,15 +112,28 @@ impl ApiProxy {
         }
     }
 
-    // Method to check if server is running, and if not, start it
-    pub async fn check_server(&self, retry: u8) -> Result<(), ApiError> {
+    // Marks this process as a REPL or TUI session, which keeps an embedded
+    // server running between commands
+    pub fn set_interactive(&mut self) {
+        self.interactive = true;
+    }
+
+    // Whether the server keeps running once the current command is done,
+    // which is what state kept on it, like the virtual queue, relies on
+    pub fn server_outlives_command(&self) -> bool {
+        !self.started_server || self.interactive
+    }
+
+    // Method to check if server is running, and if not, start it. Returns
+    // whether it had to be started.
+    pub async fn check_server(&self, retry: u8) -> Result<bool, ApiError> {
         let url = format!("{}/ping", self.base_url);
 
         let response = self.client.get(&url).send().await;
 
         if response.is_ok() && response.unwrap().status().as_u16() == 200 {
             info!("Client found server running.");
-            return Ok(());
+            return Ok(false);
         } else {
             if retry >= self.max_server_retries {
                 return Err(ApiError::InternalServerError);
@@ -127,7 +145,7 @@ impl ApiProxy {
             //     .spawn()
             //     .expect("Failed to start server script");
             // self.check_server(retry + 1).await?;
-            return Ok(());
+            return Ok(true);
         }
     }
 
@@ -178,6 +196,17 @@ impl ApiProxy {
         }
     }
 
+    // Sends a GET request and deserializes the response into one of the
+    // models. Responses without content only fit an Option.
+    pub async fn get_model<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        params: Option<HashMap<String, String>>,
+    ) -> Result<T, ApiError> {
+        let (status, json) = self.get(endpoint, params).await?;
+        models::from_response(status, json)
+    }
+
     // Method for sending POST requests to the Spotify API
     pub async fn post(
         &self,
@@ -216,7 +245,8 @@ impl ApiProxy {
 
         // match status code
         match status.as_u16() {
-            200 => {
+            // 201 for created playlists and added items
+            200 | 201 => {
                 let json = match response.json::<Value>().await {
                     Ok(data) => data,
                     Err(_) => {
@@ -307,7 +337,7 @@ impl ApiProxy {
 
         let request = self
             .client
-            .post(&url)
+            .delete(&url)
             .query(&q_params)
             .json(&body.unwrap_or_default());
 
@@ -0,0 +1,203 @@
+use crate::client::local_api_proxy::ApiProxy;
+use crate::util::errors::ApiError;
+use crate::util::models::{self, CursorPaging, Paging};
+use futures_util::stream::{self, Stream, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use url::Url;
+
+// Spotify pages its lists in one of two ways: by limit and offset, e.g. saved
+// tracks, playlists and search results, or by timestamp cursors, e.g.
+// recently played tracks. Both are read through ApiProxy::paged as a stream of
+// items, following the next links page by page.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageStyle {
+    Offset,
+    Cursor,
+}
+
+// A paged list endpoint on the forwarding route.
+#[derive(Debug, Clone)]
+pub struct PagedList {
+    pub endpoint: String,
+    pub params: HashMap<String, String>,
+    pub style: PageStyle,
+    // the largest limit the endpoint accepts
+    pub page_size: usize,
+    // the field holding the page, for responses that wrap it, e.g. search
+    pub field: Option<&'static str>,
+}
+
+impl PagedList {
+    pub fn offset(endpoint: impl Into<String>, page_size: usize) -> Self {
+        PagedList {
+            endpoint: endpoint.into(),
+            params: HashMap::new(),
+            style: PageStyle::Offset,
+            page_size,
+            field: None,
+        }
+    }
+
+    pub fn cursor(endpoint: impl Into<String>, page_size: usize) -> Self {
+        PagedList {
+            style: PageStyle::Cursor,
+            ..PagedList::offset(endpoint, page_size)
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: impl ToString) -> Self {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn in_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+}
+
+// The part of a list to read, as set by --all, --limit, --offset, --before and
+// --after. Offsets only apply to offset paged lists, timestamps only to cursor
+// paged ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageRange {
+    // None reads the whole list
+    pub limit: Option<usize>,
+    pub offset: usize,
+    // unix timestamps in ms
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+}
+
+impl PageRange {
+    pub fn first(limit: usize) -> Self {
+        PageRange {
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    pub fn all() -> Self {
+        PageRange::default()
+    }
+}
+
+// Query of the next page to fetch and how many items are still wanted.
+type PageState = (Option<HashMap<String, String>>, Option<usize>);
+
+impl ApiProxy {
+    // Streams the items of a paged list in order. A page is only fetched once
+    // the items before it have been read, so taking a few items of a long
+    // list costs a single request.
+    pub fn paged<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        list: PagedList,
+        range: &PageRange,
+    ) -> impl Stream<Item = Result<T, ApiError>> + 'a {
+        let mut params = list.params.clone();
+        match list.style {
+            PageStyle::Offset if range.offset > 0 => {
+                params.insert("offset".to_string(), range.offset.to_string());
+            }
+            PageStyle::Offset => {}
+            PageStyle::Cursor => {
+                if let Some(before) = range.before {
+                    params.insert("before".to_string(), before.to_string());
+                }
+                if let Some(after) = range.after {
+                    params.insert("after".to_string(), after.to_string());
+                }
+            }
+        }
+
+        let start: PageState = (Some(params), range.limit);
+        stream::try_unfold(start, move |(params, remaining)| {
+            let list = list.clone();
+            async move {
+                let Some(mut params) = params else {
+                    return Ok(None);
+                };
+                if remaining == Some(0) {
+                    return Ok(None);
+                }
+
+                let size = remaining.map_or(list.page_size, |r| r.min(list.page_size));
+                params.insert("limit".to_string(), size.to_string());
+
+                let (mut items, next) = self.fetch_page::<T>(&list, params).await?;
+                // an empty page ends the list, whatever its links say
+                if items.is_empty() {
+                    return Ok(None);
+                }
+                let remaining = remaining.map(|r| {
+                    items.truncate(r);
+                    r - items.len()
+                });
+                Ok(Some((items, (next, remaining))))
+            }
+        })
+        .map_ok(|items: Vec<T>| stream::iter(items.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    // Reads every item of the range into a Vec.
+    pub async fn collect_paged<T: DeserializeOwned>(
+        &self,
+        list: PagedList,
+        range: &PageRange,
+    ) -> Result<Vec<T>, ApiError> {
+        self.paged(list, range).try_collect().await
+    }
+
+    // Fetches one page, returning its items and the query of the page after
+    // it, if there is one.
+    async fn fetch_page<T: DeserializeOwned>(
+        &self,
+        list: &PagedList,
+        params: HashMap<String, String>,
+    ) -> Result<(Vec<T>, Option<HashMap<String, String>>), ApiError> {
+        let (status, mut json) = self.get(&list.endpoint, Some(params.clone())).await?;
+        if let Some(field) = list.field {
+            json = json.get_mut(field).map(Value::take).unwrap_or(Value::Null);
+        }
+
+        match list.style {
+            PageStyle::Offset => {
+                let page: Paging<T> = models::from_response(status, json)?;
+                let next = page.next.as_deref().map(|url| next_params(&params, url));
+                Ok((page.items, next))
+            }
+            PageStyle::Cursor => {
+                let page: CursorPaging<T> = models::from_response(status, json)?;
+                // next links lead back in time, so reading forward from
+                // --after goes by the after cursor instead
+                let next = if params.contains_key("after") {
+                    page.cursors.and_then(|c| c.after).map(|after| {
+                        let mut next = params.clone();
+                        next.insert("after".to_string(), after);
+                        next
+                    })
+                } else {
+                    page.next.as_deref().map(|url| next_params(&params, url))
+                };
+                Ok((page.items, next))
+            }
+        }
+    }
+}
+
+// The query of a next link, over the one of the page it follows. The link
+// points at Spotify itself, so only its query is kept.
+fn next_params(params: &HashMap<String, String>, next: &str) -> HashMap<String, String> {
+    let mut merged = params.clone();
+    if let Ok(url) = Url::parse(next) {
+        merged.extend(
+            url.query_pairs()
+                .map(|(k, v)| (k.into_owned(), v.into_owned())),
+        );
+    }
+    merged
+}
@@ -0,0 +1,485 @@
+use crate::client::cli::cli_app::{self, Output};
+use crate::client::cli::eval::Session;
+use crate::client::cli::formatter;
+use crate::client::cli::value::{TrackRecord, Value};
+use crate::client::core::playback_manager::{PlaybackManager, PlayerState};
+use crate::client::core::playlist_manager::PlaylistManager;
+use crate::client::core::queue_manager::{AddOptions, QueueContents, QueueManager};
+use crate::client::core::search_manager::SearchManager;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::client::paging::PageRange;
+use crate::client::tui::view;
+use crate::util::errors::ApiError;
+use crate::util::models::{Device, Playlist};
+use log::debug;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::DefaultTerminal;
+use std::env;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify};
+
+// How often the screen is redrawn without new data, to move the progress bar.
+const TICK: Duration = Duration::from_millis(500);
+
+// Recent tracks, devices and playlists are fetched every this many refreshes.
+const SLOW_REFRESH_ROUNDS: u32 = 10;
+
+const SEEK_STEP_MS: u64 = 10_000;
+const VOLUME_STEP: u8 = 5;
+const SEARCH_LIMIT: usize = 20;
+
+// Output lines kept for the output pane.
+const MAX_OUTPUT_LINES: usize = 200;
+
+// Seconds between refreshes of the playback state and queue.
+fn refresh_interval() -> Duration {
+    Duration::from_secs(
+        env::var("TUI_REFRESH_SECONDS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(3)
+            .max(1),
+    )
+}
+
+// Everything shown in the panes, fetched by the refresh task.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub player: PlayerState,
+    pub queue: QueueContents,
+    pub recent: Vec<TrackRecord>,
+    pub devices: Vec<Device>,
+    pub playlists: Vec<Playlist>,
+    pub fetched_at: Option<Instant>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Queue,
+    Recent,
+    Devices,
+    Playlists,
+    Search,
+}
+
+impl Pane {
+    // In the order Tab moves through them.
+    pub const ALL: [Pane; 5] = [
+        Pane::Queue,
+        Pane::Recent,
+        Pane::Devices,
+        Pane::Playlists,
+        Pane::Search,
+    ];
+
+    fn index(&self) -> usize {
+        Pane::ALL.iter().position(|p| p == self).unwrap_or(0)
+    }
+}
+
+// What typed keys do: shortcuts, or editing the command bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Command,
+    Search,
+}
+
+#[derive(Debug)]
+pub struct App {
+    pub snapshot: Snapshot,
+    pub focus: Pane,
+    pub selected: [usize; 5], // selected row of each pane, by Pane::index
+    pub search_results: Vec<TrackRecord>,
+    pub mode: Mode,
+    pub input: String,
+    pub output: Vec<Output>, // one line each, oldest first
+    quit: bool,
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            snapshot: Snapshot::default(),
+            focus: Pane::Queue,
+            selected: [0; 5],
+            search_results: Vec::new(),
+            mode: Mode::Normal,
+            input: String::new(),
+            output: Vec::new(),
+            quit: false,
+        }
+    }
+
+    // Playback progress, counting the time passed since the last refresh.
+    pub fn progress_ms(&self) -> u64 {
+        let player = &self.snapshot.player;
+        let progress = match (player.is_playing, self.snapshot.fetched_at) {
+            (true, Some(fetched_at)) => {
+                player.progress_ms + fetched_at.elapsed().as_millis() as u64
+            }
+            _ => player.progress_ms,
+        };
+        match player.track.as_ref().and_then(|t| t.duration_ms) {
+            Some(duration) => progress.min(duration.max(0) as u64),
+            None => progress,
+        }
+    }
+
+    fn pane_len(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::Queue => self.snapshot.queue.queued.len() + self.snapshot.queue.pending.len(),
+            Pane::Recent => self.snapshot.recent.len(),
+            Pane::Devices => self.snapshot.devices.len(),
+            Pane::Playlists => self.snapshot.playlists.len(),
+            Pane::Search => self.search_results.len(),
+        }
+    }
+
+    pub fn selected(&self, pane: Pane) -> usize {
+        self.selected[pane.index()].min(self.pane_len(pane).saturating_sub(1))
+    }
+
+    fn select(&mut self, pane: Pane, delta: isize) {
+        let len = self.pane_len(pane);
+        let current = self.selected(pane) as isize;
+        self.selected[pane.index()] =
+            (current + delta).clamp(0, len.saturating_sub(1) as isize) as usize;
+    }
+
+    fn cycle_focus(&mut self, forward: bool) {
+        let n = Pane::ALL.len();
+        let i = self.focus.index();
+        self.focus = Pane::ALL[if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }];
+    }
+
+    fn print(&mut self, output: Output) {
+        let lines: Vec<Output> = match &output {
+            Output::Value(text) => text.lines().map(|l| Output::Value(l.to_string())).collect(),
+            Output::Error(text) => text.lines().map(|l| Output::Error(l.to_string())).collect(),
+        };
+        self.output.extend(lines);
+        if self.output.len() > MAX_OUTPUT_LINES {
+            self.output.drain(..self.output.len() - MAX_OUTPUT_LINES);
+        }
+    }
+
+    // Shows a manager's message, as errors if it starts with "Error:".
+
+    async fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        api_proxy: &ApiProxy,
+        session: &mut Session,
+        wake: &Notify,
+    ) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+
+        match self.mode {
+            Mode::Normal => self.handle_shortcut(key, api_proxy, wake).await,
+            Mode::Command | Mode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.input.clear();
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Enter => {
+                    let input = std::mem::take(&mut self.input);
+                    let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+                    if input.trim().is_empty() {
+                        return;
+                    }
+                    match mode {
+                        Mode::Search => self.search(&input, api_proxy).await,
+                        _ => self.run_command(&input, api_proxy, session).await,
+                    }
+                    wake.notify_one();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    async fn handle_shortcut(&mut self, key: KeyEvent, api_proxy: &ApiProxy, wake: &Notify) {
+        let playback_manager = PlaybackManager::new(api_proxy);
+        let is_playing = self.snapshot.player.is_playing;
+        let volume = self.snapshot.player.volume.unwrap_or(0);
+        let result = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.quit = true;
+                return;
+            }
+            KeyCode::Char(' ') if is_playing => playback_manager.pause().await,
+            KeyCode::Char(' ') => playback_manager.play().await,
+            KeyCode::Char('n') => playback_manager.next(1).await,
+            KeyCode::Char('p') => playback_manager.previous(1).await,
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                let volume = volume.saturating_add(VOLUME_STEP).min(100);
+                playback_manager.set_volume(volume).await
+            }
+            KeyCode::Char('-') => {
+                playback_manager
+                    .set_volume(volume.saturating_sub(VOLUME_STEP))
+                    .await
+            }
+            KeyCode::Left => {
+                let position = self.progress_ms().saturating_sub(SEEK_STEP_MS);
+                playback_manager.seek(position).await
+            }
+            KeyCode::Right => {
+                playback_manager
+                    .seek(self.progress_ms() + SEEK_STEP_MS)
+                    .await
+            }
+            KeyCode::Enter => match self.activate(api_proxy).await {
+                Some(output) => {
+                    self.print(output);
+                    wake.notify_one();
+                    return;
+                }
+                None => return,
+            },
+            KeyCode::Tab => {
+                self.cycle_focus(true);
+                return;
+            }
+            KeyCode::BackTab => {
+                self.cycle_focus(false);
+                return;
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.select(self.focus, 1);
+                return;
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.select(self.focus, -1);
+                return;
+            }
+            KeyCode::Char(':') => {
+                self.mode = Mode::Command;
+                return;
+            }
+            KeyCode::Char('/') => {
+                self.mode = Mode::Search;
+                return;
+            }
+            KeyCode::Char('r') => {
+                wake.notify_one();
+                return;
+            }
+            _ => return,
+        };
+        self.print(output_of(result));
+        wake.notify_one();
+    }
+
+    // Acts on the selected row: tracks and playlists are added to the queue,
+    // devices take over playback.
+    async fn activate(&mut self, api_proxy: &ApiProxy) -> Option<Output> {
+        let i = self.selected(self.focus);
+        let uri = match self.focus {
+            Pane::Queue => return None,
+            Pane::Recent => self.snapshot.recent.get(i).map(|t| t.uri.clone()),
+            Pane::Search => self.search_results.get(i).map(|t| t.uri.clone()),
+            Pane::Playlists => self.snapshot.playlists.get(i).map(|p| p.uri.clone()),
+            Pane::Devices => {
+                let name = self.snapshot.devices.get(i)?.name.clone();
+                return Some(match PlaybackManager::new(api_proxy).device(&name).await {
+                    Ok(None) => Output::Error(format!("Error: Device '{}' not found.", name)),
+                    res => output_of(res.map(Option::unwrap_or_default)),
+                });
+            }
+        };
+        Some(output_of(
+            QueueManager::new(api_proxy)
+                .add(vec![uri?], AddOptions::default())
+                .await,
+        ))
+    }
+
+    async fn search(&mut self, query: &str, api_proxy: &ApiProxy) {
+        match SearchManager::new(api_proxy)
+            .search(query, &PageRange::first(SEARCH_LIMIT))
+            .await
+        {
+            Ok(tracks) => {
+                self.print(Output::Value(format!(
+                    "Found {} tracks for '{}'.",
+                    tracks.len(),
+                    query
+                )));
+                self.search_results = tracks;
+                self.selected[Pane::Search.index()] = 0;
+                self.focus = Pane::Search;
+            }
+            Err(err) => self.print(Output::Error(format!("Error: {}", err))),
+        }
+    }
+
+    // Runs a line of the command language, as typed at the spt> prompt.
+    async fn run_command(&mut self, input: &str, api_proxy: &ApiProxy, session: &mut Session) {
+        self.print(Output::Value(format!(":{}", input)));
+        let mut outputs = Vec::new();
+        cli_app::execute(api_proxy, session, input, "", &mut |output| {
+            outputs.push(output)
+        })
+        .await;
+        for output in outputs {
+            self.print(output);
+        }
+    }
+}
+
+// The output line for the result of a shortcut.
+fn output_of(result: Result<String, ApiError>) -> Output {
+    match result {
+        Ok(text) => Output::Value(text),
+        Err(err) => Output::Error(format!("Error: {}", err)),
+    }
+}
+
+// Fetches a snapshot every interval and sends it to the UI. Being woken skips
+// the wait and refetches everything, e.g. after a key changed playback.
+async fn refresh(
+    api_proxy: ApiProxy,
+    interval: Duration,
+    wake: Arc<Notify>,
+    snapshots: mpsc::UnboundedSender<Snapshot>,
+) {
+    let mut playback_manager = PlaybackManager::new(&api_proxy);
+    let queue_manager = QueueManager::new(&api_proxy);
+    let playlist_manager = PlaylistManager::new(&api_proxy);
+    let mut snapshot = Snapshot::default();
+    let mut round: u32 = 0;
+
+    loop {
+        let mut errors = Vec::new();
+        match playback_manager.player().await {
+            Ok(player) => snapshot.player = player,
+            Err(err) => errors.push(err.to_string()),
+        }
+        match queue_manager.contents().await {
+            Ok(queue) => snapshot.queue = queue,
+            Err(err) => errors.push(err.to_string()),
+        }
+        if round.is_multiple_of(SLOW_REFRESH_ROUNDS) {
+            match playback_manager.recent(&PageRange::first(50)).await {
+                Ok(recent) => snapshot.recent = recent,
+                Err(err) => errors.push(err.to_string()),
+            }
+            match playback_manager.list_devices().await {
+                Ok(devices) => snapshot.devices = devices,
+                Err(err) => errors.push(err.to_string()),
+            }
+            match playlist_manager.playlists(&PageRange::all()).await {
+                Ok(playlists) => snapshot.playlists = playlists,
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+        snapshot.fetched_at = Some(Instant::now());
+        snapshot.error = errors.into_iter().next();
+
+        if snapshots.send(snapshot.clone()).is_err() {
+            return;
+        }
+
+        round += 1;
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = wake.notified() => round = 0,
+        }
+    }
+}
+
+// Reads terminal events on a thread of its own, as crossterm blocks while
+// reading. Stops once the receiving end is gone.
+fn spawn_input_reader(events: mpsc::UnboundedSender<Event>) {
+    std::thread::spawn(move || {
+        while !events.is_closed() {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => match event::read() {
+                    Ok(event) => {
+                        if events.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                },
+                Ok(false) => {}
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, api_proxy: &ApiProxy) -> io::Result<()> {
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    spawn_input_reader(event_tx);
+
+    let (snapshot_tx, mut snapshots) = mpsc::unbounded_channel();
+    let wake = Arc::new(Notify::new());
+    let refresher = tokio::spawn(refresh(
+        api_proxy.clone(),
+        refresh_interval(),
+        Arc::clone(&wake),
+        snapshot_tx,
+    ));
+
+    let mut app = App::new();
+    let mut session = Session::default();
+    let mut ticker = tokio::time::interval(TICK);
+
+    while !app.quit {
+        terminal.draw(|frame| view::draw(frame, &app))?;
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    app.handle_key(key, api_proxy, &mut session, &wake).await
+                }
+                Some(_) => {}
+                None => break,
+            },
+            Some(snapshot) = snapshots.recv() => app.snapshot = snapshot,
+            _ = ticker.tick() => {}
+        }
+    }
+
+    refresher.abort();
+    Ok(())
+}
+
+// Runs the full-screen client until q is pressed, restoring the terminal
+// afterwards.
+pub async fn run(api_proxy: &ApiProxy) -> Value {
+    debug!("Starting TUI.");
+
+    let mut terminal = match ratatui::try_init() {
+        Ok(terminal) => terminal,
+        Err(err) => return Value::Error(format!("Failed to set up the terminal: {}", err)),
+    };
+    // command output is shown in a pane, not written to the terminal
+    formatter::set_plain(true);
+
+    let result = event_loop(&mut terminal, api_proxy).await;
+
+    formatter::set_plain(false);
+    ratatui::restore();
+
+    match result {
+        Ok(()) => Value::Str(String::new()),
+        Err(err) => Value::Error(format!("Terminal error: {}", err)),
+    }
+}
@@ -0,0 +1,273 @@
+use crate::client::cli::cli_app::Output;
+use crate::client::cli::formatter::{self, join_artists};
+use crate::client::cli::value::TrackRecord;
+use crate::client::tui::tui_app::{App, Mode, Pane};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+const HELP: &str = "space play/pause  n/p next/prev  +/- volume  \u{2190}/\u{2192} seek  \
+                    tab pane  enter select  / search  : command  r refresh  q quit";
+
+// Lines of the output pane, including its borders.
+const OUTPUT_HEIGHT: u16 = 8;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [now_area, panes_area, output_area, input_area] = Layout::vertical([
+        Constraint::Length(6),
+        Constraint::Min(8),
+        Constraint::Length(OUTPUT_HEIGHT),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_now_playing(frame, app, now_area);
+
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(panes_area);
+    let [queue_area, recent_area] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+    let devices_height = app.snapshot.devices.len().clamp(1, 5) as u16 + 2;
+    let [devices_area, playlists_area, search_area] = Layout::vertical([
+        Constraint::Length(devices_height),
+        Constraint::Percentage(50),
+        Constraint::Percentage(50),
+    ])
+    .areas(right);
+
+    let queue = &app.snapshot.queue;
+    let queue_items: Vec<ListItem> = queue
+        .queued
+        .iter()
+        .map(track_item)
+        .chain(
+            queue
+                .pending
+                .iter()
+                .map(|t| track_item(t).style(Style::default().add_modifier(Modifier::ITALIC))),
+        )
+        .collect();
+    let queue_title = format!(
+        "Queue ({} + {} pending)",
+        queue.queued.len(),
+        queue.pending.len()
+    );
+    draw_list(
+        frame,
+        app,
+        Pane::Queue,
+        &queue_title,
+        queue_items,
+        queue_area,
+    );
+
+    let recent_items = app.snapshot.recent.iter().map(track_item).collect();
+    draw_list(
+        frame,
+        app,
+        Pane::Recent,
+        "Recent",
+        recent_items,
+        recent_area,
+    );
+
+    let device_items = app
+        .snapshot
+        .devices
+        .iter()
+        .map(|d| {
+            let volume = d
+                .volume_percent
+                .map(|v| format!(" {}%", v))
+                .unwrap_or_default();
+            let marker = if d.is_active { "* " } else { "  " };
+            ListItem::new(format!("{}{} ({}){}", marker, d.name, d.kind, volume))
+        })
+        .collect();
+    draw_list(
+        frame,
+        app,
+        Pane::Devices,
+        "Devices",
+        device_items,
+        devices_area,
+    );
+
+    let playlist_items = app
+        .snapshot
+        .playlists
+        .iter()
+        .map(|p| ListItem::new(format!("{} ({} tracks)", p.name, p.track_count())))
+        .collect();
+    draw_list(
+        frame,
+        app,
+        Pane::Playlists,
+        "Playlists",
+        playlist_items,
+        playlists_area,
+    );
+
+    let search_items = app.search_results.iter().map(track_item).collect();
+    draw_list(
+        frame,
+        app,
+        Pane::Search,
+        "Search",
+        search_items,
+        search_area,
+    );
+
+    draw_output(frame, app, output_area);
+    draw_input(frame, app, input_area);
+}
+
+fn track_item(track: &TrackRecord) -> ListItem<'static> {
+    ListItem::new(Line::from(vec![
+        Span::raw(track.name.clone()),
+        Span::styled(
+            format!(" - {}", join_artists(&track.artists)),
+            Style::default().fg(Color::DarkGray),
+        ),
+    ]))
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'_> {
+    let style = match focused {
+        true => Style::default().fg(Color::Cyan),
+        false => Style::default(),
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(style)
+        .title(title)
+}
+
+fn draw_list(
+    frame: &mut Frame,
+    app: &App,
+    pane: Pane,
+    title: &str,
+    items: Vec<ListItem>,
+    area: Rect,
+) {
+    let focused = app.focus == pane;
+    let empty = items.is_empty();
+    let list = List::new(items)
+        .block(pane_block(title, focused))
+        .highlight_style(match focused {
+            true => Style::default().add_modifier(Modifier::REVERSED),
+            false => Style::default().add_modifier(Modifier::BOLD),
+        });
+    let mut state = ListState::default().with_selected(match empty {
+        true => None,
+        false => Some(app.selected(pane)),
+    });
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_now_playing(frame: &mut Frame, app: &App, area: Rect) {
+    let player = &app.snapshot.player;
+    let block = Block::default().borders(Borders::ALL).title("Now Playing");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let [info_area, gauge_area] =
+        Layout::vertical([Constraint::Length(3), Constraint::Length(1)]).areas(inner);
+
+    let Some(track) = &player.track else {
+        frame.render_widget(Paragraph::new("Nothing playing."), info_area);
+        return;
+    };
+
+    let status = if player.is_playing {
+        "Playing"
+    } else {
+        "Paused"
+    };
+    let device = player.device.as_deref().unwrap_or("no device");
+    let volume = player
+        .volume
+        .map(|v| format!(", {}%", v))
+        .unwrap_or_default();
+    let info = vec![
+        Line::from(Span::styled(
+            track.name.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(format!(
+            "{} - {}",
+            join_artists(&track.artists),
+            track.album.as_deref().unwrap_or("")
+        )),
+        Line::from(Span::styled(
+            format!("{} on {}{}", status, device, volume),
+            Style::default().fg(Color::DarkGray),
+        )),
+    ];
+    frame.render_widget(Paragraph::new(info), info_area);
+
+    let duration = track.duration_ms.unwrap_or(0).max(0) as u64;
+    let progress = app.progress_ms();
+    let ratio = match duration {
+        0 => 0.0,
+        _ => (progress as f64 / duration as f64).clamp(0.0, 1.0),
+    };
+    let label = format!(
+        "{} / {}",
+        formatter::print_duration(progress as i64),
+        formatter::print_duration(duration as i64)
+    );
+    frame.render_widget(
+        Gauge::default()
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(label),
+        gauge_area,
+    );
+}
+
+// The latest lines of command output that fit.
+fn draw_output(frame: &mut Frame, app: &App, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    let start = app.output.len().saturating_sub(height);
+    let lines: Vec<Line> = app.output[start..]
+        .iter()
+        .map(|output| match output {
+            Output::Value(text) => Line::from(text.clone()),
+            Output::Error(text) => {
+                Line::from(Span::styled(text.clone(), Style::default().fg(Color::Red)))
+            }
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Output")),
+        area,
+    );
+}
+
+// The command bar while typing, otherwise the shortcuts or the last refresh
+// error.
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let prompt = match app.mode {
+        Mode::Command => ":",
+        Mode::Search => "/",
+        Mode::Normal => {
+            let line = match &app.snapshot.error {
+                Some(err) => Line::from(Span::styled(
+                    format!("Refresh failed: {}", err),
+                    Style::default().fg(Color::Red),
+                )),
+                None => Line::from(Span::styled(HELP, Style::default().fg(Color::DarkGray))),
+            };
+            frame.render_widget(Paragraph::new(line), area);
+            return;
+        }
+    };
+    frame.render_widget(Paragraph::new(format!("{}{}", prompt, app.input)), area);
+    let cursor = (prompt.len() + app.input.chars().count()) as u16;
+    frame.set_cursor_position((area.x + cursor.min(area.width.saturating_sub(1)), area.y));
+}
@@ -5,7 +5,18 @@ use log::{error, info, LevelFilter};
 use tokio;
 
 mod server {
+    pub mod db {
+        pub mod history_db;
+        pub mod history_recorder;
+        pub mod smart_playlist_db;
+    }
+    pub mod queue {
+        pub mod queue_feeder;
+        pub mod virtual_queue;
+    }
     pub mod web {
+        pub mod fwd_allowlist;
+        pub mod http_fixtures;
         pub mod routes;
         pub mod server;
         pub mod spt_api_proxy;
@@ -14,25 +25,51 @@ mod server {
 
 mod util {
     pub mod errors;
+    pub mod models;
     pub mod uri_helper;
 }
 
+#[cfg(test)]
+mod testing {
+    mod e2e_tests;
+    pub mod mock_spotify;
+}
+
 mod client {
     pub mod local_api_proxy;
+    pub mod paging;
     pub mod cli {
         pub mod cli_app;
+        pub mod commands;
+        pub mod completion_cache;
+        pub mod diagnostics;
+        pub mod docs;
         pub mod eval;
         pub mod formatter;
+        pub mod macros;
         pub mod parser;
+        pub mod registry;
+        pub mod value;
     }
     pub mod core {
+        pub mod history_manager;
+        pub mod id_resolver;
+        pub mod library_manager;
         pub mod playback_manager;
-        // pub mod playlist_manager;
-        // pub mod queue_manager;
-        // pub mod search_manager;
+        pub mod playlist_file;
+        pub mod playlist_manager;
+        pub mod queue_manager;
+        pub mod radio_manager;
+        pub mod search_manager;
+        pub mod smart_playlist_manager;
+        pub mod track_query;
         // pub mod status_manager;
         // pub mod transaction_manager;
     }
+    pub mod tui {
+        pub mod tui_app;
+        pub mod view;
+    }
 }
 
 #[tokio::main]
@@ -40,6 +77,22 @@ async fn main() {
     // load environment variables from .env file
     dotenv().ok();
 
+    let mut args = std::env::args().collect::<Vec<String>>();
+
+    // --record DIR and --replay DIR come before the command, and apply to the
+    // server this process starts
+    while args.len() > 1 && matches!(args[1].as_str(), "--record" | "--replay") {
+        let Some(dir) = args.get(2).cloned() else {
+            eprintln!("Error: {} needs a directory", args[1]);
+            std::process::exit(2);
+        };
+        match args[1].as_str() {
+            "--record" => std::env::set_var("SPT_RECORD_DIR", dir),
+            _ => std::env::set_var("SPT_REPLAY_DIR", dir),
+        }
+        args.drain(1..3);
+    }
+
     // initialize logging
     let log_file_name = format!(
         "logs/spt_server_{}.log",
@@ -57,9 +110,12 @@ async fn main() {
         })
         .level(LevelFilter::Warn)
         .level_for("spt", LevelFilter::Debug)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(log_file_name).unwrap())
-        .apply();
+        .chain(fern::log_file(log_file_name).unwrap());
+    // the TUI owns the screen, so it only logs to the file
+    let logger = match args.get(1).map(|s| s.as_str()) {
+        Some("tui") => logger.apply(),
+        _ => logger.chain(std::io::stdout()).apply(),
+    };
 
     if logger.is_err() {
         eprintln!("Failed to initialize logger: {:?}", logger.err());
@@ -70,11 +126,14 @@ async fn main() {
     let mut api_proxy = client::local_api_proxy::ApiProxy::new();
     if let Err(e) = api_proxy.setup().await {
         error!("Failed to set up API proxy: {}", e);
-        return;
+        std::process::exit(1);
     }
 
-    let args = std::env::args().collect::<Vec<String>>();
-    client::cli::cli_app::run_cli(&mut api_proxy, args).await;
+    let exit_code = client::cli::cli_app::run_cli(&mut api_proxy, args).await;
 
     info!("Stopping program.");
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 }
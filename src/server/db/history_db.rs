@@ -0,0 +1,284 @@
+use crate::util::errors::ApiError;
+use log::{debug, error, info};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+// A single finished play, as recorded by the history recorder.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayRecord {
+    pub track_uri: String,
+    pub track_name: String,
+    pub artists: Vec<(String, String)>, // (artist uri, artist name)
+    pub album_uri: Option<String>,
+    pub album_name: Option<String>,
+    pub context_uri: Option<String>,
+    pub device_name: Option<String>,
+    pub started_at: i64, // unix time in ms
+    pub duration_ms: i64,
+    pub played_ms: i64,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackStat {
+    pub uri: String,
+    pub name: String,
+    pub artists: String,
+    pub plays: i64,
+    pub played_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NamedStat {
+    pub uri: String,
+    pub name: String,
+    pub plays: i64,
+    pub played_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayStat {
+    pub day: String,
+    pub plays: i64,
+    pub played_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkipStat {
+    pub plays: i64,
+    pub skips: i64,
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryStats {
+    pub top_tracks: Vec<TrackStat>,
+    pub top_artists: Vec<NamedStat>,
+    pub top_albums: Vec<NamedStat>,
+    pub per_day: Vec<DayStat>,
+    pub skips: SkipStat,
+}
+
+// Time range for history queries, bounds are unix time in ms (from inclusive,
+// to exclusive). A missing bound is unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl TimeRange {
+    fn bounds(&self) -> (i64, i64) {
+        (self.from.unwrap_or(i64::MIN), self.to.unwrap_or(i64::MAX))
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryDb {
+    conn: Mutex<Connection>,
+}
+
+fn db_error(e: rusqlite::Error) -> ApiError {
+    error!("History database error: {}", e);
+    ApiError::DatabaseError
+}
+
+impl HistoryDb {
+    pub fn open(path: &str) -> Result<Self, ApiError> {
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() && std::fs::create_dir_all(parent).is_err() {
+                error!("Failed to create directory for database {}.", path);
+                return Err(ApiError::DatabaseError);
+            }
+        }
+
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS plays (
+                id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                track_uri   TEXT NOT NULL,
+                track_name  TEXT NOT NULL,
+                artists     TEXT NOT NULL,
+                album_uri   TEXT,
+                album_name  TEXT,
+                context_uri TEXT,
+                device_name TEXT,
+                started_at  INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                played_ms   INTEGER NOT NULL,
+                skipped     INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS plays_started_at ON plays (started_at);
+            CREATE TABLE IF NOT EXISTS play_artists (
+                play_id     INTEGER NOT NULL REFERENCES plays (id),
+                position    INTEGER NOT NULL,
+                artist_uri  TEXT NOT NULL,
+                artist_name TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS play_artists_play_id ON play_artists (play_id);",
+        )
+        .map_err(db_error)?;
+
+        info!("Opened history database at {}.", path);
+
+        Ok(HistoryDb {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn record_play(&self, play: &PlayRecord) -> Result<(), ApiError> {
+        let mut conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        let tx = conn.transaction().map_err(db_error)?;
+
+        let artist_names = play
+            .artists
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        tx.execute(
+            "INSERT INTO plays (track_uri, track_name, artists, album_uri, album_name,
+                context_uri, device_name, started_at, duration_ms, played_ms, skipped)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                play.track_uri,
+                play.track_name,
+                artist_names,
+                play.album_uri,
+                play.album_name,
+                play.context_uri,
+                play.device_name,
+                play.started_at,
+                play.duration_ms,
+                play.played_ms,
+                play.skipped,
+            ],
+        )
+        .map_err(db_error)?;
+
+        let play_id = tx.last_insert_rowid();
+        for (position, (uri, name)) in play.artists.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO play_artists (play_id, position, artist_uri, artist_name)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![play_id, position as i64, uri, name],
+            )
+            .map_err(db_error)?;
+        }
+
+        tx.commit().map_err(db_error)?;
+
+        debug!(
+            "Recorded play of {} ({}ms of {}ms, skipped: {}).",
+            play.track_uri, play.played_ms, play.duration_ms, play.skipped
+        );
+
+        Ok(())
+    }
+
+    pub fn stats(&self, range: TimeRange, limit: u32) -> Result<HistoryStats, ApiError> {
+        let conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        let (from, to) = range.bounds();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT track_uri, MAX(track_name), MAX(artists), COUNT(*), SUM(played_ms)
+                 FROM plays WHERE started_at >= ?1 AND started_at < ?2
+                 GROUP BY track_uri ORDER BY COUNT(*) DESC, SUM(played_ms) DESC LIMIT ?3",
+            )
+            .map_err(db_error)?;
+        let top_tracks = stmt
+            .query_map(params![from, to, limit], |row| {
+                Ok(TrackStat {
+                    uri: row.get(0)?,
+                    name: row.get(1)?,
+                    artists: row.get(2)?,
+                    plays: row.get(3)?,
+                    played_ms: row.get(4)?,
+                })
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(db_error)?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT a.artist_uri, MAX(a.artist_name), COUNT(*), SUM(p.played_ms)
+                 FROM play_artists a JOIN plays p ON p.id = a.play_id
+                 WHERE p.started_at >= ?1 AND p.started_at < ?2
+                 GROUP BY a.artist_uri ORDER BY COUNT(*) DESC, SUM(p.played_ms) DESC LIMIT ?3",
+            )
+            .map_err(db_error)?;
+        let top_artists = stmt
+            .query_map(params![from, to, limit], named_stat)
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(db_error)?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT album_uri, MAX(album_name), COUNT(*), SUM(played_ms)
+                 FROM plays WHERE started_at >= ?1 AND started_at < ?2 AND album_uri IS NOT NULL
+                 GROUP BY album_uri ORDER BY COUNT(*) DESC, SUM(played_ms) DESC LIMIT ?3",
+            )
+            .map_err(db_error)?;
+        let top_albums = stmt
+            .query_map(params![from, to, limit], named_stat)
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(db_error)?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT date(started_at / 1000, 'unixepoch', 'localtime') AS day,
+                    COUNT(*), SUM(played_ms)
+                 FROM plays WHERE started_at >= ?1 AND started_at < ?2
+                 GROUP BY day ORDER BY day",
+            )
+            .map_err(db_error)?;
+        let per_day = stmt
+            .query_map(params![from, to], |row| {
+                Ok(DayStat {
+                    day: row.get(0)?,
+                    plays: row.get(1)?,
+                    played_ms: row.get(2)?,
+                })
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(db_error)?;
+
+        let (plays, skips): (i64, i64) = conn
+            .query_row(
+                "SELECT COUNT(*), COALESCE(SUM(skipped), 0)
+                 FROM plays WHERE started_at >= ?1 AND started_at < ?2",
+                params![from, to],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(db_error)?;
+
+        Ok(HistoryStats {
+            top_tracks,
+            top_artists,
+            top_albums,
+            per_day,
+            skips: SkipStat {
+                plays,
+                skips,
+                rate: if plays > 0 {
+                    skips as f64 / plays as f64
+                } else {
+                    0.0
+                },
+            },
+        })
+    }
+}
+
+fn named_stat(row: &rusqlite::Row) -> rusqlite::Result<NamedStat> {
+    Ok(NamedStat {
+        uri: row.get(0)?,
+        name: row.get(1)?,
+        plays: row.get(2)?,
+        played_ms: row.get(3)?,
+    })
+}
@@ -0,0 +1,131 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tokio::time;
+
+use crate::server::db::history_db::{HistoryDb, PlayRecord};
+use crate::server::web::spt_api_proxy::{authorized_proxy, ApiProxy};
+use crate::util::models::{self, PlayableItem, PlaybackState};
+
+// Extra slack (in ms) allowed at the end of a track before a play is
+// considered skipped, on top of the polling interval.
+const SKIP_TOLERANCE_MS: i64 = 5000;
+
+// Play currently being observed by the recorder.
+#[derive(Debug, Clone)]
+struct ActivePlay {
+    record: PlayRecord,
+    last_progress_ms: i64,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// Builds a new play from a `me/player` response, returns None if no track
+// is playing.
+fn play_from_state(state: &PlaybackState) -> Option<ActivePlay> {
+    let Some(PlayableItem::Track(track)) = &state.item else {
+        return None;
+    };
+
+    let progress_ms = state.progress_ms.unwrap_or(0) as i64;
+    let artists = track
+        .artists
+        .iter()
+        .filter_map(|a| Some((a.uri.clone()?, a.name.clone())))
+        .collect();
+
+    Some(ActivePlay {
+        record: PlayRecord {
+            track_uri: track.uri.clone(),
+            track_name: track.name.clone(),
+            artists,
+            album_uri: track.album.as_ref().and_then(|a| a.uri.clone()),
+            album_name: track.album.as_ref().map(|a| a.name.clone()),
+            context_uri: state.context.as_ref().map(|c| c.uri.clone()),
+            device_name: state.device.as_ref().map(|d| d.name.clone()),
+            started_at: now_ms() - progress_ms,
+            duration_ms: track.duration_ms as i64,
+            played_ms: 0,
+            skipped: false,
+        },
+        last_progress_ms: progress_ms,
+    })
+}
+
+fn finish_play(db: &HistoryDb, play: ActivePlay, poll_interval: Duration) {
+    let mut record = play.record;
+    record.played_ms = play.last_progress_ms.min(record.duration_ms);
+    record.skipped = record.played_ms + poll_interval.as_millis() as i64 + SKIP_TOLERANCE_MS
+        < record.duration_ms;
+
+    if record.played_ms <= 0 {
+        return;
+    }
+
+    if let Err(e) = db.record_play(&record) {
+        warn!("Failed to record play of {}: {}", record.track_uri, e);
+    }
+}
+
+// Polls the player state as the latest authorized client and records every
+// finished play into the history database. The server runs one recorder, so
+// that each play is recorded once however many clients it has.
+pub async fn record_history(
+    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    db: Arc<HistoryDb>,
+    poll_interval: Duration,
+) {
+    let mut active: Option<ActivePlay> = None;
+
+    loop {
+        time::sleep(poll_interval).await;
+
+        // never trigger the browser authorization flow from the recorder
+        let Some(proxy) = authorized_proxy(&api_proxies).await else {
+            continue;
+        };
+
+        let state = proxy
+            .get("me/player", None)
+            .await
+            .and_then(|(status, json)| {
+                models::from_response::<Option<PlaybackState>>(status, json)
+            });
+        let current = match state {
+            Ok(state) => state.as_ref().and_then(play_from_state),
+            Err(e) => {
+                debug!("History recorder failed to fetch player state: {}", e);
+                continue;
+            }
+        };
+
+        active = match (active, current) {
+            (Some(mut prev), Some(curr)) => {
+                // a new track started, or the same track was restarted. A
+                // seek back that doesn't reach the start continues the play.
+                if prev.record.track_uri != curr.record.track_uri
+                    || (curr.last_progress_ms < SKIP_TOLERANCE_MS
+                        && prev.last_progress_ms > 2 * SKIP_TOLERANCE_MS)
+                {
+                    finish_play(&db, prev, poll_interval);
+                    Some(curr)
+                } else {
+                    prev.last_progress_ms = curr.last_progress_ms;
+                    Some(prev)
+                }
+            }
+            (Some(prev), None) => {
+                finish_play(&db, prev, poll_interval);
+                None
+            }
+            (None, curr) => curr,
+        };
+    }
+}
@@ -0,0 +1,117 @@
+use crate::util::errors::ApiError;
+use chrono::Utc;
+use log::{error, info};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Mutex;
+
+// A smart playlist: a rule the client regenerates the playlist from, and the
+// Spotify playlist it was last synced to. The rule is stored as the client
+// sends it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartPlaylistRecord {
+    pub name: String,
+    pub playlist_id: Option<String>,
+    pub rule: Value,
+    #[serde(default)]
+    pub updated_at: i64, // unix time in ms
+}
+
+// Smart playlist definitions, kept in the database at DB_PATH next to the
+// play history.
+#[derive(Debug)]
+pub struct SmartPlaylistDb {
+    conn: Mutex<Connection>,
+}
+
+fn db_error(e: rusqlite::Error) -> ApiError {
+    error!("Smart playlist database error: {}", e);
+    ApiError::DatabaseError
+}
+
+fn record(row: &rusqlite::Row) -> rusqlite::Result<SmartPlaylistRecord> {
+    let rule: String = row.get(2)?;
+    Ok(SmartPlaylistRecord {
+        name: row.get(0)?,
+        playlist_id: row.get(1)?,
+        rule: serde_json::from_str(&rule).unwrap_or(Value::Null),
+        updated_at: row.get(3)?,
+    })
+}
+
+impl SmartPlaylistDb {
+    // Opens the database, which HistoryDb::open has already created.
+    pub fn open(path: &str) -> Result<Self, ApiError> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS smart_playlists (
+                name        TEXT PRIMARY KEY COLLATE NOCASE,
+                playlist_id TEXT,
+                rule        TEXT NOT NULL,
+                updated_at  INTEGER NOT NULL
+            );",
+        )
+        .map_err(db_error)?;
+
+        info!("Opened smart playlists in {}.", path);
+
+        Ok(SmartPlaylistDb {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn list(&self) -> Result<Vec<SmartPlaylistRecord>, ApiError> {
+        let conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT name, playlist_id, rule, updated_at FROM smart_playlists
+                 ORDER BY name COLLATE NOCASE",
+            )
+            .map_err(db_error)?;
+        let records = stmt
+            .query_map([], record)
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(db_error)?;
+        Ok(records)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<SmartPlaylistRecord>, ApiError> {
+        let conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        conn.query_row(
+            "SELECT name, playlist_id, rule, updated_at FROM smart_playlists WHERE name = ?1",
+            params![name],
+            record,
+        )
+        .optional()
+        .map_err(db_error)
+    }
+
+    // Adds a definition or replaces the one of the same name, ignoring case.
+    pub fn save(&self, smart: &SmartPlaylistRecord) -> Result<(), ApiError> {
+        let conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        conn.execute(
+            "INSERT INTO smart_playlists (name, playlist_id, rule, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (name) DO UPDATE SET name = ?1, playlist_id = ?2, rule = ?3,
+                updated_at = ?4",
+            params![
+                smart.name,
+                smart.playlist_id,
+                smart.rule.to_string(),
+                Utc::now().timestamp_millis(),
+            ],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    // Returns whether there was a definition of the name.
+    pub fn remove(&self, name: &str) -> Result<bool, ApiError> {
+        let conn = self.conn.lock().map_err(|_| ApiError::DatabaseError)?;
+        let removed = conn
+            .execute("DELETE FROM smart_playlists WHERE name = ?1", params![name])
+            .map_err(db_error)?;
+        Ok(removed > 0)
+    }
+}
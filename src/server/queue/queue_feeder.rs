@@ -0,0 +1,87 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time;
+
+use crate::server::queue::virtual_queue::VirtualQueue;
+use crate::server::web::spt_api_proxy::{authorized_proxy, ApiProxy};
+use crate::util::models::{self, PlaybackState};
+
+// How long (in ms) before the end of the current track, on top of the
+// polling interval, the next local queue item is handed to Spotify.
+const FEED_MARGIN_MS: i64 = 5000;
+
+// Watches the player state as the latest authorized client and appends the
+// next item of the server's virtual queue to the Spotify queue once the
+// current track nears its end.
+pub async fn feed_queue(
+    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    queue: Arc<VirtualQueue>,
+    poll_interval: Duration,
+) {
+    // track uri the last item was fed for, so each play only feeds once
+    let mut fed_for: Option<String> = None;
+
+    loop {
+        time::sleep(poll_interval).await;
+
+        if queue.is_empty().await {
+            continue;
+        }
+        let Some(proxy) = authorized_proxy(&api_proxies).await else {
+            continue;
+        };
+
+        let state = proxy
+            .get("me/player", None)
+            .await
+            .and_then(|(status, json)| {
+                models::from_response::<Option<PlaybackState>>(status, json)
+            });
+        let state = match state {
+            Ok(Some(state)) => state,
+            Ok(None) => continue,
+            Err(e) => {
+                debug!("Queue feeder failed to fetch player state: {}", e);
+                continue;
+            }
+        };
+
+        if !state.is_playing {
+            continue;
+        }
+
+        let Some(item) = &state.item else {
+            continue;
+        };
+        let current_uri = item.uri().to_string();
+        let remaining_ms = item.duration_ms() as i64 - state.progress_ms.unwrap_or(0) as i64;
+
+        if remaining_ms > poll_interval.as_millis() as i64 + FEED_MARGIN_MS {
+            fed_for = None;
+            continue;
+        }
+        if fed_for.as_deref() == Some(current_uri.as_str()) {
+            continue;
+        }
+
+        let next_uri = match queue.pop_front().await {
+            Some(uri) => uri,
+            None => continue,
+        };
+
+        let params = HashMap::from([("uri".to_string(), next_uri.clone())]);
+        match proxy.post("me/player/queue", None, Some(params)).await {
+            Ok(_) => {
+                debug!("Fed {} from virtual queue to Spotify.", next_uri);
+                fed_for = Some(current_uri);
+            }
+            Err(e) => {
+                warn!("Failed to feed {} to Spotify queue: {}", next_uri, e);
+                queue.push_front(next_uri).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use rand::seq::SliceRandom;
+use tokio::sync::Mutex;
+
+// Local queue of URIs kept on the server, fed to Spotify one track at a time
+// so that it can be reordered and edited, which the Spotify queue cannot.
+#[derive(Debug, Default)]
+pub struct VirtualQueue {
+    items: Mutex<Vec<String>>,
+}
+
+impl VirtualQueue {
+    pub fn new() -> Self {
+        VirtualQueue {
+            items: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub async fn list(&self) -> Vec<String> {
+        self.items.lock().await.clone()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.items.lock().await.is_empty()
+    }
+
+    pub async fn push_back(&self, uris: Vec<String>) {
+        self.items.lock().await.extend(uris);
+    }
+
+    pub async fn insert_next(&self, uris: Vec<String>) {
+        let mut items = self.items.lock().await;
+        items.splice(0..0, uris);
+    }
+
+    pub async fn pop_front(&self) -> Option<String> {
+        let mut items = self.items.lock().await;
+        if items.is_empty() {
+            return None;
+        }
+        Some(items.remove(0))
+    }
+
+    pub async fn push_front(&self, uri: String) {
+        self.items.lock().await.insert(0, uri);
+    }
+
+    pub async fn remove(&self, index: usize) -> Option<String> {
+        let mut items = self.items.lock().await;
+        if index >= items.len() {
+            return None;
+        }
+        Some(items.remove(index))
+    }
+
+    // Moves the item at index `from` so that it ends up at index `to`.
+    pub async fn move_item(&self, from: usize, to: usize) -> bool {
+        let mut items = self.items.lock().await;
+        if from >= items.len() || to >= items.len() {
+            return false;
+        }
+        let item = items.remove(from);
+        items.insert(to, item);
+        true
+    }
+
+    pub async fn clear(&self) {
+        self.items.lock().await.clear();
+    }
+
+    pub async fn shuffle(&self) {
+        let mut items = self.items.lock().await;
+        items.shuffle(&mut rand::thread_rng());
+    }
+}
@@ -0,0 +1,167 @@
+use log::{error, info};
+use percent_encoding::percent_decode_str;
+use std::env;
+use std::fs;
+use warp::http::Method;
+
+// Which Spotify endpoints clients may reach through api/spt-fwd, and with
+// which methods. A rule is a line of methods and a path pattern, e.g.
+//
+//   GET,PUT,DELETE me/tracks
+//   GET me/top/*
+//   GET playlists/**
+//
+// where * matches one path segment and a trailing ** any number of them.
+// Rules from FWD_ALLOWLIST_PATH are added to the defaults below.
+const DEFAULT_RULES: &str = "
+GET me
+GET me/top/*
+GET,PUT me/player
+GET me/player/currently-playing
+GET me/player/devices
+GET,POST me/player/queue
+GET me/player/recently-played
+PUT me/player/play
+PUT me/player/pause
+PUT me/player/seek
+PUT me/player/volume
+PUT me/player/repeat
+PUT me/player/shuffle
+POST me/player/next
+POST me/player/previous
+GET me/playlists
+GET,PUT,DELETE me/tracks
+GET,PUT,DELETE me/albums
+GET,PUT,DELETE me/shows
+GET,PUT,DELETE me/following
+GET me/*/contains
+GET tracks/**
+GET albums/**
+GET artists/**
+GET shows/**
+GET episodes/**
+GET audio-features/**
+GET recommendations/**
+GET browse/**
+GET search
+GET users/**
+GET,PUT playlists/*
+GET,POST,PUT,DELETE playlists/*/tracks
+GET playlists/*/images
+PUT,DELETE playlists/*/followers
+GET playlists/*/followers/contains
+POST users/*/playlists
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    methods: Vec<Method>,
+    pattern: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, method: &Method, segments: &[&str]) -> bool {
+        if !self.methods.contains(method) {
+            return false;
+        }
+        match self.pattern.split_last() {
+            Some((last, init)) if last == "**" => {
+                segments.len() >= init.len() && matches_segments(init, &segments[..init.len()])
+            }
+            _ => segments.len() == self.pattern.len() && matches_segments(&self.pattern, segments),
+        }
+    }
+}
+
+fn matches_segments(pattern: &[String], segments: &[&str]) -> bool {
+    pattern
+        .iter()
+        .zip(segments)
+        .all(|(p, s)| p == "*" || p == s)
+}
+
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    rules: Vec<Rule>,
+}
+
+impl Allowlist {
+    // The default rules and those in the file at FWD_ALLOWLIST_PATH, if it
+    // exists. Lines of the file that can't be parsed are logged and skipped.
+    pub fn load() -> Allowlist {
+        let mut allowlist = Allowlist::parse(DEFAULT_RULES).expect("default rules are valid");
+
+        let path =
+            env::var("FWD_ALLOWLIST_PATH").unwrap_or("config/fwd_allowlist.conf".to_string());
+        if let Ok(text) = fs::read_to_string(&path) {
+            for (n, line) in text.lines().enumerate() {
+                match Allowlist::parse(line) {
+                    Ok(extra) => allowlist.rules.extend(extra.rules),
+                    Err(e) => error!("Skipping rule on line {} of {}: {}", n + 1, path, e),
+                }
+            }
+            info!("Loaded forwarding rules from {}.", path);
+        }
+        allowlist
+    }
+
+    // Parses rules, one per line. Blank lines and those starting with # are
+    // ignored.
+    pub fn parse(text: &str) -> Result<Allowlist, String> {
+        let mut rules = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((methods, pattern)) = line.split_once(char::is_whitespace) else {
+                return Err(format!("expected methods and a path in '{}'", line));
+            };
+            let methods = methods
+                .split(',')
+                .map(|m| match m.to_ascii_uppercase().as_str() {
+                    "GET" => Ok(Method::GET),
+                    "POST" => Ok(Method::POST),
+                    "PUT" => Ok(Method::PUT),
+                    "DELETE" => Ok(Method::DELETE),
+                    _ => Err(format!("unsupported method '{}'", m)),
+                })
+                .collect::<Result<Vec<Method>, String>>()?;
+            let pattern: Vec<String> = pattern
+                .trim()
+                .trim_matches('/')
+                .split('/')
+                .map(String::from)
+                .collect();
+            if pattern.iter().rev().skip(1).any(|p| p == "**") {
+                return Err(format!("** may only end a path, in '{}'", line));
+            }
+            rules.push(Rule { methods, pattern });
+        }
+        Ok(Allowlist { rules })
+    }
+
+    // Whether a request for the path, relative to the Web API's base URL,
+    // may be forwarded. The path is still percent-encoded, and the URL it is
+    // forwarded to resolves an encoded dot segment like a plain one, so the
+    // segments are decoded before they are checked.
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        let segments: Option<Vec<String>> = path
+            .trim_matches('/')
+            .split('/')
+            .map(|s| percent_decode_str(s).decode_utf8().ok().map(String::from))
+            .collect();
+        let Some(segments) = segments else {
+            return false;
+        };
+        if segments
+            .iter()
+            .any(|s| s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\']))
+        {
+            return false;
+        }
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        self.rules
+            .iter()
+            .any(|rule| rule.matches(method, &segments))
+    }
+}
@@ -0,0 +1,170 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Upstream request/response pairs saved as JSON files, one per exchange, so a
+// session can be captured with --record and served again with --replay.
+
+// Stands in for credentials in recorded headers.
+const REDACTED: &str = "<redacted>";
+
+// Files written by this process, across all clients, so they sort in the
+// order the requests were made.
+static NEXT_SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub method: String,
+    pub endpoint: String,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    #[serde(default)]
+    pub request_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub request_body: Option<Value>,
+    pub status: u16,
+    #[serde(default)]
+    pub response_headers: HashMap<String, String>,
+    // the body as JSON when it parses, otherwise as text
+    #[serde(default)]
+    pub response_json: Option<Value>,
+    #[serde(default)]
+    pub response_text: Option<String>,
+}
+
+impl Recording {
+    pub fn response_body(&self) -> String {
+        match (&self.response_json, &self.response_text) {
+            (Some(json), _) => json.to_string(),
+            (None, Some(text)) => text.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn matches(
+        &self,
+        method: &str,
+        endpoint: &str,
+        params: &HashMap<String, String>,
+        body: &Option<Value>,
+    ) -> bool {
+        self.method == method
+            && self.endpoint == endpoint
+            && self.params == *params
+            && self.request_body == *body
+    }
+}
+
+#[derive(Debug)]
+pub enum FixtureMode {
+    Record(PathBuf),
+    // recordings not served yet, in file order
+    Replay(Mutex<Vec<Recording>>),
+}
+
+impl FixtureMode {
+    // SPT_RECORD_DIR or SPT_REPLAY_DIR, set by the --record and --replay flags.
+    // A replay directory that can't be read serves nothing rather than
+    // falling back to the network.
+    pub fn from_env() -> Option<FixtureMode> {
+        if let Ok(dir) = std::env::var("SPT_REPLAY_DIR") {
+            let recordings = load(Path::new(&dir)).unwrap_or_else(|e| {
+                error!("Failed to load recordings from {}: {}", dir, e);
+                Vec::new()
+            });
+            return Some(FixtureMode::Replay(Mutex::new(recordings)));
+        }
+        if let Ok(dir) = std::env::var("SPT_RECORD_DIR") {
+            // carry on after the files of an earlier recording
+            let existing = fs::create_dir_all(&dir)
+                .and_then(|_| fs::read_dir(&dir))
+                .map(|entries| entries.count());
+            match existing {
+                Ok(count) => {
+                    NEXT_SEQUENCE.fetch_max(count, Ordering::SeqCst);
+                }
+                Err(e) => error!("Failed to open recording directory {}: {}", dir, e),
+            }
+            return Some(FixtureMode::Record(PathBuf::from(dir)));
+        }
+        None
+    }
+
+    // The first unserved recording of the same request, taken so repeated
+    // requests get their responses in the order they were recorded.
+    pub fn take(
+        &self,
+        method: &str,
+        endpoint: &str,
+        params: &HashMap<String, String>,
+        body: &Option<Value>,
+    ) -> Option<Recording> {
+        let FixtureMode::Replay(recordings) = self else {
+            return None;
+        };
+        let mut recordings = recordings.lock().unwrap();
+        let index = recordings
+            .iter()
+            .position(|r| r.matches(method, endpoint, params, body))?;
+        Some(recordings.remove(index))
+    }
+}
+
+// Request header values as recorded, with credentials replaced.
+pub fn redact_headers(headers: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match name.as_str() {
+                "authorization" | "cookie" | "set-cookie" => REDACTED.to_string(),
+                _ => value.to_str().unwrap_or_default().to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+// Writes a recording as {sequence}-{method}-{endpoint}.json.
+pub fn save(dir: &Path, recording: &Recording) -> io::Result<PathBuf> {
+    let sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::SeqCst);
+    let slug: String = recording
+        .endpoint
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = dir.join(format!(
+        "{:05}-{}-{}.json",
+        sequence, recording.method, slug
+    ));
+    let json = serde_json::to_string_pretty(recording)?;
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+// Every recording in the directory, ordered by file name.
+pub fn load(dir: &Path) -> io::Result<Vec<Recording>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let text = fs::read_to_string(path)?;
+            serde_json::from_str(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })
+        })
+        .collect()
+}
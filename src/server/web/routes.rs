@@ -4,341 +4,146 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{Mutex, RwLock};
+use warp::http::{Method, StatusCode};
 use warp::{Filter, Rejection, Reply};
 
+use crate::server::db::history_db::{HistoryDb, TimeRange};
+use crate::server::db::smart_playlist_db::{SmartPlaylistDb, SmartPlaylistRecord};
+use crate::server::queue::virtual_queue::VirtualQueue;
+use crate::server::web::fwd_allowlist::Allowlist;
 use crate::server::web::spt_api_proxy::ApiProxy;
 use crate::util::errors::return_response_code;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-enum RouteType {
-    Get,
-    Post,
-    Put,
-    Delete,
-}
-
-fn construct_json_fwd_route_no_body(
-    route_type: RouteType,
-    full_route: &str,
+// Forwards api/spt-fwd/<path> to the Web API, keeping the method, query and
+// body, if the allowlist allows the method on the path.
+fn construct_json_fwd_route(
+    allowlist: Arc<Allowlist>,
     api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
     last_request_time: Arc<Mutex<Instant>>,
 ) -> impl Filter<Extract = (warp::reply::WithStatus<warp::reply::Json>,), Error = Rejection> + Clone
 {
-    let full_route = full_route.to_string();
-    let path_parts: Vec<_> = full_route.split('/').map(String::from).collect();
-    let mut route = warp::any().boxed();
-    for part in path_parts.iter() {
-        route = route.and(warp::path(part.clone())).boxed();
-    }
-
-    if route_type == RouteType::Post
-        || route_type == RouteType::Put
-        || route_type == RouteType::Delete
-    {
-        error!("Cannot construct POST/PUT/DELETE route without body.");
-        panic!();
-    } else if route_type == RouteType::Get {
-        route = route.and(warp::get()).boxed();
-    }
-
-    route
-        .and(warp::path::end())
-        .and(warp::query::<std::collections::HashMap<String, String>>())
-        .and_then({
-            let full_route = full_route.clone();
-            let route_type = route_type.clone();
-
-            move |query: std::collections::HashMap<String, String>| {
-                let last_request_time = Arc::clone(&last_request_time);
+    let reply = |json: Value, status: StatusCode| {
+        Ok::<_, warp::Rejection>(warp::reply::with_status(warp::reply::json(&json), status))
+    };
+
+    warp::path("api")
+        .and(warp::path("spt-fwd"))
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::body::bytes())
+        .and_then(
+            move |tail: warp::path::Tail,
+                  method: Method,
+                  mut query: HashMap<String, String>,
+                  body: warp::hyper::body::Bytes| {
+                let allowlist = Arc::clone(&allowlist);
                 let api_proxies = Arc::clone(&api_proxies);
-                let route_type = route_type.clone();
-                let full_route = full_route.clone();
+                let last_request_time = Arc::clone(&last_request_time);
+                let endpoint = tail.as_str().to_string();
 
                 async move {
                     update_last_request_time(&last_request_time).await;
 
-                    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
-                    if client_id.is_none() {
-                        error!("Received call to route /{} without client_id.", full_route);
-                        return Ok::<_, warp::Rejection>(warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({})),
-                            warp::http::StatusCode::FORBIDDEN,
-                        ));
-                    }
+                    // client_id is ours, not Spotify's, and changes between
+                    // sessions
+                    let client_id = query
+                        .remove("client_id")
+                        .and_then(|s| s.parse::<u64>().ok());
+                    let Some(client_id) = client_id else {
+                        error!(
+                            "Received call to route /api/spt-fwd/{} without client_id.",
+                            endpoint
+                        );
+                        return reply(serde_json::json!({}), StatusCode::FORBIDDEN);
+                    };
 
                     info!(
-                        "Received call to route /{} from client_id {}.",
-                        full_route,
-                        client_id.clone().unwrap()
+                        "Received {} call to route /api/spt-fwd/{} from client_id {}.",
+                        method, endpoint, client_id
                     );
 
-                    let proxy = if let Some(id) = client_id {
-                        let proxies = api_proxies.read().await;
-                        proxies.get(&id).map(|p| Arc::clone(p))
-                    } else {
-                        None
-                    };
-                    if proxy.is_none() {
-                        error!(
-                            "No proxy found for client_id {}.",
-                            client_id.clone().unwrap()
+                    if !allowlist.allows(&method, &endpoint) {
+                        warn!(
+                            "Refusing to forward {} {}, not in the allowlist.",
+                            method, endpoint
+                        );
+                        return reply(
+                            serde_json::json!({
+                                "error": format!("{} {} is not allowed", method, endpoint)
+                            }),
+                            StatusCode::FORBIDDEN,
                         );
-                        return Ok::<_, warp::Rejection>(warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({})),
-                            warp::http::StatusCode::FORBIDDEN,
-                        ));
                     }
 
-                    let proxy = proxy.unwrap();
-                    let shortened_route = &full_route["api/spt-fwd/".len()..];
-                    let res = match route_type.clone() {
-                        RouteType::Get => proxy.get(shortened_route, Some(query)).await,
-                        RouteType::Delete => {
-                            error!("Cannot construct DELETE route without body.");
-                            proxy.delete(shortened_route, None, None).await
-                        }
-                        RouteType::Post => {
-                            error!("Cannot construct POST route without body.");
-                            proxy.post(shortened_route, None, None).await
-                        }
-                        RouteType::Put => {
-                            error!("Cannot construct PUT route without body.");
-                            proxy.put(shortened_route, None, None).await
-                        }
+                    let proxy = api_proxies.read().await.get(&client_id).map(Arc::clone);
+                    let Some(proxy) = proxy else {
+                        error!("No proxy found for client_id {}.", client_id);
+                        return reply(serde_json::json!({}), StatusCode::FORBIDDEN);
                     };
 
-                    match res {
-                        Ok((status, json)) => {
-                            info!(
-                                "Forwarding request to route /{} with status {}.",
-                                full_route, status
-                            );
-                            Ok::<_, warp::Rejection>(warp::reply::with_status(
-                                warp::reply::json(&json),
-                                status,
-                            ))
-                        }
-                        Err(err) => {
-                            warn!(
-                                "Forwarding request to route /{} with status {}.",
-                                full_route,
-                                return_response_code(err.clone())
-                            );
-                            Ok::<_, warp::Rejection>(warp::reply::with_status(
-                                warp::reply::json(&serde_json::json!({
-                                    "error": format!("Error: {}", err)
-                                })),
-                                return_response_code(err),
-                            ))
-                        }
-                    }
-                }
-            }
-        })
-}
-
-fn construct_json_fwd_route_with_body(
-    route_type: RouteType,
-    full_route: &str,
-    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
-    last_request_time: Arc<Mutex<Instant>>,
-) -> impl Filter<Extract = (warp::reply::WithStatus<warp::reply::Json>,), Error = Rejection> + Clone
-{
-    let full_route = full_route.to_string();
-    let path_parts: Vec<_> = full_route.split('/').map(String::from).collect();
-    let mut route = warp::any().boxed();
-    for part in path_parts.iter() {
-        route = route.and(warp::path(part.clone())).boxed();
-    }
-
-    if route_type == RouteType::Get {
-        // error
-        error!("Cannot construct GET route with body.");
-        panic!();
-    } else if route_type == RouteType::Post {
-        route = route.and(warp::post()).boxed();
-    } else if route_type == RouteType::Put {
-        route = route.and(warp::put()).boxed();
-    } else if route_type == RouteType::Delete {
-        route = route.and(warp::delete()).boxed();
-    }
-
-    route
-        .and(warp::path::end())
-        .and(warp::query::<std::collections::HashMap<String, String>>())
-        .and(warp::body::json::<serde_json::Value>())
-        .and_then({
-            let full_route = full_route.clone();
-            let route_type = route_type.clone();
-
-            move |query: std::collections::HashMap<String, String>, body: serde_json::Value| {
-                let last_request_time = Arc::clone(&last_request_time);
-                let api_proxies = Arc::clone(&api_proxies);
-                let full_route = full_route.clone();
-                let route_type = route_type.clone();
-
-                async move {
-                    update_last_request_time(&last_request_time).await;
-
-                    let client_id = body["client_id"].as_u64();
-                    if client_id.is_none() {
-                        error!("Received call to route /{} without client_id.", full_route);
-                        return Ok::<_, warp::Rejection>(warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({})),
-                            warp::http::StatusCode::FORBIDDEN,
-                        ));
-                    }
-
-                    info!(
-                        "Received call to route /{} from client_id {}.",
-                        full_route,
-                        client_id.unwrap()
-                    );
-
-                    let proxy = if let Some(id) = client_id {
-                        let proxies = api_proxies.read().await;
-                        proxies.get(&id).map(Arc::clone)
-                    } else {
-                        None
+                    // no body and a null one alike send none upstream
+                    let body = match body.is_empty() {
+                        true => None,
+                        false => match serde_json::from_slice::<Value>(&body) {
+                            Ok(Value::Null) => None,
+                            Ok(json) => Some(json),
+                            Err(_) => {
+                                return reply(
+                                    serde_json::json!({"error": "Request body is not JSON"}),
+                                    StatusCode::BAD_REQUEST,
+                                )
+                            }
+                        },
                     };
-                    if proxy.is_none() {
-                        error!("No proxy found for client_id {}.", client_id.unwrap());
-                        return Ok::<_, warp::Rejection>(warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({})),
-                            warp::http::StatusCode::FORBIDDEN,
-                        ));
-                    }
 
-                    let proxy = proxy.unwrap();
-                    let shortened_route = &full_route["api/spt-fwd/".len()..];
-                    let res = match route_type.clone() {
-                        RouteType::Get => {
-                            error!("Cannot construct GET route with body.");
-                            proxy.get(shortened_route, None).await
-                        }
-                        RouteType::Put => proxy.put(shortened_route, Some(body), Some(query)).await,
-                        RouteType::Post => {
-                            proxy.post(shortened_route, Some(body), Some(query)).await
-                        }
-                        RouteType::Delete => {
-                            proxy.delete(shortened_route, Some(body), Some(query)).await
-                        }
+                    let res = match method {
+                        Method::GET => proxy.get(&endpoint, Some(query)).await,
+                        Method::POST => proxy.post(&endpoint, body, Some(query)).await,
+                        Method::PUT => proxy.put(&endpoint, body, Some(query)).await,
+                        Method::DELETE => proxy.delete(&endpoint, body, Some(query)).await,
+                        _ => return reply(serde_json::json!({}), StatusCode::METHOD_NOT_ALLOWED),
                     };
 
                     match res {
                         Ok((status, json)) => {
                             info!(
-                                "Forwarding request to route /{} with status {}.",
-                                full_route, status
+                                "Forwarding request to route /api/spt-fwd/{} with status {}.",
+                                endpoint, status
                             );
-                            Ok::<_, warp::Rejection>(warp::reply::with_status(
-                                warp::reply::json(&json),
-                                status,
-                            ))
+                            reply(json, status)
                         }
                         Err(err) => {
                             warn!(
-                                "Forwarding request to route /{} with status {}.",
-                                full_route,
+                                "Forwarding request to route /api/spt-fwd/{} with status {}.",
+                                endpoint,
                                 return_response_code(err.clone())
                             );
-                            Ok::<_, warp::Rejection>(warp::reply::with_status(
-                                warp::reply::json(&serde_json::json!({
-                                    "error": format!("Error: {}", err)
-                                })),
+                            reply(
+                                serde_json::json!({ "error": format!("Error: {}", err) }),
                                 return_response_code(err),
-                            ))
+                            )
                         }
                     }
                 }
-            }
-        })
-}
-
-fn construct_json_fwd_route(
-    route_type: RouteType,
-    full_route: &str,
-    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
-    last_request_time: Arc<Mutex<Instant>>,
-) -> impl Filter<Extract = (warp::reply::WithStatus<warp::reply::Json>,), Error = Rejection> + Clone
-{
-    if route_type == RouteType::Get {
-        construct_json_fwd_route_no_body(RouteType::Get, full_route, api_proxies, last_request_time)
-            .boxed()
-    } else {
-        construct_json_fwd_route_with_body(route_type, full_route, api_proxies, last_request_time)
-            .boxed()
-    }
+            },
+        )
 }
 
 pub fn routes(
     api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    history_db: Arc<HistoryDb>,
+    smart_playlist_db: Arc<SmartPlaylistDb>,
+    queue: Arc<VirtualQueue>,
     next_client_id: Arc<Mutex<u64>>,
     last_request_time: Arc<Mutex<Instant>>,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    // Define the routes
-    let json_fwd_get_routes = vec![
-        "api/spt-fwd/me/player/currently-playing",
-        "api/spt-fwd/me/player/devices",
-        "api/spt-fwd/me/player/queue",
-        "api/spt-fwd/me/player/recently-played",
-    ];
-
-    let initial_route = construct_json_fwd_route(
-        RouteType::Get,
-        json_fwd_get_routes[0],
+    let api_routes = construct_json_fwd_route(
+        Arc::new(Allowlist::load()),
         Arc::clone(&api_proxies),
         Arc::clone(&last_request_time),
     )
     .boxed();
-    let api_routes = json_fwd_get_routes
-        .iter()
-        .skip(1)
-        .map(|route| {
-            construct_json_fwd_route(
-                RouteType::Get,
-                route,
-                Arc::clone(&api_proxies),
-                Arc::clone(&last_request_time),
-            )
-        })
-        .fold(initial_route, |acc, route| acc.or(route).unify().boxed());
-
-    let json_fwd_put_routes = vec![
-        "api/spt-fwd/me/player/play",
-        "api/spt-fwd/me/player/pause",
-        "api/spt-fwd/me/player",
-    ];
-    let api_routes = json_fwd_put_routes
-        .iter()
-        .map(|route| {
-            construct_json_fwd_route(
-                RouteType::Put,
-                route,
-                Arc::clone(&api_proxies),
-                Arc::clone(&last_request_time),
-            )
-        })
-        .fold(api_routes, |acc, route| acc.or(route).unify().boxed());
-
-    let json_fwd_post_routes = vec![
-        "api/spt-fwd/me/player/next",
-        "api/spt-fwd/me/player/previous",
-        "api/spt-fwd/me/player/seek",
-        "api/spt-fwd/me/player/volume",
-        "api/spt-fwd/me/player/queue",
-    ];
-    let api_routes = json_fwd_post_routes
-        .iter()
-        .map(|route| {
-            construct_json_fwd_route(
-                RouteType::Post,
-                route,
-                Arc::clone(&api_proxies),
-                Arc::clone(&last_request_time),
-            )
-        })
-        .fold(api_routes, |acc, route| acc.or(route).unify().boxed());
 
     let ping_route = warp::path("ping").and(warp::path::end()).and_then({
         let last_request_time = Arc::clone(&last_request_time);
@@ -387,7 +192,8 @@ pub fn routes(
 
                 {
                     let mut api_proxies = api_proxies.write().await;
-                    api_proxies.insert(client_id_val, Arc::new(ApiProxy::new(client_id_val)));
+                    let proxy = Arc::new(ApiProxy::new(client_id_val));
+                    api_proxies.insert(client_id_val, Arc::clone(&proxy));
 
                     debug!("Added client_id {} to API proxy map.", client_id_val);
 
@@ -470,14 +276,433 @@ pub fn routes(
             }
         });
 
+    let history_stats_route = warp::path!("api" / "history" / "stats")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let history_db = Arc::clone(&history_db);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let history_db = Arc::clone(&history_db);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+
+                    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
+                    let known_client = match client_id {
+                        Some(id) => api_proxies.read().await.contains_key(&id),
+                        None => false,
+                    };
+                    if !known_client {
+                        error!("Received call to route /api/history/stats from unknown client.");
+                        return Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({})),
+                            warp::http::StatusCode::FORBIDDEN,
+                        ));
+                    }
+
+                    info!(
+                        "Received call to route /api/history/stats from client_id {}.",
+                        client_id.unwrap()
+                    );
+
+                    let range = TimeRange {
+                        from: query.get("from").and_then(|s| s.parse::<i64>().ok()),
+                        to: query.get("to").and_then(|s| s.parse::<i64>().ok()),
+                    };
+                    let limit = query
+                        .get("limit")
+                        .and_then(|s| s.parse::<u32>().ok())
+                        .unwrap_or(10);
+
+                    match history_db.stats(range, limit) {
+                        Ok(stats) => Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&stats),
+                            warp::http::StatusCode::OK,
+                        )),
+                        Err(err) => Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "error": format!("Error: {}", err)
+                            })),
+                            return_response_code(err),
+                        )),
+                    }
+                }
+            }
+        });
+
+    let resolve_link_route = warp::path!("api" / "resolve-link")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+
+                    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
+                    let proxy = match client_id {
+                        Some(id) => api_proxies.read().await.get(&id).map(Arc::clone),
+                        None => None,
+                    };
+                    let (proxy, link) = match (proxy, query.get("url")) {
+                        (Some(proxy), Some(link)) => (proxy, link),
+                        _ => {
+                            error!("Received invalid call to route /api/resolve-link.");
+                            return Ok::<_, warp::Rejection>(warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({})),
+                                warp::http::StatusCode::FORBIDDEN,
+                            ));
+                        }
+                    };
+
+                    info!(
+                        "Received call to route /api/resolve-link from client_id {}.",
+                        client_id.unwrap()
+                    );
+
+                    match proxy.resolve_link(link).await {
+                        Ok(url) => Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({ "url": url })),
+                            warp::http::StatusCode::OK,
+                        )),
+                        Err(err) => Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "error": format!("Error: {}", err)
+                            })),
+                            return_response_code(err),
+                        )),
+                    }
+                }
+            }
+        });
+
+    // Whether the client has a token, so callers that mustn't send the user
+    // through authorization, like shell completion, can check first.
+    let authorized_route = warp::path!("api" / "authorized")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+
+                    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
+                    let proxy = match client_id {
+                        Some(id) => api_proxies.read().await.get(&id).map(Arc::clone),
+                        None => None,
+                    };
+                    let authorized = match proxy {
+                        Some(proxy) => proxy.has_credentials().await,
+                        None => false,
+                    };
+                    Ok::<_, warp::Rejection>(warp::reply::json(
+                        &serde_json::json!({ "authorized": authorized }),
+                    ))
+                }
+            }
+        });
+
+    let queue_list_route = warp::path!("api" / "queue")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let queue = Arc::clone(&queue);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let queue = Arc::clone(&queue);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+                    handle_queue_op("list".to_string(), query, Value::Null, api_proxies, queue)
+                        .await
+                }
+            }
+        });
+
+    let queue_op_route = warp::path!("api" / "queue" / String)
+        .and(warp::post())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::body::json::<serde_json::Value>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let queue = Arc::clone(&queue);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |op: String,
+                  query: std::collections::HashMap<String, String>,
+                  body: serde_json::Value| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let queue = Arc::clone(&queue);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+                    handle_queue_op(op, query, body, api_proxies, queue).await
+                }
+            }
+        });
+
+    let smart_playlist_list_route = warp::path!("api" / "smart-playlists")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let smart_playlist_db = Arc::clone(&smart_playlist_db);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let smart_playlist_db = Arc::clone(&smart_playlist_db);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+                    handle_smart_playlist_op(
+                        Method::GET,
+                        query,
+                        Value::Null,
+                        api_proxies,
+                        smart_playlist_db,
+                    )
+                    .await
+                }
+            }
+        });
+
+    let smart_playlist_save_route = warp::path!("api" / "smart-playlists")
+        .and(warp::put())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::body::json::<serde_json::Value>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let smart_playlist_db = Arc::clone(&smart_playlist_db);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>, body: serde_json::Value| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let smart_playlist_db = Arc::clone(&smart_playlist_db);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+                    handle_smart_playlist_op(
+                        Method::PUT,
+                        query,
+                        body,
+                        api_proxies,
+                        smart_playlist_db,
+                    )
+                    .await
+                }
+            }
+        });
+
+    let smart_playlist_remove_route = warp::path!("api" / "smart-playlists")
+        .and(warp::delete())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then({
+            let api_proxies = Arc::clone(&api_proxies);
+            let smart_playlist_db = Arc::clone(&smart_playlist_db);
+            let last_request_time = Arc::clone(&last_request_time);
+
+            move |query: std::collections::HashMap<String, String>| {
+                let api_proxies = Arc::clone(&api_proxies);
+                let smart_playlist_db = Arc::clone(&smart_playlist_db);
+                let last_request_time = Arc::clone(&last_request_time);
+
+                async move {
+                    update_last_request_time(&last_request_time).await;
+                    handle_smart_playlist_op(
+                        Method::DELETE,
+                        query,
+                        Value::Null,
+                        api_proxies,
+                        smart_playlist_db,
+                    )
+                    .await
+                }
+            }
+        });
+
     return api_routes
         // .or(now_route)
+        .or(history_stats_route)
+        .or(resolve_link_route)
+        .or(authorized_route)
+        .or(queue_list_route)
+        .or(queue_op_route)
+        .or(smart_playlist_list_route)
+        .or(smart_playlist_save_route)
+        .or(smart_playlist_remove_route)
         .or(ping_route)
         .or(init_route)
         .or(auth_cb_route)
         .or(root_route);
 }
 
+// Applies an operation to the server's virtual queue and replies with the
+// pending items after the operation. Only the feeder passes items on to
+// Spotify, so that they stay in order and positions in the reply stay valid.
+async fn handle_queue_op(
+    op: String,
+    query: HashMap<String, String>,
+    body: Value,
+    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    queue: Arc<VirtualQueue>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
+    let known = match client_id {
+        Some(id) => api_proxies.read().await.contains_key(&id),
+        None => false,
+    };
+    if !known {
+        error!(
+            "Received call to route /api/queue/{} from unknown client.",
+            op
+        );
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({})),
+            warp::http::StatusCode::FORBIDDEN,
+        ));
+    }
+
+    info!(
+        "Received call to route /api/queue/{} from client_id {}.",
+        op,
+        client_id.unwrap()
+    );
+
+    let uris: Vec<String> = body["uris"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .filter_map(|u| u.as_str().map(String::from))
+        .collect();
+    let index = |key: &str| body[key].as_u64().map(|i| i as usize);
+
+    let ok = match op.as_str() {
+        "list" => true,
+        "add" => {
+            queue.push_back(uris).await;
+            true
+        }
+        "insert-next" => {
+            queue.insert_next(uris).await;
+            true
+        }
+        "remove" => match index("index") {
+            Some(i) => queue.remove(i).await.is_some(),
+            None => false,
+        },
+        "move" => match (index("from"), index("to")) {
+            (Some(from), Some(to)) => queue.move_item(from, to).await,
+            _ => false,
+        },
+        "clear" => {
+            queue.clear().await;
+            true
+        }
+        "shuffle" => {
+            queue.shuffle().await;
+            true
+        }
+        _ => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({})),
+                warp::http::StatusCode::NOT_FOUND,
+            ))
+        }
+    };
+
+    let status = if ok {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::BAD_REQUEST
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "pending": queue.list().await })),
+        status,
+    ))
+}
+
+// Lists, saves or removes smart playlist definitions. GET replies with all of
+// them, PUT with the one saved, DELETE with whether the one named in the
+// query existed.
+async fn handle_smart_playlist_op(
+    method: Method,
+    query: HashMap<String, String>,
+    body: Value,
+    api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    smart_playlist_db: Arc<SmartPlaylistDb>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    let reply = |json: Value, status: StatusCode| {
+        Ok(warp::reply::with_status(warp::reply::json(&json), status))
+    };
+
+    let client_id = query.get("client_id").and_then(|s| s.parse::<u64>().ok());
+    let known_client = match client_id {
+        Some(id) => api_proxies.read().await.contains_key(&id),
+        None => false,
+    };
+    if !known_client {
+        error!("Received call to route /api/smart-playlists from unknown client.");
+        return reply(serde_json::json!({}), StatusCode::FORBIDDEN);
+    }
+
+    info!(
+        "Received {} call to route /api/smart-playlists from client_id {}.",
+        method,
+        client_id.unwrap()
+    );
+
+    let res = match method {
+        Method::PUT => match serde_json::from_value::<SmartPlaylistRecord>(body) {
+            Ok(smart) => smart_playlist_db
+                .save(&smart)
+                .and_then(|_| smart_playlist_db.get(&smart.name))
+                .map(|saved| serde_json::json!(saved)),
+            Err(_) => return reply(serde_json::json!({}), StatusCode::BAD_REQUEST),
+        },
+        Method::DELETE => match query.get("name") {
+            Some(name) => smart_playlist_db
+                .remove(name)
+                .map(|removed| serde_json::json!({ "removed": removed })),
+            None => return reply(serde_json::json!({}), StatusCode::BAD_REQUEST),
+        },
+        _ => smart_playlist_db
+            .list()
+            .map(|all| serde_json::json!({ "items": all })),
+    };
+
+    match res {
+        Ok(json) => reply(json, StatusCode::OK),
+        Err(err) => reply(
+            serde_json::json!({ "error": format!("Error: {}", err) }),
+            return_response_code(err),
+        ),
+    }
+}
+
 async fn update_last_request_time(last_request_time: &Arc<Mutex<Instant>>) {
     let mut last_time = last_request_time.lock().await;
     *last_time = Instant::now();
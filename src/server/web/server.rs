@@ -9,6 +9,11 @@ use tokio::sync::{Mutex, RwLock};
 // use tokio::sync::oneshot;
 use tokio::time;
 
+use crate::server::db::history_db::HistoryDb;
+use crate::server::db::history_recorder::record_history;
+use crate::server::db::smart_playlist_db::SmartPlaylistDb;
+use crate::server::queue::queue_feeder::feed_queue;
+use crate::server::queue::virtual_queue::VirtualQueue;
 use crate::server::web::routes;
 use crate::server::web::spt_api_proxy::ApiProxy;
 
@@ -19,6 +24,11 @@ pub struct ServerMeta {
     // pub db_url: String,
     // pub db_port: u16,
     pub api_proxies: Arc<RwLock<HashMap<u64, Arc<ApiProxy>>>>,
+    pub history_db: Arc<HistoryDb>,
+    pub smart_playlist_db: Arc<SmartPlaylistDb>,
+    pub history_poll_interval: Duration,
+    pub queue: Arc<VirtualQueue>, // shared by all clients, as is the account
+    pub queue_poll_interval: Duration,
     pub next_client_id: Arc<Mutex<u64>>,
     pub last_request_time: Arc<Mutex<Instant>>,
 }
@@ -31,6 +41,22 @@ pub async fn start_server(
     let last_request_time = Arc::new(Mutex::new(Instant::now()));
     // let last_request_time_clone = Arc::clone(&last_request_time);
 
+    let db_path = env::var("DB_PATH").unwrap_or("data/spt.db".to_string());
+    let history_db = HistoryDb::open(&db_path)?;
+    let smart_playlist_db = SmartPlaylistDb::open(&db_path)?;
+    let history_poll_interval = Duration::from_secs(
+        env::var("HISTORY_POLL_SECONDS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(5),
+    );
+    let queue_poll_interval = Duration::from_secs(
+        env::var("QUEUE_POLL_SECONDS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(2),
+    );
+
     let server_meta = ServerMeta {
         port,
         inactivity_timeout,
@@ -40,15 +66,36 @@ pub async fn start_server(
         //     .parse::<u16>()
         //     .unwrap(),
         api_proxies: Arc::new(RwLock::new(HashMap::new())),
+        history_db: Arc::new(history_db),
+        smart_playlist_db: Arc::new(smart_playlist_db),
+        history_poll_interval,
+        queue: Arc::new(VirtualQueue::new()),
+        queue_poll_interval,
         next_client_id: Arc::new(Mutex::new(1)),
         last_request_time: last_request_time,
     };
 
+    // one recorder and one queue feeder for the server, polling as whichever
+    // client is authorized
+    tokio::spawn(record_history(
+        Arc::clone(&server_meta.api_proxies),
+        Arc::clone(&server_meta.history_db),
+        server_meta.history_poll_interval,
+    ));
+    tokio::spawn(feed_queue(
+        Arc::clone(&server_meta.api_proxies),
+        Arc::clone(&server_meta.queue),
+        server_meta.queue_poll_interval,
+    ));
+
     // Shutdown signal - TODO delete
     // let (_, shutdown_rx) = oneshot::channel();
 
     let routes = routes::routes(
         Arc::clone(&server_meta.api_proxies),
+        Arc::clone(&server_meta.history_db),
+        Arc::clone(&server_meta.smart_playlist_db),
+        Arc::clone(&server_meta.queue),
         Arc::clone(&server_meta.next_client_id),
         Arc::clone(&server_meta.last_request_time),
     );
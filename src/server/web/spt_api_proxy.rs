@@ -1,8 +1,10 @@
+use crate::server::web::http_fixtures::{self, FixtureMode, Recording};
 use crate::util::errors::{self, ApiError};
 use base64::{engine::general_purpose, Engine};
 use log::{debug, error, info, warn};
 use rand::Rng;
-use reqwest::{Client, StatusCode};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Method, StatusCode};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
@@ -30,8 +32,11 @@ pub struct ApiProxy {
     scope: String,
 
     base_url: String,
+    accounts_url: String, // authorization and token endpoints live here
     callback_url: String,
+    headless_auth: bool, // request the authorize URL instead of opening a browser
     backoff: RwLock<SystemTime>, // time to start api calls again
+    fixtures: Option<FixtureMode>, // recording or replaying upstream exchanges
 
     user_client_id: u64, // unique client id for each user, each user gets their own ApiProxy
     auth_info: RwLock<AuthInfo>,
@@ -44,9 +49,14 @@ impl ApiProxy {
         // let client_secret =
         //     env::var("SPT_API_CLIENT_SECRET").expect("SPT_API_CLIENT_SECRET must be set");
         let base_url = env::var("SPT_API_BASE_URL").expect("SPT_API_BASE_URL must be set");
+        let accounts_url =
+            env::var("SPT_ACCOUNTS_URL").unwrap_or("https://accounts.spotify.com".to_string());
         let callback_url =
             env::var("SERVER_CALLBACK_URL").expect("SERVER_CALLBACK_URL must be set");
         let scope = env::var("SPT_API_SCOPE").expect("SPT_API_SCOPE must be set");
+        // only useful against an accounts service that grants consent at once
+        // and redirects straight to the callback, like the test mock
+        let headless_auth = env::var("SPT_AUTH_HEADLESS").is_ok_and(|v| v == "1");
 
         return ApiProxy {
             client: Client::new(),
@@ -56,8 +66,11 @@ impl ApiProxy {
             scope,
 
             base_url,
+            accounts_url,
             callback_url,
+            headless_auth,
             backoff: RwLock::new(SystemTime::now()),
+            fixtures: FixtureMode::from_env(),
 
             user_client_id,
             auth_info: RwLock::new(AuthInfo {
@@ -80,6 +93,17 @@ impl ApiProxy {
         auth_info.cb_auth_code = None;
     }
 
+    // Returns true if the client has a token that can be used without sending
+    // the user through the browser authorization flow again. A replayed
+    // session needs none, so it counts as authorized.
+    pub async fn has_credentials(&self) -> bool {
+        if self.replaying() {
+            return true;
+        }
+        let auth_info = self.auth_info.read().await;
+        auth_info.access_token.is_some() || auth_info.refresh_token.is_some()
+    }
+
     pub async fn execute_backoff(&self) -> Result<(), ApiError> {
         // loop {
         // get backoff lock here
@@ -182,12 +206,18 @@ impl ApiProxy {
             ("state", &sent_state),
         ];
 
-        let url = match Url::parse_with_params("https://accounts.spotify.com/authorize", &params) {
+        let authorize_url = format!("{}/authorize", self.accounts_url);
+        let url = match Url::parse_with_params(&authorize_url, &params) {
             Ok(parsed_url) => Into::<String>::into(parsed_url),
             Err(_) => return Err(ApiError::RequestError),
         };
 
-        if let Err(_) = open::that(url) {
+        if self.headless_auth {
+            // following the redirects ends at the callback route
+            if self.client.get(&url).send().await.is_err() {
+                return Err(ApiError::RequestError);
+            }
+        } else if let Err(_) = open::that(url) {
             return Err(ApiError::BrowserError);
         }
 
@@ -219,7 +249,7 @@ impl ApiProxy {
         // send request
         let request = self
             .client
-            .post("https://accounts.spotify.com/api/token")
+            .post(format!("{}/api/token", self.accounts_url))
             .form(&params);
 
         let response = match request.send().await {
@@ -340,7 +370,7 @@ impl ApiProxy {
         // send request
         let request = self
             .client
-            .post("https://accounts.spotify.com/api/token")
+            .post(format!("{}/api/token", self.accounts_url))
             .form(&params);
 
         let response = match request.send().await {
@@ -412,90 +442,7 @@ impl ApiProxy {
         endpoint: &str,
         params: Option<HashMap<String, String>>,
     ) -> Result<(StatusCode, Value), ApiError> {
-        // check if access token is valid, if not, auth/reauth
-        self.validate_auth().await?;
-
-        // backoff
-        self.execute_backoff().await?;
-
-        // construct and send request
-        let url = format!("{}/{}", self.base_url, endpoint);
-
-        let access_token = {
-            let auth_info = self.auth_info.read().await;
-            auth_info.access_token.clone()
-        };
-
-        info!(
-            "Client {} sending request to {}/{}.",
-            self.user_client_id, self.base_url, endpoint
-        );
-
-        let request = self
-            .client
-            .get(&url)
-            .bearer_auth(access_token.ok_or_else(|| ApiError::NoAccessToken)?.0)
-            .query(&params.unwrap_or_default());
-
-        let response = match request.send().await {
-            Ok(res) => res,
-            Err(_) => return Err(ApiError::RequestError),
-        };
-
-        let status = response.status();
-
-        if status.as_u16() == 200 || status.as_u16() == 204 {
-            info!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
-        } else {
-            warn!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
-        }
-
-        // match status code
-        match status.as_u16() {
-            200 => {
-                let json = match response.json::<Value>().await {
-                    Ok(data) => data,
-                    Err(_) => {
-                        return Err(ApiError::ResponseParseError);
-                    }
-                };
-                return Ok((status, json));
-            }
-            204 => Ok((status, serde_json::json!({}))),
-            401 => {
-                // refresh access token if needed
-                {
-                    let mut auth_info = self.auth_info.write().await;
-                    auth_info.access_token = None;
-                }
-                self.validate_auth().await?;
-                return Err(ApiError::InvalidAccessToken);
-            }
-            429 => {
-                // backoff if rate limited
-                let mut backoff = self.backoff.write().await;
-                if let Some(retry_after) = response
-                    .headers()
-                    .get("Retry-After")
-                    .and_then(|header| header.to_str().ok())
-                    .and_then(|value| value.parse::<u64>().ok())
-                {
-                    *backoff = SystemTime::now() + Duration::from_secs(retry_after);
-                } else {
-                    *backoff = SystemTime::now() + Duration::from_secs(5);
-                    // default backoff
-                }
-
-                return Err(ApiError::ResponseError429);
-            }
-            _ => Err(errors::return_response_error(status)),
-        }
+        self.request(Method::GET, endpoint, None, params).await
     }
 
     // Method for sending POST requests to the Spotify API
@@ -505,100 +452,66 @@ impl ApiProxy {
         body: Option<Value>,
         params: Option<HashMap<String, String>>,
     ) -> Result<(StatusCode, Value), ApiError> {
-        // backoff
-        self.execute_backoff().await?;
-
-        // check if access token is valid, if not, auth/reauth
-        self.validate_auth().await?;
-
-        // construct and send request
-        let url = format!("{}/{}", self.base_url, endpoint);
-
-        let access_token = {
-            let auth_info = self.auth_info.read().await;
-            auth_info.access_token.clone()
-        };
-
-        info!(
-            "Client {} sending request to {}/{}.",
-            self.user_client_id, self.base_url, endpoint
-        );
-
-        let request = self
-            .client
-            .post(&url)
-            .query(&params.unwrap_or_default())
-            .bearer_auth(access_token.ok_or_else(|| ApiError::NoAccessToken)?.0)
-            .json(&body.unwrap_or_default());
-
-        let response = match request.send().await {
-            Ok(res) => res,
-            Err(_) => return Err(ApiError::RequestError),
-        };
-
-        let status = response.status();
+        self.request(Method::POST, endpoint, body, params).await
+    }
 
-        if status.as_u16() == 200 || status.as_u16() == 204 {
-            info!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
-        } else {
-            warn!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
-        }
+    // Method for sending PUT requests to the Spotify API
+    pub async fn put(
+        &self,
+        endpoint: &str,
+        body: Option<Value>,
+        params: Option<HashMap<String, String>>,
+    ) -> Result<(StatusCode, Value), ApiError> {
+        self.request(Method::PUT, endpoint, body, params).await
+    }
 
-        // match status code
-        match status.as_u16() {
-            200 => {
-                let json = match response.json::<Value>().await {
-                    Ok(data) => data,
-                    Err(_) => {
-                        return Err(ApiError::ResponseParseError);
-                    }
-                };
-                return Ok((status, json));
-            }
-            204 => Ok((status, serde_json::json!({}))),
-            401 => {
-                // refresh access token if needed
-                {
-                    let mut auth_info = self.auth_info.write().await;
-                    auth_info.access_token = None;
-                }
-                self.validate_auth().await?;
-                return Err(ApiError::InvalidAccessToken);
-            }
-            429 => {
-                // backoff if rate limited
-                let mut backoff = self.backoff.write().await;
-                if let Some(retry_after) = response
-                    .headers()
-                    .get("Retry-After")
-                    .and_then(|header| header.to_str().ok())
-                    .and_then(|value| value.parse::<u64>().ok())
-                {
-                    *backoff = SystemTime::now() + Duration::from_secs(retry_after);
-                } else {
-                    *backoff = SystemTime::now() + Duration::from_secs(5);
-                    // default backoff
-                }
+    // Method for sending DELETE requests to the Spotify API
+    pub async fn delete(
+        &self,
+        endpoint: &str,
+        body: Option<Value>,
+        params: Option<HashMap<String, String>>,
+    ) -> Result<(StatusCode, Value), ApiError> {
+        self.request(Method::DELETE, endpoint, body, params).await
+    }
 
-                return Err(ApiError::ResponseError429);
-            }
-            _ => Err(errors::return_response_error(status)),
-        }
+    fn replaying(&self) -> bool {
+        matches!(self.fixtures, Some(FixtureMode::Replay(_)))
     }
 
-    // Method for sending PUT requests to the Spotify API
-    pub async fn put(
+    // Every Spotify API request goes through here, so that a whole session can
+    // be recorded or replayed
+    async fn request(
         &self,
+        method: Method,
         endpoint: &str,
         body: Option<Value>,
         params: Option<HashMap<String, String>>,
     ) -> Result<(StatusCode, Value), ApiError> {
+        let params = params.unwrap_or_default();
+
+        // replayed responses need neither the network nor credentials
+        if let Some(fixtures @ FixtureMode::Replay(_)) = &self.fixtures {
+            let recording = fixtures
+                .take(method.as_str(), endpoint, &params, &body)
+                .ok_or_else(|| {
+                    warn!(
+                        "Client {} has no recorded response for {} {}.",
+                        self.user_client_id, method, endpoint
+                    );
+                    ApiError::RequestError
+                })?;
+            let status =
+                StatusCode::from_u16(recording.status).map_err(|_| ApiError::ResponseDataError)?;
+            let retry_after = recording
+                .response_headers
+                .get("retry-after")
+                .and_then(|value| value.parse::<u64>().ok());
+            return self
+                .handle_response(endpoint, status, retry_after, recording.response_body())
+                .await;
+        }
+
         // backoff
         self.execute_backoff().await?;
 
@@ -618,21 +531,73 @@ impl ApiProxy {
             self.user_client_id, self.base_url, endpoint
         );
 
-        let request = self
+        let mut request = self
             .client
-            .put(&url)
-            .query(&params.unwrap_or_default())
-            .bearer_auth(access_token.ok_or_else(|| ApiError::NoAccessToken)?.0)
-            .json(&body.unwrap_or_default());
+            .request(method.clone(), &url)
+            .query(&params)
+            .bearer_auth(access_token.ok_or(ApiError::NoAccessToken)?.0);
+        if let Some(body) = body.as_ref().filter(|_| method != Method::GET) {
+            request = request.json(body);
+        }
+        let request = request.build().map_err(|_| ApiError::RequestError)?;
+        let request_headers = http_fixtures::redact_headers(request.headers());
 
-        let response = match request.send().await {
+        let response = match self.client.execute(request).await {
             Ok(res) => res,
             Err(_) => return Err(ApiError::RequestError),
         };
 
         let status = response.status();
+        let response_headers = response.headers().clone();
+        let text = response
+            .text()
+            .await
+            .map_err(|_| ApiError::ResponseParseError)?;
+
+        if let Some(FixtureMode::Record(dir)) = &self.fixtures {
+            let response_json = serde_json::from_str::<Value>(&text).ok();
+            let recording = Recording {
+                method: method.to_string(),
+                endpoint: endpoint.to_string(),
+                params,
+                request_headers,
+                request_body: body,
+                status: status.as_u16(),
+                response_headers: http_fixtures::redact_headers(&response_headers),
+                response_text: response_json.is_none().then(|| text.clone()),
+                response_json,
+            };
+            match http_fixtures::save(dir, &recording) {
+                Ok(path) => debug!(
+                    "Client {} recorded {} {} to {}.",
+                    self.user_client_id,
+                    method,
+                    endpoint,
+                    path.display()
+                ),
+                Err(e) => warn!(
+                    "Client {} failed to record {} {}: {}",
+                    self.user_client_id, method, endpoint, e
+                ),
+            }
+        }
+
+        let retry_after = response_headers
+            .get(RETRY_AFTER)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        self.handle_response(endpoint, status, retry_after, text)
+            .await
+    }
 
-        if status.as_u16() == 200 || status.as_u16() == 204 {
+    async fn handle_response(
+        &self,
+        endpoint: &str,
+        status: StatusCode,
+        retry_after: Option<u64>,
+        text: String,
+    ) -> Result<(StatusCode, Value), ApiError> {
+        if status.is_success() {
             info!(
                 "Client {} received response from {}/{} with status {}.",
                 self.user_client_id, self.base_url, endpoint, status
@@ -646,138 +611,73 @@ impl ApiProxy {
 
         // match status code
         match status.as_u16() {
-            200 => {
-                let json = match response.json::<Value>().await {
-                    Ok(data) => data,
-                    Err(_) => {
-                        return Err(ApiError::ResponseParseError);
-                    }
-                };
-                return Ok((status, json));
-            }
+            200 | 201 => match serde_json::from_str::<Value>(&text) {
+                Ok(json) => Ok((status, json)),
+                Err(_) => Err(ApiError::ResponseParseError),
+            },
             204 => Ok((status, serde_json::json!({}))),
             401 => {
                 // refresh access token if needed
-                {
-                    let mut auth_info = self.auth_info.write().await;
-                    auth_info.access_token = None;
+                if !self.replaying() {
+                    {
+                        let mut auth_info = self.auth_info.write().await;
+                        auth_info.access_token = None;
+                    }
+                    self.validate_auth().await?;
                 }
-                self.validate_auth().await?;
-                return Err(ApiError::InvalidAccessToken);
+                Err(ApiError::InvalidAccessToken)
             }
             429 => {
-                // backoff if rate limited
+                // backoff if rate limited, 5 seconds when not told how long
                 let mut backoff = self.backoff.write().await;
-                if let Some(retry_after) = response
-                    .headers()
-                    .get("Retry-After")
-                    .and_then(|header| header.to_str().ok())
-                    .and_then(|value| value.parse::<u64>().ok())
-                {
-                    *backoff = SystemTime::now() + Duration::from_secs(retry_after);
-                } else {
-                    *backoff = SystemTime::now() + Duration::from_secs(5);
-                    // default backoff
-                }
+                *backoff = SystemTime::now() + Duration::from_secs(retry_after.unwrap_or(5));
 
-                return Err(ApiError::ResponseError429);
+                Err(ApiError::ResponseError429)
             }
             _ => Err(errors::return_response_error(status)),
         }
     }
 
-    // Method for sending DELETE requests to the Spotify API
-    pub async fn delete(
-        &self,
-        endpoint: &str,
-        body: Option<Value>,
-        params: Option<HashMap<String, String>>,
-    ) -> Result<(StatusCode, Value), ApiError> {
-        // backoff
-        self.execute_backoff().await?;
-
-        // check if access token is valid, if not, auth/reauth
-        self.validate_auth().await?;
-
-        // construct and send request
-        let url = format!("{}/{}", self.base_url, endpoint);
-
-        let access_token = {
-            let auth_info = self.auth_info.read().await;
-            auth_info.access_token.clone()
-        };
+    // Method for resolving a spotify.link short link to the open.spotify.com
+    // URL it redirects to
+    pub async fn resolve_link(&self, link: &str) -> Result<String, ApiError> {
+        let url = Url::parse(link).map_err(|_| ApiError::RequestError)?;
+        if !matches!(
+            url.host_str(),
+            Some("spotify.link") | Some("spotify.app.link")
+        ) {
+            return Err(ApiError::ResponseError400);
+        }
 
         info!(
-            "Client {} sending request to {}/{}.",
-            self.user_client_id, self.base_url, endpoint
+            "Client {} resolving short link {}.",
+            self.user_client_id, link
         );
 
-        let request = self
-            .client
-            .delete(&url)
-            .query(&params.unwrap_or_default())
-            .bearer_auth(access_token.ok_or_else(|| ApiError::NoAccessToken)?.0)
-            .json(&body.unwrap_or_default());
-
-        let response = match request.send().await {
+        let response = match self.client.get(url).send().await {
             Ok(res) => res,
             Err(_) => return Err(ApiError::RequestError),
         };
 
-        let status = response.status();
-
-        if status.as_u16() == 200 || status.as_u16() == 204 {
-            info!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
-        } else {
-            warn!(
-                "Client {} received response from {}/{} with status {}.",
-                self.user_client_id, self.base_url, endpoint, status
-            );
+        let final_url = response.url().clone();
+        if final_url.host_str() == Some("open.spotify.com") {
+            return Ok(final_url.to_string());
         }
 
-        // match status code
-        match status.as_u16() {
-            200 => {
-                let json = match response.json::<Value>().await {
-                    Ok(data) => data,
-                    Err(_) => {
-                        return Err(ApiError::ResponseParseError);
-                    }
-                };
-                return Ok((status, json));
-            }
-            204 => Ok((status, serde_json::json!({}))),
-            401 => {
-                // refresh access token if needed
-                {
-                    let mut auth_info = self.auth_info.write().await;
-                    auth_info.access_token = None;
-                }
-                self.validate_auth().await?;
-                return Err(ApiError::InvalidAccessToken);
-            }
-            429 => {
-                // backoff if rate limited
-                let mut backoff = self.backoff.write().await;
-                if let Some(retry_after) = response
-                    .headers()
-                    .get("Retry-After")
-                    .and_then(|header| header.to_str().ok())
-                    .and_then(|value| value.parse::<u64>().ok())
-                {
-                    *backoff = SystemTime::now() + Duration::from_secs(retry_after);
-                } else {
-                    *backoff = SystemTime::now() + Duration::from_secs(5);
-                    // default backoff
-                }
-
-                return Err(ApiError::ResponseError429);
-            }
-            _ => Err(errors::return_response_error(status)),
-        }
+        // some links land on an app redirect page that embeds the real URL
+        let body = response
+            .text()
+            .await
+            .map_err(|_| ApiError::ResponseParseError)?;
+        body.find("https://open.spotify.com/")
+            .map(|start| {
+                body[start..]
+                    .split(|c: char| c == '"' || c == '\'' || c == '<' || c.is_whitespace())
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .ok_or(ApiError::ResponseDataError)
     }
 
     // Example method to get devices (for demonstration purposes)
@@ -785,3 +685,24 @@ impl ApiProxy {
     //     self.get("me/player/devices", None).await
     // }
 }
+
+// The latest client that can call Spotify without the browser authorization
+// flow, for the server's own background work. All clients of a server sign in
+// to the same account.
+pub async fn authorized_proxy(
+    api_proxies: &RwLock<HashMap<u64, Arc<ApiProxy>>>,
+) -> Option<Arc<ApiProxy>> {
+    let mut proxies: Vec<(u64, Arc<ApiProxy>)> = api_proxies
+        .read()
+        .await
+        .iter()
+        .map(|(id, proxy)| (*id, Arc::clone(proxy)))
+        .collect();
+    proxies.sort_by_key(|(id, _)| std::cmp::Reverse(*id));
+    for (_, proxy) in proxies {
+        if proxy.has_credentials().await {
+            return Some(proxy);
+        }
+    }
+    None
+}
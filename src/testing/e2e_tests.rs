@@ -0,0 +1,1390 @@
+// End to end tests: commands run through the CLI, the local API proxy and
+// the server against the mock Spotify service, all in this process.
+use crate::client::cli::cli_app::{self, Output};
+use crate::client::cli::eval::Session;
+use crate::client::local_api_proxy::ApiProxy;
+use crate::server::web::server::start_server;
+use crate::testing::mock_spotify::MockSpotify;
+use std::collections::HashMap;
+use std::env;
+use std::net::TcpListener;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use warp::http::{Method, StatusCode};
+
+// The mock and the server run on a runtime of their own, as each test has its
+// own runtime that ends with it.
+fn mock() -> &'static MockSpotify {
+    static MOCK: OnceLock<MockSpotify> = OnceLock::new();
+    MOCK.get_or_init(|| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                let mock = MockSpotify::start().await;
+                let port = TcpListener::bind("127.0.0.1:0")
+                    .and_then(|listener| listener.local_addr())
+                    .unwrap()
+                    .port();
+                let data_dir = env::temp_dir().join(format!("spt-e2e-{}", std::process::id()));
+                std::fs::create_dir_all(&data_dir).unwrap();
+
+                env::set_var("SPT_API_CLIENT_ID", mock.client_id());
+                env::set_var("SPT_API_BASE_URL", format!("{}/v1", mock.url()));
+                env::set_var("SPT_ACCOUNTS_URL", mock.url());
+                env::set_var("SPT_API_SCOPE", "user-read-playback-state");
+                env::set_var("SPT_AUTH_HEADLESS", "1");
+                env::set_var(
+                    "SERVER_CALLBACK_URL",
+                    format!("http://127.0.0.1:{}/auth/cb", port),
+                );
+                env::set_var("SERVER_BASE_URL", format!("http://127.0.0.1:{}", port));
+                env::set_var("SERVER_PORT", port.to_string());
+                env::set_var("SERVER_TIMEOUT_SECONDS", "600");
+                env::set_var("MAX_SERVER_RETRIES", "1");
+                env::set_var("DB_PATH", data_dir.join("spt.db"));
+                env::set_var("COMPLETION_CACHE_DIR", data_dir.join("completions"));
+                env::set_var("RADIO_CACHE_DIR", data_dir.join("radio"));
+                // keep the background pollers from using up injected failures
+                env::set_var("HISTORY_POLL_SECONDS", "3600");
+                env::set_var("QUEUE_POLL_SECONDS", "3600");
+
+                start_server(port, Duration::from_secs(600)).await.unwrap();
+                tx.send(mock).unwrap();
+                std::future::pending::<()>().await;
+            });
+        });
+        rx.recv().unwrap()
+    })
+}
+
+// Tests share the mock's fixtures, so they take turns.
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+// A client as the CLI sets it up, registered with the server as a new user.
+async fn client() -> ApiProxy {
+    let mut api_proxy = ApiProxy::new();
+    api_proxy.setup().await.unwrap();
+    api_proxy
+}
+
+// Runs a line of commands, returning whether it succeeded and its output.
+async fn run(api_proxy: &ApiProxy, input: &str) -> (bool, Vec<Output>) {
+    let mut outputs = Vec::new();
+    let mut session = Session::default();
+    let mut emit = |output| outputs.push(output);
+    let run = cli_app::execute(api_proxy, &mut session, input, "", &mut emit);
+    let succeeded = tokio::time::timeout(Duration::from_secs(30), run)
+        .await
+        .expect("command timed out");
+    (succeeded, outputs)
+}
+
+fn text(outputs: &[Output]) -> String {
+    outputs
+        .iter()
+        .map(|output| match output {
+            Output::Value(text) | Output::Error(text) => text.as_str(),
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+#[tokio::test]
+async fn authorizes_with_pkce_and_shows_current_track() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "now").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "spotify:track:4uLU6hMCjMI75M1A2tKUQC");
+    assert_eq!(mock.grants(), vec!["authorization_code"]);
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::GET && r.path == "/me/player/currently-playing"));
+}
+
+#[tokio::test]
+async fn renders_recent_tracks_as_table() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "recent 2 --columns title,artists").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    assert!(output.contains("Take On Me"), "{}", output);
+    assert!(output.contains("Queen, David Bowie"), "{}", output);
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.path == "/me/player/recently-played" && r.query.contains("limit=2")));
+}
+
+#[tokio::test]
+async fn serves_scripted_fixtures() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/player/currently-playing",
+        StatusCode::NO_CONTENT,
+        serde_json::Value::Null,
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "now -h").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "No track currently playing.");
+}
+
+#[tokio::test]
+async fn refreshes_token_after_401() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+    assert!(run(&api_proxy, "now").await.0);
+
+    mock.fail(
+        Method::GET,
+        "/me/player/currently-playing",
+        StatusCode::UNAUTHORIZED,
+        1,
+    );
+    let (succeeded, outputs) = run(&api_proxy, "now").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).starts_with("Error:"), "{}", text(&outputs));
+    assert_eq!(mock.grants(), vec!["authorization_code", "refresh_token"]);
+
+    let (succeeded, outputs) = run(&api_proxy, "now").await;
+    assert!(succeeded, "{}", text(&outputs));
+}
+
+#[tokio::test]
+async fn reports_rate_limits_and_server_errors() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    mock.fail(
+        Method::GET,
+        "/me/player/recently-played",
+        StatusCode::TOO_MANY_REQUESTS,
+        1,
+    );
+    let (succeeded, outputs) = run(&api_proxy, "recent").await;
+    assert!(!succeeded);
+    assert_eq!(text(&outputs), "Error: Too many requests");
+
+    mock.fail(
+        Method::GET,
+        "/me/player/currently-playing",
+        StatusCode::SERVICE_UNAVAILABLE,
+        1,
+    );
+    let (succeeded, outputs) = run(&api_proxy, "now").await;
+    assert!(!succeeded);
+    assert_eq!(text(&outputs), "Error: Service unavailable");
+
+    // served again once the injected failures are used up, after the backoff
+    let (succeeded, outputs) = run(&api_proxy, "recent").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert!(text(&outputs).contains("spotify:track:2WfaOiMkCvy7F5fcp2zZ8L"));
+}
+
+#[tokio::test]
+async fn rejects_wrong_pkce_verifier() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    // the challenge for the verifier "verifier"
+    let challenge = "iMnq5o6zALKXGivsnlom_0F5_WYda32GHkxlV7mq7hQ";
+
+    let mut codes = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .get(format!("{}/authorize", mock.url()))
+            .query(&[
+                ("response_type", "code"),
+                ("client_id", mock.client_id()),
+                ("redirect_uri", "http://127.0.0.1:1/cb"),
+                ("code_challenge", challenge),
+                ("code_challenge_method", "S256"),
+                ("state", "1"),
+            ])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location =
+            reqwest::Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        let query: HashMap<String, String> = location.query_pairs().into_owned().collect();
+        codes.push(query["code"].clone());
+    }
+
+    let exchange = |code: String, verifier: &'static str| {
+        client
+            .post(format!("{}/api/token", mock.url()))
+            .form(&[
+                ("grant_type", "authorization_code".to_string()),
+                ("code", code),
+                ("redirect_uri", "http://127.0.0.1:1/cb".to_string()),
+                ("code_verifier", verifier.to_string()),
+                ("client_id", mock.client_id().to_string()),
+            ])
+            .send()
+    };
+    let wrong = exchange(codes[0].clone(), "not the verifier")
+        .await
+        .unwrap();
+    assert_eq!(wrong.status(), StatusCode::BAD_REQUEST);
+    let right = exchange(codes[1].clone(), "verifier").await.unwrap();
+    assert_eq!(right.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn replays_a_recorded_session() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let dir = env::temp_dir().join(format!("spt-e2e-recording-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // proxies read the mode when a client registers
+    env::set_var("SPT_RECORD_DIR", &dir);
+    let api_proxy = client().await;
+    env::remove_var("SPT_RECORD_DIR");
+    let (succeeded, recorded) = run(&api_proxy, "now").await;
+    assert!(succeeded, "{}", text(&recorded));
+
+    let files: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(files.len(), 1);
+    assert!(files[0].contains("\"authorization\": \"<redacted>\""));
+    assert!(!files[0].contains("mock-access"));
+
+    // the mock would now answer differently, and must not be asked at all
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/player/currently-playing",
+        StatusCode::NO_CONTENT,
+        serde_json::Value::Null,
+    );
+    env::set_var("SPT_REPLAY_DIR", &dir);
+    let api_proxy = client().await;
+    env::remove_var("SPT_REPLAY_DIR");
+    let (succeeded, replayed) = run(&api_proxy, "now").await;
+    assert!(succeeded, "{}", text(&replayed));
+    assert_eq!(text(&replayed), text(&recorded));
+    assert!(mock.requests().is_empty());
+    assert!(mock.grants().is_empty());
+
+    // each recording is served once
+    let (succeeded, _) = run(&api_proxy, "now").await;
+    assert!(!succeeded);
+}
+
+#[tokio::test]
+async fn reports_unexpected_response_shapes() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/player/currently-playing",
+        StatusCode::OK,
+        serde_json::json!({"is_playing": true, "item": {"type": "track", "name": 42}}),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "now").await;
+
+    assert!(!succeeded);
+    assert_eq!(
+        text(&outputs),
+        "Error: Missing or invalid data in the response"
+    );
+}
+
+#[tokio::test]
+async fn lists_devices() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "devices").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "\tdevice-desk\n\tdevice-phone");
+
+    let (succeeded, outputs) = run(&api_proxy, "devices -h").await;
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    assert!(output.contains("*  Desk Speaker"), "{}", output);
+    assert!(output.contains("Phone"), "{}", output);
+}
+
+#[tokio::test]
+async fn reports_typos_with_a_caret_and_a_suggestion() {
+    let _turn = SERIAL.lock().await;
+    mock().reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "paly 3").await;
+    assert!(!succeeded);
+    assert_eq!(
+        text(&outputs),
+        "Error: Unknown command 'paly'\n  paly 3\n  ^^^^\n  did you mean 'play'?"
+    );
+
+    let (succeeded, outputs) = run(&api_proxy, "recent 2 --colums title").await;
+    assert!(!succeeded);
+    let output = text(&outputs);
+    assert!(
+        output.contains("\n  recent 2 --colums title\n           ^^^^^^^^\n"),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("did you mean '--columns'?"), "{}", output);
+}
+
+#[tokio::test]
+async fn fails_playback_commands_that_fail() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    // a failed command stops the rest of the line
+    let (succeeded, outputs) = run(&api_proxy, "device Kitchen && play").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("No device named 'Kitchen'"));
+    assert!(!mock.requests().iter().any(|r| r.path == "/me/player/play"));
+
+    mock.fail(Method::PUT, "/me/player/pause", StatusCode::FORBIDDEN, 1);
+    let (succeeded, outputs) = run(&api_proxy, "pause").await;
+    assert!(!succeeded);
+    assert_eq!(text(&outputs), "Error: Forbidden request");
+
+    let (succeeded, outputs) = run(&api_proxy, "queue remove 5").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("No item 5 in queue"));
+}
+
+#[tokio::test]
+async fn keeps_arguments_the_shell_split_off_whole() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let mut api_proxy = client().await;
+
+    let args = ["spt", "device", "Desk Speaker"].map(String::from).to_vec();
+    assert_eq!(cli_app::run_cli(&mut api_proxy, args).await, 0);
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/me/player"));
+
+    // a separator on its own is still a separator
+    let args = ["spt", "pause", ";", "play"].map(String::from).to_vec();
+    assert_eq!(cli_app::run_cli(&mut api_proxy, args).await, 0);
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/me/player/play"));
+
+    // and a lone argument is a whole line of commands
+    mock.reset();
+    let args = ["spt", "pause; play"].map(String::from).to_vec();
+    assert_eq!(cli_app::run_cli(&mut api_proxy, args).await, 0);
+    let requests = mock.requests();
+    assert!(requests
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/me/player/pause"));
+    assert!(requests
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/me/player/play"));
+}
+
+#[tokio::test]
+async fn refreshes_completions_only_once_authorized() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "completions refresh devices").await;
+    assert!(!succeeded);
+    assert_eq!(
+        text(&outputs),
+        "Error: Not authorized, run another command first"
+    );
+    assert!(mock.grants().is_empty());
+
+    let (succeeded, outputs) = run(&api_proxy, "devices").await;
+    assert!(succeeded, "{}", text(&outputs));
+    let (succeeded, outputs) = run(&api_proxy, "completions refresh devices").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Cached 2 devices.");
+}
+
+// A list of playlists long enough to take several pages.
+fn many_playlists(count: usize) -> serde_json::Value {
+    let items: Vec<serde_json::Value> = (0..count)
+        .map(|i| {
+            serde_json::json!({
+                "uri": format!("spotify:playlist:{:0>22}", i),
+                "name": format!("Mix {}", i),
+                "tracks": {"total": i},
+            })
+        })
+        .collect();
+    serde_json::json!({"items": items, "total": count, "limit": 20, "offset": 0})
+}
+
+fn playlist_requests(mock: &MockSpotify) -> Vec<String> {
+    mock.requests()
+        .into_iter()
+        .filter(|r| r.path == "/me/playlists")
+        .map(|r| r.query)
+        .collect()
+}
+
+#[tokio::test]
+async fn follows_next_links_across_pages() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/playlists",
+        StatusCode::OK,
+        many_playlists(120),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "playlists --all").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    assert_eq!(output.lines().count(), 120, "{}", output);
+    assert!(output.ends_with(&format!("spotify:playlist:{:0>22}", 119)));
+    let requests = playlist_requests(mock);
+    assert_eq!(requests.len(), 3, "{:?}", requests);
+    assert!(requests[2].contains("offset=100"), "{:?}", requests);
+}
+
+#[tokio::test]
+async fn limits_and_offsets_paged_lists() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/playlists",
+        StatusCode::OK,
+        many_playlists(120),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "playlists --limit 60 --offset 10").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 60, "{}", output);
+    assert_eq!(lines[0], format!("spotify:playlist:{:0>22}", 10));
+    assert_eq!(lines[59], format!("spotify:playlist:{:0>22}", 69));
+    // the second page only asks for what's left
+    let requests = playlist_requests(mock);
+    assert_eq!(requests.len(), 2, "{:?}", requests);
+    assert!(requests[1].contains("limit=10"), "{:?}", requests);
+
+    let (succeeded, outputs) = run(&api_proxy, "playlists --all --limit 5").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("--all and --limit can't be combined"));
+}
+
+#[tokio::test]
+async fn pages_recent_tracks_by_time() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "recent --after 1714560000000").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert!(text(&outputs).contains("spotify:track:7GhIk7Il098yCjg4BQjzvb"));
+    assert!(mock.requests().iter().any(|r| {
+        r.path == "/me/player/recently-played" && r.query.contains("after=1714560000000")
+    }));
+
+    let (succeeded, outputs) = run(&api_proxy, "recent --before yesterday").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("Invalid time 'yesterday'"));
+}
+
+#[tokio::test]
+async fn likes_the_current_track() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "like").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Liked Never Gonna Give You Up.");
+    assert!(mock.requests().iter().any(|r| r.method == Method::PUT
+        && r.path == "/me/tracks"
+        && r.query == "ids=4uLU6hMCjMI75M1A2tKUQC"));
+
+    let (succeeded, outputs) = run(&api_proxy, "contains").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "spotify:track:4uLU6hMCjMI75M1A2tKUQC\tyes");
+
+    // an episode can't be saved with the others, so there is no answer
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "contains spotify:episode:512ojhOuo1ktJprKbVcKyQ",
+    )
+    .await;
+    assert!(!succeeded);
+    assert!(text(&outputs)
+        .contains("Expected tracks, albums, shows, artists or playlists, got spotify:episode:"));
+}
+
+#[tokio::test]
+async fn manages_the_library() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "unlike spotify:track:7GhIk7Il098yCjg4BQjzvb spotify:track:2WfaOiMkCvy7F5fcp2zZ8L",
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Removed 2 tracks.");
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::DELETE && r.path == "/me/tracks"));
+
+    let (succeeded, outputs) =
+        run(&api_proxy, "follow spotify:artist:2PaJWoUkBfPTH7NBJTl1jt").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert!(mock.requests().iter().any(|r| r.method == Method::PUT
+        && r.path == "/me/following"
+        && r.query.contains("type=artist")));
+
+    // bare IDs are taken as artists here, not tracks
+    mock.reset();
+    let (succeeded, outputs) = run(&api_proxy, "follow 0gxyHStUsqpMadRV0Di1Qt").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert!(mock.requests().iter().any(|r| r.method == Method::PUT
+        && r.path == "/me/following"
+        && r.query.contains("type=artist")
+        && r.query.contains("0gxyHStUsqpMadRV0Di1Qt")));
+
+    let (succeeded, outputs) = run(&api_proxy, "follow spotify:track:7GhIk7Il098yCjg4BQjzvb").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("Expected artists or playlists"));
+
+    let (succeeded, outputs) = run(&api_proxy, "liked --columns title,artists").await;
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    assert!(output.contains("Under Pressure"), "{}", output);
+    assert!(output.contains("a-ha"), "{}", output);
+}
+
+#[tokio::test]
+async fn forwards_raw_requests_within_the_allowlist() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/me/top/tracks",
+        StatusCode::OK,
+        serde_json::json!({"items": [{"name": "Take On Me"}]}),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "api GET /me/top/tracks?limit=5").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert!(text(&outputs).contains("\"name\": \"Take On Me\""));
+    // the client's id stays with the local server
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.path == "/me/top/tracks" && r.query == "limit=5"));
+
+    let (_, outputs) = run(&api_proxy, "api DELETE /me/player").await;
+    assert_eq!(text(&outputs), "Error: Forbidden request");
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::DELETE && r.path == "/me/player"));
+}
+
+// Sends a GET for the path to the server as it is, without the URL parsing
+// that would resolve its dot segments, returning the response's status line
+// and body.
+async fn raw_get(path: &str) -> (String, String) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let port = env::var("SERVER_PORT").unwrap();
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response.lines().next().unwrap_or_default().to_string();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    (status, body)
+}
+
+#[tokio::test]
+async fn refuses_encoded_dot_segments() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let (_, body) = raw_get("/init").await;
+    let client_id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["client_id"].clone();
+
+    // tracks/** allows GETs, but this one resolves to me/episodes, which it
+    // doesn't
+    for path in [
+        "tracks/%2e%2e/me/episodes",
+        "tracks/%2E./me/episodes",
+        "tracks/a%2F..%2F..%2Fme",
+    ] {
+        let (status, _) = raw_get(&format!("/api/spt-fwd/{}?client_id={}", path, client_id)).await;
+        assert!(status.contains("403"), "{}: {}", path, status);
+    }
+    assert!(!mock.requests().iter().any(|r| r.path.starts_with("/me")));
+}
+
+#[tokio::test]
+async fn forwards_writes_with_their_method_and_query() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "play && volume 30").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Now Playing.\nVolume set to 30%.");
+    let requests = mock.requests();
+    assert!(requests
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/me/player/play"));
+    assert!(requests.iter().any(|r| r.method == Method::PUT
+        && r.path == "/me/player/volume"
+        && r.query == "volume_percent=30"));
+}
+
+// A track as the mock serves it, by a single artist.
+fn track_json(id: &str, name: &str, artist_id: &str, popularity: u32) -> serde_json::Value {
+    serde_json::json!({
+        "type": "track",
+        "uri": format!("spotify:track:{}", id),
+        "name": name,
+        "duration_ms": 200000,
+        "popularity": popularity,
+        "artists": [{ "uri": format!("spotify:artist:{}", artist_id), "name": artist_id }],
+        "album": { "uri": "spotify:album:1ER3B6zev5JEAaqhnyyfbf", "name": "Album" }
+    })
+}
+
+#[tokio::test]
+async fn builds_a_radio_from_recommendations() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    // the seed and a recently played track are left out
+    mock.set(
+        Method::GET,
+        "/recommendations",
+        StatusCode::OK,
+        serde_json::json!({ "tracks": [
+            track_json("4uLU6hMCjMI75M1A2tKUQC", "Never Gonna Give You Up", "rick", 80),
+            track_json("7GhIk7Il098yCjg4BQjzvb", "Take On Me", "a-ha", 85),
+            track_json("1111111111111111111111", "Together Forever", "rick", 60),
+            track_json("2222222222222222222222", "Venus", "bananarama", 70),
+        ]}),
+    );
+    mock.set(
+        Method::GET,
+        "/me",
+        StatusCode::OK,
+        serde_json::json!({ "id": "listener" }),
+    );
+    mock.set(
+        Method::POST,
+        "/users/listener/playlists",
+        StatusCode::CREATED,
+        serde_json::json!({ "id": "radio1", "name": "Rick Radio", "uri": "spotify:playlist:radio1" }),
+    );
+    mock.set(
+        Method::POST,
+        "/playlists/radio1/tracks",
+        StatusCode::CREATED,
+        serde_json::json!({ "snapshot_id": "snapshot1" }),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "radio --energy 0.8 --tempo 100-130").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "spotify:track:1111111111111111111111\nspotify:track:2222222222222222222222"
+    );
+    assert!(mock.requests().iter().any(|r| r.path == "/recommendations"
+        && r.query.contains("seed_tracks=4uLU6hMCjMI75M1A2tKUQC")
+        && r.query.contains("target_energy=0.8")
+        && r.query.contains("min_tempo=100")));
+
+    let (succeeded, outputs) = run(&api_proxy, "radio --limit 1 --save \"Rick Radio\"").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Saved 1 track to Rick Radio.");
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::POST && r.path == "/playlists/radio1/tracks"));
+
+    let (succeeded, outputs) = run(&api_proxy, "radio --tempo 130-100").await;
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("Expected a range like MIN-MAX"));
+}
+
+#[tokio::test]
+async fn falls_back_to_related_artists_without_recommendations() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/recommendations",
+        StatusCode::NOT_FOUND,
+        serde_json::json!({ "error": { "status": 404, "message": "Not found" } }),
+    );
+    mock.set(
+        Method::GET,
+        "/artists/0gxyHStUsqpMadRV0Di1Qt/related-artists",
+        StatusCode::OK,
+        serde_json::json!({ "artists": [{ "uri": "spotify:artist:3mvkWMe6swnknwscwvGCHO", "name": "Related" }] }),
+    );
+    mock.set(
+        Method::GET,
+        "/artists/0gxyHStUsqpMadRV0Di1Qt/top-tracks",
+        StatusCode::OK,
+        serde_json::json!({ "tracks": [
+            track_json("3333333333333333333333", "Hit", "0gxyHStUsqpMadRV0Di1Qt", 90),
+            track_json("4444444444444444444444", "Deep Cut", "0gxyHStUsqpMadRV0Di1Qt", 20),
+        ]}),
+    );
+    mock.set(
+        Method::GET,
+        "/artists/3mvkWMe6swnknwscwvGCHO/top-tracks",
+        StatusCode::OK,
+        serde_json::json!({ "tracks": [
+            track_json("5555555555555555555555", "Related Hit", "3mvkWMe6swnknwscwvGCHO", 75),
+        ]}),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "radio spotify:artist:0gxyHStUsqpMadRV0Di1Qt --popularity 50-100 --keep-recent",
+    )
+    .await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    let mut uris: Vec<&str> = output.lines().collect();
+    uris.sort();
+    assert_eq!(
+        uris,
+        vec![
+            "spotify:track:3333333333333333333333",
+            "spotify:track:5555555555555555555555"
+        ]
+    );
+    // --keep-recent skips reading the history
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.path == "/me/player/recently-played"));
+}
+
+// A file in this run's data directory.
+fn data_file(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("spt-e2e-{}", std::process::id()))
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Answers creating a playlist named name, as the user "listener", and adding
+// items to it.
+fn expect_new_playlist(mock: &MockSpotify, id: &str, name: &str) {
+    mock.set(
+        Method::GET,
+        "/me",
+        StatusCode::OK,
+        serde_json::json!({ "id": "listener" }),
+    );
+    mock.set(
+        Method::POST,
+        "/users/listener/playlists",
+        StatusCode::CREATED,
+        serde_json::json!({ "id": id, "name": name, "uri": format!("spotify:playlist:{}", id) }),
+    );
+    mock.set(
+        Method::POST,
+        &format!("/playlists/{}/tracks", id),
+        StatusCode::CREATED,
+        serde_json::json!({ "snapshot_id": "snapshot-2" }),
+    );
+}
+
+#[tokio::test]
+async fn exports_and_reimports_a_playlist() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    expect_new_playlist(mock, "copy", "Road Trip Copy");
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist export \"Road Trip\" --format csv").await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    assert!(
+        output.starts_with("uri,title,artists,album,duration_ms,isrc,added_at\n"),
+        "{}",
+        output
+    );
+    assert!(output.contains(
+        "spotify:track:2WfaOiMkCvy7F5fcp2zZ8L,Under Pressure,Queen; David Bowie,Hot Space,248440,GBUM71029604,2024-05-02T10:00:00Z"
+    ), "{}", output);
+
+    let path = data_file("road_trip.xspf");
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist export \"Road Trip\" --output {}", path),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    let xspf = std::fs::read_to_string(&path).unwrap();
+    assert!(xspf.contains("<title>Road Trip</title>"), "{}", xspf);
+    assert!(
+        xspf.contains("<identifier>isrc:GBAYE8500001</identifier>"),
+        "{}",
+        xspf
+    );
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist import {} --name \"Road Trip Copy\"", path),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Added 3 tracks to Road Trip Copy.");
+    // every entry had its URI, so nothing was searched for
+    let requests = mock.requests();
+    assert!(!requests.iter().any(|r| r.path == "/search"));
+    assert!(requests
+        .iter()
+        .any(|r| r.method == Method::POST && r.path == "/playlists/copy/tracks"));
+}
+
+#[tokio::test]
+async fn matches_imported_entries_by_isrc_and_search() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/search",
+        StatusCode::OK,
+        serde_json::json!({ "tracks": {
+            "items": [track_json("7GhIk7Il098yCjg4BQjzvb", "Take On Me", "a-ha", 85)],
+            "total": 1,
+            "limit": 5,
+            "offset": 0,
+            "next": null
+        }}),
+    );
+    mock.set(
+        Method::POST,
+        "/playlists/5O2ERf8kAYARVVdfCKZ9G7/tracks",
+        StatusCode::CREATED,
+        serde_json::json!({ "snapshot_id": "snapshot-2" }),
+    );
+    let api_proxy = client().await;
+
+    let path = data_file("mix.m3u");
+    std::fs::write(
+        &path,
+        "#EXTM3U\n\
+         #EXTINF:225,a-ha - Take On Me (2015 Remaster)\n\
+         /music/a-ha/take_on_me.mp3\n\
+         https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC\n\
+         #EXTINF:200,Nobody - Nothing Here\n\
+         /music/nothing.mp3\n",
+    )
+    .unwrap();
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist import {} --into \"Road Trip\"", path),
+    )
+    .await;
+
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Added 2 tracks to Road Trip.\nCouldn't match 1 of 3:\n  line 6: Nobody - Nothing Here"
+    );
+
+    let path = data_file("mix.csv");
+    std::fs::write(&path, "ISRC,Track Name\nGBAYE8500001,Take On Me\n").unwrap();
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist import {} --into \"Road Trip\"", path),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Added 1 track to Road Trip.");
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.path == "/search" && r.query.contains("isrc%3AGBAYE8500001")));
+}
+
+fn playlist_items_json(tracks: &[serde_json::Value]) -> serde_json::Value {
+    let items: Vec<serde_json::Value> = tracks
+        .iter()
+        .map(|track| serde_json::json!({ "added_at": "2024-05-03T12:00:00Z", "track": track }))
+        .collect();
+    serde_json::json!({
+        "items": items,
+        "total": tracks.len(),
+        "limit": 100,
+        "offset": 0,
+        "next": null
+    })
+}
+
+#[tokio::test]
+async fn syncs_a_smart_playlist_by_diff() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    expect_new_playlist(mock, "workout1", "Workout");
+    mock.set(
+        Method::GET,
+        "/audio-features",
+        StatusCode::OK,
+        serde_json::json!({ "audio_features": [
+            { "uri": "spotify:track:2WfaOiMkCvy7F5fcp2zZ8L", "energy": 0.7, "tempo": 114.0,
+              "danceability": 0.67, "valence": 0.46, "acousticness": 0.4 },
+            { "uri": "spotify:track:7GhIk7Il098yCjg4BQjzvb", "energy": 0.9, "tempo": 169.0,
+              "danceability": 0.57, "valence": 0.88, "acousticness": 0.02 }
+        ]}),
+    );
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "playlist smart Workout --from liked --where \"energy>0.6\" --sort tempo:desc",
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Saved smart playlist Workout: liked where energy>0.6, by tempo:desc."
+    );
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist sync workout --dry-run").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Would create Workout with 2 tracks.");
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::POST && r.path == "/users/listener/playlists"));
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist sync Workout").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Created Workout with 2 tracks.");
+
+    // someone edited the playlist since
+    mock.set(
+        Method::GET,
+        "/playlists/workout1",
+        StatusCode::OK,
+        serde_json::json!({
+            "id": "workout1",
+            "uri": "spotify:playlist:workout1",
+            "name": "Workout",
+            "snapshot_id": "snapshot-2"
+        }),
+    );
+    mock.set(
+        Method::GET,
+        "/playlists/workout1/tracks",
+        StatusCode::OK,
+        playlist_items_json(&[
+            track_json("2WfaOiMkCvy7F5fcp2zZ8L", "Under Pressure", "queen", 80),
+            track_json(
+                "4uLU6hMCjMI75M1A2tKUQC",
+                "Never Gonna Give You Up",
+                "rick",
+                75,
+            ),
+            // no longer available
+            serde_json::Value::Null,
+        ]),
+    );
+    for method in [Method::DELETE, Method::PUT] {
+        mock.set(
+            method,
+            "/playlists/workout1/tracks",
+            StatusCode::OK,
+            serde_json::json!({ "snapshot_id": "snapshot-3" }),
+        );
+    }
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist sync").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Synced Workout: added 1 track, removed 2 tracks and moved 1 track."
+    );
+    let requests = mock.requests();
+    // the unavailable entry goes by its position alone, in a request of its own
+    let deletes = requests
+        .iter()
+        .filter(|r| r.method == Method::DELETE && r.path == "/playlists/workout1/tracks");
+    assert_eq!(deletes.count(), 2);
+    assert!(requests
+        .iter()
+        .any(|r| r.method == Method::PUT && r.path == "/playlists/workout1/tracks"));
+
+    mock.fail(
+        Method::GET,
+        "/playlists/workout1",
+        StatusCode::SERVICE_UNAVAILABLE,
+        1,
+    );
+    let (succeeded, outputs) = run(&api_proxy, "playlist sync && now").await;
+    assert!(!succeeded);
+    assert_eq!(
+        text(&outputs),
+        "Error: Failed to sync Workout: Service unavailable"
+    );
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist smart").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Workout\tliked where energy>0.6, by tempo:desc"
+    );
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist smart Workout --delete").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Removed smart playlist Workout.");
+    let (succeeded, _) = run(&api_proxy, "playlist sync Workout").await;
+    assert!(!succeeded);
+}
+
+#[tokio::test]
+async fn dedupes_and_sorts_a_playlist() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist sort \"Road Trip\" --by title").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Sorting Road Trip by title would move 3 tracks:\n  \
+         3 -> 1: Rick Astley - Never Gonna Give You Up\n  \
+         1 -> 2: a-ha - Take On Me\n  \
+         2 -> 3: Queen, David Bowie - Under Pressure\n\
+         Run again with --apply to make these changes."
+    );
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "playlist sort \"Road Trip\" --by popularity:desc",
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Road Trip is already sorted by popularity:desc."
+    );
+
+    let tracks = "/playlists/5O2ERf8kAYARVVdfCKZ9G7/tracks";
+    let take_on_me = track_json("7GhIk7Il098yCjg4BQjzvb", "Take On Me", "a-ha", 85);
+    mock.set(
+        Method::GET,
+        tracks,
+        StatusCode::OK,
+        playlist_items_json(&[
+            take_on_me.clone(),
+            track_json("2WfaOiMkCvy7F5fcp2zZ8L", "Under Pressure", "queen", 80),
+            take_on_me,
+            track_json(
+                "3B9LhR0bBoBpcFjTALjMnQ",
+                "Take On Me - 2015 Remaster",
+                "a-ha",
+                60,
+            ),
+        ]),
+    );
+    for method in [Method::DELETE, Method::POST, Method::PUT] {
+        mock.set(
+            method,
+            tracks,
+            StatusCode::OK,
+            serde_json::json!({ "snapshot_id": "snapshot-2" }),
+        );
+    }
+
+    let (succeeded, outputs) = run(&api_proxy, "playlist dedupe \"Road Trip\" --by track").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Would remove 2 tracks from Road Trip:\n  \
+         3: a-ha - Take On Me, a repeat of 1\n  \
+         4: a-ha - Take On Me - 2015 Remaster, a repeat of 1\n\
+         Run again with --apply to make these changes."
+    );
+    assert!(!mock.requests().iter().any(|r| r.method == Method::DELETE));
+
+    // only the repeat is removed, by its position, so the first stays as it is
+    let (succeeded, outputs) = run(&api_proxy, "playlist dedupe \"Road Trip\" --apply").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Removed 1 track from Road Trip:\n  3: a-ha - Take On Me, a repeat of 1"
+    );
+    let edits: Vec<Method> = mock
+        .requests()
+        .into_iter()
+        .filter(|r| r.path == tracks && r.method != Method::GET)
+        .map(|r| r.method)
+        .collect();
+    assert_eq!(edits, vec![Method::DELETE]);
+}
+
+#[tokio::test]
+async fn diffs_and_merges_playlists() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/playlists/37i9dQZF1DXcBWIGoYBM5M/tracks",
+        StatusCode::OK,
+        playlist_items_json(&[
+            track_json("2WfaOiMkCvy7F5fcp2zZ8L", "Under Pressure", "queen", 80),
+            track_json("3B9LhR0bBoBpcFjTALjMnQ", "Dancing Queen", "abba", 82),
+        ]),
+    );
+    expect_new_playlist(mock, "mix1", "Mix");
+    let api_proxy = client().await;
+    let other = "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M";
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist diff \"Road Trip\" {}", other),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "spotify:track:7GhIk7Il098yCjg4BQjzvb\nspotify:track:4uLU6hMCjMI75M1A2tKUQC"
+    );
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist diff \"Road Trip\" {} --common", other),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "spotify:track:2WfaOiMkCvy7F5fcp2zZ8L");
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!(
+            "playlist merge {} \"Road Trip\" --into \"Road Trip\"",
+            other
+        ),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Would add 1 track to Road Trip:\n  abba - Dancing Queen\n\
+         Run again with --apply to make these changes."
+    );
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::POST && r.path.ends_with("/tracks")));
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        &format!("playlist merge \"Road Trip\" {} --into Mix --apply", other),
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(
+        text(&outputs),
+        "Created Mix with 4 tracks:\n  \
+         a-ha - Take On Me\n  \
+         Queen, David Bowie - Under Pressure\n  \
+         Rick Astley - Never Gonna Give You Up\n  \
+         abba - Dancing Queen"
+    );
+    assert!(mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::POST && r.path == "/playlists/mix1/tracks"));
+}
+
+#[tokio::test]
+async fn reports_stats_errors() {
+    let _turn = SERIAL.lock().await;
+    mock().reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(&api_proxy, "stats tracks -j").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "[]");
+
+    let (succeeded, outputs) = run(&api_proxy, "stats genres").await;
+    assert!(!succeeded);
+    assert!(
+        text(&outputs).contains("Unknown stats section 'genres'"),
+        "{}",
+        text(&outputs)
+    );
+    let (succeeded, outputs) = run(&api_proxy, "stats --limit lots").await;
+    assert!(!succeeded);
+    assert!(
+        text(&outputs).contains("Expected a number for --limit, got 'lots'"),
+        "{}",
+        text(&outputs)
+    );
+}
+
+#[tokio::test]
+async fn shares_the_queue_between_clients() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    mock.set(
+        Method::GET,
+        "/tracks",
+        StatusCode::OK,
+        serde_json::json!({ "tracks": [
+            track_json("2WfaOiMkCvy7F5fcp2zZ8L", "Take On Me", "a-ha", 80),
+            track_json("4uLU6hMCjMI75M1A2tKUQC", "Never Gonna Give You Up", "rick", 90),
+        ]}),
+    );
+    let first = client().await;
+    let second = client().await;
+
+    let (succeeded, outputs) = run(
+        &first,
+        "queue add spotify:track:2WfaOiMkCvy7F5fcp2zZ8L spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+    )
+    .await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Added 2 tracks to queue.");
+    // only the feeder passes tracks on to Spotify, as the current one ends
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.method == Method::POST && r.path == "/me/player/queue"));
+
+    // they are pending on the server, where other clients see them
+    let (succeeded, outputs) = run(&second, "queue --columns title").await;
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    let pending = &output[output.find("Pending:").unwrap()..];
+    assert!(pending.contains("Take On Me"), "{}", output);
+    assert!(pending.contains("Never Gonna Give You Up"), "{}", output);
+
+    let (succeeded, outputs) = run(&second, "queue remove 1").await;
+    assert!(succeeded, "{}", text(&outputs));
+    assert_eq!(text(&outputs), "Removed item 1 from queue.");
+
+    let (succeeded, outputs) = run(&first, "queue --columns title").await;
+    assert!(succeeded, "{}", text(&outputs));
+    let output = text(&outputs);
+    let pending = &output[output.find("Pending:").unwrap()..];
+    assert!(!pending.contains("Take On Me"), "{}", output);
+    assert!(pending.contains("Never Gonna Give You Up"), "{}", output);
+
+    let (succeeded, outputs) = run(&first, "queue clear").await;
+    assert!(succeeded, "{}", text(&outputs));
+}
+
+#[tokio::test]
+async fn rejects_a_queue_limit_that_is_not_a_number() {
+    let _turn = SERIAL.lock().await;
+    let mock = mock();
+    mock.reset();
+    let api_proxy = client().await;
+
+    let (succeeded, outputs) = run(
+        &api_proxy,
+        "queue add spotify:album:1ER3B6zev5JEAaqhnyyfbf --limit abc",
+    )
+    .await;
+
+    assert!(!succeeded);
+    assert!(text(&outputs).contains("Expected a number for --limit, got 'abc'"));
+    assert!(!mock
+        .requests()
+        .iter()
+        .any(|r| r.path.starts_with("/albums")));
+}
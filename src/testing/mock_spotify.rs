@@ -0,0 +1,407 @@
+use base64::{engine::general_purpose, Engine};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use warp::http::{Method, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+// In-process stand-in for the Spotify Web API and accounts service.
+//
+// Web API calls go to {url}/v1/... and are answered from fixtures keyed by
+// method and path, defaulting to the JSON files in fixtures/. They need a
+// bearer token issued by the mock. The accounts service at {url}/authorize
+// and {url}/api/token grants consent at once, redirecting straight to the
+// callback, and checks the PKCE verifier when the code is exchanged.
+#[derive(Debug, Clone)]
+pub struct MockSpotify {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+}
+
+// A request as seen by the mock, for assertions.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+}
+
+#[derive(Debug)]
+struct Failure {
+    method: Method,
+    path: String,
+    status: StatusCode,
+    remaining: usize,
+}
+
+#[derive(Debug)]
+struct PendingCode {
+    challenge: String,
+    redirect_uri: String,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    fixtures: HashMap<(Method, String), (StatusCode, Value)>,
+    failures: Vec<Failure>,
+    requests: Vec<MockRequest>,
+    grants: Vec<String>,
+    codes: HashMap<String, PendingCode>,
+    access_tokens: Vec<String>,
+    refresh_tokens: Vec<String>,
+    issued: usize,
+}
+
+const CLIENT_ID: &str = "mock-client-id";
+
+fn fixture(name: &str) -> Value {
+    let text = match name {
+        "currently_playing" => include_str!("fixtures/currently_playing.json"),
+        "devices" => include_str!("fixtures/devices.json"),
+        "queue" => include_str!("fixtures/queue.json"),
+        "recently_played" => include_str!("fixtures/recently_played.json"),
+        "playlists" => include_str!("fixtures/playlists.json"),
+        "playlist" => include_str!("fixtures/playlist.json"),
+        "playlist_tracks" => include_str!("fixtures/playlist_tracks.json"),
+        "saved_tracks" => include_str!("fixtures/saved_tracks.json"),
+        _ => "{}",
+    };
+    serde_json::from_str(text).expect("fixture is valid JSON")
+}
+
+fn default_fixtures() -> HashMap<(Method, String), (StatusCode, Value)> {
+    let get = |path: &str, name: &str| {
+        (
+            (Method::GET, path.to_string()),
+            (StatusCode::OK, fixture(name)),
+        )
+    };
+    let no_content = |method: Method, path: &str| {
+        (
+            (method, path.to_string()),
+            (StatusCode::NO_CONTENT, Value::Null),
+        )
+    };
+    HashMap::from([
+        get("/me/player", "currently_playing"),
+        get("/me/player/currently-playing", "currently_playing"),
+        get("/me/player/devices", "devices"),
+        get("/me/player/queue", "queue"),
+        get("/me/player/recently-played", "recently_played"),
+        get("/me/playlists", "playlists"),
+        get("/me/tracks", "saved_tracks"),
+        get("/playlists/5O2ERf8kAYARVVdfCKZ9G7", "playlist"),
+        get(
+            "/playlists/5O2ERf8kAYARVVdfCKZ9G7/tracks",
+            "playlist_tracks",
+        ),
+        (
+            (Method::GET, "/me/tracks/contains".to_string()),
+            (StatusCode::OK, serde_json::json!([true])),
+        ),
+        no_content(Method::PUT, "/me/player"),
+        no_content(Method::PUT, "/me/player/play"),
+        no_content(Method::PUT, "/me/player/pause"),
+        no_content(Method::PUT, "/me/player/seek"),
+        no_content(Method::PUT, "/me/player/volume"),
+        no_content(Method::POST, "/me/player/next"),
+        no_content(Method::POST, "/me/player/previous"),
+        no_content(Method::POST, "/me/player/queue"),
+        no_content(Method::PUT, "/me/tracks"),
+        no_content(Method::DELETE, "/me/tracks"),
+        no_content(Method::PUT, "/me/following"),
+        no_content(Method::DELETE, "/me/following"),
+    ])
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response {
+    if status == StatusCode::NO_CONTENT {
+        return warp::reply::with_status(warp::reply(), status).into_response();
+    }
+    warp::reply::with_status(warp::reply::json(body), status).into_response()
+}
+
+// Offset paged fixtures, those with an offset field, are served a page at a
+// time by the limit and offset of the request, with a next link to the rest.
+fn page(body: &Value, request: &MockRequest) -> Value {
+    let (Some(items), Some(_)) = (body["items"].as_array(), body.get("offset")) else {
+        return body.clone();
+    };
+    let query: HashMap<String, String> = url::form_urlencoded::parse(request.query.as_bytes())
+        .into_owned()
+        .collect();
+    let number = |name: &str, default: usize| {
+        query
+            .get(name)
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(default)
+    };
+    let (limit, offset) = (number("limit", 20), number("offset", 0));
+
+    let end = (offset + limit).min(items.len());
+    let next = (end < items.len()).then(|| {
+        format!(
+            "https://api.spotify.com/v1{}?offset={}&limit={}",
+            request.path, end, limit
+        )
+    });
+    let mut body = body.clone();
+    body["items"] = Value::from(items[offset.min(end)..end].to_vec());
+    body["total"] = Value::from(items.len());
+    body["limit"] = Value::from(limit);
+    body["offset"] = Value::from(offset);
+    body["next"] = Value::from(next);
+    body
+}
+
+// Spotify's error body, {"error": {"status": ..., "message": ...}}.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let body = serde_json::json!({"error": {"status": status.as_u16(), "message": message}});
+    let response = json_response(status, &body);
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => {
+            warp::reply::with_header(response, "Retry-After", "1").into_response()
+        }
+        _ => response,
+    }
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+impl MockSpotify {
+    // Starts the mock on a free local port, on the current runtime.
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            fixtures: default_fixtures(),
+            ..Default::default()
+        }));
+        let (addr, server) =
+            warp::serve(routes(Arc::clone(&state))).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        MockSpotify { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn client_id(&self) -> &'static str {
+        CLIENT_ID
+    }
+
+    // Back to the default fixtures with nothing injected or recorded. Issued
+    // tokens stay valid.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.fixtures = default_fixtures();
+        state.failures.clear();
+        state.requests.clear();
+        state.grants.clear();
+    }
+
+    // Answers method and path (without /v1) with the given status and body.
+    pub fn set(&self, method: Method, path: &str, status: StatusCode, body: Value) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .fixtures
+            .insert((method, path.to_string()), (status, body));
+    }
+
+    // Fails the next times calls to method and path with the given status,
+    // before the fixture is served again.
+    pub fn fail(&self, method: Method, path: &str, status: StatusCode, times: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.push(Failure {
+            method,
+            path: path.to_string(),
+            status,
+            remaining: times,
+        });
+    }
+
+    // Web API requests received since the last reset.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    // grant_type of each successful token request since the last reset.
+    pub fn grants(&self) -> Vec<String> {
+        self.state.lock().unwrap().grants.clone()
+    }
+}
+
+fn routes(
+    state: Arc<Mutex<MockState>>,
+) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone {
+    let authorize = warp::get()
+        .and(warp::path("authorize"))
+        .and(warp::path::end())
+        .and(warp::query::<HashMap<String, String>>())
+        .map({
+            let state = Arc::clone(&state);
+            move |query: HashMap<String, String>| authorize(&state, query)
+        });
+
+    let token = warp::post()
+        .and(warp::path("api"))
+        .and(warp::path("token"))
+        .and(warp::path::end())
+        .and(warp::body::form::<HashMap<String, String>>())
+        .map({
+            let state = Arc::clone(&state);
+            move |form: HashMap<String, String>| token(&state, form)
+        });
+
+    let api = warp::path("v1")
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::optional::<String>("authorization"))
+        .map({
+            let state = Arc::clone(&state);
+            move |method: Method,
+                  tail: warp::path::Tail,
+                  query: String,
+                  authorization: Option<String>| {
+                let request = MockRequest {
+                    method,
+                    path: format!("/{}", tail.as_str()),
+                    query,
+                };
+                api(&state, request, authorization)
+            }
+        });
+
+    authorize.or(token).unify().or(api).unify()
+}
+
+fn authorize(state: &Mutex<MockState>, query: HashMap<String, String>) -> Response {
+    let param = |name: &str| query.get(name).cloned().unwrap_or_default();
+    if param("response_type") != "code"
+        || param("client_id") != CLIENT_ID
+        || param("code_challenge_method") != "S256"
+        || param("code_challenge").is_empty()
+        || param("redirect_uri").is_empty()
+    {
+        return error_response(StatusCode::BAD_REQUEST, "invalid authorization request");
+    }
+
+    let mut state = state.lock().unwrap();
+    state.issued += 1;
+    let code = format!("mock-code-{}", state.issued);
+    state.codes.insert(
+        code.clone(),
+        PendingCode {
+            challenge: param("code_challenge"),
+            redirect_uri: param("redirect_uri"),
+        },
+    );
+
+    let location = url::Url::parse_with_params(
+        &param("redirect_uri"),
+        &[("code", code.as_str()), ("state", param("state").as_str())],
+    );
+    match location {
+        Ok(location) => warp::http::Response::builder()
+            .status(StatusCode::FOUND)
+            .header("Location", location.as_str())
+            .body(warp::hyper::Body::empty())
+            .unwrap(),
+        Err(_) => error_response(StatusCode::BAD_REQUEST, "invalid redirect_uri"),
+    }
+}
+
+fn token(state: &Mutex<MockState>, form: HashMap<String, String>) -> Response {
+    let param = |name: &str| form.get(name).cloned().unwrap_or_default();
+    let invalid_grant = || {
+        json_response(
+            StatusCode::BAD_REQUEST,
+            &serde_json::json!({"error": "invalid_grant"}),
+        )
+    };
+    if param("client_id") != CLIENT_ID {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            &serde_json::json!({"error": "invalid_client"}),
+        );
+    }
+
+    let mut state = state.lock().unwrap();
+    let grant = param("grant_type");
+    match grant.as_str() {
+        "authorization_code" => {
+            let Some(pending) = state.codes.remove(&param("code")) else {
+                return invalid_grant();
+            };
+            if pending.redirect_uri != param("redirect_uri")
+                || pending.challenge != pkce_challenge(&param("code_verifier"))
+            {
+                return invalid_grant();
+            }
+        }
+        "refresh_token" => {
+            if !state.refresh_tokens.contains(&param("refresh_token")) {
+                return invalid_grant();
+            }
+        }
+        _ => return invalid_grant(),
+    }
+
+    state.issued += 1;
+    let access_token = format!("mock-access-{}", state.issued);
+    let refresh_token = format!("mock-refresh-{}", state.issued);
+    state.access_tokens.push(access_token.clone());
+    state.refresh_tokens.push(refresh_token.clone());
+    state.grants.push(grant);
+
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": 3600,
+            "refresh_token": refresh_token,
+            "scope": "",
+        }),
+    )
+}
+
+fn api(state: &Mutex<MockState>, request: MockRequest, authorization: Option<String>) -> Response {
+    let mut state = state.lock().unwrap();
+    state.requests.push(request.clone());
+
+    let token = authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "));
+    if !token.is_some_and(|t| state.access_tokens.iter().any(|a| a == t)) {
+        return error_response(StatusCode::UNAUTHORIZED, "The access token expired");
+    }
+
+    let failure = state
+        .failures
+        .iter_mut()
+        .find(|f| f.method == request.method && f.path == request.path && f.remaining > 0);
+    if let Some(failure) = failure {
+        failure.remaining -= 1;
+        let status = failure.status;
+        if status == StatusCode::UNAUTHORIZED {
+            // a rejected token stays rejected, like an expired one
+            let token = token.unwrap_or_default().to_string();
+            state.access_tokens.retain(|t| *t != token);
+        }
+        return error_response(status, "Injected failure");
+    }
+
+    match state
+        .fixtures
+        .get(&(request.method.clone(), request.path.clone()))
+    {
+        Some((status, body)) => json_response(*status, &page(body, &request)),
+        None => error_response(StatusCode::NOT_FOUND, "Service not found"),
+    }
+}
@@ -12,8 +12,10 @@ pub enum ApiError {
     BackoffError,        // Error occurred while backing off
     BrowserError,        // Error occurred while interacting with browser
     InternalServerError, // Error occurred on the api server
+    DatabaseError,       // Error occurred while accessing the local database
 
     ResponseError204, // Error returned in the response
+    ResponseError400, // Error returned in the response
     ResponseError401, // Error returned in the response
     ResponseError403, // Error returned in the response
     ResponseError404, // Error returned in the response
@@ -30,9 +32,12 @@ impl Display for ApiError {
     }
 }
 
+impl std::error::Error for ApiError {}
+
 pub fn return_response_error(sc: StatusCode) -> ApiError {
     match sc {
         StatusCode::NO_CONTENT => ApiError::ResponseError204,
+        StatusCode::BAD_REQUEST => ApiError::ResponseError400,
         StatusCode::UNAUTHORIZED => ApiError::ResponseError401,
         StatusCode::FORBIDDEN => ApiError::ResponseError403,
         StatusCode::NOT_FOUND => ApiError::ResponseError404,
@@ -48,6 +53,7 @@ pub fn return_response_error(sc: StatusCode) -> ApiError {
 pub fn return_response_code(ae: ApiError) -> StatusCode {
     match ae {
         ApiError::ResponseError204 => StatusCode::NO_CONTENT,
+        ApiError::ResponseError400 => StatusCode::BAD_REQUEST,
         ApiError::ResponseError401 => StatusCode::UNAUTHORIZED,
         ApiError::ResponseError403 => StatusCode::FORBIDDEN,
         ApiError::ResponseError404 => StatusCode::NOT_FOUND,
@@ -71,8 +77,10 @@ pub fn return_error_message(e: &ApiError) -> String {
         ApiError::BackoffError => "Error occurred while backing off".to_string(),
         ApiError::BrowserError => "Error occurred while interacting with browser".to_string(),
         ApiError::InternalServerError => "Error occurred on the api server".to_string(),
+        ApiError::DatabaseError => "Error occurred while accessing the local database".to_string(),
 
         ApiError::ResponseError204 => "No content returned in the response".to_string(),
+        ApiError::ResponseError400 => "Bad request".to_string(),
         ApiError::ResponseError401 => "Unauthorized request".to_string(),
         ApiError::ResponseError403 => "Forbidden request".to_string(),
         ApiError::ResponseError404 => "Resource not found".to_string(),
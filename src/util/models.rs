@@ -0,0 +1,306 @@
+// Not every field is read yet; the structs mirror what the Web API returns.
+#![allow(dead_code)]
+
+use crate::util::errors::ApiError;
+use log::warn;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Shapes of the Spotify Web API responses the app reads. Only the fields in
+// use are declared, unknown fields are ignored. IDs are optional, as local
+// files in playlists have none.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artist {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Album {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
+    pub release_date: Option<String>,
+    pub total_tracks: Option<u32>,
+}
+
+// Full and simplified tracks alike; simplified ones, e.g. from an album's
+// track list, have no album.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: String,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
+    pub album: Option<Album>,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub explicit: bool,
+    #[serde(default)]
+    pub is_local: bool,
+    pub popularity: Option<u32>,
+    pub external_ids: Option<ExternalIds>,
+}
+
+impl Track {
+    pub fn isrc(&self) -> Option<&str> {
+        self.external_ids.as_ref()?.isrc.as_deref()
+    }
+}
+
+// Identifiers of a recording outside Spotify.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalIds {
+    pub isrc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Show {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: Option<String>,
+    pub publisher: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Episode {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u64,
+    pub release_date: Option<String>,
+    pub show: Option<Show>,
+}
+
+// Anything that can be played or queued, told apart by its "type" field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PlayableItem {
+    Track(Track),
+    Episode(Episode),
+}
+
+impl PlayableItem {
+    pub fn uri(&self) -> &str {
+        match self {
+            PlayableItem::Track(track) => &track.uri,
+            PlayableItem::Episode(episode) => &episode.uri,
+        }
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        match self {
+            PlayableItem::Track(track) => track.duration_ms,
+            PlayableItem::Episode(episode) => episode.duration_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: Option<String>,
+    pub display_name: Option<String>,
+}
+
+// Where a playlist's items can be fetched, and how many there are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistTracksRef {
+    pub href: Option<String>,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: String,
+    pub description: Option<String>,
+    pub owner: Option<User>,
+    pub public: Option<bool>,
+    #[serde(default)]
+    pub collaborative: bool,
+    pub snapshot_id: Option<String>,
+    pub tracks: Option<PlaylistTracksRef>,
+}
+
+impl Playlist {
+    pub fn track_count(&self) -> u32 {
+        self.tracks.as_ref().map_or(0, |tracks| tracks.total)
+    }
+}
+
+// An entry of a playlist. The item is missing when it's no longer available.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    pub added_at: Option<String>,
+    pub track: Option<PlayableItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub is_active: bool,
+    pub volume_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Devices {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Context {
+    pub uri: String,
+}
+
+// Response of me/player and me/player/currently-playing; the latter has no
+// device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackState {
+    pub device: Option<Device>,
+    pub progress_ms: Option<u64>,
+    pub is_playing: bool,
+    pub item: Option<PlayableItem>,
+    pub context: Option<Context>,
+    pub shuffle_state: Option<bool>,
+    pub repeat_state: Option<String>,
+    pub currently_playing_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Queue {
+    pub currently_playing: Option<PlayableItem>,
+    #[serde(default)]
+    pub queue: Vec<PlayableItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayHistory {
+    pub track: Track,
+    pub played_at: String,
+    pub context: Option<Context>,
+}
+
+// Items of the user's library, with when they were saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedTrack {
+    pub added_at: Option<String>,
+    pub track: Track,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedAlbum {
+    pub added_at: Option<String>,
+    pub album: Album,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedShow {
+    pub added_at: Option<String>,
+    pub show: Show,
+}
+
+// Response of the endpoints that look up several tracks at once. Unknown IDs
+// come back as null.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tracks {
+    pub tracks: Vec<Option<Track>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub tracks: Option<Paging<Track>>,
+    pub artists: Option<Paging<Artist>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recommendations {
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedArtists {
+    pub artists: Vec<Artist>,
+}
+
+// Spotify's audio analysis of a track, each rating from 0.0 to 1.0 but
+// tempo, in beats per minute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioFeatures {
+    pub uri: String,
+    pub energy: f64,
+    pub tempo: f64,
+    pub danceability: f64,
+    pub valence: f64,
+    pub acousticness: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioFeaturesList {
+    pub audio_features: Vec<Option<AudioFeatures>>,
+}
+
+// Version of a playlist, returned by every edit of its items.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistSnapshot {
+    pub snapshot_id: String,
+}
+
+// A page of an offset paged list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Paging<T> {
+    pub items: Vec<T>,
+    #[serde(default)]
+    pub total: u32,
+    #[serde(default)]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+    pub next: Option<String>,
+    pub previous: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursors {
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+// A page of a cursor paged list, such as recently played tracks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CursorPaging<T> {
+    pub items: Vec<T>,
+    #[serde(default)]
+    pub limit: u32,
+    pub next: Option<String>,
+    pub cursors: Option<Cursors>,
+    pub total: Option<u32>,
+}
+
+// Deserializes a response. No content reads as null, so it only fits an
+// Option. Responses of another shape are a ResponseDataError.
+pub fn from_response<T: DeserializeOwned>(status: StatusCode, json: Value) -> Result<T, ApiError> {
+    let json = match status {
+        StatusCode::NO_CONTENT => Value::Null,
+        _ => json,
+    };
+    serde_json::from_value(json).map_err(|e| {
+        warn!(
+            "Unexpected response shape for {}: {}",
+            std::any::type_name::<T>(),
+            e
+        );
+        ApiError::ResponseDataError
+    })
+}
@@ -1,59 +1,386 @@
-#[derive(Debug)]
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum UriType {
     Track,
+    Episode,
     Album,
     Artist,
     Playlist,
+    Show,
+    User,
+    Collection,
     Unknown,
 }
 
-pub fn get_uri_type(uri: &str) -> UriType {
-    if uri.starts_with("spotify:track") {
-        return UriType::Track;
-    } else if uri.starts_with("spotify:album") {
-        return UriType::Album;
-    } else if uri.starts_with("spotify:artist") {
-        return UriType::Artist;
-    } else if uri.starts_with("spotify:playlist") {
-        return UriType::Playlist;
-    } else {
-        return UriType::Unknown;
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum UriError {
+    Empty,                  // Nothing to parse
+    UnknownType(String),    // Type segment is not a known Spotify type
+    InvalidId(String),      // ID is not a 22 character base62 string
+    Malformed(String),      // Input is not a URI, URL or ID
+    BareId(String),         // Bare ID given where the type can't be inferred
+    UnresolvedLink(String), // Short link could not be resolved
+}
+
+impl Display for UriError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UriError::Empty => write!(f, "Expected a Spotify URI, URL or ID"),
+            UriError::UnknownType(t) => write!(f, "Unknown Spotify item type '{}'", t),
+            UriError::InvalidId(id) => write!(
+                f,
+                "Invalid Spotify ID '{}' (expected 22 base62 characters)",
+                id
+            ),
+            UriError::Malformed(s) => write!(f, "'{}' is not a Spotify URI, URL or ID", s),
+            UriError::BareId(id) => write!(
+                f,
+                "Cannot tell what '{}' refers to, use a full URI or URL instead",
+                id
+            ),
+            UriError::UnresolvedLink(s) => write!(f, "Could not resolve link '{}'", s),
+        }
     }
 }
 
-pub fn get_id_from_uri(uri: &str) -> Option<String> {
-    let uri_parts: Vec<&str> = uri.split(':').collect();
-    if uri_parts.len() < 3 {
-        return None;
+// A parsed and validated Spotify identifier.
+// user is set for user URIs and for user scoped URIs such as collections or
+// legacy playlist URIs (spotify:user:<name>:playlist:<id>).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SpotifyId {
+    pub uri_type: UriType,
+    pub id: String,
+    pub user: Option<String>,
+}
+
+impl Display for SpotifyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.uri_type, &self.user) {
+            (UriType::User, _) => write!(f, "spotify:user:{}", self.id),
+            (UriType::Collection, Some(user)) => write!(f, "spotify:user:{}:collection", user),
+            (UriType::Collection, None) => write!(f, "spotify:collection:tracks"),
+            (t, _) => write!(f, "spotify:{}:{}", type_name(t), self.id),
+        }
     }
-    return Some(uri_parts[2].to_string());
 }
 
-pub fn get_uri_from_id(uri_type: &UriType, id: &str) -> Option<String> {
+fn type_name(uri_type: UriType) -> &'static str {
     match uri_type {
-        UriType::Track => return Some(format!("spotify:track:{}", id)),
-        UriType::Album => return Some(format!("spotify:album:{}", id)),
-        UriType::Artist => return Some(format!("spotify:artist:{}", id)),
-        UriType::Playlist => return Some(format!("spotify:playlist:{}", id)),
-        _ => return None,
+        UriType::Track => "track",
+        UriType::Episode => "episode",
+        UriType::Album => "album",
+        UriType::Artist => "artist",
+        UriType::Playlist => "playlist",
+        UriType::Show => "show",
+        UriType::User => "user",
+        UriType::Collection => "collection",
+        UriType::Unknown => "unknown",
     }
 }
 
-pub fn get_url_from_uri(uri: &str) -> Option<String> {
-    let uri_parts: Vec<&str> = uri.split(':').collect();
-    if uri_parts.len() < 3 {
-        return None;
+fn type_from_name(name: &str) -> Option<UriType> {
+    match name {
+        "track" => Some(UriType::Track),
+        "episode" => Some(UriType::Episode),
+        "album" => Some(UriType::Album),
+        "artist" => Some(UriType::Artist),
+        "playlist" => Some(UriType::Playlist),
+        "show" => Some(UriType::Show),
+        "user" => Some(UriType::User),
+        "collection" => Some(UriType::Collection),
+        _ => None,
     }
-    return Some(format!(
-        "https://open.spotify.com/{}/{}",
-        uri_parts[1], uri_parts[2]
-    ));
 }
 
-pub fn split_uris(uris: &str) -> Vec<String> {
-    return uris
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Returns true for shortened share links, which have to be resolved by
+// following their redirect before they can be parsed.
+pub fn is_short_link(input: &str) -> bool {
+    let rest = input
         .trim()
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .collect();
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    rest.starts_with("spotify.link/") || rest.starts_with("spotify.app.link/")
+}
+
+impl SpotifyId {
+    pub fn new(uri_type: UriType, id: &str) -> Result<Self, UriError> {
+        if !is_valid_id(id) {
+            return Err(UriError::InvalidId(id.to_string()));
+        }
+        Ok(SpotifyId {
+            uri_type,
+            id: id.to_string(),
+            user: None,
+        })
+    }
+
+    // Parses a spotify:type:id URI, an open.spotify.com URL or, if a default
+    // type is given, a bare ID. Short links must be resolved beforehand.
+    pub fn parse(input: &str, default_type: Option<UriType>) -> Result<Self, UriError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(UriError::Empty);
+        }
+
+        if let Some(rest) = input.strip_prefix("spotify:") {
+            let parts: Vec<&str> = rest.split(':').collect();
+            return SpotifyId::from_segments(&parts, input);
+        }
+
+        let without_scheme = input
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        if let Some(path) = without_scheme.strip_prefix("open.spotify.com/") {
+            let path = path.split(['?', '#']).next().unwrap_or("");
+            let mut parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+            // localized URLs, e.g. open.spotify.com/intl-de/track/<id>
+            if parts.first().is_some_and(|p| p.starts_with("intl-")) {
+                parts.remove(0);
+            }
+            // embed URLs, e.g. open.spotify.com/embed/track/<id>
+            if parts.first() == Some(&"embed") {
+                parts.remove(0);
+            }
+            return SpotifyId::from_segments(&parts, input);
+        }
+
+        if is_short_link(input) {
+            return Err(UriError::UnresolvedLink(input.to_string()));
+        }
+
+        if is_valid_id(input) {
+            return match default_type {
+                Some(uri_type) => SpotifyId::new(uri_type, input),
+                None => Err(UriError::BareId(input.to_string())),
+            };
+        }
+
+        Err(UriError::Malformed(input.to_string()))
+    }
+
+    // Parses the type/id segments shared by URIs and URLs.
+    fn from_segments(parts: &[&str], input: &str) -> Result<Self, UriError> {
+        match parts {
+            ["user", user] => Ok(SpotifyId {
+                uri_type: UriType::User,
+                id: user.to_string(),
+                user: None,
+            }),
+            ["user", user, "collection", ..] => Ok(SpotifyId {
+                uri_type: UriType::Collection,
+                id: String::new(),
+                user: Some(user.to_string()),
+            }),
+            ["user", user, "playlist", id] => {
+                let mut parsed = SpotifyId::new(UriType::Playlist, id)?;
+                parsed.user = Some(user.to_string());
+                Ok(parsed)
+            }
+            ["collection"] | ["collection", "tracks"] => Ok(SpotifyId {
+                uri_type: UriType::Collection,
+                id: String::new(),
+                user: None,
+            }),
+            [type_part, id] => match type_from_name(type_part) {
+                Some(UriType::User) | Some(UriType::Collection) | None => {
+                    Err(UriError::UnknownType(type_part.to_string()))
+                }
+                Some(uri_type) => SpotifyId::new(uri_type, id),
+            },
+            _ => Err(UriError::Malformed(input.to_string())),
+        }
+    }
+
+    pub fn uri(&self) -> String {
+        self.to_string()
+    }
+}
+
+pub fn get_uri_type(uri: &str) -> UriType {
+    SpotifyId::parse(uri, None)
+        .map(|id| id.uri_type)
+        .unwrap_or(UriType::Unknown)
+}
+
+pub fn get_id_from_uri(uri: &str) -> Option<String> {
+    SpotifyId::parse(uri, None).ok().map(|id| id.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4uLU6hMCjMI75M1A2tKUQC";
+
+    #[test]
+    fn parses_uris_urls_and_ids() {
+        let cases: &[(&str, Option<UriType>, &str)] = &[
+            (
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "  spotify:album:4uLU6hMCjMI75M1A2tKUQC ",
+                None,
+                "spotify:album:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "https://open.spotify.com/artist/4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:artist:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "http://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc",
+                None,
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "open.spotify.com/playlist/4uLU6hMCjMI75M1A2tKUQC#x",
+                None,
+                "spotify:playlist:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "https://open.spotify.com/intl-de/track/4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "https://open.spotify.com/embed/episode/4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:episode:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "https://open.spotify.com/intl-fr/embed/show/4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:show:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            ("spotify:user:rick", None, "spotify:user:rick"),
+            (
+                "https://open.spotify.com/user/rick",
+                None,
+                "spotify:user:rick",
+            ),
+            (
+                "spotify:user:rick:collection",
+                None,
+                "spotify:user:rick:collection",
+            ),
+            (
+                "spotify:user:rick:playlist:4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                "spotify:playlist:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "spotify:collection:tracks",
+                None,
+                "spotify:collection:tracks",
+            ),
+            (
+                "https://open.spotify.com/collection/tracks",
+                None,
+                "spotify:collection:tracks",
+            ),
+            (
+                "4uLU6hMCjMI75M1A2tKUQC",
+                Some(UriType::Track),
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "4uLU6hMCjMI75M1A2tKUQC",
+                Some(UriType::Artist),
+                "spotify:artist:4uLU6hMCjMI75M1A2tKUQC",
+            ),
+        ];
+
+        for (input, default_type, expected) in cases {
+            let parsed = SpotifyId::parse(input, *default_type);
+            assert_eq!(
+                parsed.map(|id| id.uri()).as_deref(),
+                Ok(*expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_user_of_user_scoped_uris() {
+        let parsed = SpotifyId::parse("spotify:user:rick:playlist:4uLU6hMCjMI75M1A2tKUQC", None);
+        assert_eq!(
+            parsed,
+            Ok(SpotifyId {
+                uri_type: UriType::Playlist,
+                id: ID.to_string(),
+                user: Some("rick".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_spotify_id() {
+        let cases: &[(&str, Option<UriType>, UriError)] = &[
+            ("", None, UriError::Empty),
+            ("   ", Some(UriType::Track), UriError::Empty),
+            (ID, None, UriError::BareId(ID.to_string())),
+            (
+                "spotify:song:4uLU6hMCjMI75M1A2tKUQC",
+                None,
+                UriError::UnknownType("song".to_string()),
+            ),
+            (
+                "spotify:user:4uLU6hMCjMI75M1A2tKUQC:album",
+                None,
+                UriError::Malformed("spotify:user:4uLU6hMCjMI75M1A2tKUQC:album".to_string()),
+            ),
+            (
+                "spotify:track:short",
+                None,
+                UriError::InvalidId("short".to_string()),
+            ),
+            (
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQ!",
+                None,
+                UriError::InvalidId("4uLU6hMCjMI75M1A2tKUQ!".to_string()),
+            ),
+            (
+                "https://spotify.link/abc",
+                None,
+                UriError::UnresolvedLink("https://spotify.link/abc".to_string()),
+            ),
+            (
+                "\"spotify:track:4uLU6hMCjMI75M1A2tKUQC\"",
+                None,
+                UriError::Malformed("\"spotify:track:4uLU6hMCjMI75M1A2tKUQC\"".to_string()),
+            ),
+            (
+                "never gonna",
+                Some(UriType::Track),
+                UriError::Malformed("never gonna".to_string()),
+            ),
+        ];
+
+        for (input, default_type, expected) in cases {
+            assert_eq!(
+                SpotifyId::parse(input, *default_type).as_ref(),
+                Err(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn detects_short_links() {
+        assert!(is_short_link("https://spotify.link/abc"));
+        assert!(is_short_link("spotify.app.link/abc"));
+        assert!(!is_short_link(
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+        ));
+    }
 }